@@ -7,28 +7,43 @@ use std::sync::Arc;
 use tokio::sync::RwLock;
 
 // Import the AI services
-use crate::providers::{OpenAIService, AnthropicService, GeminiService, OpenRouterService, AIProvider};
+use crate::providers::ollama::OLLAMA_MODEL_PREFIX;
+use crate::providers::{
+    failover, structured, GenerationOptions, OpenRouterProviderPreferences, ProviderRegistry, Reasoning, RequestFeatures,
+    ResolvedProvider, ResponseFormat, ToolCall, ToolSpec,
+};
+use crate::providers::tools::{TOOL_CALLS_KEY, TOOL_CALL_ID_KEY, TOOL_NAME_KEY};
 
 /// AI Agent configuration and execution engine
 #[derive(Debug, Clone)]
 pub struct AgentManager {
     agents: Arc<RwLock<HashMap<String, Agent>>>,
     tools: Arc<RwLock<HashMap<String, ToolDefinition>>>,
+    providers: Arc<ProviderRegistry>,
 }
 
 impl AgentManager {
-    pub fn new() -> Self {
+    pub fn new(providers: Arc<ProviderRegistry>) -> Self {
         Self {
             agents: Arc::new(RwLock::new(HashMap::new())),
             tools: Arc::new(RwLock::new(HashMap::new())),
+            providers,
         }
     }
 
     /// Create a new agent with specified configuration
+    ///
+    /// The provider must be one the registry knows, unless the model is an alias; whether it
+    /// is configured is checked when the agent runs.
     pub async fn create_agent(&self, config: AgentConfig) -> Result<String> {
         let agent_id = config.id.clone();
         let agent = Agent::from_config(config)?;
 
+        let provider = Agent::provider_for(&agent.config);
+        if !self.providers.is_alias(&agent.config.model) && !self.providers.knows(provider) {
+            return Err(anyhow!("Unsupported provider: {}", provider));
+        }
+
         let mut agents = self.agents.write().await;
         agents.insert(agent_id.clone(), agent);
 
@@ -47,7 +62,7 @@ impl AgentManager {
         let agent = agents.get(agent_id)
             .ok_or_else(|| anyhow!("Agent not found: {}", agent_id))?;
 
-        let execution = agent.execute(prompt, &self.tools).await?;
+        let execution = agent.execute(prompt, &self.tools, &self.providers).await?;
         Ok(execution)
     }
 
@@ -61,7 +76,7 @@ impl AgentManager {
         let agent = agents.get(agent_id)
             .ok_or_else(|| anyhow!("Agent not found: {}", agent_id))?;
 
-        let stream = agent.execute_stream(prompt, &self.tools).await?;
+        let stream = agent.execute_stream(prompt, &self.tools, &self.providers).await?;
         Ok(stream)
     }
 
@@ -105,7 +120,7 @@ pub struct AgentConfig {
     pub id: String,
     pub name: String,
     pub description: String,
-    pub provider: String, // "openai", "anthropic", "gemini", "openrouter"
+    pub provider: String, // "openai", "anthropic", "gemini", "openrouter", "ollama", "bedrock", "vertex"
     pub model: String,
     pub system: String, // System prompt
     pub temperature: Option<f32>,
@@ -115,6 +130,15 @@ pub struct AgentConfig {
     pub created_at: DateTime<Utc>,
     pub last_used: Option<DateTime<Utc>>,
     pub metadata: Option<HashMap<String, Value>>,
+    /// OpenRouter provider routing preferences (order, allow_fallbacks, data_collection)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub provider_routing: Option<OpenRouterProviderPreferences>,
+    /// OpenRouter message transforms, e.g. ["middle-out"]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub transforms: Option<Vec<String>>,
+    /// Format of the final answer; a JSON schema is enforced on it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub response_format: Option<ResponseFormat>,
 }
 
 impl Default for AgentConfig {
@@ -134,6 +158,9 @@ impl Default for AgentConfig {
             created_at: now,
             last_used: None,
             metadata: None,
+            provider_routing: None,
+            transforms: None,
+            response_format: None,
         }
     }
 }
@@ -157,11 +184,19 @@ impl Agent {
         &self.config
     }
 
+    /// Provider that serves the agent's model; an `ollama/` model prefix selects Ollama
+    fn provider_for(config: &AgentConfig) -> &str {
+        if config.model.starts_with(OLLAMA_MODEL_PREFIX) {
+            "ollama"
+        } else {
+            config.provider.as_str()
+        }
+    }
+
     fn validate_config(config: &AgentConfig) -> Result<()> {
-        // Validate provider
-        match config.provider.as_str() {
-            "openai" | "anthropic" | "gemini" | "openrouter" => {},
-            _ => return Err(anyhow!("Unsupported provider: {}", config.provider)),
+        // Validate provider; whether it is configured is checked when the agent runs
+        if Self::provider_for(config).is_empty() {
+            return Err(anyhow!("Provider must not be empty"));
         }
 
         // Validate temperature
@@ -178,11 +213,28 @@ impl Agent {
             }
         }
 
+        // Validate the response schema
+        if let Some(ResponseFormat::JsonSchema { json_schema }) = &config.response_format {
+            structured::check_schema(&json_schema.schema)?;
+        }
+
+        // Validate OpenRouter data collection policy
+        if let Some(policy) = config.provider_routing.as_ref().and_then(|p| p.data_collection.as_deref()) {
+            if policy != "allow" && policy != "deny" {
+                return Err(anyhow!("provider_routing.data_collection must be \"allow\" or \"deny\""));
+            }
+        }
+
         Ok(())
     }
 
     /// Execute the agent with a prompt
-    pub async fn execute(&self, prompt: &str, tools: &Arc<RwLock<HashMap<String, ToolDefinition>>>) -> Result<AgentExecution> {
+    pub async fn execute(
+        &self,
+        prompt: &str,
+        tools: &Arc<RwLock<HashMap<String, ToolDefinition>>>,
+        providers: &ProviderRegistry,
+    ) -> Result<AgentExecution> {
         let start_time = Utc::now();
         let execution_id = format!("exec_{}", fastrand::u64(1000..9999));
 
@@ -211,7 +263,7 @@ impl Agent {
         };
 
         // Execute the tool loop
-        self.execute_tool_loop(&mut state, &available_tools).await?;
+        self.execute_tool_loop(&mut state, &available_tools, providers).await?;
 
         // Create execution result
         let execution = AgentExecution {
@@ -232,16 +284,22 @@ impl Agent {
     }
 
     /// Stream execution of the agent
-    pub async fn execute_stream(&self, prompt: &str, tools: &Arc<RwLock<HashMap<String, ToolDefinition>>>) -> Result<AgentExecutionStream> {
+    pub async fn execute_stream(
+        &self,
+        prompt: &str,
+        tools: &Arc<RwLock<HashMap<String, ToolDefinition>>>,
+        providers: &Arc<ProviderRegistry>,
+    ) -> Result<AgentExecutionStream> {
         let (tx, rx) = tokio::sync::mpsc::unbounded_channel();
 
         let agent = self.clone();
         let prompt = prompt.to_string();
         let tools = tools.clone();
+        let providers = providers.clone();
 
         // Spawn streaming execution task
         tokio::spawn(async move {
-            match agent.execute(&prompt, &tools).await {
+            match agent.execute(&prompt, &tools, &providers).await {
                 Ok(execution) => {
                     // Send final result
                     let _ = tx.send(AgentStreamEvent::Complete(execution));
@@ -256,22 +314,32 @@ impl Agent {
         Ok(AgentExecutionStream { rx })
     }
 
-    async fn execute_tool_loop(&self, state: &mut AgentExecutionState, tools: &[ToolDefinition]) -> Result<()> {
+    async fn execute_tool_loop(
+        &self,
+        state: &mut AgentExecutionState,
+        tools: &[ToolDefinition],
+        providers: &ProviderRegistry,
+    ) -> Result<()> {
         // Add initial user message
         state.messages.push(ChatMessage {
             role: "user".to_string(),
             content: state.prompt.clone(),
             tool_calls: None,
+            tool_call_id: None,
+            reasoning: None,
         });
 
         while state.rounds < state.max_rounds {
             state.rounds += 1;
 
             // Make API call to the AI provider
-            let response = self.make_api_call(&state.system_prompt, &state.messages).await?;
+            let response = self.make_api_call(&state.system_prompt, &state.messages, tools, providers).await?;
 
             // Add assistant response
             state.messages.push(response.message.clone());
+            if let Some(usage) = &response.usage {
+                state.add_usage(usage);
+            }
 
             // Check if there are tool calls
             if let Some(tool_calls) = response.message.tool_calls {
@@ -287,6 +355,8 @@ impl Agent {
                         role: "tool".to_string(),
                         content: serde_json::to_string(&tool_result.result).unwrap_or_default(),
                         tool_calls: None,
+                        tool_call_id: Some(tool_call.id.clone()),
+                        reasoning: None,
                     });
                 }
 
@@ -294,10 +364,10 @@ impl Agent {
                 continue;
             } else {
                 // No more tool calls, we have a final response
-                state.final_response = Some(response.message.content);
+                let content = self.checked_output(state, response.message.content, tools, providers).await?;
+                state.final_response = Some(content);
                 state.status = ExecutionStatus::Completed;
                 state.end_time = Some(Utc::now());
-                state.usage = response.usage;
                 break;
             }
         }
@@ -311,7 +381,53 @@ impl Agent {
         Ok(())
     }
 
-    async fn make_api_call(&self, system_prompt: &str, messages: &[ChatMessage]) -> Result<AIResponse> {
+    /// The final answer, checked against the agent's response schema if it has one
+    ///
+    /// An answer that does not match is sent back once with the errors for the model to repair.
+    async fn checked_output(
+        &self,
+        state: &mut AgentExecutionState,
+        content: String,
+        tools: &[ToolDefinition],
+        providers: &ProviderRegistry,
+    ) -> Result<String> {
+        let format = match &self.config.response_format {
+            Some(ResponseFormat::JsonSchema { json_schema }) => json_schema,
+            _ => return Ok(content),
+        };
+        let errors = match structured::check(format, &content) {
+            Ok(json) => return Ok(json),
+            Err(errors) => errors,
+        };
+
+        state.messages.push(ChatMessage {
+            role: "user".to_string(),
+            content: structured::repair_message(&errors).content,
+            tool_calls: None,
+            tool_call_id: None,
+            reasoning: None,
+        });
+        let response = self.make_api_call(&state.system_prompt, &state.messages, tools, providers).await?;
+        state.messages.push(response.message.clone());
+        if let Some(usage) = &response.usage {
+            state.add_usage(usage);
+        }
+
+        structured::check(format, &response.message.content).map_err(|errors| {
+            anyhow!(
+                "Agent answer does not match the JSON schema after a repair attempt: {}",
+                errors.join("; ")
+            )
+        })
+    }
+
+    async fn make_api_call(
+        &self,
+        system_prompt: &str,
+        messages: &[ChatMessage],
+        tools: &[ToolDefinition],
+        providers: &ProviderRegistry,
+    ) -> Result<AIResponse> {
         // Convert agent message format to chat service format
         let mut chat_messages = vec![
             crate::chat::ChatMessage {
@@ -329,7 +445,7 @@ impl Agent {
             let role = match msg.role.as_str() {
                 "user" => crate::chat::ChatRole::User,
                 "assistant" => crate::chat::ChatRole::Assistant,
-                "tool" => crate::chat::ChatRole::User, // Convert tool messages to user messages for now
+                "tool" => crate::chat::ChatRole::Tool,
                 _ => crate::chat::ChatRole::User,
             };
 
@@ -339,76 +455,150 @@ impl Agent {
                 content: msg.content.clone(),
                 created_at: Some(chrono::Utc::now()),
                 attachments: None,
-                metadata: None,
+                metadata: Self::message_metadata(msg, messages),
             });
         }
 
-        // Call the appropriate AI provider
-        let response = match self.config.provider.as_str() {
-            "openai" => {
-                let service = OpenAIService::from_env()
-                    .map_err(|e| anyhow!("Failed to initialize OpenAI service: {}", e))?;
+        let tool_specs: Option<Vec<ToolSpec>> = if tools.is_empty() {
+            None
+        } else {
+            Some(tools.iter().map(ToolSpec::from).collect())
+        };
 
-                service.chat_completion(chat_messages, Some(self.config.model.clone()), self.config.temperature, self.config.max_tokens).await?
-            }
-            "anthropic" => {
-                let service = AnthropicService::from_env()
-                    .map_err(|e| anyhow!("Failed to initialize Anthropic service: {}", e))?;
-
-                service.chat_completion(
-                    chat_messages,
-                    Some(self.config.model.clone()),
-                    self.config.temperature,
-                    self.config.max_tokens
-                ).await?
-            }
-            "gemini" => {
-                let service = GeminiService::from_env()
-                    .map_err(|e| anyhow!("Failed to initialize Gemini service: {}", e))?;
-
-                service.chat_completion(
-                    chat_messages,
-                    Some(self.config.model.clone()),
-                    self.config.temperature,
-                    self.config.max_tokens
-                ).await?
-            }
-            "openrouter" => {
-                let service = OpenRouterService::from_env()
-                    .map_err(|e| anyhow!("Failed to initialize OpenRouter service: {}", e))?;
-
-                service.chat_completion(
-                    chat_messages,
-                    Some(self.config.model.clone()),
-                    self.config.temperature,
-                    self.config.max_tokens
-                ).await?
+        // Call the agent's AI provider, or each provider of a model alias in turn
+        let mut chain = self.provider_chain(providers)?;
+        if self.config.provider_routing.is_some() || self.config.transforms.is_some() {
+            for resolved in &mut chain {
+                if let Some(routed) = resolved.provider.with_provider_routing(
+                    self.config.provider_routing.clone(),
+                    self.config.transforms.clone(),
+                ) {
+                    resolved.provider = routed;
+                }
             }
-            _ => return Err(anyhow!("Unsupported provider: {}", self.config.provider)),
+        }
+
+        let options = GenerationOptions {
+            response_format: self.config.response_format.clone(),
+            ..GenerationOptions::sampling(self.config.temperature, self.config.max_tokens)
         };
+        let features = RequestFeatures::of(&chat_messages, &options, tool_specs.as_deref());
+        let chain = providers.supporting(chain, &features).map_err(|reason| anyhow!(reason))?;
+
+        let (resolved, result) = failover::run(&chain, |resolved| {
+            resolved.provider.chat_completion(
+                chat_messages.clone(),
+                Some(resolved.model.clone()),
+                options.clone(),
+                tool_specs.clone(),
+                None,
+            )
+        })
+        .await;
+        let response = result?;
+        tracing::debug!("Agent {} answered by {} ({})", self.config.id, resolved.name, resolved.model);
 
         // Convert the response back to agent format
-        // Note: For now, we don't parse tool calls from real providers, but this would need to be added
-        let usage = response.metadata
-            .as_ref()
-            .and_then(|m| m.get("usage"))
-            .and_then(|u| serde_json::from_value(u.clone()).ok())
-            .map(|usage: crate::chat::Usage| UsageInfo {
+        let usage = crate::chat::Usage::from_metadata(response.metadata.as_ref())
+            .map(|usage| UsageInfo {
                 prompt_tokens: usage.prompt_tokens,
                 completion_tokens: usage.completion_tokens,
                 total_tokens: usage.total_tokens,
+                cache_creation_input_tokens: usage.cache_creation_input_tokens,
+                cache_read_input_tokens: usage.cache_read_input_tokens,
             });
 
+        let tool_calls = ToolCall::from_metadata(response.metadata.as_ref()).map(|calls| {
+            calls
+                .into_iter()
+                .map(|call| ToolCallInfo {
+                    call_type: "function".to_string(),
+                    function: ToolFunctionCall {
+                        arguments: call.arguments_string(),
+                        name: call.name,
+                    },
+                    id: call.id,
+                })
+                .collect()
+        });
+
         Ok(AIResponse {
             message: ChatMessage {
                 role: "assistant".to_string(),
                 content: response.content,
-                tool_calls: None, // TODO: Parse tool calls from real AI responses
+                tool_calls,
+                tool_call_id: None,
+                reasoning: Reasoning::from_metadata(response.metadata.as_ref()),
             },
             usage,
         })
     }
 
+    /// Providers to try for the agent's model: every entry of a model alias, otherwise the
+    /// agent's configured provider
+    fn provider_chain(&self, providers: &ProviderRegistry) -> Result<Vec<ResolvedProvider>> {
+        if providers.is_alias(&self.config.model) {
+            let chain = providers.resolve_chain(&self.config.model);
+            if chain.is_empty() {
+                return Err(anyhow!("No provider configured for model alias: {}", self.config.model));
+            }
+            return Ok(chain);
+        }
+
+        let name = Self::provider_for(&self.config);
+        let provider = providers
+            .get(name)
+            .ok_or_else(|| anyhow!("Provider not configured: {}", name))?;
+        Ok(vec![ResolvedProvider {
+            name: name.to_string(),
+            provider,
+            model: self.config.model.clone(),
+        }])
+    }
+
+    /// Build provider metadata carrying the tool calls and reasoning, or the tool result id,
+    /// of an agent message
+    fn message_metadata(msg: &ChatMessage, history: &[ChatMessage]) -> Option<HashMap<String, Value>> {
+        if let Some(tool_calls) = &msg.tool_calls {
+            let calls: Vec<ToolCall> = tool_calls
+                .iter()
+                .map(|call| ToolCall::from_raw_arguments(
+                    call.id.clone(),
+                    call.function.name.clone(),
+                    &call.function.arguments,
+                ))
+                .collect();
+            let mut metadata = HashMap::from([(
+                TOOL_CALLS_KEY.to_string(),
+                serde_json::to_value(calls).unwrap_or(Value::Null),
+            )]);
+            // Providers that sign their reasoning require it back on tool-use turns
+            if let Some(reasoning) = &msg.reasoning {
+                Reasoning::insert_into(reasoning, &mut metadata);
+            }
+            return Some(metadata);
+        }
+
+        let tool_call_id = msg.tool_call_id.as_ref()?;
+        let mut metadata = HashMap::from([(
+            TOOL_CALL_ID_KEY.to_string(),
+            Value::String(tool_call_id.clone()),
+        )]);
+
+        // Some providers identify tool results by function name rather than call id
+        let tool_name = history
+            .iter()
+            .filter_map(|m| m.tool_calls.as_ref())
+            .flatten()
+            .find(|call| &call.id == tool_call_id)
+            .map(|call| call.function.name.clone());
+        if let Some(name) = tool_name {
+            metadata.insert(TOOL_NAME_KEY.to_string(), Value::String(name));
+        }
+
+        Some(metadata)
+    }
+
     async fn execute_tool_call(&self, tool_call: &ToolCallInfo, tools: &[ToolDefinition]) -> Result<ToolResult> {
         let tool = tools.iter()
             .find(|t| t.name == tool_call.function.name)
@@ -510,6 +700,16 @@ pub struct ToolDefinition {
     pub function: ToolFunction,
 }
 
+impl From<&ToolDefinition> for ToolSpec {
+    fn from(tool: &ToolDefinition) -> Self {
+        ToolSpec {
+            name: tool.function.name.clone(),
+            description: tool.function.description.clone(),
+            parameters: tool.function.parameters.clone(),
+        }
+    }
+}
+
 /// Tool function definition
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ToolFunction {
@@ -546,12 +746,43 @@ struct AgentExecutionState {
     usage: Option<UsageInfo>,
 }
 
+impl AgentExecutionState {
+    /// Add one round's token usage to the execution total
+    fn add_usage(&mut self, usage: &UsageInfo) {
+        let total = self.usage.get_or_insert(UsageInfo {
+            prompt_tokens: 0,
+            completion_tokens: 0,
+            total_tokens: 0,
+            cache_creation_input_tokens: None,
+            cache_read_input_tokens: None,
+        });
+        total.prompt_tokens += usage.prompt_tokens;
+        total.completion_tokens += usage.completion_tokens;
+        total.total_tokens += usage.total_tokens;
+        total.cache_creation_input_tokens = add_tokens(total.cache_creation_input_tokens, usage.cache_creation_input_tokens);
+        total.cache_read_input_tokens = add_tokens(total.cache_read_input_tokens, usage.cache_read_input_tokens);
+    }
+}
+
+/// Sum of two token counts that providers may not report
+fn add_tokens(total: Option<u32>, tokens: Option<u32>) -> Option<u32> {
+    match (total, tokens) {
+        (None, None) => None,
+        (total, tokens) => Some(total.unwrap_or(0) + tokens.unwrap_or(0)),
+    }
+}
+
 /// Chat message
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ChatMessage {
     pub role: String,
     pub content: String,
     pub tool_calls: Option<Vec<ToolCallInfo>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tool_call_id: Option<String>,
+    /// The model's reasoning before this message, kept so signed reasoning can be sent back
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reasoning: Option<Vec<Reasoning>>,
 }
 
 /// Tool call information
@@ -590,6 +821,12 @@ pub struct UsageInfo {
     pub prompt_tokens: u32,
     pub completion_tokens: u32,
     pub total_tokens: u32,
+    /// Prompt tokens written to the provider's prompt cache
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cache_creation_input_tokens: Option<u32>,
+    /// Prompt tokens read from the provider's prompt cache
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cache_read_input_tokens: Option<u32>,
 }
 
 /// Agent execution result
@@ -720,10 +957,86 @@ pub fn create_default_tools() -> Vec<ToolDefinition> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::providers::testing::{PreambleProvider, StaticProvider};
+    use crate::providers::{AIProvider, ChunkStream, ToolChoice};
+    use async_trait::async_trait;
+
+    /// Calls the calculator until it has seen a tool result, reporting usage every round
+    ///
+    /// Like Anthropic with thinking enabled, it signs its reasoning and rejects a tool result
+    /// whose tool call comes back without it.
+    struct CalculatingProvider;
+
+    #[async_trait]
+    impl AIProvider for CalculatingProvider {
+        async fn chat_completion(
+            &self,
+            messages: Vec<crate::chat::ChatMessage>,
+            _model: Option<String>,
+            _options: GenerationOptions,
+            _tools: Option<Vec<ToolSpec>>,
+            _tool_choice: Option<ToolChoice>,
+        ) -> Result<crate::chat::ChatMessage> {
+            let answered = messages.iter().any(|m| m.role == crate::chat::ChatRole::Tool);
+            if answered {
+                let signed = messages
+                    .iter()
+                    .filter(|m| m.role == crate::chat::ChatRole::Assistant)
+                    .filter_map(|m| Reasoning::from_metadata(m.metadata.as_ref()))
+                    .flatten()
+                    .any(|part| part.signature.as_deref() == Some("sig_1"));
+                if !signed {
+                    return Err(anyhow!("tool_use without its thinking block"));
+                }
+            }
+            let mut metadata = HashMap::from([(
+                "usage".to_string(),
+                serde_json::to_value(crate::chat::Usage::new(10, 5)).unwrap(),
+            )]);
+            if !answered {
+                let call = ToolCall {
+                    id: "call_1".to_string(),
+                    name: "calculator".to_string(),
+                    arguments: serde_json::json!({"expression": "2 + 2"}),
+                };
+                metadata.insert(TOOL_CALLS_KEY.to_string(), serde_json::to_value(vec![call]).unwrap());
+                let reasoning = Reasoning {
+                    text: "The calculator can do this.".to_string(),
+                    signature: Some("sig_1".to_string()),
+                    redacted: None,
+                };
+                Reasoning::insert_into(&[reasoning], &mut metadata);
+            }
+
+            Ok(crate::chat::ChatMessage {
+                id: "calc".to_string(),
+                role: crate::chat::ChatRole::Assistant,
+                content: if answered { "4".to_string() } else { String::new() },
+                created_at: None,
+                attachments: None,
+                metadata: Some(metadata),
+            })
+        }
+
+        async fn chat_completion_stream(
+            &self,
+            _messages: Vec<crate::chat::ChatMessage>,
+            _model: Option<String>,
+            _options: GenerationOptions,
+            _tools: Option<Vec<ToolSpec>>,
+            _tool_choice: Option<ToolChoice>,
+        ) -> Result<ChunkStream> {
+            Err(anyhow!("not used"))
+        }
+
+        fn get_available_models(&self) -> Vec<&'static str> {
+            vec![]
+        }
+    }
 
     #[tokio::test]
     async fn test_agent_creation() {
-        let manager = AgentManager::new();
+        let manager = AgentManager::new(Arc::new(ProviderRegistry::new()));
 
         let config = AgentConfig {
             name: "Test Agent".to_string(),
@@ -739,9 +1052,51 @@ mod tests {
         assert!(!agent_id.is_empty());
     }
 
+    #[test]
+    fn test_ollama_model_prefix_selects_ollama() {
+        let config = AgentConfig {
+            provider: "openai".to_string(),
+            model: "ollama/llama3.2".to_string(),
+            ..Default::default()
+        };
+        assert_eq!(Agent::provider_for(&config), "ollama");
+        assert!(Agent::from_config(config).is_ok());
+
+        let config = AgentConfig {
+            provider: "ollama".to_string(),
+            model: "qwen2.5".to_string(),
+            ..Default::default()
+        };
+        assert!(Agent::from_config(config).is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_unknown_provider_fails_at_creation_and_unconfigured_at_execution() {
+        let manager = AgentManager::new(Arc::new(ProviderRegistry::new()));
+        let config = AgentConfig {
+            provider: "opneai".to_string(),
+            ..Default::default()
+        };
+        let error = manager.create_agent(config).await.unwrap_err();
+        assert_eq!(error.to_string(), "Unsupported provider: opneai");
+
+        // A built-in provider is known even when it is not configured
+        let config = AgentConfig {
+            provider: "anthropic".to_string(),
+            model: "claude-3-5-haiku-20241022".to_string(),
+            ..Default::default()
+        };
+
+        let agent_id = manager.create_agent(config).await.unwrap();
+        let error = manager.execute_agent(&agent_id, "Hello").await.unwrap_err();
+        assert!(error.to_string().contains("Provider not configured: anthropic"));
+    }
+
     #[tokio::test]
     async fn test_agent_execution() {
-        let manager = AgentManager::new();
+        let mut providers = ProviderRegistry::new();
+        providers.register("openai", Arc::new(StaticProvider("2 + 2 = 4")));
+        let manager = AgentManager::new(Arc::new(providers));
 
         // Register default tools
         for tool in create_default_tools() {
@@ -764,4 +1119,59 @@ mod tests {
         assert_eq!(execution.agent_id, agent_id);
         assert!(execution.response.len() > 0);
     }
-}
\ No newline at end of file
+
+    #[tokio::test]
+    async fn test_usage_is_summed_across_rounds() {
+        let mut providers = ProviderRegistry::new();
+        providers.register("openai", Arc::new(CalculatingProvider));
+        let manager = AgentManager::new(Arc::new(providers));
+        for tool in create_default_tools() {
+            manager.register_tool(tool).await.unwrap();
+        }
+
+        let config = AgentConfig {
+            provider: "openai".to_string(),
+            model: "gpt-4o-mini".to_string(),
+            tools: vec!["calculator".to_string()],
+            ..Default::default()
+        };
+        let agent_id = manager.create_agent(config).await.unwrap();
+        // The second round also fails unless the signed reasoning is sent back
+        let execution = manager.execute_agent(&agent_id, "What is 2 + 2?").await.unwrap();
+
+        assert_eq!(execution.rounds, 2);
+        assert_eq!(execution.response, "4");
+        let usage = execution.usage.unwrap();
+        assert_eq!(usage.prompt_tokens, 20);
+        assert_eq!(usage.completion_tokens, 10);
+        assert_eq!(usage.total_tokens, 30);
+    }
+
+    #[tokio::test]
+    async fn test_answer_is_repaired_to_match_the_schema() {
+        let mut providers = ProviderRegistry::new();
+        providers.register("openai", Arc::new(PreambleProvider));
+        let manager = AgentManager::new(Arc::new(providers));
+
+        let config = AgentConfig {
+            provider: "openai".to_string(),
+            model: "gpt-4o-mini".to_string(),
+            response_format: serde_json::from_value(serde_json::json!({
+                "type": "json_schema",
+                "schema": {"type": "object", "properties": {"answer": {"type": "integer"}}, "required": ["answer"]}
+            }))
+            .unwrap(),
+            ..Default::default()
+        };
+        let agent_id = manager.create_agent(config).await.unwrap();
+        let execution = manager.execute_agent(&agent_id, "What is 2 + 2?").await.unwrap();
+        assert_eq!(execution.response, r#"{"answer": 4}"#);
+
+        let config = AgentConfig {
+            response_format: serde_json::from_value(serde_json::json!({"type": "json_schema", "schema": {"type": 7}}))
+                .unwrap(),
+            ..Default::default()
+        };
+        assert!(Agent::from_config(config).is_err());
+    }
+}
@@ -43,6 +43,9 @@ pub async fn create_agent(
         created_at: chrono::Utc::now(),
         last_used: None,
         metadata: request.metadata,
+        provider_routing: request.provider_routing,
+        transforms: request.transforms,
+        response_format: request.response_format,
     };
 
     match state.agent_manager.create_agent(config).await {
@@ -138,6 +141,8 @@ pub async fn use_completion(
                         prompt_tokens: u.prompt_tokens,
                         completion_tokens: u.completion_tokens,
                         total_tokens: u.total_tokens,
+                        cache_creation_input_tokens: u.cache_creation_input_tokens,
+                        cache_read_input_tokens: u.cache_read_input_tokens,
                     }),
                     tool_calls: execution.tool_calls.into_iter().map(|tc| UseCompletionToolCall {
                         id: tc.id,
@@ -218,6 +223,9 @@ pub struct CreateAgentRequest {
     pub tools: Option<Vec<String>>,
     pub max_tool_rounds: Option<u32>,
     pub metadata: Option<std::collections::HashMap<String, Value>>,
+    pub provider_routing: Option<crate::providers::OpenRouterProviderPreferences>,
+    pub transforms: Option<Vec<String>>,
+    pub response_format: Option<crate::providers::ResponseFormat>,
 }
 
 #[derive(Debug, Serialize)]
@@ -284,6 +292,10 @@ pub struct UseCompletionUsage {
     pub prompt_tokens: u32,
     pub completion_tokens: u32,
     pub total_tokens: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cache_creation_input_tokens: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cache_read_input_tokens: Option<u32>,
 }
 
 #[derive(Debug, Serialize)]
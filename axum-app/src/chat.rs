@@ -4,14 +4,18 @@ use axum::{
     response::{sse::Event, IntoResponse, Response, Sse},
     Json,
 };
-use futures::{stream, Stream, StreamExt};
+use futures::Stream;
 use async_stream::stream;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::time::Duration;
 
 use crate::{AppState};
-use crate::providers::{GeminiService, OpenAIService, AnthropicService};
+use crate::providers::options::{self, WARNINGS_KEY};
+use crate::providers::{
+    failover, structured, ContentFilterError, GenerationOptions, ProviderError, Reasoning, RequestFeatures,
+    ResolvedProvider, ResponseFormat, SafetySetting, ToolCall, ToolChoice, ToolSpec,
+};
 
 /// Chat message structure compatible with AI SDK
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -33,6 +37,7 @@ pub enum ChatRole {
     User,
     Assistant,
     System,
+    Tool,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -70,6 +75,60 @@ pub struct ChatCompletionRequest {
     pub user: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub logit_bias: Option<HashMap<String, i32>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub seed: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub top_k: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub response_format: Option<ResponseFormat>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tools: Option<Vec<ChatTool>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tool_choice: Option<ToolChoice>,
+    #[serde(default, alias = "n", skip_serializing_if = "Option::is_none")]
+    pub candidate_count: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub safety_settings: Option<Vec<SafetySetting>>,
+}
+
+/// Tool definition in OpenAI request format
+#[derive(Debug, Clone, Deserialize)]
+pub struct ChatTool {
+    #[serde(rename = "type")]
+    pub tool_type: String,
+    pub function: ToolSpec,
+}
+
+impl ChatCompletionRequest {
+    /// Sampling and output options of the request
+    fn generation_options(&self) -> GenerationOptions {
+        GenerationOptions {
+            temperature: self.temperature,
+            max_tokens: self.max_tokens,
+            top_p: self.top_p,
+            top_k: self.top_k,
+            frequency_penalty: self.frequency_penalty,
+            presence_penalty: self.presence_penalty,
+            stop: self.stop.clone(),
+            seed: self.seed,
+            user: self.user.clone(),
+            logit_bias: self.logit_bias.clone(),
+            response_format: self.response_format.clone(),
+            candidate_count: self.candidate_count,
+            safety_settings: self.safety_settings.clone(),
+        }
+    }
+
+    /// Function tools declared on the request
+    fn tool_specs(&self) -> Option<Vec<ToolSpec>> {
+        self.tools.as_ref().map(|tools| {
+            tools
+                .iter()
+                .filter(|tool| tool.tool_type == "function")
+                .map(|tool| tool.function.clone())
+                .collect()
+        })
+    }
 }
 
 /// Chat completion delta for streaming responses
@@ -109,6 +168,21 @@ pub enum UIMessageChunk {
     },
     #[serde(rename = "text-finish")]
     TextFinish,
+    #[serde(rename = "reasoning-start")]
+    ReasoningStart,
+    #[serde(rename = "reasoning-delta")]
+    ReasoningDelta {
+        reasoningDelta: String,
+    },
+    #[serde(rename = "reasoning-end")]
+    ReasoningEnd {
+        /// Signature to send back with the reasoning, for providers that sign it
+        #[serde(skip_serializing_if = "Option::is_none")]
+        signature: Option<String>,
+        /// Redacted reasoning, as opaque data to send back
+        #[serde(skip_serializing_if = "Option::is_none")]
+        redacted: Option<String>,
+    },
     #[serde(rename = "tool-call")]
     ToolCall {
         toolCallId: String,
@@ -150,124 +224,34 @@ pub struct Usage {
     pub prompt_tokens: u32,
     pub completion_tokens: u32,
     pub total_tokens: u32,
+    /// Prompt tokens written to the provider's prompt cache, included in `prompt_tokens`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cache_creation_input_tokens: Option<u32>,
+    /// Prompt tokens read from the provider's prompt cache, included in `prompt_tokens`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cache_read_input_tokens: Option<u32>,
 }
 
-/// Determine the provider based on model name
-fn get_provider_from_model(model: &str) -> Provider {
-    if model.starts_with("gemini") || model.starts_with("models/gemini") {
-        Provider::Gemini
-    } else if model.starts_with("claude") {
-        Provider::Anthropic
-    } else {
-        Provider::OpenAI
-    }
-}
-
-/// AI provider enum
-#[derive(Debug, Clone, PartialEq)]
-enum Provider {
-    OpenAI,
-    Gemini,
-    Anthropic,
-}
-
-/// Generate mock AI response
-fn generate_mock_response() -> &'static str {
-    let responses = [
-        "That's interesting! Tell me more about that.",
-        "I understand. How can I help you with that?",
-        "Thanks for sharing! What else would you like to discuss?",
-        "I see your point. Let me think about that for a moment.",
-        "That's a great question! Here's what I think about it.",
-        "I appreciate you sharing that with me.",
-        "That makes sense. What are your thoughts on this?",
-        "Interesting perspective! Have you considered other angles?",
-        "I'd love to help you explore that idea further.",
-        "That's a fascinating topic! Let me share what I know about it.",
-    ];
-
-    responses[fastrand::usize(0..responses.len())]
-}
-
-/// Create streaming response using AI SDK patterns
-fn create_streaming_response(
-    content: String,
-) -> impl Stream<Item = Result<Event, anyhow::Error>> {
-    let words: Vec<String> = content
-        .split_whitespace()
-        .map(|word| word.to_string() + " ")
-        .collect();
-
-    stream! {
-        // Send text-start event first
-        yield Ok::<Event, anyhow::Error>(
-            Event::default().json_data(UIMessageChunk::TextStart)
-                .map_err(|e| anyhow::anyhow!("SSE error: {}", e))?
-        );
-
-        // Send text-delta events for each word
-        for (index, word) in words.into_iter().enumerate() {
-            let chunk = UIMessageChunk::TextDelta {
-                textDelta: word,
-            };
-
-            yield Ok::<Event, anyhow::Error>(
-                Event::default().json_data(chunk)
-                    .map_err(|e| anyhow::anyhow!("SSE error: {}", e))?
-            );
+impl Usage {
+    pub fn new(prompt_tokens: u32, completion_tokens: u32) -> Self {
+        Self {
+            prompt_tokens,
+            completion_tokens,
+            total_tokens: prompt_tokens + completion_tokens,
+            cache_creation_input_tokens: None,
+            cache_read_input_tokens: None,
         }
-
-        // Send text-finish event at the end
-        yield Ok::<Event, anyhow::Error>(
-            Event::default().json_data(UIMessageChunk::TextFinish)
-                .map_err(|e| anyhow::anyhow!("SSE error: {}", e))?
-        );
-
-        // Send final finish event with usage
-        let usage = Usage {
-            prompt_tokens: 0,
-            completion_tokens: 0,
-            total_tokens: 0,
-        };
-        let finish_chunk = UIMessageChunk::Finish {
-            reasoning: None,
-            sources: None,
-            usage: Some(usage),
-            logprobs: None,
-        };
-        yield Ok::<Event, anyhow::Error>(
-            Event::default().json_data(finish_chunk)
-                .map_err(|e| anyhow::anyhow!("SSE error: {}", e))?
-        );
     }
-}
 
-/// Create non-streaming response
-fn create_chat_response(messages: &[ChatMessage]) -> ChatMessage {
-    let user_message = messages.last().and_then(|msg| {
-        if matches!(msg.role, ChatRole::User) {
-            Some(msg.content.clone())
-        } else {
-            None
-        }
-    });
-
-    let response_content = user_message
-        .map(|_| generate_mock_response().to_string())
-        .unwrap_or_else(|| "Hello! How can I help you today?".to_string());
-
-    ChatMessage {
-        id: format!("msg_{}", fastrand::u64(1000..9999)),
-        role: ChatRole::Assistant,
-        content: response_content,
-        created_at: Some(chrono::Utc::now()),
-        attachments: None,
-        metadata: None,
+    /// Token usage a provider recorded under `metadata.usage`
+    pub fn from_metadata(metadata: Option<&HashMap<String, serde_json::Value>>) -> Option<Self> {
+        metadata
+            .and_then(|m| m.get("usage"))
+            .and_then(|usage| serde_json::from_value(usage.clone()).ok())
     }
 }
 
-
-/// Chat completion endpoint - handles both OpenAI and Gemini with streaming and non-streaming
+/// Chat completion endpoint - routes the model to a registered provider, streaming or not
 pub async fn chat_completion(
     State(state): State<AppState>,
     Json(request): Json<ChatCompletionRequest>,
@@ -275,353 +259,325 @@ pub async fn chat_completion(
     if request.messages.is_empty() {
         return Err(StatusCode::BAD_REQUEST);
     }
+    if let Some(ResponseFormat::JsonSchema { json_schema }) = &request.response_format {
+        if let Err(e) = structured::check_schema(&json_schema.schema) {
+            return Ok(invalid_response_format_response(&e));
+        }
+    }
 
     // Determine provider based on model (default to OpenAI)
     let model = request.model.clone().unwrap_or_else(|| "gpt-3.5-turbo".to_string());
-    let provider = get_provider_from_model(&model);
 
-    // Route to appropriate provider
-    match provider {
-        Provider::OpenAI => {
-            handle_openai_request(state, request, &model).await
-        }
-        Provider::Gemini => {
-            handle_gemini_request(state, request, &model).await
-        }
-        Provider::Anthropic => {
-            handle_anthropic_request(state, request, &model).await
-        }
+    let chain = state.providers.resolve_chain(&model);
+    if chain.is_empty() {
+        return Ok(provider_not_configured_response(&model));
     }
-}
-
-/// Handle OpenAI requests
-async fn handle_openai_request(
-    state: AppState,
-    request: ChatCompletionRequest,
-    model: &str,
-) -> Result<Response, StatusCode> {
-    // Check if OpenAI service is available
-    let openai_service = match &state.openai_service {
-        Some(service) => service,
-        None => {
-            // Fallback to mock response if OpenAI service is not configured
-            return handle_fallback_response(request).await;
-        }
+    let features = RequestFeatures::of(
+        &request.messages,
+        &request.generation_options(),
+        request.tool_specs().as_deref(),
+    );
+    let chain = match state.providers.supporting(chain, &features) {
+        Ok(chain) => chain,
+        Err(reason) => return Ok(unsupported_feature_response(&reason)),
     };
-
-    // Check if streaming is requested
-    if request.stream.unwrap_or(false) {
-        // Streaming response
-        match openai_service
-            .chat_completion_stream(request.messages, Some(model.to_string()), request.temperature, request.max_tokens)
-            .await
-        {
-            Ok(openai_stream) => {
-                // Convert OpenAI stream to SSE format
-                let sse_stream = stream! {
-                    for await result in openai_stream {
-                        match result {
-                            Ok(chunk) => {
-                                yield Ok::<Event, Box<dyn std::error::Error + Send + Sync>>(
-                                    Event::default().json_data(chunk)
-                                        .unwrap_or_else(|_| Event::default().data("serialization error"))
-                                );
-                            }
-                            Err(e) => {
-                                yield Ok::<Event, Box<dyn std::error::Error + Send + Sync>>(
-                                    Event::default().data(format!("error: {}", e))
-                                );
-                            }
-                        }
-                    }
-                };
-
-                let sse_response = Sse::new(sse_stream)
-                    .keep_alive(
-                        axum::response::sse::KeepAlive::new()
-                            .interval(Duration::from_secs(15))
-                            .text("keep-alive-text"),
-                    )
-                    .into_response();
-
-                // Add AI SDK compatible headers
-                let mut response = sse_response;
-                let headers = response.headers_mut();
-                headers.insert("content-type", "text/event-stream".parse().unwrap());
-                headers.insert("cache-control", "no-cache".parse().unwrap());
-                headers.insert("connection", "keep-alive".parse().unwrap());
-                headers.insert("x-vercel-ai-ui-message-stream", "v1".parse().unwrap());
-                headers.insert("x-accel-buffering", "no".parse().unwrap());
-
-                Ok(response)
-            }
-            Err(e) => {
-                // Return error response
-                let error_response = Json(serde_json::json!({
-                    "error": {
-                        "message": format!("OpenAI API error: {}", e),
-                        "type": "api_error",
-                        "code": "openai_error"
-                    }
-                }));
-                Ok((StatusCode::INTERNAL_SERVER_ERROR, error_response).into_response())
-            }
-        }
-    } else {
-        // Non-streaming response
-        match openai_service
-            .chat_completion(request.messages, Some(model.to_string()), request.temperature, request.max_tokens)
-            .await
-        {
-            Ok(response) => Ok(Json(response).into_response()),
-            Err(e) => {
-                // Return error response
-                let error_response = Json(serde_json::json!({
-                    "error": {
-                        "message": format!("OpenAI API error: {}", e),
-                        "type": "api_error",
-                        "code": "openai_error"
-                    }
-                }));
-                Ok((StatusCode::INTERNAL_SERVER_ERROR, error_response).into_response())
-            }
-        }
-    }
+    handle_provider_request(&chain, request).await
 }
 
-/// Handle Gemini requests
-async fn handle_gemini_request(
-    state: AppState,
+/// Send a chat request to the first provider in `chain` that answers
+async fn handle_provider_request(
+    chain: &[ResolvedProvider],
     request: ChatCompletionRequest,
-    model: &str,
 ) -> Result<Response, StatusCode> {
-    // Check if Gemini service is available
-    let gemini_service = match &state.gemini_service {
-        Some(service) => service,
-        None => {
-            // Fallback to mock response if Gemini service is not configured
-            return handle_fallback_response(request).await;
-        }
-    };
-
-    // Check if streaming is requested
-    if request.stream.unwrap_or(false) {
-        // Streaming response
-        let gemini_stream = gemini_service
-            .chat_completion_stream(
-                request.messages,
-                Some(model.to_string()),
-                request.temperature,
-                request.max_tokens,
+    let tools = request.tool_specs();
+    let options = request.generation_options();
+
+    // Check if streaming is requested; an answer that must match a schema is checked before
+    // anything is sent, so it is not streamed from the provider
+    if request.stream.unwrap_or(false) && options.json_schema().is_none() {
+        // Failover happens before the first chunk; a stream that fails later is not restarted
+        let (resolved, result) = failover::run(chain, |resolved| {
+            resolved.provider.chat_completion_stream(
+                request.messages.clone(),
+                Some(resolved.model.clone()),
+                options.clone(),
+                tools.clone(),
+                request.tool_choice.clone(),
             )
-            .await;
+        })
+        .await;
+        let name = resolved.name.clone();
+        let provider_stream = match result {
+            Ok(provider_stream) => provider_stream,
+            Err(e) => return Ok(provider_error_response(&name, &e)),
+        };
+        let warnings = option_warnings(resolved, &options, request.tool_choice.as_ref());
 
         let sse_stream = stream! {
-            for await result in gemini_stream {
-                match result {
-                    Ok(chunk) => {
-                        yield Ok::<Event, Box<dyn std::error::Error + Send + Sync>>(
-                            Event::default().json_data(chunk)
-                                .unwrap_or_else(|_| Event::default().data("serialization error"))
-                        );
-                    }
-                    Err(e) => {
-                        yield Ok::<Event, Box<dyn std::error::Error + Send + Sync>>(
-                            Event::default().data(format!("error: {}", e))
-                        );
-                    }
+            // Streams have no message metadata, so warnings go out as a data chunk ahead of the answer
+            if !warnings.is_empty() {
+                let chunk = UIMessageChunk::Data { data: serde_json::json!({ WARNINGS_KEY: warnings }) };
+                yield Ok::<Event, Box<dyn std::error::Error + Send + Sync>>(chunk_event(chunk));
+            }
+            for await result in provider_stream {
+                // A mid-stream failure ends the stream with an AI SDK error event
+                let (chunk, failed) = match result {
+                    Ok(chunk) => (chunk, false),
+                    Err(e) => (UIMessageChunk::Error { error: error_message(&name, &e) }, true),
+                };
+                yield Ok::<Event, Box<dyn std::error::Error + Send + Sync>>(chunk_event(chunk));
+                if failed {
+                    break;
                 }
             }
         };
 
-        let sse_response = Sse::new(sse_stream)
-            .keep_alive(
-                axum::response::sse::KeepAlive::new()
-                    .interval(Duration::from_secs(15))
-                    .text("keep-alive-text"),
-            )
-            .into_response();
-
-        // Add AI SDK compatible headers
-        let mut response = sse_response;
-        let headers = response.headers_mut();
-        headers.insert("content-type", "text/event-stream".parse().unwrap());
-        headers.insert("cache-control", "no-cache".parse().unwrap());
-        headers.insert("connection", "keep-alive".parse().unwrap());
-        headers.insert("x-vercel-ai-ui-message-stream", "v1".parse().unwrap());
-        headers.insert("x-accel-buffering", "no".parse().unwrap());
-
+        let mut response = sse_response(sse_stream);
+        record_provider_headers(&mut response, resolved);
         Ok(response)
     } else {
-        // Non-streaming response
-        match gemini_service
-            .chat_completion(
-                request.messages,
-                Some(model.to_string()),
-                request.temperature,
-                request.max_tokens,
+        let (resolved, result) = failover::run(chain, |resolved| {
+            resolved.provider.chat_completion(
+                request.messages.clone(),
+                Some(resolved.model.clone()),
+                options.clone(),
+                tools.clone(),
+                request.tool_choice.clone(),
             )
-            .await
-        {
-            Ok(response) => Ok(Json(response).into_response()),
-            Err(e) => {
-                // Return error response
-                let error_response = Json(serde_json::json!({
-                    "error": {
-                        "message": format!("Gemini API error: {}", e),
-                        "type": "api_error",
-                        "code": "gemini_error"
-                    }
-                }));
-                Ok((StatusCode::INTERNAL_SERVER_ERROR, error_response).into_response())
+        })
+        .await;
+        let result = match result {
+            Ok(response) => {
+                ensure_valid_output(resolved, &request.messages, &options, &tools, &request.tool_choice, response).await
             }
+            Err(e) => Err(e),
+        };
+        let mut response = match result {
+            Ok(response) => response,
+            Err(e) => return Ok(provider_error_response(&resolved.name, &e)),
+        };
+
+        failover::record_provider(&mut response, resolved);
+        let warnings = option_warnings(resolved, &options, request.tool_choice.as_ref());
+        if !warnings.is_empty() {
+            response
+                .metadata
+                .get_or_insert_with(Default::default)
+                .insert(WARNINGS_KEY.to_string(), serde_json::json!(warnings));
+        }
+
+        if request.stream.unwrap_or(false) {
+            let events = message_chunks(&response)
+                .into_iter()
+                .map(|chunk| Ok::<Event, std::convert::Infallible>(chunk_event(chunk)));
+            let mut response = sse_response(futures::stream::iter(events));
+            record_provider_headers(&mut response, resolved);
+            Ok(response)
+        } else {
+            Ok(Json(response).into_response())
         }
     }
 }
 
-/// Handle Anthropic requests
-async fn handle_anthropic_request(
-    state: AppState,
-    request: ChatCompletionRequest,
-    model: &str,
-) -> Result<Response, StatusCode> {
-    // Check if Anthropic service is available
-    let anthropic_service = match &state.anthropic_service {
-        Some(service) => service,
-        None => {
-            // Fallback to mock response if Anthropic service is not configured
-            return handle_fallback_response(request).await;
-        }
+/// Check an answer against the JSON schema of `options`, if any, giving the provider one
+/// chance to repair it
+///
+/// Turns that call tools are not checked; only the final answer has to match.
+async fn ensure_valid_output(
+    resolved: &ResolvedProvider,
+    messages: &[ChatMessage],
+    options: &GenerationOptions,
+    tools: &Option<Vec<ToolSpec>>,
+    tool_choice: &Option<ToolChoice>,
+    response: ChatMessage,
+) -> anyhow::Result<ChatMessage> {
+    let format = match options.json_schema() {
+        Some(format) if ToolCall::from_metadata(response.metadata.as_ref()).is_none() => format,
+        _ => return Ok(response),
+    };
+    let errors = match structured::check(format, &response.content) {
+        Ok(json) => return Ok(ChatMessage { content: json, ..response }),
+        Err(errors) => errors,
     };
 
-    // Check if streaming is requested
-    if request.stream.unwrap_or(false) {
-        // Streaming response
-        let anthropic_stream = anthropic_service
-            .chat_completion_stream(
-                request.messages,
-                Some(model.to_string()),
-                request.temperature,
-                request.max_tokens,
-            )
-            .await;
+    let mut repair = messages.to_vec();
+    repair.push(ChatMessage { metadata: None, ..response });
+    repair.push(structured::repair_message(&errors));
+    let repaired = resolved
+        .provider
+        .chat_completion(repair, Some(resolved.model.clone()), options.clone(), tools.clone(), tool_choice.clone())
+        .await?;
+
+    match structured::check(format, &repaired.content) {
+        Ok(json) => Ok(ChatMessage { content: json, ..repaired }),
+        Err(errors) => Err(anyhow::anyhow!(
+            "the answer does not match the JSON schema after a repair attempt: {}",
+            errors.join("; ")
+        )),
+    }
+}
 
-        let sse_stream = stream! {
-            for await result in anthropic_stream {
-                match result {
-                    Ok(chunk) => {
-                        yield Ok::<Event, Box<dyn std::error::Error + Send + Sync>>(
-                            Event::default().json_data(chunk)
-                                .unwrap_or_else(|_| Event::default().data("serialization error"))
-                        );
-                    }
-                    Err(e) => {
-                        yield Ok::<Event, Box<dyn std::error::Error + Send + Sync>>(
-                            Event::default().data(format!("error: {}", e))
-                        );
-                    }
-                }
-            }
-        };
+/// Stream chunks replaying a complete answer
+fn message_chunks(message: &ChatMessage) -> Vec<UIMessageChunk> {
+    let metadata = message.metadata.as_ref();
+    let mut chunks = Vec::new();
+    if let Some(warnings) = metadata.and_then(|m| m.get(WARNINGS_KEY)) {
+        chunks.push(UIMessageChunk::Data { data: serde_json::json!({ WARNINGS_KEY: warnings }) });
+    }
+    chunks.push(UIMessageChunk::TextStart);
+    if !message.content.is_empty() {
+        chunks.push(UIMessageChunk::TextDelta { textDelta: message.content.clone() });
+    }
+    for call in ToolCall::from_metadata(metadata).into_iter().flatten() {
+        chunks.push(call.into_chunk());
+    }
+    chunks.push(UIMessageChunk::TextFinish);
+
+    let reasoning = Reasoning::from_metadata(metadata)
+        .map(|parts| parts.into_iter().map(|part| part.text).collect::<String>())
+        .filter(|text| !text.is_empty());
+    chunks.push(UIMessageChunk::Finish {
+        reasoning,
+        sources: None,
+        usage: Usage::from_metadata(metadata),
+        logprobs: None,
+    });
+    chunks
+}
 
-        let sse_response = Sse::new(sse_stream)
-            .keep_alive(
-                axum::response::sse::KeepAlive::new()
-                    .interval(Duration::from_secs(15))
-                    .text("keep-alive-text"),
-            )
-            .into_response();
+/// SSE event carrying one chunk
+fn chunk_event(chunk: UIMessageChunk) -> Event {
+    Event::default().json_data(chunk)
+        .unwrap_or_else(|_| Event::default().data("serialization error"))
+}
 
-        // Add AI SDK compatible headers
-        let mut response = sse_response;
-        let headers = response.headers_mut();
-        headers.insert("content-type", "text/event-stream".parse().unwrap());
-        headers.insert("cache-control", "no-cache".parse().unwrap());
-        headers.insert("connection", "keep-alive".parse().unwrap());
-        headers.insert("x-vercel-ai-ui-message-stream", "v1".parse().unwrap());
-        headers.insert("x-accel-buffering", "no".parse().unwrap());
+/// Warnings for the options in `options`, and the `tool_choice`, that the provider answering
+/// the request ignores
+fn option_warnings(
+    resolved: &ResolvedProvider,
+    options: &GenerationOptions,
+    tool_choice: Option<&ToolChoice>,
+) -> Vec<String> {
+    let mut unsupported = resolved.provider.unsupported_options(&resolved.model, options);
+    if tool_choice.is_some_and(|choice| !resolved.provider.supports_tool_choice(choice)) {
+        unsupported.push("tool_choice");
+    }
+    options::unsupported_warnings(&resolved.name, &unsupported)
+}
 
-        Ok(response)
-    } else {
-        // Non-streaming response
-        match anthropic_service
-            .chat_completion(
-                request.messages,
-                Some(model.to_string()),
-                request.temperature,
-                request.max_tokens,
-            )
-            .await
-        {
-            Ok(response) => Ok(Json(response).into_response()),
-            Err(e) => {
-                // Return error response
-                let error_response = Json(serde_json::json!({
-                    "error": {
-                        "message": format!("Anthropic API error: {}", e),
-                        "type": "api_error",
-                        "code": "anthropic_error"
-                    }
-                }));
-                Ok((StatusCode::INTERNAL_SERVER_ERROR, error_response).into_response())
-            }
-        }
+/// Name the provider and model answering a stream, which has no message metadata to carry them
+fn record_provider_headers(response: &mut Response, resolved: &ResolvedProvider) {
+    let headers = response.headers_mut();
+    if let Ok(value) = resolved.name.parse() {
+        headers.insert("x-provider", value);
+    }
+    if let Ok(value) = resolved.model.parse() {
+        headers.insert("x-model", value);
     }
 }
 
-/// Fallback handler when OpenAI service is not available
-async fn handle_fallback_response(request: ChatCompletionRequest) -> Result<Response, StatusCode> {
-    // Use mock response when OpenAI is not configured
-    let user_message = request.messages.last().and_then(|msg| {
-        if matches!(msg.role, ChatRole::User) {
-            Some(msg.content.clone())
-        } else {
-            None
+/// JSON error response for a model with no configured provider
+pub(crate) fn provider_not_configured_response(model: &str) -> Response {
+    let error_response = Json(serde_json::json!({
+        "error": {
+            "message": format!("No provider is configured for model '{}'", model),
+            "type": "invalid_request_error",
+            "code": "provider_not_configured"
         }
-    });
+    }));
+    (StatusCode::SERVICE_UNAVAILABLE, error_response).into_response()
+}
 
-    let response_content = user_message
-        .map(|_| generate_mock_response().to_string())
-        .unwrap_or_else(|| "Hello! How can I help you today? OpenAI API is not configured. Please set OPENAI_API_KEY environment variable.".to_string());
+/// JSON error response for a response format whose schema cannot be used
+fn invalid_response_format_response(error: &anyhow::Error) -> Response {
+    let error_response = Json(serde_json::json!({
+        "error": {
+            "message": error.to_string(),
+            "type": "invalid_request_error",
+            "code": "invalid_response_format"
+        }
+    }));
+    (StatusCode::BAD_REQUEST, error_response).into_response()
+}
 
-    if request.stream.unwrap_or(false) {
-        let stream = create_streaming_response(response_content)
-            .map(|result| result.map_err(|_| anyhow::anyhow!("Stream error")));
+/// JSON error response for a request needing a feature its model is known to lack
+fn unsupported_feature_response(reason: &str) -> Response {
+    let error_response = Json(serde_json::json!({
+        "error": {
+            "message": reason,
+            "type": "invalid_request_error",
+            "code": "unsupported_feature"
+        }
+    }));
+    (StatusCode::BAD_REQUEST, error_response).into_response()
+}
 
-        let sse_response = Sse::new(stream)
-            .keep_alive(
-                axum::response::sse::KeepAlive::new()
-                    .interval(Duration::from_secs(15))
-                    .text("keep-alive-text"),
-            )
-            .into_response();
+/// JSON error response for a provider failure
+///
+/// Rate limits and rejected requests keep their upstream status so clients can react to
+/// them; every other failure is an internal error. Blocked content is a client error.
+pub(crate) fn provider_error_response(name: &str, error: &anyhow::Error) -> Response {
+    if let Some(filtered) = ContentFilterError::from_error(error) {
+        let error_response = Json(serde_json::json!({
+            "error": {
+                "message": filtered.to_string(),
+                "type": "invalid_request_error",
+                "code": "content_filter",
+                "reason": filtered.reason,
+                "categories": filtered.categories
+            }
+        }));
+        return (StatusCode::BAD_REQUEST, error_response).into_response();
+    }
 
-        // Add AI SDK compatible headers
-        let mut response = sse_response;
-        let headers = response.headers_mut();
-        headers.insert("content-type", "text/event-stream".parse().unwrap());
-        headers.insert("cache-control", "no-cache".parse().unwrap());
-        headers.insert("connection", "keep-alive".parse().unwrap());
-        headers.insert("x-vercel-ai-ui-message-stream", "v1".parse().unwrap());
-        headers.insert("x-accel-buffering", "no".parse().unwrap());
+    let status = match ProviderError::from_error(error).map(|e| e.status) {
+        Some(status) if status == StatusCode::TOO_MANY_REQUESTS || status == StatusCode::BAD_REQUEST => status,
+        _ => StatusCode::INTERNAL_SERVER_ERROR,
+    };
 
-        Ok(response)
-    } else {
-        let response = ChatMessage {
-            id: format!("msg_fallback_{}", fastrand::u64(1000..9999)),
-            role: ChatRole::Assistant,
-            content: response_content,
-            created_at: Some(chrono::Utc::now()),
-            attachments: None,
-            metadata: Some(HashMap::from([
-                ("warning".to_string(), serde_json::Value::String("OpenAI API not configured, using fallback response".to_string()))
-            ])),
-        };
+    let error_response = Json(serde_json::json!({
+        "error": {
+            "message": error_message(name, error),
+            "type": "api_error",
+            "code": format!("{}_error", name)
+        }
+    }));
+    (status, error_response).into_response()
+}
 
-        Ok(Json(response).into_response())
+/// Client-facing text for a provider failure; upstream errors already name their provider
+fn error_message(name: &str, error: &anyhow::Error) -> String {
+    match ProviderError::from_error(error) {
+        Some(upstream) => upstream.to_string(),
+        None => format!("{} API error: {}", name, error),
     }
 }
 
+/// Wrap an event stream in an SSE response with the AI SDK UI message stream headers
+fn sse_response<S, E>(stream: S) -> Response
+where
+    S: Stream<Item = Result<Event, E>> + Send + 'static,
+    E: Into<axum::BoxError>,
+{
+    let mut response = Sse::new(stream)
+        .keep_alive(
+            axum::response::sse::KeepAlive::new()
+                .interval(Duration::from_secs(15))
+                .text("keep-alive-text"),
+        )
+        .into_response();
+
+    // Add AI SDK compatible headers
+    let headers = response.headers_mut();
+    headers.insert("content-type", "text/event-stream".parse().unwrap());
+    headers.insert("cache-control", "no-cache".parse().unwrap());
+    headers.insert("connection", "keep-alive".parse().unwrap());
+    headers.insert("x-vercel-ai-ui-message-stream", "v1".parse().unwrap());
+    headers.insert("x-accel-buffering", "no".parse().unwrap());
+
+    response
+}
+
 /// Completion endpoint for useCompletion hook from AI SDK
 #[derive(Debug, Deserialize)]
 pub struct CompletionRequest {
@@ -643,7 +599,6 @@ pub async fn completion_handler(
 ) -> Result<Response, StatusCode> {
     // Determine model first
     let model = request.model.clone().unwrap_or_else(|| "gpt-3.5-turbo".to_string());
-    let provider = get_provider_from_model(&model);
 
     // Create a simple chat request for completion
     let chat_request = ChatCompletionRequest {
@@ -667,88 +622,48 @@ pub async fn completion_handler(
         stop: None,
         user: None,
         logit_bias: None,
+        seed: None,
+        top_k: None,
+        response_format: None,
+        tools: None,
+        tool_choice: None,
+        candidate_count: None,
+        safety_settings: None,
     };
 
-    // Route to appropriate provider
-    match provider {
-        Provider::OpenAI => {
-            handle_openai_completion(state, chat_request, &model).await
-        }
-        Provider::Gemini => {
-            handle_gemini_completion(state, chat_request, &model).await
-        }
-        Provider::Anthropic => {
-            handle_anthropic_completion(state, chat_request, &model).await
-        }
-    }
-}
-
-/// Handle OpenAI completion and extract content
-async fn handle_openai_completion(
-    state: AppState,
-    request: ChatCompletionRequest,
-    model: &str,
-) -> Result<Response, StatusCode> {
-    if let Some(openai_service) = &state.openai_service {
-        match openai_service.chat_completion(request.messages, Some(model.to_string()), request.temperature, request.max_tokens).await {
-            Ok(chat_message) => {
-                // Return just the content as a plain string for useCompletion
-                Ok(axum::response::Json(chat_message.content).into_response())
-            }
-            Err(e) => {
-                // Fallback to mock response
-                let mock_content = generate_mock_response().to_string();
-                Ok(axum::response::Json(mock_content).into_response())
-            }
-        }
-    } else {
-        // Fallback to mock response
-        let mock_content = generate_mock_response().to_string();
-        Ok(axum::response::Json(mock_content).into_response())
-    }
+    handle_completion(state, chat_request, &model).await
 }
 
-/// Handle Gemini completion and extract content
-async fn handle_gemini_completion(
+/// Run a completion through the provider for `model` and return just the content
+async fn handle_completion(
     state: AppState,
     request: ChatCompletionRequest,
     model: &str,
 ) -> Result<Response, StatusCode> {
-    if let Some(gemini_service) = &state.gemini_service {
-        match gemini_service.chat_completion(request.messages, Some(model.to_string()), request.temperature, request.max_tokens).await {
-            Ok(chat_message) => {
-                Ok(axum::response::Json(chat_message.content).into_response())
-            }
-            Err(e) => {
-                let mock_content = generate_mock_response().to_string();
-                Ok(axum::response::Json(mock_content).into_response())
-            }
-        }
-    } else {
-        let mock_content = generate_mock_response().to_string();
-        Ok(axum::response::Json(mock_content).into_response())
+    let chain = state.providers.resolve_chain(model);
+    if chain.is_empty() {
+        return Ok(provider_not_configured_response(model));
     }
-}
+    let features = RequestFeatures::of(&request.messages, &request.generation_options(), None);
+    let chain = match state.providers.supporting(chain, &features) {
+        Ok(chain) => chain,
+        Err(reason) => return Ok(unsupported_feature_response(&reason)),
+    };
 
-/// Handle Anthropic completion and extract content
-async fn handle_anthropic_completion(
-    state: AppState,
-    request: ChatCompletionRequest,
-    model: &str,
-) -> Result<Response, StatusCode> {
-    if let Some(anthropic_service) = &state.anthropic_service {
-        match anthropic_service.chat_completion(request.messages, Some(model.to_string()), request.temperature, request.max_tokens).await {
-            Ok(chat_message) => {
-                Ok(axum::response::Json(chat_message.content).into_response())
-            }
-            Err(e) => {
-                let mock_content = generate_mock_response().to_string();
-                Ok(axum::response::Json(mock_content).into_response())
-            }
-        }
-    } else {
-        let mock_content = generate_mock_response().to_string();
-        Ok(axum::response::Json(mock_content).into_response())
+    let (resolved, result) = failover::run(&chain, |resolved| {
+        resolved.provider.chat_completion(
+            request.messages.clone(),
+            Some(resolved.model.clone()),
+            request.generation_options(),
+            None,
+            None,
+        )
+    })
+    .await;
+    match result {
+        // Return just the content as a plain string for useCompletion
+        Ok(chat_message) => Ok(axum::response::Json(chat_message.content).into_response()),
+        Err(e) => Ok(provider_error_response(&resolved.name, &e)),
     }
 }
 
@@ -756,7 +671,7 @@ async fn handle_anthropic_completion(
 pub async fn legacy_chat_handler(
     State(state): State<AppState>,
     Json(request): Json<serde_json::Value>,
-) -> Result<Json<serde_json::Value>, StatusCode> {
+) -> Result<Response, StatusCode> {
     // Try to extract messages from the request
     let messages_array = request
         .get("messages")
@@ -804,33 +719,17 @@ pub async fn legacy_chat_handler(
 
     let chat_messages = chat_messages.map_err(|_| StatusCode::BAD_REQUEST)?;
 
-    // Use OpenAI service if available
-    if let Some(openai_service) = &state.openai_service {
-        match openai_service.chat_completion(chat_messages, None, None, None).await {
-            Ok(response) => {
-                Ok(Json(serde_json::json!({
-                    "role": "assistant",
-                    "content": response.content
-                })))
-            }
-            Err(e) => {
-                // Fallback to mock on error
-                let fallback_response = generate_mock_response();
-                Ok(Json(serde_json::json!({
-                    "role": "assistant",
-                    "content": fallback_response,
-                    "error": format!("OpenAI API error, using fallback: {}", e)
-                })))
-            }
-        }
-    } else {
-        // Fallback to mock when OpenAI is not configured
-        let response = generate_mock_response();
-        Ok(Json(serde_json::json!({
+    // The legacy API names no model, so the default provider answers
+    let Some((name, provider)) = state.providers.default_provider() else {
+        return Ok(provider_not_configured_response("default"));
+    };
+    match provider.chat_completion(chat_messages, None, GenerationOptions::default(), None, None).await {
+        Ok(response) => Ok(Json(serde_json::json!({
             "role": "assistant",
-            "content": response,
-            "warning": "OpenAI API not configured, using fallback response"
-        })))
+            "content": response.content
+        }))
+        .into_response()),
+        Err(e) => Ok(provider_error_response(name, &e)),
     }
 }
 
@@ -843,17 +742,59 @@ mod tests {
         routing::post,
         Router,
     };
+    use crate::providers::testing::{PreambleProvider, StaticProvider};
+    use futures::stream::{self, StreamExt};
     use serde_json::json;
     use tower::ServiceExt;
 
-    fn create_test_app() -> Router {
+    async fn create_test_app() -> Router {
         // Create test state without services to force fallback mode
+        create_test_app_with(crate::providers::ProviderRegistry::new()).await
+    }
+
+    /// Test app whose providers answer with their own name
+    async fn create_test_app_serving(names: &[&'static str]) -> Router {
+        let mut providers = crate::providers::ProviderRegistry::new();
+        for name in names {
+            providers.register(name, std::sync::Arc::new(StaticProvider(name)));
+        }
+        providers.route_prefix("gemini", "gemini");
+        providers.route_prefix("claude", "anthropic");
+        providers.set_default("openai");
+        create_test_app_with(providers).await
+    }
+
+    async fn json_body(response: Response) -> serde_json::Value {
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        serde_json::from_slice(&body).unwrap()
+    }
+
+    fn completion_request(model: &str) -> Request<Body> {
+        let request_body = json!({
+            "messages": [{ "id": "msg1", "role": "user", "content": "Hello!" }],
+            "model": model,
+            "stream": false
+        });
+        Request::builder()
+            .method("POST")
+            .uri("/api/v1/chat/completions")
+            .header("content-type", "application/json")
+            .body(Body::from(request_body.to_string()))
+            .unwrap()
+    }
+
+    async fn create_test_app_with(providers: crate::providers::ProviderRegistry) -> Router {
+        let providers = std::sync::Arc::new(providers);
         let state = crate::AppState {
-            todos: std::sync::Arc::new(std::sync::Mutex::new(Vec::new())),
-            openai_service: None, // Force fallback mode for tests
-            gemini_service: None,  // Force fallback mode for tests
-            anthropic_service: None, // Force fallback mode for tests
+            providers: providers.clone(),
             database: None, // Force fallback mode for tests
+            agent_manager: std::sync::Arc::new(crate::agent::AgentManager::new(providers)),
+            mcp_tool_manager: std::sync::Arc::new(crate::mcp::MCPToolManager::new()),
+            mcp_server_manager: std::sync::Arc::new(
+                crate::mcp::MCPServerManager::with_config_path("nonexistent.json")
+                    .await
+                    .unwrap(),
+            ),
         };
         Router::new()
             .route("/api/chat", post(super::legacy_chat_handler))
@@ -863,7 +804,7 @@ mod tests {
 
     #[tokio::test]
     async fn test_legacy_chat_endpoint() {
-        let app = create_test_app();
+        let app = create_test_app().await;
 
         let request_body = json!({
             "messages": [
@@ -883,12 +824,39 @@ mod tests {
 
         let response = app.oneshot(request).await.unwrap();
 
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(json_body(response).await["error"]["code"], "provider_not_configured");
+    }
+
+    #[tokio::test]
+    async fn test_legacy_chat_uses_the_default_provider() {
+        let legacy_request = || {
+            Request::builder()
+                .method("POST")
+                .uri("/api/chat")
+                .header("content-type", "application/json")
+                .body(Body::from(json!({ "messages": [{ "role": "user", "content": "Hello" }] }).to_string()))
+                .unwrap()
+        };
+
+        let app = create_test_app_serving(&["openai"]).await;
+        let response = app.oneshot(legacy_request()).await.unwrap();
         assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(json_body(response).await, json!({ "role": "assistant", "content": "openai ()" }));
+
+        // Provider failures are errors, not made-up answers
+        let mut providers = crate::providers::ProviderRegistry::new();
+        providers.register("openai", std::sync::Arc::new(FailingProvider { at_setup: true }));
+        providers.set_default("openai");
+        let app = create_test_app_with(providers).await;
+        let response = app.oneshot(legacy_request()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(json_body(response).await["error"]["code"], "openai_error");
     }
 
     #[tokio::test]
     async fn test_chat_completion_endpoint() {
-        let app = create_test_app();
+        let app = create_test_app_serving(&["openai"]).await;
 
         let request_body = json!({
             "messages": [
@@ -910,14 +878,28 @@ mod tests {
             .unwrap();
 
         let response = app.oneshot(request).await.unwrap();
-
-        // Should return 200 even with fallback
         assert_eq!(response.status(), StatusCode::OK);
+
+        let body = json_body(response).await;
+        assert_eq!(body["content"], "openai (gpt-3.5-turbo)");
+        assert_eq!(body["metadata"]["provider"], "openai");
+        assert_eq!(body["metadata"]["model"], "gpt-3.5-turbo");
+    }
+
+    #[tokio::test]
+    async fn test_unconfigured_provider_is_an_error() {
+        let app = create_test_app().await;
+
+        let response = app.oneshot(completion_request("gpt-4o")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+
+        let body = json_body(response).await;
+        assert_eq!(body["error"]["code"], "provider_not_configured");
     }
 
     #[tokio::test]
     async fn test_empty_messages_error() {
-        let app = create_test_app();
+        let app = create_test_app().await;
 
         let request_body = json!({
             "messages": []
@@ -937,7 +919,7 @@ mod tests {
 
     #[tokio::test]
     async fn test_invalid_messages_format() {
-        let app = create_test_app();
+        let app = create_test_app().await;
 
         let request_body = json!({
             "messages": "invalid"
@@ -957,79 +939,231 @@ mod tests {
 
     #[tokio::test]
     async fn test_gemini_model_routing() {
-        let app = create_test_app();
+        let app = create_test_app_serving(&["openai", "gemini"]).await;
+
+        let response = app.oneshot(completion_request("gemini-1.5-flash")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(json_body(response).await["content"], "gemini (gemini-1.5-flash)");
+    }
 
+    #[tokio::test]
+    async fn test_claude_model_routing() {
+        let app = create_test_app_serving(&["openai", "anthropic"]).await;
+
+        let response = app.oneshot(completion_request("claude-3-5-sonnet-20241022")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(json_body(response).await["content"], "anthropic (claude-3-5-sonnet-20241022)");
+    }
+
+    /// Provider that fails, either at setup with a rate limit or, for streams, after the first chunk
+    struct FailingProvider {
+        at_setup: bool,
+    }
+
+    fn rate_limited() -> anyhow::Error {
+        ProviderError {
+            provider: "OpenAI",
+            status: StatusCode::TOO_MANY_REQUESTS,
+            message: "Rate limit reached".to_string(),
+        }
+        .into()
+    }
+
+    #[async_trait::async_trait]
+    impl crate::providers::AIProvider for FailingProvider {
+        async fn chat_completion(
+            &self,
+            _messages: Vec<ChatMessage>,
+            _model: Option<String>,
+            _options: GenerationOptions,
+            _tools: Option<Vec<ToolSpec>>,
+            _tool_choice: Option<ToolChoice>,
+        ) -> anyhow::Result<ChatMessage> {
+            Err(rate_limited())
+        }
+
+        async fn chat_completion_stream(
+            &self,
+            _messages: Vec<ChatMessage>,
+            _model: Option<String>,
+            _options: GenerationOptions,
+            _tools: Option<Vec<ToolSpec>>,
+            _tool_choice: Option<ToolChoice>,
+        ) -> anyhow::Result<crate::providers::ChunkStream> {
+            if self.at_setup {
+                return Err(rate_limited());
+            }
+            Ok(stream::iter(vec![
+                Ok(UIMessageChunk::TextDelta { textDelta: "Hel".to_string() }),
+                Err(anyhow::anyhow!("connection reset")),
+                Ok(UIMessageChunk::TextDelta { textDelta: "lo".to_string() }),
+            ])
+            .boxed())
+        }
+
+        fn get_available_models(&self) -> Vec<&'static str> {
+            vec![]
+        }
+    }
+
+    fn streaming_request() -> Request<Body> {
         let request_body = json!({
-            "messages": [
-                {
-                    "id": "msg1",
-                    "role": "user",
-                    "content": "Hello Gemini!"
-                }
-            ],
-            "model": "gemini-1.5-flash",
-            "stream": false
+            "messages": [{ "id": "msg1", "role": "user", "content": "Hello" }],
+            "model": "gpt-4o",
+            "stream": true
         });
-
-        let request = Request::builder()
+        Request::builder()
             .method("POST")
             .uri("/api/v1/chat/completions")
             .header("content-type", "application/json")
             .body(Body::from(request_body.to_string()))
-            .unwrap();
+            .unwrap()
+    }
 
-        let response = app.oneshot(request).await.unwrap();
+    #[tokio::test]
+    async fn test_stream_setup_error_is_a_json_error() {
+        let mut providers = crate::providers::ProviderRegistry::new();
+        providers.register("openai", std::sync::Arc::new(FailingProvider { at_setup: true }));
+        providers.set_default("openai");
+        let app = create_test_app_with(providers).await;
+
+        let response = app.oneshot(streaming_request()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["error"]["code"], "openai_error");
+        assert_eq!(body["error"]["message"], "OpenAI API error: 429 Too Many Requests - Rate limit reached");
+    }
+
+    #[tokio::test]
+    async fn test_mid_stream_error_ends_with_error_event() {
+        let mut providers = crate::providers::ProviderRegistry::new();
+        providers.register("openai", std::sync::Arc::new(FailingProvider { at_setup: false }));
+        providers.set_default("openai");
+        let app = create_test_app_with(providers).await;
 
-        // Should return 200 even with fallback (Gemini service not configured)
+        let response = app.oneshot(streaming_request()).await.unwrap();
         assert_eq!(response.status(), StatusCode::OK);
+
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let body = String::from_utf8(body.to_vec()).unwrap();
+        assert!(body.contains(r#""textDelta":"Hel""#));
+        assert!(body.contains(r#""type":"error""#));
+        assert!(body.contains(r#""error":"openai API error: connection reset""#));
+        assert!(!body.contains(r#""textDelta":"lo""#));
     }
 
     #[tokio::test]
-    async fn test_claude_model_routing() {
-        let app = create_test_app();
+    async fn test_alias_fails_over_to_the_next_provider() {
+        let mut providers = crate::providers::ProviderRegistry::new();
+        providers.register("anthropic", std::sync::Arc::new(FailingProvider { at_setup: true }));
+        providers.register("openai", std::sync::Arc::new(StaticProvider("openai")));
+        providers.add_alias(
+            "smart",
+            vec!["anthropic/claude-sonnet-4-5".to_string(), "openai/gpt-4o".to_string()],
+        );
+        let app = create_test_app_with(providers).await;
 
+        let response = app.clone().oneshot(completion_request("smart")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = json_body(response).await;
+        assert_eq!(body["content"], "openai (gpt-4o)");
+        assert_eq!(body["metadata"]["provider"], "openai");
+        assert_eq!(body["metadata"]["model"], "gpt-4o");
+
+        let mut request = streaming_request();
+        *request.body_mut() = Body::from(
+            json!({
+                "messages": [{ "id": "msg1", "role": "user", "content": "Hello" }],
+                "model": "smart",
+                "stream": true
+            })
+            .to_string(),
+        );
+        let response = app.oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()["x-provider"], "openai");
+        assert_eq!(response.headers()["x-model"], "gpt-4o");
+    }
+
+    #[tokio::test]
+    async fn test_ignored_options_are_reported_as_warnings() {
+        let app = create_test_app_serving(&["openai"]).await;
         let request_body = json!({
-            "messages": [
-                {
-                    "id": "msg1",
-                    "role": "user",
-                    "content": "Hello Claude!"
-                }
-            ],
-            "model": "claude-3-5-sonnet-20241022",
-            "stream": false
+            "messages": [{ "id": "msg1", "role": "user", "content": "Hello" }],
+            "model": "gpt-4o",
+            "temperature": 0.2,
+            "seed": 7,
+            "stop": ["END"]
         });
 
-        let request = Request::builder()
-            .method("POST")
-            .uri("/api/v1/chat/completions")
-            .header("content-type", "application/json")
-            .body(Body::from(request_body.to_string()))
-            .unwrap();
+        let mut request = completion_request("gpt-4o");
+        *request.body_mut() = Body::from(request_body.to_string());
+        let response = app.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            json_body(response).await["metadata"]["warnings"],
+            json!([
+                "seed is not supported by openai and was ignored",
+                "stop is not supported by openai and was ignored"
+            ])
+        );
 
+        let mut request = streaming_request();
+        let mut request_body = request_body;
+        request_body["stream"] = json!(true);
+        *request.body_mut() = Body::from(request_body.to_string());
         let response = app.oneshot(request).await.unwrap();
-
-        // Should return 200 even with fallback (Anthropic service not configured)
-        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let body = String::from_utf8(body.to_vec()).unwrap();
+        let warnings = body.find(r#""type":"data""#).unwrap();
+        assert!(warnings < body.find(r#""textDelta":"openai""#).unwrap());
+        assert!(body.contains("seed is not supported by openai and was ignored"));
     }
 
     #[tokio::test]
-    async fn test_provider_detection() {
-        // Test OpenAI model detection
-        assert_eq!(get_provider_from_model("gpt-4"), Provider::OpenAI);
-        assert_eq!(get_provider_from_model("gpt-3.5-turbo"), Provider::OpenAI);
-
-        // Test Gemini model detection
-        assert_eq!(get_provider_from_model("gemini-1.5-flash"), Provider::Gemini);
-        assert_eq!(get_provider_from_model("gemini-pro"), Provider::Gemini);
-        assert_eq!(get_provider_from_model("models/gemini-1.5-pro"), Provider::Gemini);
-
-        // Test Anthropic model detection
-        assert_eq!(get_provider_from_model("claude-3-5-sonnet-20241022"), Provider::Anthropic);
-        assert_eq!(get_provider_from_model("claude-3-opus-20240229"), Provider::Anthropic);
-        assert_eq!(get_provider_from_model("claude-3-haiku-20240307"), Provider::Anthropic);
-
-        // Test default to OpenAI
-        assert_eq!(get_provider_from_model("unknown-model"), Provider::OpenAI);
+    async fn test_structured_answer_is_validated_and_repaired() {
+        let mut providers = crate::providers::ProviderRegistry::new();
+        providers.register("openai", std::sync::Arc::new(PreambleProvider));
+        providers.set_default("openai");
+        let app = create_test_app_with(providers).await;
+        let schema = json!({"type": "object", "properties": {"answer": {"type": "integer"}}, "required": ["answer"]});
+
+        for stream in [false, true] {
+            let mut request = completion_request("gpt-4o");
+            *request.body_mut() = Body::from(
+                json!({
+                    "messages": [{ "id": "msg1", "role": "user", "content": "What is 2 + 2?" }],
+                    "model": "gpt-4o",
+                    "stream": stream,
+                    "response_format": { "type": "json_schema", "schema": schema }
+                })
+                .to_string(),
+            );
+            let response = app.clone().oneshot(request).await.unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+
+            let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+            let body = String::from_utf8(body.to_vec()).unwrap();
+            if stream {
+                assert!(body.contains(r#""textDelta":"{\"answer\": 4}""#));
+            } else {
+                let body: serde_json::Value = serde_json::from_str(&body).unwrap();
+                assert_eq!(body["content"], r#"{"answer": 4}"#);
+            }
+        }
+
+        let mut request = completion_request("gpt-4o");
+        *request.body_mut() = Body::from(
+            json!({
+                "messages": [{ "id": "msg1", "role": "user", "content": "What is 2 + 2?" }],
+                "response_format": { "type": "json_schema", "schema": { "type": "thing" } }
+            })
+            .to_string(),
+        );
+        let response = app.oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(json_body(response).await["error"]["code"], "invalid_response_format");
     }
-}
\ No newline at end of file
+}
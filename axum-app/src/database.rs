@@ -472,7 +472,7 @@ fn convert_chat_to_enhanced(chat_message: &ChatMessage) -> EnhancedMessage {
         attachments: chat_message.attachments.clone(),
         metadata,
         model: None,
-        usage: None,
+        usage: Usage::from_metadata(chat_message.metadata.as_ref()),
         tool_calls: None,
         tool_results: None,
         finish_reason: None,
@@ -624,6 +624,24 @@ mod tests {
         assert_eq!(enhanced_messages[0].role, ChatRole::User);
     }
 
+    #[test]
+    fn test_chat_message_usage_is_recorded() {
+        let chat_message = ChatMessage {
+            id: "conv_1234_msg_5".to_string(),
+            role: ChatRole::Assistant,
+            content: "Hi!".to_string(),
+            created_at: None,
+            attachments: None,
+            metadata: Some(HashMap::from([(
+                "usage".to_string(),
+                serde_json::json!({"prompt_tokens": 12, "completion_tokens": 3, "total_tokens": 15}),
+            )])),
+        };
+
+        let enhanced = convert_chat_to_enhanced(&chat_message);
+        assert_eq!(enhanced.usage.unwrap().total_tokens, 15);
+    }
+
     #[tokio::test]
     async fn test_search_conversations() {
         let db = ChatDatabase::new("file::memory:").await.unwrap();
@@ -677,6 +695,8 @@ mod tests {
             prompt_tokens: 50,
             completion_tokens: 30,
             total_tokens: 80,
+            cache_creation_input_tokens: None,
+            cache_read_input_tokens: None,
         };
 
         let message = EnhancedMessage {
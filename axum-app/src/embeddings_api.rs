@@ -0,0 +1,120 @@
+use crate::chat::{provider_error_response, provider_not_configured_response};
+use crate::AppState;
+use axum::{
+    extract::State,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::{Deserialize, Serialize};
+
+/// Embedding model used when a request names none
+const DEFAULT_MODEL: &str = "text-embedding-3-small";
+
+/// OpenAI-compatible embeddings endpoint
+///
+/// The model is routed like a chat model, e.g. `gemini/text-embedding-004` or
+/// `ollama/nomic-embed-text`. Aliases are not tried in turn, since vectors of different models
+/// cannot be compared.
+pub async fn create_embeddings(
+    State(state): State<AppState>,
+    Json(request): Json<EmbeddingsRequest>,
+) -> Response {
+    let texts = request.input.into_texts();
+    if texts.is_empty() {
+        return invalid_request_response("input must not be empty", "invalid_input");
+    }
+
+    let model = request.model.unwrap_or_else(|| DEFAULT_MODEL.to_string());
+    let Some(resolved) = state.providers.resolve(&model) else {
+        return provider_not_configured_response(&model);
+    };
+    let Some(embedder) = resolved.provider.as_embedder() else {
+        return invalid_request_response(
+            &format!("{} does not provide embeddings", resolved.name),
+            "embeddings_not_supported",
+        );
+    };
+
+    match embedder.embed(texts, Some(resolved.model.clone())).await {
+        Ok(embeddings) => {
+            let prompt_tokens = embeddings.prompt_tokens.unwrap_or(0);
+            Json(EmbeddingsResponse {
+                object: "list",
+                data: embeddings
+                    .vectors
+                    .into_iter()
+                    .enumerate()
+                    .map(|(index, embedding)| EmbeddingData {
+                        object: "embedding",
+                        index,
+                        embedding,
+                    })
+                    .collect(),
+                model: embeddings.model,
+                usage: EmbeddingsUsage {
+                    prompt_tokens,
+                    total_tokens: prompt_tokens,
+                },
+            })
+            .into_response()
+        }
+        Err(e) => provider_error_response(&resolved.name, &e),
+    }
+}
+
+fn invalid_request_response(message: &str, code: &str) -> Response {
+    let error_response = Json(serde_json::json!({
+        "error": {
+            "message": message,
+            "type": "invalid_request_error",
+            "code": code
+        }
+    }));
+    (StatusCode::BAD_REQUEST, error_response).into_response()
+}
+
+#[derive(Debug, Deserialize)]
+pub struct EmbeddingsRequest {
+    pub input: EmbeddingsInput,
+    pub model: Option<String>,
+}
+
+/// A single text or a batch of texts
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+pub enum EmbeddingsInput {
+    Single(String),
+    Batch(Vec<String>),
+}
+
+impl EmbeddingsInput {
+    fn into_texts(self) -> Vec<String> {
+        match self {
+            EmbeddingsInput::Single(text) => vec![text],
+            EmbeddingsInput::Batch(texts) => texts,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct EmbeddingsResponse {
+    pub object: &'static str,
+    pub data: Vec<EmbeddingData>,
+    pub model: String,
+    pub usage: EmbeddingsUsage,
+}
+
+#[derive(Debug, Serialize)]
+pub struct EmbeddingData {
+    pub object: &'static str,
+    pub index: usize,
+    pub embedding: Vec<f32>,
+}
+
+/// Providers that do not count tokens report zero
+#[derive(Debug, Serialize)]
+pub struct EmbeddingsUsage {
+    pub prompt_tokens: u32,
+    pub total_tokens: u32,
+}
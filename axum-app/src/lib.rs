@@ -2,7 +2,9 @@ mod agent;
 mod agent_api;
 mod chat;
 mod database;
+mod embeddings_api;
 mod mcp;
+mod models_api;
 mod providers;
 
 use agent::AgentManager;
@@ -13,16 +15,16 @@ use axum::{
 };
 use chat::{chat_completion, completion_handler, legacy_chat_handler};
 use database::ChatDatabase;
+use embeddings_api::create_embeddings;
 use dotenvy::dotenv;
 use mcp::{MCPServerManager, MCPToolManager};
-use providers::{AnthropicService, GeminiService, OpenAIService};
+use models_api::list_models;
+use providers::ProviderRegistry;
 use std::sync::{Arc, Mutex};
 
 #[derive(Debug, Clone)]
 pub struct AppState {
-    openai_service: Option<Arc<OpenAIService>>,
-    gemini_service: Option<Arc<GeminiService>>,
-    anthropic_service: Option<Arc<AnthropicService>>,
+    providers: Arc<ProviderRegistry>,
     database: Option<Arc<ChatDatabase>>,
     agent_manager: Arc<AgentManager>,
     mcp_tool_manager: Arc<MCPToolManager>,
@@ -34,14 +36,24 @@ impl AppState {
         // Load environment variables
         let _ = dotenv();
 
-        // Try to initialize OpenAI service from environment
-        let openai_service = OpenAIService::from_env().ok().map(Arc::new);
+        // Register every AI provider configured in the environment
+        let providers = Arc::new(ProviderRegistry::from_env().await);
 
-        // Try to initialize Gemini service from environment
-        let gemini_service = GeminiService::from_env().ok().map(Arc::new);
-
-        // Try to initialize Anthropic service from environment
-        let anthropic_service = AnthropicService::from_env().ok().map(Arc::new);
+        // List the providers' models now, and again whenever the cache expires unless the
+        // catalog refreshes on every request
+        {
+            let providers = providers.clone();
+            tokio::spawn(async move {
+                loop {
+                    providers.catalog().refresh(&providers).await;
+                    let ttl = providers.catalog().ttl();
+                    if ttl.is_zero() {
+                        break;
+                    }
+                    tokio::time::sleep(ttl).await;
+                }
+            });
+        }
 
         // Try to initialize database from environment
         let database_url =
@@ -50,7 +62,7 @@ impl AppState {
         let database = ChatDatabase::new(&database_url).await.ok().map(Arc::new);
 
         // Initialize agent manager and register default tools
-        let agent_manager = Arc::new(AgentManager::new());
+        let agent_manager = Arc::new(AgentManager::new(providers.clone()));
         for tool in agent::create_default_tools() {
             let _ = agent_manager.register_tool(tool).await;
         }
@@ -80,9 +92,7 @@ impl AppState {
         };
 
         Self {
-            openai_service,
-            gemini_service,
-            anthropic_service,
+            providers,
             database,
             agent_manager,
             mcp_tool_manager,
@@ -99,6 +109,8 @@ pub async fn create_axum_app() -> Router {
         .route("/api/chat", post(completion_handler))
         .route("/api/v1/chat/completions", post(chat_completion))
         .route("/api/legacy/chat", post(legacy_chat_handler))
+        .route("/api/v1/models", get(list_models))
+        .route("/api/v1/embeddings", post(create_embeddings))
         // Agent API routes
         .nest("/api", agent_routes())
         .with_state(state)
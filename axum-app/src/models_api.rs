@@ -0,0 +1,22 @@
+use crate::AppState;
+use crate::providers::discovery::CatalogModel;
+use axum::{extract::State, Json};
+use serde::Serialize;
+
+/// List the models of every configured provider, for the model picker
+///
+/// Models come from the providers' own model listings, cached for `MODEL_CACHE_TTL_SECS`.
+/// Each `id` can be sent as the `model` of a chat request.
+pub async fn list_models(State(state): State<AppState>) -> Json<ListModelsResponse> {
+    let data = state.providers.catalog().models(&state.providers).await;
+    Json(ListModelsResponse {
+        object: "list",
+        data,
+    })
+}
+
+#[derive(Debug, Serialize)]
+pub struct ListModelsResponse {
+    pub object: &'static str,
+    pub data: Vec<CatalogModel>,
+}
@@ -1,59 +1,206 @@
 use anyhow::Result;
 use async_stream::stream;
-use futures::{stream::BoxStream, StreamExt};
+use futures::StreamExt;
 use reqwest::Client;
 use serde::{Deserialize, Serialize};
 use sha2::{Digest, Sha256};
 use std::collections::HashMap;
 use std::time::Duration;
 
-use crate::chat::{ChatMessage, ChatRole, UIMessageChunk};
+use crate::chat::{ChatMessage, ChatRole, UIMessageChunk, Usage};
 use async_trait::async_trait;
-use super::AIProvider;
+use super::attachments;
+use super::decoder;
+use super::reasoning::{Reasoning, ReasoningStream};
+use super::structured::{AnswerStream, ResponseTool};
+use super::tools::{ToolCallAccumulator, TOOL_CALLS_KEY, TOOL_CALL_ID_KEY};
+use super::{AIProvider, ChunkStream, GenerationOptions, ModelInfo, RetryPolicy, ToolCall, ToolChoice, ToolSpec};
 
 /// Anthropic Claude API Service
 /// Provides integration with Anthropic's Claude AI models
 #[derive(Debug)]
 pub struct AnthropicService {
     client: Client,
+    /// Client for streams, which can outlive the request timeout of `client`
+    stream_client: Client,
     api_key: String,
     base_url: String,
     default_model: String,
+    /// Token budget for extended thinking; `None` disables thinking
+    thinking_budget: Option<u32>,
+    /// Mark the stable prompt prefix for Anthropic's prompt cache
+    prompt_caching: bool,
+    retry: RetryPolicy,
 }
 
+/// Smallest thinking budget Anthropic accepts
+const MIN_THINKING_BUDGET: u32 = 1024;
+
 impl AnthropicService {
     /// Create a new Anthropic service with the given API key
     pub fn new(api_key: String, model: Option<String>) -> Self {
         Self {
             client: Client::builder()
                 .timeout(Duration::from_secs(60))
-                .default_headers({
-                    let mut headers = reqwest::header::HeaderMap::new();
-                    headers.insert(
-                        "anthropic-version",
-                        reqwest::header::HeaderValue::from_static("2023-06-01"),
-                    );
-                    headers.insert(
-                        "content-type",
-                        reqwest::header::HeaderValue::from_static("application/json"),
-                    );
-                    headers
-                })
+                .default_headers(Self::default_headers())
+                .build()
+                .unwrap(),
+            stream_client: super::stream_client_builder()
+                .default_headers(Self::default_headers())
                 .build()
                 .unwrap(),
             api_key,
             base_url: "https://api.anthropic.com".to_string(),
             default_model: model.unwrap_or_else(|| "claude-3-5-sonnet-20241022".to_string()),
+            thinking_budget: None,
+            prompt_caching: true,
+            retry: RetryPolicy::from_env(),
         }
     }
 
+    /// Headers sent with every request
+    fn default_headers() -> reqwest::header::HeaderMap {
+        let mut headers = reqwest::header::HeaderMap::new();
+        headers.insert(
+            "anthropic-version",
+            reqwest::header::HeaderValue::from_static("2023-06-01"),
+        );
+        headers.insert(
+            "content-type",
+            reqwest::header::HeaderValue::from_static("application/json"),
+        );
+        headers
+    }
+
     /// Create Anthropic service from environment variables
     pub fn from_env() -> Result<Self> {
         let api_key = std::env::var("ANTHROPIC_API_KEY")
             .map_err(|_| anyhow::anyhow!("ANTHROPIC_API_KEY environment variable not set"))?;
 
         let model = std::env::var("ANTHROPIC_MODEL").ok();
-        Ok(Self::new(api_key, model))
+        let mut service = Self::new(api_key, model);
+        service.thinking_budget = std::env::var("ANTHROPIC_THINKING_BUDGET")
+            .ok()
+            .and_then(|v| v.parse::<u32>().ok())
+            .filter(|budget| *budget > 0)
+            .map(|budget| budget.max(MIN_THINKING_BUDGET));
+        service.prompt_caching = std::env::var("ANTHROPIC_PROMPT_CACHING")
+            .map(|v| v != "false" && v != "0")
+            .unwrap_or(true);
+        Ok(service)
+    }
+
+    /// Enable extended thinking on `request` if a budget is configured
+    ///
+    /// Thinking tokens count towards `max_tokens`, so the budget is added on top of the
+    /// requested answer length. Thinking is incompatible with a custom temperature or top_k,
+    /// and with forcing tool use.
+    fn apply_thinking(&self, request: &mut AnthropicRequest) {
+        if let Some(budget_tokens) = self.thinking_budget {
+            request.thinking = Some(AnthropicThinking {
+                type_: "enabled".to_string(),
+                budget_tokens,
+            });
+            request.max_tokens = request.max_tokens.saturating_add(budget_tokens);
+            request.temperature = None;
+            request.top_k = None;
+            // Thinking only allows the model to choose whether to call tools
+            let forced = request
+                .tool_choice
+                .as_ref()
+                .is_some_and(|choice| choice["type"] == "tool" || choice["type"] == "any");
+            if forced {
+                request.tool_choice = Some(serde_json::json!({ "type": "auto" }));
+            }
+        }
+    }
+
+    /// Add cache breakpoints to `request` if prompt caching is enabled
+    ///
+    /// Anthropic caches the prompt up to each breakpoint: the tool definitions, the system
+    /// prompt, the conversation so far and the conversation before the latest user turn, which
+    /// is where the previous request of an agent run ended. Prompts shorter than the model's
+    /// minimum are not cached, at no cost.
+    fn apply_prompt_caching(&self, request: &mut AnthropicRequest) {
+        if !self.prompt_caching {
+            return;
+        }
+        if let Some(tool) = request.tools.as_mut().and_then(|tools| tools.last_mut()) {
+            tool.cache_control = Some(AnthropicCacheControl::ephemeral());
+        }
+        if let Some(block) = request.system.as_mut().and_then(|system| system.last_mut()) {
+            block.cache_control = Some(AnthropicCacheControl::ephemeral());
+        }
+
+        let last_user = request.messages.iter().rposition(|message| message.role == "user");
+        let previous_user = last_user.and_then(|last| {
+            request.messages[..last].iter().rposition(|message| message.role == "user")
+        });
+        let last = request.messages.len().checked_sub(1);
+        for index in [last, previous_user].into_iter().flatten() {
+            if let Some(cache_control) = request.messages[index]
+                .content
+                .iter_mut()
+                .rev()
+                .find_map(AnthropicContent::cache_control_mut)
+            {
+                *cache_control = Some(AnthropicCacheControl::ephemeral());
+            }
+        }
+    }
+
+    fn build_request(
+        model: String,
+        messages: Vec<AnthropicMessage>,
+        system: Option<String>,
+        options: GenerationOptions,
+        tools: Option<Vec<ToolSpec>>,
+        tool_choice: Option<ToolChoice>,
+        stream: bool,
+    ) -> AnthropicRequest {
+        let response_tool = Self::response_tool(&options);
+        let mut request = AnthropicRequest {
+            model,
+            messages,
+            max_tokens: options.max_tokens.unwrap_or(4096),
+            temperature: options.temperature,
+            top_p: options.top_p,
+            top_k: options.top_k,
+            stop_sequences: options.stop_sequences(),
+            metadata: options.user.map(|user_id| AnthropicMetadata { user_id }),
+            stream,
+            system: system.map(|text| vec![AnthropicSystemBlock::text(text)]),
+            tools: Self::convert_tools(tools),
+            tool_choice: None,
+            thinking: None,
+        };
+
+        request.tool_choice = match (&response_tool, &request.tools, tool_choice) {
+            // Without other tools the model must answer through the response tool
+            (Some(tool), None, _) => Some(serde_json::json!({ "type": "tool", "name": tool.name })),
+            // With them it may still call those first, but every turn must end in a tool call
+            (Some(_), Some(_), None | Some(ToolChoice::Auto) | Some(ToolChoice::Required)) => {
+                Some(serde_json::json!({ "type": "any" }))
+            }
+            (_, _, tool_choice) => Self::convert_tool_choice(tool_choice),
+        };
+        if let Some(tool) = response_tool {
+            request.tools.get_or_insert_with(Vec::new).push(tool);
+        }
+        request
+    }
+
+    /// Tool whose input is the answer, for requests that ask for JSON
+    ///
+    /// Anthropic has no JSON mode; a tool call is the only way to make the model produce
+    /// JSON that matches a schema.
+    fn response_tool(options: &GenerationOptions) -> Option<AnthropicTool> {
+        ResponseTool::from_options(options).map(|tool| AnthropicTool {
+            name: tool.name,
+            description: tool.description,
+            input_schema: tool.input_schema,
+            cache_control: None,
+        })
     }
 
     /// Get available Anthropic models
@@ -69,32 +216,131 @@ impl AnthropicService {
 
     /// Convert chat messages to Anthropic format
     fn convert_to_anthropic_messages(messages: &[ChatMessage]) -> Vec<AnthropicMessage> {
-        messages
-            .iter()
-            .filter_map(|msg| {
-                match msg.role {
-                    ChatRole::User => Some(AnthropicMessage {
+        let mut anthropic_messages: Vec<AnthropicMessage> = Vec::new();
+
+        for msg in messages {
+            match msg.role {
+                ChatRole::User => {
+                    // Images and documents go before the text, as Anthropic recommends
+                    let mut content: Vec<AnthropicContent> = attachments::images(msg)
+                        .filter_map(AnthropicSource::of)
+                        .map(|source| AnthropicContent::Image {
+                            source,
+                            cache_control: None,
+                        })
+                        .collect();
+                    content.extend(attachments::documents(msg).filter_map(|document| {
+                        AnthropicSource::of(document).map(|source| AnthropicContent::Document {
+                            source,
+                            title: document.filename.clone(),
+                            cache_control: None,
+                        })
+                    }));
+                    if !msg.content.is_empty() || content.is_empty() {
+                        content.push(AnthropicContent::Text {
+                            text: msg.content.clone(),
+                            cache_control: None,
+                        });
+                    }
+                    anthropic_messages.push(AnthropicMessage {
                         role: "user".to_string(),
-                        content: vec![AnthropicContent {
-                            type_: "text".to_string(),
+                        content,
+                    });
+                }
+                ChatRole::Assistant => {
+                    // Signed thinking must come back first and unchanged; unsigned reasoning
+                    // from other providers cannot be verified and is dropped
+                    let mut content: Vec<AnthropicContent> = Reasoning::from_metadata(msg.metadata.as_ref())
+                        .unwrap_or_default()
+                        .into_iter()
+                        .filter_map(|part| match (part.signature, part.redacted) {
+                            (Some(signature), _) => Some(AnthropicContent::Thinking {
+                                thinking: part.text,
+                                signature,
+                            }),
+                            (None, Some(data)) => Some(AnthropicContent::RedactedThinking { data }),
+                            (None, None) => None,
+                        })
+                        .collect();
+                    if !msg.content.is_empty() {
+                        content.push(AnthropicContent::Text {
                             text: msg.content.clone(),
-                        }],
-                    }),
-                    ChatRole::Assistant => Some(AnthropicMessage {
+                            cache_control: None,
+                        });
+                    }
+                    for call in ToolCall::from_metadata(msg.metadata.as_ref()).unwrap_or_default() {
+                        content.push(AnthropicContent::ToolUse {
+                            id: call.id,
+                            name: call.name,
+                            input: call.arguments,
+                            cache_control: None,
+                        });
+                    }
+                    anthropic_messages.push(AnthropicMessage {
                         role: "assistant".to_string(),
-                        content: vec![AnthropicContent {
-                            type_: "text".to_string(),
-                            text: msg.content.clone(),
-                        }],
-                    }),
-                    ChatRole::System => {
-                        // Anthropic supports system messages in a different way
-                        // We'll handle this in the request building
-                        None
+                        content,
+                    });
+                }
+                ChatRole::Tool => {
+                    let tool_use_id = msg
+                        .metadata
+                        .as_ref()
+                        .and_then(|m| m.get(TOOL_CALL_ID_KEY))
+                        .and_then(|v| v.as_str())
+                        .unwrap_or_default()
+                        .to_string();
+                    let block = AnthropicContent::ToolResult {
+                        tool_use_id,
+                        content: msg.content.clone(),
+                        cache_control: None,
+                    };
+
+                    // Results for parallel tool calls must share a single user turn
+                    match anthropic_messages.last_mut() {
+                        Some(last) if last.role == "user"
+                            && last.content.iter().all(|c| matches!(c, AnthropicContent::ToolResult { .. })) =>
+                        {
+                            last.content.push(block);
+                        }
+                        _ => anthropic_messages.push(AnthropicMessage {
+                            role: "user".to_string(),
+                            content: vec![block],
+                        }),
                     }
                 }
-            })
-            .collect()
+                ChatRole::System => {
+                    // Anthropic supports system messages in a different way
+                    // We'll handle this in the request building
+                }
+            }
+        }
+
+        anthropic_messages
+    }
+
+    /// Convert tool specs to Anthropic format
+    fn convert_tools(tools: Option<Vec<ToolSpec>>) -> Option<Vec<AnthropicTool>> {
+        tools.filter(|t| !t.is_empty()).map(|tools| {
+            tools
+                .into_iter()
+                .map(|tool| AnthropicTool {
+                    name: tool.name,
+                    description: tool.description,
+                    input_schema: tool.parameters,
+                    cache_control: None,
+                })
+                .collect()
+        })
+    }
+
+    /// Convert tool choice to Anthropic format
+    fn convert_tool_choice(tool_choice: Option<ToolChoice>) -> Option<serde_json::Value> {
+        tool_choice.map(|choice| match choice {
+            ToolChoice::Auto => serde_json::json!({ "type": "auto" }),
+            ToolChoice::None => serde_json::json!({ "type": "none" }),
+            ToolChoice::Required => serde_json::json!({ "type": "any" }),
+            ToolChoice::Function { name } => serde_json::json!({ "type": "tool", "name": name }),
+        })
     }
 
     /// Extract system message and handle it separately
@@ -118,8 +364,9 @@ impl AnthropicService {
         &self,
         messages: Vec<ChatMessage>,
         model: Option<String>,
-        temperature: Option<f32>,
-        max_tokens: Option<u32>,
+        options: GenerationOptions,
+        tools: Option<Vec<ToolSpec>>,
+        tool_choice: Option<ToolChoice>,
     ) -> Result<ChatMessage> {
         let model = model.unwrap_or_else(|| self.default_model.clone());
         let (system_msg, filtered_messages) = Self::extract_system_message(&messages);
@@ -130,43 +377,105 @@ impl AnthropicService {
 
         let anthropic_messages = Self::convert_to_anthropic_messages(&filtered_messages);
 
-        let mut request = AnthropicRequest {
-            model: model.clone(),
-            messages: anthropic_messages,
-            max_tokens: max_tokens.unwrap_or(4096),
-            temperature: temperature.unwrap_or(0.7),
-            stream: false,
-            system: system_msg,
-        };
+        let answer_tool = ResponseTool::from_options(&options);
+        let mut request =
+            Self::build_request(model.clone(), anthropic_messages, system_msg, options, tools, tool_choice, false);
+        self.apply_thinking(&mut request);
+        self.apply_prompt_caching(&mut request);
 
         let url = format!("{}/v1/messages", self.base_url);
 
-        let response = self
+        let builder = self
             .client
             .post(&url)
             .header("x-api-key", &self.api_key)
-            .json(&request)
-            .send()
-            .await?;
+            .json(&request);
 
-        if !response.status().is_success() {
-            let error_text = response.text().await?;
-            return Err(anyhow::anyhow!("Anthropic API error: {}", error_text));
-        }
+        let response = self.retry.send("Anthropic", builder).await?;
 
         let anthropic_response: AnthropicResponse = response.json().await?;
 
-        if let Some(content) = anthropic_response.content.first() {
+        // A JSON answer is the input of the response tool, and replaces any text around it
+        let answer = anthropic_response.content.iter().find_map(|block| match block {
+            AnthropicContent::ToolUse { name, input, .. } => answer_tool
+                .as_ref()
+                .filter(|tool| &tool.name == name)
+                .map(|tool| tool.answer(input)),
+            _ => None,
+        });
+        let text: String = match &answer {
+            Some(answer) => answer.clone(),
+            None => anthropic_response
+                .content
+                .iter()
+                .filter_map(|block| match block {
+                    AnthropicContent::Text { text, .. } => Some(text.as_str()),
+                    _ => None,
+                })
+                .collect(),
+        };
+
+        let reasoning: Vec<Reasoning> = anthropic_response
+            .content
+            .iter()
+            .filter_map(|block| match block {
+                AnthropicContent::Thinking { thinking, signature } => Some(Reasoning {
+                    text: thinking.clone(),
+                    signature: Some(signature.clone()),
+                    redacted: None,
+                }),
+                AnthropicContent::RedactedThinking { data } => Some(Reasoning {
+                    redacted: Some(data.clone()),
+                    ..Default::default()
+                }),
+                _ => None,
+            })
+            .collect();
+
+        let tool_calls: Vec<ToolCall> = anthropic_response
+            .content
+            .iter()
+            .filter_map(|block| match block {
+                AnthropicContent::ToolUse { id, name, input, .. } if answer_tool.as_ref().is_none_or(|tool| &tool.name != name) => Some(ToolCall {
+                    id: id.clone(),
+                    name: name.clone(),
+                    arguments: input.clone(),
+                }),
+                _ => None,
+            })
+            .collect();
+
+        if !text.is_empty() || !tool_calls.is_empty() {
+            let mut metadata = HashMap::from([
+                ("model".to_string(), serde_json::Value::String(model)),
+                ("provider".to_string(), serde_json::Value::String("anthropic".to_string())),
+            ]);
+            if let Some(stop_reason) = &anthropic_response.stop_reason {
+                // Answering through the response tool is the end of the turn
+                let stop_reason = match answer {
+                    Some(_) if tool_calls.is_empty() && stop_reason == "tool_use" => "end_turn",
+                    _ => stop_reason.as_str(),
+                };
+                metadata.insert("finish_reason".to_string(), serde_json::Value::String(stop_reason.to_string()));
+            }
+            if let Some(usage) = &anthropic_response.usage {
+                metadata.insert("usage".to_string(), serde_json::to_value(usage.to_usage()).unwrap_or(serde_json::Value::Null));
+            }
+            Reasoning::insert_into(&reasoning, &mut metadata);
+            if !tool_calls.is_empty() {
+                metadata.insert(
+                    TOOL_CALLS_KEY.to_string(),
+                    serde_json::to_value(&tool_calls).unwrap_or(serde_json::Value::Null),
+                );
+            }
+
             return Ok(ChatMessage {
                 id: format!("claude_{}", fastrand::u64(1000..9999)),
                 role: ChatRole::Assistant,
-                content: content.text.clone(),
+                content: text,
                 created_at: Some(chrono::Utc::now()),
                 attachments: None,
-                metadata: Some(HashMap::from([
-                    ("model".to_string(), serde_json::Value::String(model)),
-                    ("provider".to_string(), serde_json::Value::String("anthropic".to_string())),
-                ])),
+                metadata: Some(metadata),
             });
         }
 
@@ -178,123 +487,164 @@ impl AnthropicService {
         &self,
         messages: Vec<ChatMessage>,
         model: Option<String>,
-        temperature: Option<f32>,
-        max_tokens: Option<u32>,
-    ) -> BoxStream<'static, Result<UIMessageChunk, anyhow::Error>> {
+        options: GenerationOptions,
+        tools: Option<Vec<ToolSpec>>,
+        tool_choice: Option<ToolChoice>,
+    ) -> Result<ChunkStream> {
         let model = model.unwrap_or_else(|| self.default_model.clone());
         let (system_msg, filtered_messages) = Self::extract_system_message(&messages);
 
         if filtered_messages.is_empty() {
-            return Box::pin(stream! {
-                yield Err(anyhow::anyhow!("No valid messages to process"));
-            });
+            return Err(anyhow::anyhow!("No valid messages to process"));
         }
 
         let anthropic_messages = Self::convert_to_anthropic_messages(&filtered_messages);
-        let api_key = self.api_key.clone();
-        let base_url = self.base_url.clone();
 
-        Box::pin(stream! {
+        let answer_tool = ResponseTool::from_options(&options);
+        let mut request =
+            Self::build_request(model.clone(), anthropic_messages, system_msg, options, tools, tool_choice, true);
+        self.apply_thinking(&mut request);
+        self.apply_prompt_caching(&mut request);
+
+        let url = format!("{}/v1/messages", self.base_url);
+
+        let builder = self
+            .stream_client
+            .post(&url)
+            .header("x-api-key", &self.api_key)
+            .json(&request);
+
+        let response = self.retry.send("Anthropic", builder).await?;
+
+        Ok(Box::pin(stream! {
             // Send text-start event
             yield Ok(UIMessageChunk::TextStart);
 
-            let mut request = AnthropicRequest {
-                model: model.clone(),
-                messages: anthropic_messages,
-                max_tokens: max_tokens.unwrap_or(4096),
-                temperature: temperature.unwrap_or(0.7),
-                stream: true,
-                system: system_msg,
-            };
-
-            let url = format!("{}/v1/messages", base_url);
-
-            let client = Client::builder()
-                .default_headers({
-                    let mut headers = reqwest::header::HeaderMap::new();
-                    headers.insert(
-                        "anthropic-version",
-                        reqwest::header::HeaderValue::from_static("2023-06-01"),
-                    );
-                    headers.insert(
-                        "content-type",
-                        reqwest::header::HeaderValue::from_static("application/json"),
-                    );
-                    headers
-                })
-                .build()
-                .unwrap();
-
-            match client.post(&url).header("x-api-key", &api_key).json(&request).send().await {
-                Ok(response) => {
-                    if !response.status().is_success() {
-                        let error_text = response.text().await.unwrap_or_default();
-                        yield Ok(UIMessageChunk::Error {
-                            error: format!("Anthropic API error: {}", error_text),
-                        });
+            let mut events = decoder::sse_events(response.bytes_stream());
+            let mut tool_calls = ToolCallAccumulator::new();
+            let mut reasoning = ReasoningStream::new();
+            let mut signature = None;
+            let mut answer_index = None;
+            let mut answer = answer_tool.as_ref().map(ResponseTool::answer_stream);
+            let mut usage = AnthropicUsage::default();
+
+            while let Some(event) = events.next().await {
+                let event = match event {
+                    Ok(event) => event,
+                    Err(e) => {
+                        yield Err(e);
                         return;
                     }
+                };
 
-                    let mut stream = response.bytes_stream();
-
-                    while let Some(chunk_result) = stream.next().await {
-                        match chunk_result {
-                            Ok(chunk) => {
-                                if let Ok(text) = String::from_utf8(chunk.to_vec()) {
-                                    // Process Anthropic's SSE format
-                                    let lines: Vec<&str> = text.split('\n').collect();
-                                    for line in lines {
-                                        if line.trim().is_empty() {
-                                            continue;
-                                        }
-                                        if line.trim() == "event: message_stop" {
-                                            yield Ok(UIMessageChunk::TextFinish);
-
-                                            // Send finish event
-                                            let usage = crate::chat::Usage {
-                                                prompt_tokens: 0,
-                                                completion_tokens: 0,
-                                                total_tokens: 0,
-                                            };
-                                            yield Ok(UIMessageChunk::Finish {
-                                                reasoning: None,
-                                                sources: None,
-                                                usage: Some(usage),
-                                                logprobs: None,
-                                            });
-                                            return;
-                                        }
-                                        if line.trim().starts_with("data: ") {
-                                            let json_str = &line.trim()[6..];
-                                            if let Ok(anthropic_chunk) = serde_json::from_str::<AnthropicStreamChunk>(json_str) {
-                                                if let Some(delta) = anthropic_chunk.delta {
-                                                    if let Some(text) = delta.text {
-                                                        yield Ok(UIMessageChunk::TextDelta {
-                                                            textDelta: text,
-                                                        });
-                                                    }
-                                                }
-                                            }
-                                        }
+                // Every data payload repeats the event name as its `type`
+                let anthropic_chunk = match serde_json::from_str::<AnthropicStreamChunk>(&event.data) {
+                    Ok(chunk) => chunk,
+                    Err(_) => continue,
+                };
+                let index = anthropic_chunk.index.unwrap_or(0);
+                match anthropic_chunk.type_.as_str() {
+                    "message_start" => {
+                        if let Some(message_usage) = anthropic_chunk.message.and_then(|m| m.usage) {
+                            usage = message_usage;
+                        }
+                    }
+                    "message_delta" => {
+                        // Output token counts here are cumulative
+                        if let Some(delta_usage) = anthropic_chunk.usage {
+                            usage.output_tokens = delta_usage.output_tokens;
+                        }
+                    }
+                    "content_block_start" => match anthropic_chunk.content_block {
+                        // The response tool's input is streamed as the answer text
+                        Some(AnthropicContent::ToolUse { name, .. }) if answer_tool.as_ref().is_some_and(|tool| tool.name == name) => {
+                            answer_index = Some(index);
+                        }
+                        Some(AnthropicContent::ToolUse { id, name, .. }) => {
+                            tool_calls.push(index, Some(&id), Some(&name), None);
+                        }
+                        // Thinking may be signed without any visible text
+                        Some(AnthropicContent::Thinking { .. }) => {
+                            if let Some(chunk) = reasoning.start() {
+                                yield Ok(chunk);
+                            }
+                        }
+                        Some(AnthropicContent::RedactedThinking { data }) => {
+                            for chunk in reasoning.redacted(data) {
+                                yield Ok(chunk);
+                            }
+                        }
+                        _ => {}
+                    },
+                    "content_block_delta" => {
+                        if let Some(delta) = anthropic_chunk.delta {
+                            if let Some(thinking) = delta.thinking {
+                                for chunk in reasoning.delta(&thinking) {
+                                    yield Ok(chunk);
+                                }
+                            }
+                            if delta.signature.is_some() {
+                                signature = delta.signature;
+                            }
+                            if let Some(text) = delta.text {
+                                yield Ok(UIMessageChunk::TextDelta {
+                                    textDelta: text,
+                                });
+                            }
+                            if let Some(partial_json) = delta.partial_json {
+                                if answer_index == Some(index) {
+                                    if let Some(text) = answer.as_mut().and_then(|answer| answer.delta(partial_json)) {
+                                        yield Ok(UIMessageChunk::TextDelta {
+                                            textDelta: text,
+                                        });
                                     }
+                                } else {
+                                    tool_calls.push(index, None, None, Some(&partial_json));
                                 }
                             }
-                            Err(e) => {
-                                yield Ok(UIMessageChunk::Error {
-                                    error: format!("Stream error: {}", e),
+                        }
+                    }
+                    "content_block_stop" => {
+                        // Only a thinking block leaves the reasoning part open
+                        if let Some(chunk) = reasoning.end(signature.take()) {
+                            yield Ok(chunk);
+                        }
+                        if answer_index == Some(index) {
+                            if let Some(text) = answer.as_mut().and_then(AnswerStream::finish) {
+                                yield Ok(UIMessageChunk::TextDelta {
+                                    textDelta: text,
                                 });
-                                break;
                             }
                         }
+
+                        // Tool input is complete once its block closes
+                        for call in tool_calls.finish() {
+                            yield Ok(call.into_chunk());
+                        }
                     }
-                }
-                Err(e) => {
-                    yield Ok(UIMessageChunk::Error {
-                        error: format!("Request error: {}", e),
-                    });
+                    "message_stop" => {
+                        yield Ok(UIMessageChunk::TextFinish);
+
+                        // Send finish event
+                        yield Ok(UIMessageChunk::Finish {
+                            reasoning: reasoning.text(),
+                            sources: None,
+                            usage: Some(usage.to_usage()),
+                            logprobs: None,
+                        });
+                        return;
+                    }
+                    "error" => {
+                        // Overloaded and similar errors arrive as an SSE event mid-stream
+                        yield Err(anyhow::anyhow!("Anthropic stream error: {}", event.data));
+                        return;
+                    }
+                    _ => {}
                 }
             }
-        })
+
+            yield Err(anyhow::anyhow!("Anthropic stream ended before message_stop"));
+        }))
     }
 }
 
@@ -304,11 +654,81 @@ struct AnthropicRequest {
     model: String,
     messages: Vec<AnthropicMessage>,
     max_tokens: u32,
-    temperature: f32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    temperature: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    top_p: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    top_k: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    stop_sequences: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    metadata: Option<AnthropicMetadata>,
     #[serde(rename = "stream")]
     stream: bool,
     #[serde(skip_serializing_if = "Option::is_none")]
-    system: Option<String>,
+    system: Option<Vec<AnthropicSystemBlock>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tools: Option<Vec<AnthropicTool>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tool_choice: Option<serde_json::Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    thinking: Option<AnthropicThinking>,
+}
+
+/// Request metadata; `user_id` identifies the end user for abuse detection
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct AnthropicMetadata {
+    user_id: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct AnthropicThinking {
+    #[serde(rename = "type")]
+    type_: String,
+    budget_tokens: u32,
+}
+
+/// Marks the end of a prompt prefix to cache
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct AnthropicCacheControl {
+    #[serde(rename = "type")]
+    type_: String,
+}
+
+impl AnthropicCacheControl {
+    /// Cached for five minutes, refreshed on every hit
+    fn ephemeral() -> Self {
+        Self { type_: "ephemeral".to_string() }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct AnthropicSystemBlock {
+    #[serde(rename = "type")]
+    type_: String,
+    text: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    cache_control: Option<AnthropicCacheControl>,
+}
+
+impl AnthropicSystemBlock {
+    fn text(text: String) -> Self {
+        Self {
+            type_: "text".to_string(),
+            text,
+            cache_control: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct AnthropicTool {
+    name: String,
+    description: String,
+    input_schema: serde_json::Value,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    cache_control: Option<AnthropicCacheControl>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -318,10 +738,107 @@ struct AnthropicMessage {
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
-struct AnthropicContent {
+#[serde(tag = "type", rename_all = "snake_case")]
+enum AnthropicContent {
+    Text {
+        text: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        cache_control: Option<AnthropicCacheControl>,
+    },
+    ToolUse {
+        id: String,
+        name: String,
+        input: serde_json::Value,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        cache_control: Option<AnthropicCacheControl>,
+    },
+    ToolResult {
+        tool_use_id: String,
+        content: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        cache_control: Option<AnthropicCacheControl>,
+    },
+    Image {
+        source: AnthropicSource,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        cache_control: Option<AnthropicCacheControl>,
+    },
+    Document {
+        source: AnthropicSource,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        title: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        cache_control: Option<AnthropicCacheControl>,
+    },
+    Thinking {
+        thinking: String,
+        /// Empty on the `content_block_start` event; it arrives as a `signature_delta`
+        #[serde(default)]
+        signature: String,
+    },
+    RedactedThinking {
+        data: String,
+    },
+    /// Block types we don't handle yet
+    #[serde(other)]
+    Unsupported,
+}
+
+impl AnthropicContent {
+    /// Where a cache breakpoint goes on this block; thinking and empty text cannot take one
+    fn cache_control_mut(&mut self) -> Option<&mut Option<AnthropicCacheControl>> {
+        match self {
+            AnthropicContent::Text { text, cache_control } if !text.is_empty() => Some(cache_control),
+            AnthropicContent::ToolUse { cache_control, .. }
+            | AnthropicContent::ToolResult { cache_control, .. }
+            | AnthropicContent::Image { cache_control, .. }
+            | AnthropicContent::Document { cache_control, .. } => Some(cache_control),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct AnthropicSource {
     #[serde(rename = "type")]
-    type_: String, // "text"
-    text: String,
+    type_: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    media_type: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    data: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    url: Option<String>,
+}
+
+impl AnthropicSource {
+    /// The attachment's data, or its URL for Anthropic to download
+    fn of(attachment: &crate::chat::Attachment) -> Option<Self> {
+        if attachments::is_remote(attachment) {
+            return Some(Self {
+                type_: "url".to_string(),
+                media_type: None,
+                data: None,
+                url: Some(attachment.url.clone()),
+            });
+        }
+        attachments::inline_data(attachment).map(|inline| Self {
+            type_: "base64".to_string(),
+            media_type: Some(inline.media_type),
+            data: Some(inline.data),
+            url: None,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct AnthropicModelList {
+    data: Vec<AnthropicModel>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct AnthropicModel {
+    id: String,
+    display_name: Option<String>,
 }
 
 #[derive(Debug, Clone, Deserialize)]
@@ -344,22 +861,52 @@ struct AnthropicResponse {
 struct AnthropicStreamChunk {
     #[serde(rename = "type")]
     type_: String,
+    index: Option<u64>,
+    content_block: Option<AnthropicContent>,
     delta: Option<AnthropicDelta>,
+    /// The message being streamed, on `message_start`
+    message: Option<AnthropicStreamMessage>,
+    /// Usage so far, on `message_delta`
+    usage: Option<AnthropicUsage>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct AnthropicStreamMessage {
+    usage: Option<AnthropicUsage>,
 }
 
 #[derive(Debug, Clone, Deserialize)]
 struct AnthropicDelta {
     #[serde(rename = "type")]
-    type_: String,
+    type_: Option<String>,
     text: Option<String>,
+    partial_json: Option<String>,
+    thinking: Option<String>,
+    signature: Option<String>,
 }
 
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Default, Deserialize)]
 struct AnthropicUsage {
-    #[serde(rename = "input_tokens")]
+    #[serde(rename = "input_tokens", default)]
     input_tokens: u32,
-    #[serde(rename = "output_tokens")]
+    #[serde(rename = "output_tokens", default)]
     output_tokens: u32,
+    /// Prompt tokens written to the cache, not included in `input_tokens`
+    cache_creation_input_tokens: Option<u32>,
+    /// Prompt tokens read from the cache, not included in `input_tokens`
+    cache_read_input_tokens: Option<u32>,
+}
+
+impl AnthropicUsage {
+    /// Usage with cached prompt tokens counted as prompt tokens
+    fn to_usage(&self) -> Usage {
+        let cached = self.cache_creation_input_tokens.unwrap_or(0) + self.cache_read_input_tokens.unwrap_or(0);
+        Usage {
+            cache_creation_input_tokens: self.cache_creation_input_tokens,
+            cache_read_input_tokens: self.cache_read_input_tokens,
+            ..Usage::new(self.input_tokens + cached, self.output_tokens)
+        }
+    }
 }
 
 #[async_trait]
@@ -368,30 +915,67 @@ impl AIProvider for AnthropicService {
         &self,
         messages: Vec<ChatMessage>,
         model: Option<String>,
-        temperature: Option<f32>,
-        max_tokens: Option<u32>,
+        options: GenerationOptions,
+        tools: Option<Vec<ToolSpec>>,
+        tool_choice: Option<ToolChoice>,
     ) -> Result<ChatMessage> {
-        self.chat_completion(messages, model, temperature, max_tokens).await
+        self.chat_completion(messages, model, options, tools, tool_choice).await
     }
 
     async fn chat_completion_stream(
         &self,
         messages: Vec<ChatMessage>,
         model: Option<String>,
-        temperature: Option<f32>,
-        max_tokens: Option<u32>,
-    ) -> BoxStream<'static, Result<UIMessageChunk, anyhow::Error>> {
-        self.chat_completion_stream(messages, model, temperature, max_tokens).await
+        options: GenerationOptions,
+        tools: Option<Vec<ToolSpec>>,
+        tool_choice: Option<ToolChoice>,
+    ) -> Result<ChunkStream> {
+        self.chat_completion_stream(messages, model, options, tools, tool_choice).await
     }
 
     fn get_available_models(&self) -> Vec<&'static str> {
         Self::get_model_options()
     }
+
+    async fn discover_models(&self) -> Result<Vec<ModelInfo>> {
+        let builder = self
+            .client
+            .get(format!("{}/v1/models?limit=1000", self.base_url))
+            .header("x-api-key", &self.api_key);
+        let response = self.retry.send("Anthropic", builder).await?;
+        let list: AnthropicModelList = response.json().await?;
+
+        Ok(list
+            .data
+            .into_iter()
+            .map(|model| ModelInfo {
+                display_name: model.display_name,
+                ..ModelInfo::new(model.id)
+            })
+            .collect())
+    }
+
+    fn unsupported_options(&self, _model: &str, options: &GenerationOptions) -> Vec<&'static str> {
+        let mut unsupported = options.set_among(&[
+            "frequency_penalty",
+            "presence_penalty",
+            "seed",
+            "logit_bias",
+            "candidate_count",
+            "safety_settings",
+        ]);
+        // Extended thinking clears these, see `apply_thinking`
+        if self.thinking_budget.is_some() {
+            unsupported.extend(options.set_among(&["temperature", "top_k"]));
+        }
+        unsupported
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::providers::testing::{message, serve, user};
 
     #[test]
     fn test_model_options() {
@@ -404,22 +988,8 @@ mod tests {
     #[test]
     fn test_convert_messages() {
         let messages = vec![
-            ChatMessage {
-                id: "1".to_string(),
-                role: ChatRole::User,
-                content: "Hello".to_string(),
-                created_at: None,
-                attachments: None,
-                metadata: None,
-            },
-            ChatMessage {
-                id: "2".to_string(),
-                role: ChatRole::Assistant,
-                content: "Hi there!".to_string(),
-                created_at: None,
-                attachments: None,
-                metadata: None,
-            },
+            user("Hello"),
+            message(ChatRole::Assistant, "Hi there!"),
         ];
 
         let anthropic_messages = AnthropicService::convert_to_anthropic_messages(&messages);
@@ -431,22 +1001,8 @@ mod tests {
     #[test]
     fn test_extract_system_message() {
         let messages = vec![
-            ChatMessage {
-                id: "1".to_string(),
-                role: ChatRole::System,
-                content: "You are a helpful assistant.".to_string(),
-                created_at: None,
-                attachments: None,
-                metadata: None,
-            },
-            ChatMessage {
-                id: "2".to_string(),
-                role: ChatRole::User,
-                content: "Hello".to_string(),
-                created_at: None,
-                attachments: None,
-                metadata: None,
-            },
+            message(ChatRole::System, "You are a helpful assistant."),
+            user("Hello"),
         ];
 
         let (system_msg, filtered) = AnthropicService::extract_system_message(&messages);
@@ -463,4 +1019,408 @@ mod tests {
         assert!(models.iter().any(|&m| m.contains("haiku")));
         assert!(models.iter().any(|&m| m.contains("opus")));
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_tool_messages_become_content_blocks() {
+        let messages = vec![
+            ChatMessage {
+                metadata: Some(HashMap::from([(
+                    TOOL_CALLS_KEY.to_string(),
+                    serde_json::json!([
+                        {"id": "toolu_1", "name": "calculator", "arguments": {"expression": "2 + 2"}},
+                        {"id": "toolu_2", "name": "web_search", "arguments": {"query": "rust"}}
+                    ]),
+                )])),
+                ..message(ChatRole::Assistant, "")
+            },
+            ChatMessage {
+                metadata: Some(HashMap::from([(TOOL_CALL_ID_KEY.to_string(), serde_json::json!("toolu_1"))])),
+                ..message(ChatRole::Tool, "{\"result\":4}")
+            },
+            ChatMessage {
+                metadata: Some(HashMap::from([(TOOL_CALL_ID_KEY.to_string(), serde_json::json!("toolu_2"))])),
+                ..message(ChatRole::Tool, "{\"results\":[]}")
+            },
+        ];
+
+        let anthropic_messages = AnthropicService::convert_to_anthropic_messages(&messages);
+        assert_eq!(anthropic_messages.len(), 2);
+
+        let assistant = serde_json::to_value(&anthropic_messages[0]).unwrap();
+        assert_eq!(assistant["content"][0]["type"], "tool_use");
+        assert_eq!(assistant["content"][0]["input"]["expression"], "2 + 2");
+
+        let results = serde_json::to_value(&anthropic_messages[1]).unwrap();
+        assert_eq!(results["role"], "user");
+        assert_eq!(results["content"][0]["type"], "tool_result");
+        assert_eq!(results["content"][1]["tool_use_id"], "toolu_2");
+    }
+
+    #[test]
+    fn test_parse_tool_use_response() {
+        let response: AnthropicResponse = serde_json::from_value(serde_json::json!({
+            "id": "msg_1",
+            "type": "message",
+            "role": "assistant",
+            "content": [
+                {"type": "text", "text": "Let me calculate."},
+                {"type": "tool_use", "id": "toolu_1", "name": "calculator", "input": {"expression": "2 + 2"}}
+            ],
+            "model": "claude-3-5-sonnet-20241022",
+            "stop_reason": "tool_use",
+            "stop_sequence": null,
+            "usage": {"input_tokens": 10, "output_tokens": 20}
+        }))
+        .unwrap();
+
+        assert!(matches!(response.content[0], AnthropicContent::Text { .. }));
+        assert!(matches!(
+            &response.content[1],
+            AnthropicContent::ToolUse { name, .. } if name == "calculator"
+        ));
+    }
+
+    #[test]
+    fn test_parse_stream_usage() {
+        let start: AnthropicStreamChunk = serde_json::from_str(
+            r#"{"type":"message_start","message":{"id":"msg_1","type":"message","role":"assistant","content":[],"model":"claude-3-5-haiku-20241022","usage":{"input_tokens":25,"output_tokens":1}}}"#,
+        )
+        .unwrap();
+        let delta: AnthropicStreamChunk = serde_json::from_str(
+            r#"{"type":"message_delta","delta":{"stop_reason":"end_turn","stop_sequence":null},"usage":{"output_tokens":15}}"#,
+        )
+        .unwrap();
+
+        let mut usage = start.message.and_then(|m| m.usage).unwrap();
+        usage.output_tokens = delta.usage.unwrap().output_tokens;
+        let usage = usage.to_usage();
+        assert_eq!(usage.prompt_tokens, 25);
+        assert_eq!(usage.completion_tokens, 15);
+        assert_eq!(usage.total_tokens, 40);
+    }
+
+    #[tokio::test]
+    async fn test_stream_cut_off_before_message_stop_is_an_error() {
+        let body = concat!(
+            "event: message_start\n",
+            "data: {\"type\":\"message_start\",\"message\":{\"usage\":{\"input_tokens\":10,\"output_tokens\":1}}}\n\n",
+            "event: content_block_delta\n",
+            "data: {\"type\":\"content_block_delta\",\"index\":0,\"delta\":{\"type\":\"text_delta\",\"text\":\"The answer is\"}}\n\n",
+        );
+        let app = axum::Router::new().route("/v1/messages", axum::routing::post(move || async move { body }));
+        let url = serve(app).await;
+
+        let mut service = AnthropicService::new("key".to_string(), None);
+        service.base_url = url;
+        let results: Vec<Result<UIMessageChunk>> = service
+            .chat_completion_stream(vec![user("2 + 2?")], None, GenerationOptions::default(), None, None)
+            .await
+            .unwrap()
+            .collect()
+            .await;
+
+        assert!(results.iter().all(|chunk| !matches!(chunk, Ok(UIMessageChunk::Finish { .. }))));
+        let error = results.last().unwrap().as_ref().unwrap_err();
+        assert_eq!(error.to_string(), "Anthropic stream ended before message_stop");
+    }
+
+    #[test]
+    fn test_thinking_is_parsed_and_sent_back_signed() {
+        let response: AnthropicResponse = serde_json::from_str(
+            r#"{"id":"msg_1","type":"message","role":"assistant","model":"claude-sonnet-4-5","stop_reason":"tool_use","stop_sequence":null,
+                "content":[
+                    {"type":"thinking","thinking":"Need the calculator.","signature":"EqQBCgIYAhIM"},
+                    {"type":"redacted_thinking","data":"EmwKAhgBEgy3va3pzix"},
+                    {"type":"tool_use","id":"toolu_1","name":"calculator","input":{"expression":"2 + 2"}}
+                ]}"#,
+        )
+        .unwrap();
+        assert!(matches!(&response.content[0], AnthropicContent::Thinking { signature, .. } if signature == "EqQBCgIYAhIM"));
+        assert!(matches!(&response.content[1], AnthropicContent::RedactedThinking { .. }));
+
+        let delta: AnthropicStreamChunk = serde_json::from_str(
+            r#"{"type":"content_block_delta","index":0,"delta":{"type":"thinking_delta","thinking":"Let me"}}"#,
+        )
+        .unwrap();
+        assert_eq!(delta.delta.unwrap().thinking.as_deref(), Some("Let me"));
+
+        let reasoning = vec![
+            Reasoning { text: "Need the calculator.".to_string(), signature: Some("EqQBCgIYAhIM".to_string()), redacted: None },
+            Reasoning::new("Unsigned reasoning from another provider".to_string()),
+        ];
+        let mut metadata = HashMap::new();
+        Reasoning::insert_into(&reasoning, &mut metadata);
+        let messages = vec![ChatMessage {
+            metadata: Some(metadata),
+            ..message(ChatRole::Assistant, "Calculating.")
+        }];
+
+        let converted = serde_json::to_value(AnthropicService::convert_to_anthropic_messages(&messages)).unwrap();
+        assert_eq!(
+            converted[0]["content"],
+            serde_json::json!([
+                {"type": "thinking", "thinking": "Need the calculator.", "signature": "EqQBCgIYAhIM"},
+                {"type": "text", "text": "Calculating."}
+            ])
+        );
+    }
+
+    #[test]
+    fn test_unset_temperature_is_left_to_the_api() {
+        let request = AnthropicService::build_request(
+            "claude-sonnet-4-5".to_string(),
+            vec![],
+            None,
+            GenerationOptions::default(),
+            None,
+            None,
+            false,
+        );
+        let body = serde_json::to_value(&request).unwrap();
+        assert!(body.get("temperature").is_none());
+    }
+
+    #[test]
+    fn test_thinking_budget_replaces_temperature() {
+        let mut service = AnthropicService::new("key".to_string(), None);
+        service.thinking_budget = Some(2048);
+        let options = GenerationOptions {
+            top_k: Some(40),
+            ..GenerationOptions::sampling(Some(0.7), Some(1000))
+        };
+        let mut request =
+            AnthropicService::build_request("claude-sonnet-4-5".to_string(), vec![], None, options.clone(), None, None, false);
+        service.apply_thinking(&mut request);
+
+        let body = serde_json::to_value(&request).unwrap();
+        assert_eq!(body["thinking"], serde_json::json!({"type": "enabled", "budget_tokens": 2048}));
+        assert_eq!(body["max_tokens"], 3048);
+        assert!(body.get("temperature").is_none());
+        assert!(body.get("top_k").is_none());
+        assert_eq!(service.unsupported_options("", &options), vec!["temperature", "top_k"]);
+
+        // A budget on top of a huge max_tokens saturates instead of overflowing
+        let options = GenerationOptions::sampling(None, Some(u32::MAX));
+        let mut request =
+            AnthropicService::build_request("claude-sonnet-4-5".to_string(), vec![], None, options, None, None, false);
+        service.apply_thinking(&mut request);
+        assert_eq!(request.max_tokens, u32::MAX);
+    }
+
+    #[test]
+    fn test_prompt_caching_marks_stable_prefix() {
+        let calculator = ToolSpec {
+            name: "calculator".to_string(),
+            description: "Evaluate an expression".to_string(),
+            parameters: serde_json::json!({"type": "object"}),
+        };
+        let message = |role, content: &str, metadata| ChatMessage {
+            id: "1".to_string(),
+            role,
+            content: content.to_string(),
+            created_at: None,
+            attachments: None,
+            metadata,
+        };
+        let messages = vec![
+            message(ChatRole::User, "What is 2 + 2?", None),
+            message(
+                ChatRole::Assistant,
+                "",
+                Some(HashMap::from([(
+                    TOOL_CALLS_KEY.to_string(),
+                    serde_json::json!([{"id": "toolu_1", "name": "calculator", "arguments": {"expression": "2 + 2"}}]),
+                )])),
+            ),
+            message(
+                ChatRole::Tool,
+                "4",
+                Some(HashMap::from([(TOOL_CALL_ID_KEY.to_string(), serde_json::json!("toolu_1"))])),
+            ),
+        ];
+        let mut request = AnthropicService::build_request(
+            "claude-sonnet-4-5".to_string(),
+            AnthropicService::convert_to_anthropic_messages(&messages),
+            Some("You are a helpful agent.".to_string()),
+            GenerationOptions::default(),
+            Some(vec![calculator]),
+            None,
+            false,
+        );
+        AnthropicService::new("key".to_string(), None).apply_prompt_caching(&mut request);
+
+        let body = serde_json::to_value(&request).unwrap();
+        let ephemeral = serde_json::json!({"type": "ephemeral"});
+        assert_eq!(body["tools"][0]["cache_control"], ephemeral);
+        assert_eq!(body["system"][0]["text"], "You are a helpful agent.");
+        assert_eq!(body["system"][0]["cache_control"], ephemeral);
+        // The latest tool result and the question before it end cached prefixes
+        assert_eq!(body["messages"][0]["content"][0]["cache_control"], ephemeral);
+        assert!(body["messages"][1]["content"][0].get("cache_control").is_none());
+        assert_eq!(body["messages"][2]["content"][0]["cache_control"], ephemeral);
+
+        let usage: AnthropicUsage = serde_json::from_str(
+            r#"{"input_tokens":12,"cache_creation_input_tokens":0,"cache_read_input_tokens":2048,"output_tokens":30}"#,
+        )
+        .unwrap();
+        let usage = usage.to_usage();
+        assert_eq!(usage.prompt_tokens, 2060);
+        assert_eq!(usage.cache_read_input_tokens, Some(2048));
+        assert_eq!(usage.total_tokens, 2090);
+    }
+
+    #[test]
+    fn test_generation_options_map_to_request() {
+        let options = GenerationOptions {
+            top_p: Some(0.9),
+            top_k: Some(40),
+            stop: Some(vec!["END".to_string()]),
+            seed: Some(7),
+            user: Some("user-1".to_string()),
+            ..Default::default()
+        };
+        let service = AnthropicService::new("key".to_string(), None);
+        assert_eq!(service.unsupported_options("", &options), vec!["seed"]);
+
+        let request = AnthropicService::build_request(
+            "claude-sonnet-4-5".to_string(),
+            vec![],
+            None,
+            options,
+            None,
+            None,
+            false,
+        );
+        let body = serde_json::to_value(&request).unwrap();
+        assert_eq!(body["max_tokens"], 4096);
+        assert_eq!(body["top_k"], 40);
+        assert_eq!(body["stop_sequences"], serde_json::json!(["END"]));
+        assert_eq!(body["metadata"]["user_id"], "user-1");
+        assert!(body.get("seed").is_none());
+    }
+
+    fn answer_format() -> GenerationOptions {
+        serde_json::from_value(serde_json::json!({
+            "response_format": {
+                "type": "json_schema",
+                "json_schema": {"name": "answer", "schema": {"type": "object", "properties": {"answer": {"type": "integer"}}}}
+            }
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_json_schema_forces_the_response_tool() {
+        let request =
+            AnthropicService::build_request("claude-sonnet-4-5".to_string(), vec![], None, answer_format(), None, None, false);
+        let body = serde_json::to_value(&request).unwrap();
+        assert_eq!(body["tools"][0]["name"], "answer");
+        assert_eq!(body["tools"][0]["input_schema"]["properties"]["answer"]["type"], "integer");
+        assert_eq!(body["tool_choice"], serde_json::json!({"type": "tool", "name": "answer"}));
+
+        let calculator = ToolSpec {
+            name: "calculator".to_string(),
+            description: "Evaluate an expression".to_string(),
+            parameters: serde_json::json!({"type": "object"}),
+        };
+        let mut request = AnthropicService::build_request(
+            "claude-sonnet-4-5".to_string(),
+            vec![],
+            None,
+            answer_format(),
+            Some(vec![calculator]),
+            None,
+            false,
+        );
+        let body = serde_json::to_value(&request).unwrap();
+        assert_eq!(body["tools"][1]["name"], "answer");
+        assert_eq!(body["tool_choice"], serde_json::json!({"type": "any"}));
+
+        let mut service = AnthropicService::new("key".to_string(), None);
+        service.thinking_budget = Some(2048);
+        service.apply_thinking(&mut request);
+        assert_eq!(request.tool_choice, Some(serde_json::json!({"type": "auto"})));
+    }
+
+    #[tokio::test]
+    async fn test_response_tool_input_is_the_answer() {
+        let body = serde_json::json!({
+            "id": "msg_1",
+            "type": "message",
+            "role": "assistant",
+            "model": "claude-sonnet-4-5",
+            "content": [
+                {"type": "text", "text": "Here is the answer:"},
+                {"type": "tool_use", "id": "toolu_1", "name": "answer", "input": {"answer": 4}}
+            ],
+            "stop_reason": "tool_use",
+            "usage": {"input_tokens": 10, "output_tokens": 5}
+        });
+        let app = axum::Router::new().route(
+            "/v1/messages",
+            axum::routing::post(move || async move { axum::Json(body) }),
+        );
+        let url = serve(app).await;
+
+        let mut service = AnthropicService::new("key".to_string(), None);
+        service.base_url = url;
+        let messages = vec![user("2 + 2?")];
+        let message = service.chat_completion(messages, None, answer_format(), None, None).await.unwrap();
+
+        assert_eq!(message.content, r#"{"answer":4}"#);
+        let metadata = message.metadata.unwrap();
+        assert!(!metadata.contains_key(TOOL_CALLS_KEY));
+        assert_eq!(metadata["finish_reason"], "end_turn");
+    }
+
+    #[test]
+    fn test_image_attachments_become_base64_blocks() {
+        let messages = vec![ChatMessage {
+            attachments: Some(vec![
+                crate::chat::Attachment {
+                    attachment_type: "file".to_string(),
+                    url: "data:image/png;base64,iVBORw0KGgo=".to_string(),
+                    media_type: Some("image/png".to_string()),
+                    filename: Some("screenshot.png".to_string()),
+                },
+                crate::chat::Attachment {
+                    attachment_type: "file".to_string(),
+                    url: "https://example.com/cat.jpg".to_string(),
+                    media_type: None,
+                    filename: None,
+                },
+            ]),
+            ..user("What is in this picture?")
+        }];
+
+        let json = serde_json::to_value(AnthropicService::convert_to_anthropic_messages(&messages)).unwrap();
+        let content = &json[0]["content"];
+        assert_eq!(content[0]["type"], "image");
+        assert_eq!(content[0]["source"]["type"], "base64");
+        assert_eq!(content[0]["source"]["media_type"], "image/png");
+        assert_eq!(content[0]["source"]["data"], "iVBORw0KGgo=");
+        // Anthropic downloads remote images itself
+        assert_eq!(content[1]["source"], serde_json::json!({"type": "url", "url": "https://example.com/cat.jpg"}));
+        assert_eq!(content[2]["type"], "text");
+    }
+
+    #[test]
+    fn test_pdf_attachments_become_document_blocks() {
+        let messages = vec![ChatMessage {
+            attachments: Some(vec![crate::chat::Attachment {
+                attachment_type: "file".to_string(),
+                url: "data:application/pdf;base64,JVBERi0xLjQ=".to_string(),
+                media_type: Some("application/pdf".to_string()),
+                filename: Some("spec.pdf".to_string()),
+            }]),
+            ..user("Summarize this spec")
+        }];
+
+        let json = serde_json::to_value(AnthropicService::convert_to_anthropic_messages(&messages)).unwrap();
+        let content = &json[0]["content"];
+        assert_eq!(content[0]["type"], "document");
+        assert_eq!(content[0]["source"]["media_type"], "application/pdf");
+        assert_eq!(content[0]["title"], "spec.pdf");
+        assert_eq!(content[1]["text"], "Summarize this spec");
+    }
+}
@@ -0,0 +1,697 @@
+//! Message attachment helpers shared by the providers
+//!
+//! Attachments arrive as AI SDK file parts: a `url` that is either a `data:` URL or a remote
+//! `http(s)` URL, plus an optional media type. Providers that only accept inline base64 data
+//! call [`inline_remote_attachments`] first so their converters only ever see `data:` URLs.
+//!
+//! Downloading a URL a client sent is a request made from the server, so it is opt-in: only
+//! hosts listed in `ATTACHMENT_FETCH_HOSTS` are fetched, and only when they resolve to public
+//! addresses. Downloads are cached for a while, since every request resends the history.
+//!
+//! PDFs go natively to providers that accept documents (Anthropic, Gemini). For the rest,
+//! [`inline_document_text`] extracts their text and appends it to the message.
+
+use anyhow::{anyhow, Result};
+use base64::{engine::general_purpose::STANDARD, Engine as _};
+use reqwest::{Client, Response};
+use sha2::{Digest, Sha256};
+use std::borrow::Cow;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant};
+use url::{Host, Url};
+
+use crate::chat::{Attachment, ChatMessage};
+
+/// Largest remote attachment we are willing to download and inline
+const MAX_ATTACHMENT_BYTES: usize = 20 * 1024 * 1024;
+
+/// Most characters of a document's text inlined into a message, so that one large PDF
+/// doesn't push the conversation past the model's context window
+const MAX_DOCUMENT_CHARS: usize = 100_000;
+
+/// How long an attachment download may take
+const FETCH_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Downloaded attachments, by URL
+static FETCHED: Cache<InlineData> = Cache::new(Duration::from_secs(600), 64 * 1024 * 1024);
+
+/// Text extracted from PDFs, or why it could not be, by hash of the document
+static EXTRACTED: Cache<std::result::Result<String, String>> = Cache::new(Duration::from_secs(600), 16 * 1024 * 1024);
+
+/// Base64-encoded attachment data with its media type
+#[derive(Debug, Clone, PartialEq)]
+pub struct InlineData {
+    pub media_type: String,
+    pub data: String,
+}
+
+/// Split a `data:<media type>;base64,<data>` URL into its parts
+pub fn parse_data_url(url: &str) -> Option<InlineData> {
+    let rest = url.strip_prefix("data:")?;
+    let (header, data) = rest.split_once(',')?;
+    let media_type = header.strip_suffix(";base64")?;
+
+    Some(InlineData {
+        media_type: if media_type.is_empty() {
+            "application/octet-stream".to_string()
+        } else {
+            media_type.to_string()
+        },
+        data: data.to_string(),
+    })
+}
+
+/// Best-effort media type of an attachment, from its declared type, data URL or file extension
+pub fn media_type(attachment: &Attachment) -> Option<String> {
+    if let Some(media_type) = attachment.media_type.as_ref().filter(|m| !m.is_empty()) {
+        return Some(media_type.clone());
+    }
+    if let Some(inline) = parse_data_url(&attachment.url) {
+        return Some(inline.media_type);
+    }
+
+    let name = attachment.filename.as_deref().unwrap_or(&attachment.url);
+    let path = name.split(['?', '#']).next().unwrap_or(name);
+    let extension = path.rsplit_once('.')?.1.to_ascii_lowercase();
+    let media_type = match extension.as_str() {
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        "pdf" => "application/pdf",
+        _ => return None,
+    };
+    Some(media_type.to_string())
+}
+
+/// Whether the attachment is an image a vision model can look at
+pub fn is_image(attachment: &Attachment) -> bool {
+    attachment.attachment_type == "image"
+        || media_type(attachment).is_some_and(|m| m.starts_with("image/"))
+}
+
+/// Whether the attachment is a PDF document
+pub fn is_pdf(attachment: &Attachment) -> bool {
+    media_type(attachment).is_some_and(|m| m == "application/pdf")
+}
+
+/// Whether the attachment is an image or a PDF, the media providers can take inline
+pub fn is_media(attachment: &Attachment) -> bool {
+    is_image(attachment) || is_pdf(attachment)
+}
+
+/// The image attachments of a message, in order
+pub fn images(message: &ChatMessage) -> impl Iterator<Item = &Attachment> {
+    message.attachments.iter().flatten().filter(|a| is_image(a))
+}
+
+/// The PDF attachments of a message, in order
+pub fn documents(message: &ChatMessage) -> impl Iterator<Item = &Attachment> {
+    message.attachments.iter().flatten().filter(|a| is_pdf(a))
+}
+
+/// Append the text of each message's PDF attachments to its content, and drop the PDFs
+///
+/// Used by providers without native document input; remote PDFs are downloaded first.
+/// Extraction runs off the async runtime, once per document for as long as its text stays
+/// cached. Documents whose text cannot be extracted are still mentioned, so the model can
+/// tell the user it could not read them.
+pub async fn inline_document_text(messages: Vec<ChatMessage>) -> Result<Vec<ChatMessage>> {
+    let mut messages = inline_remote_attachments(messages, is_pdf).await?;
+
+    for message in &mut messages {
+        if documents(message).next().is_none() {
+            continue;
+        }
+        let (documents, others): (Vec<Attachment>, Vec<Attachment>) =
+            message.attachments.take().into_iter().flatten().partition(is_pdf);
+
+        for document in documents {
+            let name = document_name(document.filename.as_deref().unwrap_or("document.pdf"));
+            let text = match inline_data(&document) {
+                Some(inline) => document_text(inline.data).await,
+                None => Err(anyhow!("document was not inlined")),
+            };
+
+            if !message.content.is_empty() {
+                message.content.push_str("\n\n");
+            }
+            match text {
+                Ok(text) => {
+                    message.content.push_str(&format!(
+                        "<document name=\"{}\">\n{}\n</document>",
+                        name,
+                        truncate_document(text.trim())
+                    ));
+                }
+                Err(e) => {
+                    message.content.push_str(&format!("[Attached document {} could not be read: {}]", name, e));
+                }
+            }
+        }
+        message.attachments = (!others.is_empty()).then_some(others);
+    }
+
+    Ok(messages)
+}
+
+/// A client-supplied file name made safe to quote in a `<document name="...">` tag: control
+/// characters become spaces and markup characters are escaped
+fn document_name(filename: &str) -> String {
+    filename
+        .chars()
+        .map(|c| match c {
+            '&' => "&amp;".to_string(),
+            '"' => "&quot;".to_string(),
+            '<' => "&lt;".to_string(),
+            '>' => "&gt;".to_string(),
+            c if c.is_control() => " ".to_string(),
+            c => c.to_string(),
+        })
+        .collect()
+}
+
+/// `text` cut to [`MAX_DOCUMENT_CHARS`], marked as truncated if it was longer
+fn truncate_document(text: &str) -> Cow<'_, str> {
+    match text.char_indices().nth(MAX_DOCUMENT_CHARS) {
+        Some((end, _)) => Cow::Owned(format!("{}\n[truncated]", &text[..end])),
+        None => Cow::Borrowed(text),
+    }
+}
+
+/// The text of a base64-encoded PDF, extracted on a blocking thread unless it is cached
+async fn document_text(data: String) -> Result<String> {
+    let key = format!("{:x}", Sha256::digest(data.as_bytes()));
+    if let Some(text) = EXTRACTED.get(&key) {
+        return text.map_err(|e| anyhow!(e));
+    }
+
+    let text = tokio::task::spawn_blocking(move || extract_pdf_text(&data))
+        .await
+        .map_err(|e| anyhow!("text extraction failed: {}", e))?;
+    let cached = match &text {
+        Ok(text) => Ok(text.clone()),
+        Err(e) => Err(e.to_string()),
+    };
+    let size = cached.as_ref().map_or_else(String::len, String::len);
+    EXTRACTED.insert(&key, size, cached);
+    text
+}
+
+/// Extract the text of a base64-encoded PDF
+///
+/// CPU-bound; async callers go through [`inline_document_text`].
+pub fn extract_pdf_text(data: &str) -> Result<String> {
+    let bytes = STANDARD
+        .decode(data.trim())
+        .map_err(|e| anyhow!("invalid base64 data: {}", e))?;
+
+    // pdf-extract panics on some malformed documents; treat that as an extraction failure.
+    // The panic hook still runs, so the panic message is printed to stderr all the same.
+    std::panic::catch_unwind(|| pdf_extract::extract_text_from_mem(&bytes))
+        .map_err(|_| anyhow!("malformed PDF"))?
+        .map_err(|e| anyhow!("failed to extract text: {}", e))
+}
+
+/// Inline data for an attachment that is already a `data:` URL
+///
+/// The declared media type wins over the one in the URL, since browsers often report
+/// `application/octet-stream` for pasted files.
+pub fn inline_data(attachment: &Attachment) -> Option<InlineData> {
+    let mut inline = parse_data_url(&attachment.url)?;
+    if let Some(media_type) = attachment.media_type.as_ref().filter(|m| !m.is_empty()) {
+        inline.media_type = media_type.clone();
+    }
+    Some(inline)
+}
+
+/// Whether the attachment is a remote `http(s)` URL rather than inline data
+pub fn is_remote(attachment: &Attachment) -> bool {
+    attachment.url.starts_with("http://") || attachment.url.starts_with("https://")
+}
+
+/// Download the remote attachments selected by `wanted` and rewrite them as `data:` URLs
+///
+/// Fails for a URL the [`FetchPolicy`] refuses, telling the client to send the file inline.
+pub async fn inline_remote_attachments(
+    mut messages: Vec<ChatMessage>,
+    wanted: impl Fn(&Attachment) -> bool,
+) -> Result<Vec<ChatMessage>> {
+    for message in &mut messages {
+        for attachment in message.attachments.iter_mut().flatten() {
+            if is_remote(attachment) && wanted(attachment) {
+                let inline = match FETCHED.get(&attachment.url) {
+                    Some(inline) => inline,
+                    None => {
+                        let inline = fetch_attachment(FetchPolicy::get(), attachment, MAX_ATTACHMENT_BYTES).await?;
+                        FETCHED.insert(&attachment.url, inline.data.len(), inline.clone());
+                        inline
+                    }
+                };
+                attachment.url = format!("data:{};base64,{}", inline.media_type, inline.data);
+                attachment.media_type = Some(inline.media_type);
+            }
+        }
+    }
+    Ok(messages)
+}
+
+/// The hosts remote attachments may be downloaded from, read from `ATTACHMENT_FETCH_HOSTS`
+///
+/// A comma-separated list of host names, where `*.example.com` matches any subdomain of
+/// `example.com` and `*` matches any host. Nothing is downloaded when it is unset. Hosts that
+/// resolve to loopback, private, link-local or other non-public addresses are always refused.
+#[derive(Debug, Clone, Default)]
+pub struct FetchPolicy {
+    hosts: Vec<String>,
+}
+
+impl FetchPolicy {
+    pub fn new(hosts: &str) -> Self {
+        Self {
+            hosts: hosts
+                .split(',')
+                .map(|host| host.trim().trim_end_matches('.').to_ascii_lowercase())
+                .filter(|host| !host.is_empty())
+                .collect(),
+        }
+    }
+
+    pub fn from_env() -> Self {
+        Self::new(&std::env::var("ATTACHMENT_FETCH_HOSTS").unwrap_or_default())
+    }
+
+    /// The policy of this process, read from the environment once
+    fn get() -> &'static Self {
+        static POLICY: OnceLock<FetchPolicy> = OnceLock::new();
+        POLICY.get_or_init(Self::from_env)
+    }
+
+    fn allows_host(&self, host: &str) -> bool {
+        let host = host.trim_end_matches('.').to_ascii_lowercase();
+        self.hosts.iter().any(|allowed| match allowed.strip_prefix("*") {
+            Some("") => true,
+            Some(suffix) if suffix.starts_with('.') => host.ends_with(suffix),
+            _ => *allowed == host,
+        })
+    }
+}
+
+/// Whether `ip` is a public unicast address, as opposed to loopback, private, link-local,
+/// shared, reserved, documentation or multicast space
+fn is_public_ip(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, c, _] = ip.octets();
+            !(ip.is_unspecified()
+                || ip.is_loopback()
+                || ip.is_private()
+                || ip.is_link_local()
+                || ip.is_broadcast()
+                || ip.is_documentation()
+                || ip.is_multicast()
+                || a == 0
+                || (a == 100 && (64..128).contains(&b))
+                || (a == 192 && b == 0 && c == 0)
+                || (a == 198 && (b == 18 || b == 19))
+                || a >= 240)
+        }
+        IpAddr::V6(ip) => {
+            let segments = ip.segments();
+            // IPv4-mapped and NAT64 addresses reach the IPv4 address they embed
+            let embedded = ip.to_ipv4_mapped().or_else(|| {
+                (segments[..6] == [0x64, 0xff9b, 0, 0, 0, 0]).then(|| {
+                    let [.., a, b, c, d] = ip.octets();
+                    std::net::Ipv4Addr::new(a, b, c, d)
+                })
+            });
+            match embedded {
+                Some(ip) => is_public_ip(IpAddr::V4(ip)),
+                None => !(ip.is_unspecified()
+                    || ip.is_loopback()
+                    || ip.is_multicast()
+                    || (segments[0] & 0xfe00) == 0xfc00
+                    || (segments[0] & 0xffc0) == 0xfe80
+                    || (segments[0] == 0x2001 && segments[1] == 0x0db8)),
+            }
+        }
+    }
+}
+
+async fn fetch_attachment(policy: &FetchPolicy, attachment: &Attachment, limit: usize) -> Result<InlineData> {
+    let url = Url::parse(&attachment.url).map_err(|e| anyhow!("Invalid attachment URL {}: {}", attachment.url, e))?;
+    let host = url
+        .host()
+        .ok_or_else(|| anyhow!("Invalid attachment URL {}: no host", attachment.url))?;
+    let host_name = match &host {
+        Host::Domain(domain) => domain.to_string(),
+        Host::Ipv4(ip) => ip.to_string(),
+        Host::Ipv6(ip) => ip.to_string(),
+    };
+    if !policy.allows_host(&host_name) {
+        return Err(anyhow!(
+            "Attachment {} is not on a host the server may download from; send the file inline as a data: URL",
+            attachment.url
+        ));
+    }
+
+    // Check every address the host resolves to, and connect only to those
+    let port = url.port_or_known_default().unwrap_or(443);
+    let pinned = matches!(host, Host::Domain(_));
+    let addresses: Vec<SocketAddr> = match host {
+        Host::Domain(domain) => tokio::net::lookup_host((domain, port))
+            .await
+            .map_err(|e| anyhow!("Failed to fetch attachment {}: {}", attachment.url, e))?
+            .collect(),
+        Host::Ipv4(ip) => vec![SocketAddr::new(IpAddr::V4(ip), port)],
+        Host::Ipv6(ip) => vec![SocketAddr::new(IpAddr::V6(ip), port)],
+    };
+    if addresses.is_empty() || !addresses.iter().all(|address| is_public_ip(address.ip())) {
+        return Err(anyhow!("Attachment {} is not on a public address", attachment.url));
+    }
+    let mut builder = Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .timeout(FETCH_TIMEOUT);
+    if pinned {
+        builder = builder.resolve_to_addrs(&host_name, &addresses);
+    }
+    let client = builder.build()?;
+
+    let response = client
+        .get(url)
+        .send()
+        .await
+        .map_err(|e| anyhow!("Failed to fetch attachment {}: {}", attachment.url, e))?;
+    read_attachment(attachment, response, limit).await
+}
+
+/// The body of a successful download, refusing bodies over `limit` bytes without reading them
+async fn read_attachment(attachment: &Attachment, mut response: Response, limit: usize) -> Result<InlineData> {
+    if !response.status().is_success() {
+        return Err(anyhow!(
+            "Failed to fetch attachment {}: HTTP {}",
+            attachment.url,
+            response.status()
+        ));
+    }
+
+    let too_large = || anyhow!("Attachment {} is larger than {} bytes", attachment.url, limit);
+    if response.content_length().is_some_and(|length| length > limit as u64) {
+        return Err(too_large());
+    }
+
+    let header_type = response
+        .headers()
+        .get(reqwest::header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .map(|v| v.split(';').next().unwrap_or(v).trim().to_string())
+        .filter(|v| !v.is_empty() && v != "application/octet-stream");
+
+    let mut bytes = Vec::new();
+    while let Some(chunk) = response
+        .chunk()
+        .await
+        .map_err(|e| anyhow!("Failed to fetch attachment {}: {}", attachment.url, e))?
+    {
+        if bytes.len() + chunk.len() > limit {
+            return Err(too_large());
+        }
+        bytes.extend_from_slice(&chunk);
+    }
+
+    // Servers often label files generically, e.g. `text/plain`, so their Content-Type only
+    // wins over the declared type or file extension when it names media providers take
+    let media_type = header_type
+        .clone()
+        .filter(|header| header.starts_with("image/") || header == "application/pdf")
+        .or_else(|| media_type(attachment))
+        .or(header_type)
+        .unwrap_or_else(|| "application/octet-stream".to_string());
+
+    Ok(InlineData {
+        media_type,
+        data: STANDARD.encode(&bytes),
+    })
+}
+
+/// A small in-memory cache whose entries expire after `ttl`, dropping the oldest entries
+/// once their sizes add up to more than `max_bytes`
+struct Cache<V> {
+    ttl: Duration,
+    max_bytes: usize,
+    /// Entries in insertion order: key, time stored, size and value
+    entries: Mutex<Vec<(String, Instant, usize, V)>>,
+}
+
+impl<V: Clone> Cache<V> {
+    const fn new(ttl: Duration, max_bytes: usize) -> Self {
+        Self {
+            ttl,
+            max_bytes,
+            entries: Mutex::new(Vec::new()),
+        }
+    }
+
+    fn get(&self, key: &str) -> Option<V> {
+        let mut entries = self.entries.lock().unwrap();
+        entries.retain(|(_, stored_at, _, _)| stored_at.elapsed() < self.ttl);
+        entries.iter().find(|(k, ..)| k == key).map(|(.., value)| value.clone())
+    }
+
+    fn insert(&self, key: &str, size: usize, value: V) {
+        if size > self.max_bytes {
+            return;
+        }
+        let mut entries = self.entries.lock().unwrap();
+        entries.retain(|(k, ..)| k != key);
+        entries.push((key.to_string(), Instant::now(), size, value));
+        let mut total: usize = entries.iter().map(|(_, _, size, _)| size).sum();
+        while total > self.max_bytes {
+            total -= entries.remove(0).2;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::providers::testing::{serve, user};
+
+    fn attachment(url: &str, media_type: Option<&str>) -> Attachment {
+        Attachment {
+            attachment_type: "file".to_string(),
+            url: url.to_string(),
+            media_type: media_type.map(|m| m.to_string()),
+            filename: None,
+        }
+    }
+
+    #[test]
+    fn test_parse_data_url() {
+        let inline = parse_data_url("data:image/png;base64,iVBORw0KGgo=").unwrap();
+        assert_eq!(inline.media_type, "image/png");
+        assert_eq!(inline.data, "iVBORw0KGgo=");
+
+        assert!(parse_data_url("https://example.com/cat.png").is_none());
+        assert!(parse_data_url("data:text/plain,hello").is_none());
+    }
+
+    #[test]
+    fn test_image_detection() {
+        assert!(is_image(&attachment("data:image/jpeg;base64,AAAA", None)));
+        assert!(is_image(&attachment("https://example.com/cat.PNG?size=large", None)));
+        assert!(is_image(&attachment("https://example.com/photo", Some("image/webp"))));
+        assert!(!is_image(&attachment("https://example.com/report.pdf", None)));
+        assert!(!is_image(&attachment("data:application/pdf;base64,AAAA", None)));
+        assert!(is_pdf(&attachment("data:application/pdf;base64,AAAA", None)));
+        assert!(is_pdf(&attachment("https://example.com/spec.pdf", None)));
+    }
+
+    /// A one-page PDF showing `text`, with a correct cross-reference table
+    fn minimal_pdf(text: &str) -> Vec<u8> {
+        let stream = format!("BT /F1 24 Tf 72 720 Td ({}) Tj ET", text);
+        let objects = [
+            "<< /Type /Catalog /Pages 2 0 R >>".to_string(),
+            "<< /Type /Pages /Kids [3 0 R] /Count 1 >>".to_string(),
+            "<< /Type /Page /Parent 2 0 R /MediaBox [0 0 612 792] /Contents 4 0 R \
+             /Resources << /Font << /F1 5 0 R >> >> >>"
+                .to_string(),
+            format!("<< /Length {} >>\nstream\n{}\nendstream", stream.len(), stream),
+            "<< /Type /Font /Subtype /Type1 /BaseFont /Helvetica >>".to_string(),
+        ];
+
+        let mut pdf = String::from("%PDF-1.4\n");
+        let mut offsets = Vec::new();
+        for (i, object) in objects.iter().enumerate() {
+            offsets.push(pdf.len());
+            pdf.push_str(&format!("{} 0 obj\n{}\nendobj\n", i + 1, object));
+        }
+        let xref = pdf.len();
+        pdf.push_str(&format!("xref\n0 {}\n0000000000 65535 f \n", objects.len() + 1));
+        for offset in offsets {
+            pdf.push_str(&format!("{:010} 00000 n \n", offset));
+        }
+        pdf.push_str(&format!(
+            "trailer\n<< /Size {} /Root 1 0 R >>\nstartxref\n{}\n%%EOF\n",
+            objects.len() + 1,
+            xref
+        ));
+        pdf.into_bytes()
+    }
+
+    #[tokio::test]
+    async fn test_pdf_text_is_inlined() {
+        let data = STANDARD.encode(minimal_pdf("Quarterly revenue grew"));
+        let message = ChatMessage {
+            attachments: Some(vec![
+                Attachment {
+                    filename: Some("report.pdf".to_string()),
+                    ..attachment(&format!("data:application/pdf;base64,{}", data), None)
+                },
+                attachment("data:image/png;base64,iVBORw0KGgo=", None),
+            ]),
+            ..user("Summarize the spec")
+        };
+
+        let messages = inline_document_text(vec![message]).await.unwrap();
+        assert!(messages[0].content.contains("<document name=\"report.pdf\">"));
+        assert!(messages[0].content.contains("Quarterly revenue grew"));
+        // The PDF is replaced by its text; other attachments stay
+        let remaining = messages[0].attachments.as_ref().unwrap();
+        assert_eq!(remaining.len(), 1);
+        assert!(is_image(&remaining[0]));
+    }
+
+    #[test]
+    fn test_document_text_is_capped_and_names_escaped() {
+        let long = "a".repeat(MAX_DOCUMENT_CHARS + 10);
+        let truncated = truncate_document(&long);
+        assert!(truncated.ends_with("a\n[truncated]"));
+        assert_eq!(truncated.len(), MAX_DOCUMENT_CHARS + "\n[truncated]".len());
+        assert_eq!(truncate_document("short"), "short");
+
+        assert_eq!(document_name("a\"><b>&\nc.pdf"), "a&quot;&gt;&lt;b&gt;&amp; c.pdf");
+    }
+
+    #[tokio::test]
+    async fn test_unreadable_document_is_mentioned() {
+        let message = ChatMessage {
+            attachments: Some(vec![Attachment {
+                filename: Some("spec.pdf".to_string()),
+                ..attachment("data:application/pdf;base64,bm90IGEgcGRm", None)
+            }]),
+            ..user("Summarize the spec")
+        };
+
+        let messages = inline_document_text(vec![message]).await.unwrap();
+        assert!(messages[0].content.starts_with("Summarize the spec\n\n"));
+        assert!(messages[0].content.contains("[Attached document spec.pdf could not be read"));
+        assert!(messages[0].attachments.is_none());
+    }
+
+    #[test]
+    fn test_public_addresses() {
+        for ip in ["93.184.216.34", "8.8.8.8", "2606:4700::1111", "::ffff:8.8.8.8"] {
+            assert!(is_public_ip(ip.parse().unwrap()), "{} is public", ip);
+        }
+        for ip in [
+            "127.0.0.1",
+            "10.1.2.3",
+            "172.16.0.1",
+            "192.168.1.1",
+            "169.254.169.254",
+            "100.64.0.1",
+            "0.0.0.0",
+            "255.255.255.255",
+            "224.0.0.1",
+            "::1",
+            "::",
+            "fd00::1",
+            "fe80::1",
+            "::ffff:127.0.0.1",
+            "64:ff9b::a9fe:a9fe",
+        ] {
+            assert!(!is_public_ip(ip.parse().unwrap()), "{} is not public", ip);
+        }
+    }
+
+    #[test]
+    fn test_fetch_policy_hosts() {
+        let policy = FetchPolicy::new("cdn.example.com, *.images.example.org");
+        assert!(policy.allows_host("cdn.example.com"));
+        assert!(policy.allows_host("CDN.example.com."));
+        assert!(policy.allows_host("a.images.example.org"));
+        assert!(!policy.allows_host("images.example.org"));
+        assert!(!policy.allows_host("example.com"));
+        assert!(!policy.allows_host("evilcdn.example.com"));
+
+        assert!(FetchPolicy::new("*").allows_host("anything.test"));
+        assert!(!FetchPolicy::default().allows_host("cdn.example.com"));
+    }
+
+    #[tokio::test]
+    async fn test_refused_downloads() {
+        let cat = |url: &str| attachment(url, Some("image/png"));
+
+        let error = fetch_attachment(&FetchPolicy::default(), &cat("https://cdn.example.com/cat.png"), 10)
+            .await
+            .unwrap_err();
+        assert!(error.to_string().contains("send the file inline as a data: URL"));
+
+        let any = FetchPolicy::new("*");
+        for url in ["http://127.0.0.1:9/cat.png", "http://169.254.169.254/latest/meta-data/", "http://[::1]:9/cat.png", "http://localhost:9/cat.png"] {
+            let error = fetch_attachment(&any, &cat(url), 10).await.unwrap_err();
+            assert!(error.to_string().contains("is not on a public address"), "{}: {}", url, error);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_downloads_stop_at_the_size_limit() {
+        let app = axum::Router::new()
+            .route("/small.png", axum::routing::get(|| async { "12345" }))
+            .route(
+                "/photo",
+                axum::routing::get(|| async { ([(axum::http::header::CONTENT_TYPE, "image/jpeg")], "12345") }),
+            )
+            .route("/large.png", axum::routing::get(|| async { "0123456789abcdef" }))
+            .route(
+                "/unsized.png",
+                axum::routing::get(|| async {
+                    let chunks = futures::stream::iter(["01234567", "89abcdef"].map(Ok::<_, std::io::Error>));
+                    axum::body::Body::from_stream(chunks)
+                }),
+            );
+        let url = serve(app).await;
+        let download = |name: &str| {
+            let attachment = attachment(&format!("{}/{}", url, name), None);
+            async move {
+                let response = reqwest::get(&attachment.url).await.unwrap();
+                read_attachment(&attachment, response, 10).await
+            }
+        };
+
+        let small = download("small.png").await.unwrap();
+        assert_eq!(small.data, STANDARD.encode("12345"));
+        // A generic Content-Type doesn't override the extension, but a media type fills one in
+        assert_eq!(small.media_type, "image/png");
+        assert_eq!(download("photo").await.unwrap().media_type, "image/jpeg");
+        for name in ["large.png", "unsized.png"] {
+            let error = download(name).await.unwrap_err();
+            assert!(error.to_string().contains("is larger than 10 bytes"), "{}: {}", name, error);
+        }
+    }
+
+    #[test]
+    fn test_cache_drops_the_oldest_entries_over_budget() {
+        let cache = Cache::new(Duration::from_secs(60), 10);
+        cache.insert("a", 4, 1);
+        cache.insert("b", 4, 2);
+        cache.insert("c", 4, 3);
+        cache.insert("huge", 11, 4);
+        assert_eq!(cache.get("a"), None);
+        assert_eq!(cache.get("b"), Some(2));
+        assert_eq!(cache.get("c"), Some(3));
+        assert_eq!(cache.get("huge"), None);
+    }
+}
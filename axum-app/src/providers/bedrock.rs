@@ -0,0 +1,1160 @@
+use anyhow::{anyhow, Result};
+use async_stream::stream;
+use async_trait::async_trait;
+use futures::StreamExt;
+use reqwest::{Client, RequestBuilder};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::time::Duration;
+use url::Url;
+
+use crate::chat::{ChatMessage, ChatRole, UIMessageChunk, Usage};
+use super::attachments::{self, InlineData};
+use super::decoder;
+use super::reasoning::{Reasoning, ReasoningStream};
+use super::sigv4::{self, AwsCredentials, SigV4Signer};
+use super::structured::{AnswerStream, ResponseTool};
+use super::tools::{ToolCallAccumulator, TOOL_CALLS_KEY, TOOL_CALL_ID_KEY};
+use super::{
+    AIProvider, ChunkStream, ContentFilterError, GenerationOptions, RetryPolicy, ToolCall,
+    ToolChoice, ToolSpec,
+};
+
+/// Model used when a request names none
+const DEFAULT_MODEL: &str = "anthropic.claude-3-5-sonnet-20240620-v1:0";
+
+/// Region used when none is configured
+const DEFAULT_REGION: &str = "us-east-1";
+
+/// Stop reasons of an answer stopped by a guardrail or the model's content filter
+const BLOCKED_STOP_REASONS: [&str; 2] = ["guardrail_intervened", "content_filtered"];
+
+/// AWS Bedrock Runtime service
+///
+/// Talks to every Bedrock chat model through the Converse API, with requests signed by
+/// SigV4. Models are addressed by model or inference profile id, e.g.
+/// `anthropic.claude-3-5-sonnet-20240620-v1:0` or `us.anthropic.claude-3-5-sonnet-20241022-v2:0`.
+#[derive(Debug)]
+pub struct BedrockService {
+    client: Client,
+    /// Client for streams, which can outlive the request timeout of `client`
+    stream_client: Client,
+    signer: SigV4Signer,
+    base_url: String,
+    default_model: String,
+    retry: RetryPolicy,
+}
+
+impl BedrockService {
+    pub fn new(credentials: AwsCredentials, region: String, default_model: Option<String>) -> Self {
+        let client = Client::builder()
+            .timeout(Duration::from_secs(60))
+            .build()
+            .expect("Failed to create HTTP client");
+
+        Self {
+            client,
+            stream_client: super::stream_client_builder()
+                .build()
+                .expect("Failed to create HTTP client"),
+            base_url: format!("https://bedrock-runtime.{}.amazonaws.com", region),
+            signer: SigV4Signer::new(credentials, region, "bedrock"),
+            default_model: default_model.unwrap_or_else(|| DEFAULT_MODEL.to_string()),
+            retry: RetryPolicy::from_env(),
+        }
+    }
+
+    /// Send requests to another endpoint, e.g. a VPC endpoint
+    pub fn with_base_url(mut self, base_url: String) -> Self {
+        self.base_url = base_url.trim_end_matches('/').to_string();
+        self
+    }
+
+    /// Create the service from the standard AWS credential variables
+    ///
+    /// The region comes from `AWS_REGION` or `AWS_DEFAULT_REGION`, the default model from
+    /// `BEDROCK_MODEL`, and `BEDROCK_BASE_URL` overrides the regional endpoint.
+    pub fn from_env() -> Result<Self> {
+        let credentials = AwsCredentials::from_env()?;
+        let region = std::env::var("AWS_REGION")
+            .or_else(|_| std::env::var("AWS_DEFAULT_REGION"))
+            .unwrap_or_else(|_| DEFAULT_REGION.to_string());
+        let model = std::env::var("BEDROCK_MODEL").ok();
+
+        let service = Self::new(credentials, region, model);
+        Ok(match std::env::var("BEDROCK_BASE_URL") {
+            Ok(base_url) => service.with_base_url(base_url),
+            Err(_) => service,
+        })
+    }
+
+    /// Get available Bedrock models
+    pub fn get_model_options() -> Vec<&'static str> {
+        vec![
+            "anthropic.claude-3-5-sonnet-20241022-v2:0",
+            "anthropic.claude-3-5-sonnet-20240620-v1:0",
+            "anthropic.claude-3-5-haiku-20241022-v1:0",
+            "anthropic.claude-3-opus-20240229-v1:0",
+            "anthropic.claude-3-haiku-20240307-v1:0",
+            "amazon.nova-pro-v1:0",
+            "amazon.nova-lite-v1:0",
+            "meta.llama3-1-70b-instruct-v1:0",
+            "mistral.mistral-large-2407-v1:0",
+        ]
+    }
+
+    /// A signed POST of `body` to the Converse API `action` for `model`
+    fn post(&self, client: &Client, model: &str, action: &str, body: &BedrockRequest) -> Result<RequestBuilder> {
+        // Model ids contain `:` and inference profile ARNs `/`, so the id is one encoded segment
+        let url = Url::parse(&format!(
+            "{}/model/{}/{}",
+            self.base_url,
+            sigv4::uri_encode(model, true),
+            action
+        ))?;
+        let body = serde_json::to_vec(body)?;
+        let signed = self.signer.sign(
+            "POST",
+            &url,
+            &[("content-type", "application/json")],
+            &body,
+            chrono::Utc::now(),
+        );
+
+        let mut builder = client.post(url).header("content-type", "application/json");
+        for (name, value) in signed {
+            builder = builder.header(name, value);
+        }
+        Ok(builder.body(body))
+    }
+
+    /// Whether `model` accepts top_k, which only Claude does on Bedrock
+    fn takes_top_k(model: &str) -> bool {
+        model.contains("anthropic.")
+    }
+
+    /// The tool a JSON answer is given through, since Converse has no response format
+    fn response_tool(options: &GenerationOptions) -> Option<BedrockToolSpec> {
+        ResponseTool::from_options(options).map(|tool| BedrockToolSpec {
+            name: tool.name,
+            description: tool.description,
+            input_schema: BedrockSchema { json: tool.input_schema },
+        })
+    }
+
+    /// Convert chat messages to Converse messages
+    ///
+    /// Converse wants user and assistant turns to alternate, so consecutive messages of one
+    /// role, such as the results of parallel tool calls, share a turn.
+    fn convert_messages(messages: &[ChatMessage]) -> Vec<BedrockMessage> {
+        let mut bedrock_messages: Vec<BedrockMessage> = Vec::new();
+
+        for msg in messages {
+            let (role, content) = match msg.role {
+                ChatRole::User => {
+                    let mut content: Vec<BedrockContent> = attachments::images(msg)
+                        .filter_map(attachments::inline_data)
+                        .filter_map(BedrockContent::image)
+                        .collect();
+                    content.extend(attachments::documents(msg).filter_map(|document| {
+                        attachments::inline_data(document)
+                            .map(|inline| BedrockContent::document(inline, document.filename.as_deref()))
+                    }));
+                    if !msg.content.is_empty() || content.is_empty() {
+                        content.push(BedrockContent::text(msg.content.clone()));
+                    }
+                    ("user", content)
+                }
+                ChatRole::Assistant => {
+                    // Only signed reasoning can be sent back
+                    let mut content: Vec<BedrockContent> = Reasoning::from_metadata(msg.metadata.as_ref())
+                        .unwrap_or_default()
+                        .into_iter()
+                        .filter_map(|part| match (part.signature, part.redacted) {
+                            (Some(signature), _) => Some(BedrockReasoningContent {
+                                reasoning_text: Some(BedrockReasoningText {
+                                    text: part.text,
+                                    signature: Some(signature),
+                                }),
+                                redacted_content: None,
+                            }),
+                            (None, Some(data)) => Some(BedrockReasoningContent {
+                                reasoning_text: None,
+                                redacted_content: Some(data),
+                            }),
+                            (None, None) => None,
+                        })
+                        .map(|reasoning| BedrockContent {
+                            reasoning_content: Some(reasoning),
+                            ..Default::default()
+                        })
+                        .collect();
+                    if !msg.content.is_empty() {
+                        content.push(BedrockContent::text(msg.content.clone()));
+                    }
+                    for call in ToolCall::from_metadata(msg.metadata.as_ref()).unwrap_or_default() {
+                        content.push(BedrockContent {
+                            tool_use: Some(BedrockToolUse {
+                                tool_use_id: call.id,
+                                name: call.name,
+                                input: call.arguments,
+                            }),
+                            ..Default::default()
+                        });
+                    }
+                    ("assistant", content)
+                }
+                ChatRole::Tool => {
+                    let tool_use_id = msg
+                        .metadata
+                        .as_ref()
+                        .and_then(|m| m.get(TOOL_CALL_ID_KEY))
+                        .and_then(|v| v.as_str())
+                        .unwrap_or_default()
+                        .to_string();
+                    let result = BedrockContent {
+                        tool_result: Some(BedrockToolResult {
+                            tool_use_id,
+                            content: vec![BedrockToolResultContent {
+                                text: msg.content.clone(),
+                            }],
+                        }),
+                        ..Default::default()
+                    };
+                    ("user", vec![result])
+                }
+                // Sent as the system prompt
+                ChatRole::System => continue,
+            };
+
+            match bedrock_messages.last_mut() {
+                Some(last) if last.role == role => last.content.extend(content),
+                _ => bedrock_messages.push(BedrockMessage {
+                    role: role.to_string(),
+                    content,
+                }),
+            }
+        }
+
+        bedrock_messages
+    }
+
+    fn build_request(
+        model: &str,
+        messages: &[ChatMessage],
+        options: &GenerationOptions,
+        tools: Option<Vec<ToolSpec>>,
+        tool_choice: Option<ToolChoice>,
+    ) -> BedrockRequest {
+        let system: Vec<BedrockSystemBlock> = messages
+            .iter()
+            .filter(|msg| matches!(msg.role, ChatRole::System) && !msg.content.is_empty())
+            .map(|msg| BedrockSystemBlock {
+                text: msg.content.clone(),
+            })
+            .collect();
+
+        // Converse cannot forbid tool use, so the tools are left out instead
+        let tools = match tool_choice {
+            Some(ToolChoice::None) => None,
+            _ => tools,
+        };
+        let mut specs: Vec<BedrockTool> = tools
+            .unwrap_or_default()
+            .into_iter()
+            .map(|tool| BedrockTool {
+                tool_spec: BedrockToolSpec {
+                    name: tool.name,
+                    description: tool.description,
+                    input_schema: BedrockSchema { json: tool.parameters },
+                },
+            })
+            .collect();
+        let mut tool_choice = tool_choice.and_then(|choice| match choice {
+            ToolChoice::Auto => Some(serde_json::json!({ "auto": {} })),
+            ToolChoice::None => None,
+            ToolChoice::Required => Some(serde_json::json!({ "any": {} })),
+            ToolChoice::Function { name } => Some(serde_json::json!({ "tool": { "name": name } })),
+        });
+        // A JSON answer is forced through the response tool
+        if let Some(tool) = Self::response_tool(options) {
+            tool_choice = Some(serde_json::json!({ "tool": { "name": tool.name } }));
+            specs.push(BedrockTool { tool_spec: tool });
+        }
+        let tool_config = (!specs.is_empty()).then_some(BedrockToolConfig {
+            tools: specs,
+            tool_choice,
+        });
+
+        // Claude takes top_k as a model-specific field
+        let additional_model_request_fields = options
+            .top_k
+            .filter(|_| Self::takes_top_k(model))
+            .map(|top_k| serde_json::json!({ "top_k": top_k }));
+
+        BedrockRequest {
+            messages: Self::convert_messages(messages),
+            system: (!system.is_empty()).then_some(system),
+            inference_config: BedrockInferenceConfig {
+                max_tokens: options.max_tokens,
+                temperature: options.temperature,
+                top_p: options.top_p,
+                stop_sequences: options.stop_sequences(),
+            },
+            tool_config,
+            additional_model_request_fields,
+        }
+    }
+
+    /// The content filter error for an answer a guardrail stopped
+    fn content_filter(stop_reason: Option<&str>) -> Option<ContentFilterError> {
+        let reason = stop_reason.filter(|reason| BLOCKED_STOP_REASONS.contains(reason))?;
+        Some(ContentFilterError {
+            provider: "Bedrock",
+            reason: reason.to_string(),
+            prompt: false,
+            categories: Vec::new(),
+        })
+    }
+
+    /// Generate chat completion (non-streaming)
+    pub async fn chat_completion(
+        &self,
+        messages: Vec<ChatMessage>,
+        model: Option<String>,
+        options: GenerationOptions,
+        tools: Option<Vec<ToolSpec>>,
+        tool_choice: Option<ToolChoice>,
+    ) -> Result<ChatMessage> {
+        let model = model.unwrap_or_else(|| self.default_model.clone());
+        if messages.iter().all(|msg| matches!(msg.role, ChatRole::System)) {
+            return Err(anyhow!("No valid messages to process"));
+        }
+
+        let messages = attachments::inline_remote_attachments(messages, attachments::is_media).await?;
+        let answer_tool = ResponseTool::from_options(&options);
+        let request = Self::build_request(&model, &messages, &options, tools, tool_choice);
+
+        let builder = self.post(&self.client, &model, "converse", &request)?;
+        let response = self.retry.send("Bedrock", builder).await?;
+        let bedrock_response: BedrockResponse = response.json().await?;
+
+        if let Some(blocked) = Self::content_filter(bedrock_response.stop_reason.as_deref()) {
+            return Err(blocked.into());
+        }
+
+        let content = bedrock_response
+            .output
+            .message
+            .map(|message| message.content)
+            .unwrap_or_default();
+
+        // A JSON answer is the input of the response tool, and replaces any text around it
+        let answer = content.iter().find_map(|block| match &block.tool_use {
+            Some(tool_use) => answer_tool
+                .as_ref()
+                .filter(|tool| tool.name == tool_use.name)
+                .map(|tool| tool.answer(&tool_use.input)),
+            None => None,
+        });
+        let text: String = match &answer {
+            Some(answer) => answer.clone(),
+            None => content.iter().filter_map(|block| block.text.as_deref()).collect(),
+        };
+
+        let reasoning: Vec<Reasoning> = content
+            .iter()
+            .filter_map(|block| block.reasoning_content.as_ref())
+            .map(|reasoning| match (&reasoning.reasoning_text, &reasoning.redacted_content) {
+                (Some(text), _) => Reasoning {
+                    text: text.text.clone(),
+                    signature: text.signature.clone(),
+                    redacted: None,
+                },
+                (None, redacted) => Reasoning {
+                    redacted: redacted.clone(),
+                    ..Default::default()
+                },
+            })
+            .collect();
+
+        let tool_calls: Vec<ToolCall> = content
+            .iter()
+            .filter_map(|block| block.tool_use.as_ref())
+            .filter(|tool_use| answer_tool.as_ref().is_none_or(|tool| tool.name != tool_use.name))
+            .map(|tool_use| ToolCall {
+                id: tool_use.tool_use_id.clone(),
+                name: tool_use.name.clone(),
+                arguments: tool_use.input.clone(),
+            })
+            .collect();
+
+        if text.is_empty() && tool_calls.is_empty() {
+            return Err(anyhow!("No valid response from Bedrock API"));
+        }
+
+        let mut metadata = HashMap::from([
+            ("model".to_string(), serde_json::Value::String(model)),
+            ("provider".to_string(), serde_json::Value::String("bedrock".to_string())),
+        ]);
+        if let Some(stop_reason) = &bedrock_response.stop_reason {
+            // Answering through the response tool is the end of the turn
+            let stop_reason = match answer {
+                Some(_) if tool_calls.is_empty() && stop_reason == "tool_use" => "end_turn",
+                _ => stop_reason.as_str(),
+            };
+            metadata.insert("finish_reason".to_string(), serde_json::Value::String(stop_reason.to_string()));
+        }
+        if let Some(usage) = &bedrock_response.usage {
+            metadata.insert("usage".to_string(), serde_json::to_value(usage.to_usage()).unwrap_or(serde_json::Value::Null));
+        }
+        Reasoning::insert_into(&reasoning, &mut metadata);
+        if !tool_calls.is_empty() {
+            metadata.insert(
+                TOOL_CALLS_KEY.to_string(),
+                serde_json::to_value(&tool_calls).unwrap_or(serde_json::Value::Null),
+            );
+        }
+
+        Ok(ChatMessage {
+            id: format!("bedrock_{}", fastrand::u64(1000..9999)),
+            role: ChatRole::Assistant,
+            content: text,
+            created_at: Some(chrono::Utc::now()),
+            attachments: None,
+            metadata: Some(metadata),
+        })
+    }
+
+    /// Generate chat completion (streaming)
+    pub async fn chat_completion_stream(
+        &self,
+        messages: Vec<ChatMessage>,
+        model: Option<String>,
+        options: GenerationOptions,
+        tools: Option<Vec<ToolSpec>>,
+        tool_choice: Option<ToolChoice>,
+    ) -> Result<ChunkStream> {
+        let model = model.unwrap_or_else(|| self.default_model.clone());
+        if messages.iter().all(|msg| matches!(msg.role, ChatRole::System)) {
+            return Err(anyhow!("No valid messages to process"));
+        }
+
+        let messages = attachments::inline_remote_attachments(messages, attachments::is_media).await?;
+        let answer_tool = ResponseTool::from_options(&options);
+        let request = Self::build_request(&model, &messages, &options, tools, tool_choice);
+
+        let builder = self.post(&self.stream_client, &model, "converse-stream", &request)?;
+        let response = self.retry.send("Bedrock", builder).await?;
+
+        Ok(Box::pin(stream! {
+            yield Ok(UIMessageChunk::TextStart);
+
+            let mut messages = decoder::event_stream_messages(response.bytes_stream());
+            let mut tool_calls = ToolCallAccumulator::new();
+            let mut reasoning = ReasoningStream::new();
+            let mut signature = None;
+            let mut answer_index = None;
+            let mut answer = answer_tool.as_ref().map(ResponseTool::answer_stream);
+            let mut usage = None;
+            let mut stopped = false;
+
+            while let Some(message) = messages.next().await {
+                let message = match message {
+                    Ok(message) => message,
+                    Err(e) => {
+                        yield Err(e);
+                        return;
+                    }
+                };
+
+                // Throttling and similar errors arrive as exception messages mid-stream
+                if message.header(":message-type") == Some("exception") {
+                    let kind = message.header(":exception-type").unwrap_or("exception");
+                    let payload = String::from_utf8_lossy(&message.payload);
+                    yield Err(anyhow!("Bedrock stream error: {}: {}", kind, payload));
+                    return;
+                }
+
+                let event = match serde_json::from_slice::<BedrockStreamEvent>(&message.payload) {
+                    Ok(event) => event,
+                    Err(_) => continue,
+                };
+                let index = event.content_block_index.unwrap_or(0);
+                match message.header(":event-type").unwrap_or_default() {
+                    "contentBlockStart" => {
+                        if let Some(tool_use) = event.start.and_then(|start| start.tool_use) {
+                            // The response tool's input is streamed as the answer text
+                            if answer_tool.as_ref().is_some_and(|tool| tool.name == tool_use.name) {
+                                answer_index = Some(index);
+                            } else {
+                                tool_calls.push(index, Some(&tool_use.tool_use_id), Some(&tool_use.name), None);
+                            }
+                        }
+                    }
+                    "contentBlockDelta" => {
+                        let Some(delta) = event.delta else { continue };
+                        if let Some(delta) = delta.reasoning_content {
+                            if let Some(text) = delta.text {
+                                for chunk in reasoning.delta(&text) {
+                                    yield Ok(chunk);
+                                }
+                            }
+                            if delta.signature.is_some() {
+                                signature = delta.signature;
+                            }
+                            if let Some(data) = delta.redacted_content {
+                                for chunk in reasoning.redacted(data) {
+                                    yield Ok(chunk);
+                                }
+                            }
+                        }
+                        if let Some(text) = delta.text {
+                            if let Some(chunk) = reasoning.end(signature.take()) {
+                                yield Ok(chunk);
+                            }
+                            yield Ok(UIMessageChunk::TextDelta {
+                                textDelta: text,
+                            });
+                        }
+                        if let Some(tool_use) = delta.tool_use {
+                            if answer_index == Some(index) {
+                                if let Some(text) = answer.as_mut().and_then(|answer| answer.delta(tool_use.input)) {
+                                    yield Ok(UIMessageChunk::TextDelta {
+                                        textDelta: text,
+                                    });
+                                }
+                            } else {
+                                tool_calls.push(index, None, None, Some(&tool_use.input));
+                            }
+                        }
+                    }
+                    "contentBlockStop" => {
+                        if let Some(chunk) = reasoning.end(signature.take()) {
+                            yield Ok(chunk);
+                        }
+                        if answer_index == Some(index) {
+                            if let Some(text) = answer.as_mut().and_then(AnswerStream::finish) {
+                                yield Ok(UIMessageChunk::TextDelta {
+                                    textDelta: text,
+                                });
+                            }
+                        }
+                        // Tool input is complete once its block closes
+                        for call in tool_calls.finish() {
+                            yield Ok(call.into_chunk());
+                        }
+                    }
+                    "messageStop" => {
+                        if let Some(blocked) = Self::content_filter(event.stop_reason.as_deref()) {
+                            yield Err(blocked.into());
+                            return;
+                        }
+                        stopped = true;
+                    }
+                    // Usage comes after the message stops
+                    "metadata" => {
+                        usage = event.usage.map(|usage| usage.to_usage());
+                    }
+                    _ => {}
+                }
+            }
+
+            if !stopped {
+                yield Err(anyhow!("Bedrock stream ended before messageStop"));
+                return;
+            }
+
+            if let Some(chunk) = reasoning.end(signature.take()) {
+                yield Ok(chunk);
+            }
+            for call in tool_calls.finish() {
+                yield Ok(call.into_chunk());
+            }
+            yield Ok(UIMessageChunk::TextFinish);
+            yield Ok(UIMessageChunk::Finish {
+                reasoning: reasoning.text(),
+                sources: None,
+                usage,
+                logprobs: None,
+            });
+        }))
+    }
+}
+
+// Bedrock Converse API structures
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct BedrockRequest {
+    messages: Vec<BedrockMessage>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    system: Option<Vec<BedrockSystemBlock>>,
+    inference_config: BedrockInferenceConfig,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tool_config: Option<BedrockToolConfig>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    additional_model_request_fields: Option<serde_json::Value>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct BedrockSystemBlock {
+    text: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct BedrockInferenceConfig {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    max_tokens: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    temperature: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    top_p: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    stop_sequences: Option<Vec<String>>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct BedrockToolConfig {
+    tools: Vec<BedrockTool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tool_choice: Option<serde_json::Value>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct BedrockTool {
+    tool_spec: BedrockToolSpec,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct BedrockToolSpec {
+    name: String,
+    description: String,
+    input_schema: BedrockSchema,
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct BedrockSchema {
+    json: serde_json::Value,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct BedrockMessage {
+    role: String,
+    content: Vec<BedrockContent>,
+}
+
+/// A content block; exactly one field is set
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct BedrockContent {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    text: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    image: Option<BedrockImage>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    document: Option<BedrockDocument>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tool_use: Option<BedrockToolUse>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tool_result: Option<BedrockToolResult>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reasoning_content: Option<BedrockReasoningContent>,
+}
+
+impl BedrockContent {
+    fn text(text: String) -> Self {
+        Self {
+            text: Some(text),
+            ..Default::default()
+        }
+    }
+
+    /// An image block, for the formats Converse accepts
+    fn image(inline: InlineData) -> Option<Self> {
+        let format = match inline.media_type.as_str() {
+            "image/png" => "png",
+            "image/jpeg" | "image/jpg" => "jpeg",
+            "image/gif" => "gif",
+            "image/webp" => "webp",
+            _ => return None,
+        };
+        Some(Self {
+            image: Some(BedrockImage {
+                format: format.to_string(),
+                source: BedrockSource { bytes: inline.data },
+            }),
+            ..Default::default()
+        })
+    }
+
+    /// A PDF document block
+    ///
+    /// Document names may only hold letters, digits, whitespace, hyphens, parentheses and
+    /// square brackets, so other characters become hyphens.
+    fn document(inline: InlineData, filename: Option<&str>) -> Self {
+        let name: String = filename
+            .map(|name| name.strip_suffix(".pdf").unwrap_or(name))
+            .filter(|name| !name.is_empty())
+            .unwrap_or("document")
+            .chars()
+            .map(|c| match c {
+                c if c.is_alphanumeric() || c.is_whitespace() => c,
+                '-' | '(' | ')' | '[' | ']' => c,
+                _ => '-',
+            })
+            .collect();
+        Self {
+            document: Some(BedrockDocument {
+                format: "pdf".to_string(),
+                name,
+                source: BedrockSource { bytes: inline.data },
+            }),
+            ..Default::default()
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct BedrockImage {
+    format: String,
+    source: BedrockSource,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct BedrockDocument {
+    format: String,
+    name: String,
+    source: BedrockSource,
+}
+
+/// Base64-encoded bytes
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct BedrockSource {
+    bytes: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct BedrockToolUse {
+    tool_use_id: String,
+    name: String,
+    input: serde_json::Value,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct BedrockToolResult {
+    tool_use_id: String,
+    content: Vec<BedrockToolResultContent>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct BedrockToolResultContent {
+    text: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct BedrockReasoningContent {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reasoning_text: Option<BedrockReasoningText>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    redacted_content: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct BedrockReasoningText {
+    text: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    signature: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct BedrockResponse {
+    output: BedrockOutput,
+    stop_reason: Option<String>,
+    usage: Option<BedrockUsage>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct BedrockOutput {
+    message: Option<BedrockMessage>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct BedrockUsage {
+    input_tokens: u32,
+    output_tokens: u32,
+    cache_read_input_tokens: Option<u32>,
+    cache_write_input_tokens: Option<u32>,
+}
+
+impl BedrockUsage {
+    /// Usage with cached prompt tokens counted as prompt tokens
+    fn to_usage(&self) -> Usage {
+        let cached = self.cache_write_input_tokens.unwrap_or(0) + self.cache_read_input_tokens.unwrap_or(0);
+        Usage {
+            cache_creation_input_tokens: self.cache_write_input_tokens,
+            cache_read_input_tokens: self.cache_read_input_tokens,
+            ..Usage::new(self.input_tokens + cached, self.output_tokens)
+        }
+    }
+}
+
+/// Payload of a ConverseStream event; which fields are set depends on the event type
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct BedrockStreamEvent {
+    content_block_index: Option<u64>,
+    start: Option<BedrockBlockStart>,
+    delta: Option<BedrockDelta>,
+    stop_reason: Option<String>,
+    usage: Option<BedrockUsage>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct BedrockBlockStart {
+    tool_use: Option<BedrockToolUseStart>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct BedrockToolUseStart {
+    tool_use_id: String,
+    name: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct BedrockDelta {
+    text: Option<String>,
+    tool_use: Option<BedrockToolUseDelta>,
+    reasoning_content: Option<BedrockReasoningDelta>,
+}
+
+/// A fragment of the tool input's JSON
+#[derive(Debug, Clone, Deserialize)]
+struct BedrockToolUseDelta {
+    input: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct BedrockReasoningDelta {
+    text: Option<String>,
+    signature: Option<String>,
+    redacted_content: Option<String>,
+}
+
+#[async_trait]
+impl AIProvider for BedrockService {
+    async fn chat_completion(
+        &self,
+        messages: Vec<ChatMessage>,
+        model: Option<String>,
+        options: GenerationOptions,
+        tools: Option<Vec<ToolSpec>>,
+        tool_choice: Option<ToolChoice>,
+    ) -> Result<ChatMessage> {
+        self.chat_completion(messages, model, options, tools, tool_choice).await
+    }
+
+    async fn chat_completion_stream(
+        &self,
+        messages: Vec<ChatMessage>,
+        model: Option<String>,
+        options: GenerationOptions,
+        tools: Option<Vec<ToolSpec>>,
+        tool_choice: Option<ToolChoice>,
+    ) -> Result<ChunkStream> {
+        self.chat_completion_stream(messages, model, options, tools, tool_choice).await
+    }
+
+    fn get_available_models(&self) -> Vec<&'static str> {
+        Self::get_model_options()
+    }
+
+    fn unsupported_options(&self, model: &str, options: &GenerationOptions) -> Vec<&'static str> {
+        let mut unsupported = options.set_among(&[
+            "frequency_penalty",
+            "presence_penalty",
+            "seed",
+            "user",
+            "logit_bias",
+            "candidate_count",
+            "safety_settings",
+        ]);
+        let model = if model.is_empty() { &self.default_model } else { model };
+        if !Self::takes_top_k(model) {
+            unsupported.extend(options.set_among(&["top_k"]));
+        }
+        unsupported
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::providers::structured::JSON_OBJECT_TOOL;
+    use crate::providers::ResponseFormat;
+    use crate::providers::testing::{serve, user};
+
+    /// An AWS event stream message with string headers, as Bedrock frames it
+    fn frame(event_type: &str, payload: serde_json::Value) -> Vec<u8> {
+        let mut headers = Vec::new();
+        for (name, value) in [(":event-type", event_type), (":content-type", "application/json"), (":message-type", "event")] {
+            headers.push(name.len() as u8);
+            headers.extend_from_slice(name.as_bytes());
+            headers.push(7);
+            headers.extend_from_slice(&(value.len() as u16).to_be_bytes());
+            headers.extend_from_slice(value.as_bytes());
+        }
+        let payload = payload.to_string().into_bytes();
+
+        let total_len = (12 + headers.len() + payload.len() + 4) as u32;
+        let mut message = total_len.to_be_bytes().to_vec();
+        message.extend_from_slice(&(headers.len() as u32).to_be_bytes());
+        let prelude_crc = crc32fast::hash(&message);
+        message.extend_from_slice(&prelude_crc.to_be_bytes());
+        message.extend(headers);
+        message.extend(payload);
+        let message_crc = crc32fast::hash(&message);
+        message.extend_from_slice(&message_crc.to_be_bytes());
+        message
+    }
+
+    /// A mock Bedrock Runtime endpoint that checks the request is signed
+    async fn bedrock_mock(stream: Vec<u8>) -> BedrockService {
+        let converse = |headers: axum::http::HeaderMap, axum::Json(body): axum::Json<serde_json::Value>| async move {
+            assert!(headers["authorization"]
+                .to_str()
+                .unwrap()
+                .starts_with("AWS4-HMAC-SHA256 Credential=AKIDEXAMPLE/"));
+            assert!(headers.contains_key("x-amz-date"));
+            assert_eq!(body["system"][0]["text"], "Be brief.");
+            assert_eq!(body["messages"][0]["content"][0]["text"], "Hello");
+            axum::Json(serde_json::json!({
+                "output": {"message": {"role": "assistant", "content": [{"text": "Hi"}]}},
+                "stopReason": "end_turn",
+                "usage": {"inputTokens": 5, "outputTokens": 1, "totalTokens": 6, "cacheReadInputTokens": 4}
+            }))
+        };
+        let app = axum::Router::new()
+            .route("/model/{model}/converse", axum::routing::post(converse))
+            .route("/model/{model}/converse-stream", axum::routing::post(move || std::future::ready(stream.clone())));
+        let url = serve(app).await;
+
+        let credentials = AwsCredentials::new("AKIDEXAMPLE".to_string(), "secret".to_string(), None);
+        BedrockService::new(credentials, "us-east-1".to_string(), None).with_base_url(url)
+    }
+
+    #[tokio::test]
+    async fn test_converse_is_signed_and_parsed() {
+        let service = bedrock_mock(Vec::new()).await;
+        let system = ChatMessage {
+            role: ChatRole::System,
+            ..user("Be brief.")
+        };
+
+        let message = service
+            .chat_completion(vec![system, user("Hello")], None, GenerationOptions::default(), None, None)
+            .await
+            .unwrap();
+        assert_eq!(message.content, "Hi");
+        let usage = Usage::from_metadata(message.metadata.as_ref()).unwrap();
+        assert_eq!(usage.prompt_tokens, 9);
+        assert_eq!(usage.cache_read_input_tokens, Some(4));
+    }
+
+    #[tokio::test]
+    async fn test_converse_stream_becomes_chunks() {
+        let mut stream = Vec::new();
+        for (event_type, payload) in [
+            ("messageStart", serde_json::json!({"role": "assistant"})),
+            ("contentBlockDelta", serde_json::json!({"contentBlockIndex": 0, "delta": {"text": "Let me "}})),
+            ("contentBlockDelta", serde_json::json!({"contentBlockIndex": 0, "delta": {"text": "check."}})),
+            ("contentBlockStop", serde_json::json!({"contentBlockIndex": 0})),
+            (
+                "contentBlockStart",
+                serde_json::json!({"contentBlockIndex": 1, "start": {"toolUse": {"toolUseId": "tooluse_1", "name": "calculator"}}}),
+            ),
+            ("contentBlockDelta", serde_json::json!({"contentBlockIndex": 1, "delta": {"toolUse": {"input": "{\"expression\":"}}})),
+            ("contentBlockDelta", serde_json::json!({"contentBlockIndex": 1, "delta": {"toolUse": {"input": "\"2 + 2\"}"}}})),
+            ("contentBlockStop", serde_json::json!({"contentBlockIndex": 1})),
+            ("messageStop", serde_json::json!({"stopReason": "tool_use"})),
+            (
+                "metadata",
+                serde_json::json!({"usage": {"inputTokens": 10, "outputTokens": 5, "totalTokens": 15}, "metrics": {"latencyMs": 100}}),
+            ),
+        ] {
+            stream.extend(frame(event_type, payload));
+        }
+        let service = bedrock_mock(stream).await;
+
+        let chunks: Vec<serde_json::Value> = service
+            .chat_completion_stream(vec![user("2 + 2?")], None, GenerationOptions::default(), None, None)
+            .await
+            .unwrap()
+            .map(|chunk| serde_json::to_value(chunk.unwrap()).unwrap())
+            .collect()
+            .await;
+
+        let types: Vec<&str> = chunks.iter().map(|c| c["type"].as_str().unwrap()).collect();
+        assert_eq!(
+            types,
+            vec!["text-start", "text-delta", "text-delta", "tool-call", "text-finish", "finish"]
+        );
+        assert_eq!(chunks[3]["toolCallId"], "tooluse_1");
+        assert_eq!(chunks[3]["args"], serde_json::json!({"expression": "2 + 2"}));
+        assert_eq!(chunks[5]["usage"]["total_tokens"], 15);
+    }
+
+    #[tokio::test]
+    async fn test_wrapped_answer_is_unwrapped_from_the_stream() {
+        let stream = [
+            frame(
+                "contentBlockStart",
+                serde_json::json!({"contentBlockIndex": 0, "start": {"toolUse": {"toolUseId": "tooluse_1", "name": "primes"}}}),
+            ),
+            frame("contentBlockDelta", serde_json::json!({"contentBlockIndex": 0, "delta": {"toolUse": {"input": "{\"value\": [2,"}}})),
+            frame("contentBlockDelta", serde_json::json!({"contentBlockIndex": 0, "delta": {"toolUse": {"input": " 3]}"}}})),
+            frame("contentBlockStop", serde_json::json!({"contentBlockIndex": 0})),
+            frame("messageStop", serde_json::json!({"stopReason": "tool_use"})),
+        ]
+        .concat();
+        let service = bedrock_mock(stream).await;
+        let options: GenerationOptions = serde_json::from_value(serde_json::json!({
+            "response_format": {"type": "json_schema", "json_schema": {"name": "primes", "schema": {"type": "array"}}}
+        }))
+        .unwrap();
+
+        let text: String = service
+            .chat_completion_stream(vec![user("First two primes?")], None, options, None, None)
+            .await
+            .unwrap()
+            .filter_map(|chunk| async move {
+                match chunk.unwrap() {
+                    UIMessageChunk::TextDelta { textDelta } => Some(textDelta),
+                    _ => None,
+                }
+            })
+            .collect()
+            .await;
+        assert_eq!(text, "[2,3]");
+    }
+
+    #[tokio::test]
+    async fn test_guardrail_stop_is_a_content_filter_error() {
+        let stream = [
+            frame("contentBlockDelta", serde_json::json!({"contentBlockIndex": 0, "delta": {"text": "Sorry"}})),
+            frame("messageStop", serde_json::json!({"stopReason": "guardrail_intervened"})),
+        ]
+        .concat();
+        let service = bedrock_mock(stream).await;
+
+        let results: Vec<Result<UIMessageChunk>> = service
+            .chat_completion_stream(vec![user("Hello")], None, GenerationOptions::default(), None, None)
+            .await
+            .unwrap()
+            .collect()
+            .await;
+        let error = results.last().unwrap().as_ref().unwrap_err();
+        assert_eq!(ContentFilterError::from_error(error).unwrap().reason, "guardrail_intervened");
+    }
+
+    #[tokio::test]
+    async fn test_stream_cut_off_before_message_stop_is_an_error() {
+        let stream = frame("contentBlockDelta", serde_json::json!({"contentBlockIndex": 0, "delta": {"text": "The answer is"}}));
+        let service = bedrock_mock(stream).await;
+
+        let results: Vec<Result<UIMessageChunk>> = service
+            .chat_completion_stream(vec![user("2 + 2?")], None, GenerationOptions::default(), None, None)
+            .await
+            .unwrap()
+            .collect()
+            .await;
+
+        assert!(results.iter().all(|chunk| !matches!(chunk, Ok(UIMessageChunk::Finish { .. }))));
+        let error = results.last().unwrap().as_ref().unwrap_err();
+        assert_eq!(error.to_string(), "Bedrock stream ended before messageStop");
+    }
+
+    #[test]
+    fn test_request_shape() {
+        let tool = ChatMessage {
+            role: ChatRole::Tool,
+            metadata: Some(HashMap::from([(TOOL_CALL_ID_KEY.to_string(), serde_json::json!("tooluse_1"))])),
+            ..user("{\"result\":4}")
+        };
+        let options = GenerationOptions {
+            top_k: Some(40),
+            stop: Some(vec!["END".to_string()]),
+            response_format: Some(ResponseFormat::JsonObject),
+            ..GenerationOptions::sampling(Some(0.2), Some(256))
+        };
+
+        let request = BedrockService::build_request(
+            DEFAULT_MODEL,
+            &[tool.clone(), tool, user("Thanks")],
+            &options,
+            None,
+            None,
+        );
+        let json = serde_json::to_value(&request).unwrap();
+
+        // Both tool results and the user text share one user turn
+        assert_eq!(json["messages"].as_array().unwrap().len(), 1);
+        assert_eq!(json["messages"][0]["content"][0]["toolResult"]["toolUseId"], "tooluse_1");
+        assert_eq!(json["messages"][0]["content"][2]["text"], "Thanks");
+        assert_eq!(json["inferenceConfig"]["maxTokens"], 256);
+        assert_eq!(json["inferenceConfig"]["stopSequences"][0], "END");
+        assert_eq!(json["additionalModelRequestFields"]["top_k"], 40);
+        assert_eq!(json["toolConfig"]["tools"][0]["toolSpec"]["name"], JSON_OBJECT_TOOL);
+        assert_eq!(json["toolConfig"]["toolChoice"]["tool"]["name"], JSON_OBJECT_TOOL);
+        assert!(json.get("system").is_none());
+    }
+
+    #[test]
+    fn test_tool_choice_none_leaves_tools_out() {
+        let calculator = ToolSpec {
+            name: "calculator".to_string(),
+            description: "Evaluate an expression".to_string(),
+            parameters: serde_json::json!({"type": "object"}),
+        };
+        let request = BedrockService::build_request(
+            DEFAULT_MODEL,
+            &[user("2 + 2?")],
+            &GenerationOptions::default(),
+            Some(vec![calculator]),
+            Some(ToolChoice::None),
+        );
+        let json = serde_json::to_value(&request).unwrap();
+        assert!(json.get("toolConfig").is_none());
+    }
+
+    #[test]
+    fn test_top_k_is_unsupported_outside_claude() {
+        let service = BedrockService::new(
+            AwsCredentials::new("AKIDEXAMPLE".to_string(), "secret".to_string(), None),
+            "us-east-1".to_string(),
+            None,
+        );
+        let options = GenerationOptions {
+            top_k: Some(40),
+            ..GenerationOptions::default()
+        };
+        assert!(service.unsupported_options("anthropic.claude-3-5-haiku-20241022-v1:0", &options).is_empty());
+        assert_eq!(service.unsupported_options("amazon.nova-pro-v1:0", &options), vec!["top_k"]);
+
+        let request = BedrockService::build_request("amazon.nova-pro-v1:0", &[user("Hi")], &options, None, None);
+        let json = serde_json::to_value(&request).unwrap();
+        assert!(json.get("additionalModelRequestFields").is_none());
+    }
+}
@@ -0,0 +1,206 @@
+//! Model capabilities
+//!
+//! What a model can take and produce: its token limits, whether it accepts images, tools and
+//! JSON output, whether it reasons, and its price. Capabilities come from the providers' model
+//! listings (see [`super::discovery`]) and from a local file (`MODELS_CONFIG_PATH`, default
+//! `models.json`) that fills in or overrides what the providers report. A capability that is
+//! not known is not checked, so requests are only rejected for what a model is known to lack.
+
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::Path;
+use tokio::fs;
+use tracing::{info, warn};
+
+use crate::chat::ChatMessage;
+use super::attachments;
+use super::{GenerationOptions, ResponseFormat, ToolSpec};
+
+/// What a model supports; `None` where it is not known
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ModelCapabilities {
+    /// Input tokens the model accepts
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub context_window: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_output_tokens: Option<u32>,
+    /// Accepts image attachments
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub vision: Option<bool>,
+    /// Can call tools
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tools: Option<bool>,
+    /// Thinks before answering
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reasoning: Option<bool>,
+    /// Can be asked for a JSON object or for JSON matching a schema
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub json_mode: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pricing: Option<Pricing>,
+}
+
+/// Price of a model, in USD per million tokens
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Pricing {
+    pub input_per_million: f64,
+    pub output_per_million: f64,
+}
+
+impl ModelCapabilities {
+    /// These capabilities with every one that `overrides` knows replaced
+    pub fn merged(self, overrides: &ModelCapabilities) -> Self {
+        Self {
+            context_window: overrides.context_window.or(self.context_window),
+            max_output_tokens: overrides.max_output_tokens.or(self.max_output_tokens),
+            vision: overrides.vision.or(self.vision),
+            tools: overrides.tools.or(self.tools),
+            reasoning: overrides.reasoning.or(self.reasoning),
+            json_mode: overrides.json_mode.or(self.json_mode),
+            pricing: overrides.pricing.or(self.pricing),
+        }
+    }
+
+    /// Check that `model` can serve a request needing `features`
+    ///
+    /// Returns what the model lacks, for the error message.
+    pub fn check(&self, model: &str, features: &RequestFeatures) -> std::result::Result<(), String> {
+        if features.images && self.vision == Some(false) {
+            return Err(format!("{} does not accept images", model));
+        }
+        if features.tools && self.tools == Some(false) {
+            return Err(format!("{} does not support tools", model));
+        }
+        if features.json && self.json_mode == Some(false) {
+            return Err(format!("{} does not support JSON output", model));
+        }
+        if let (Some(requested), Some(limit)) = (features.max_tokens, self.max_output_tokens) {
+            if requested > limit {
+                return Err(format!("max_tokens {} exceeds the {} output tokens of {}", requested, limit, model));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The capabilities a request needs
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RequestFeatures {
+    pub images: bool,
+    pub tools: bool,
+    pub json: bool,
+    pub max_tokens: Option<u32>,
+}
+
+impl RequestFeatures {
+    pub fn of(messages: &[ChatMessage], options: &GenerationOptions, tools: Option<&[ToolSpec]>) -> Self {
+        Self {
+            images: messages
+                .iter()
+                .flat_map(|message| message.attachments.iter().flatten())
+                .any(attachments::is_image),
+            tools: tools.is_some_and(|tools| !tools.is_empty()),
+            json: matches!(
+                options.response_format,
+                Some(ResponseFormat::JsonObject | ResponseFormat::JsonSchema { .. })
+            ),
+            max_tokens: options.max_tokens,
+        }
+    }
+}
+
+/// Capabilities set in the models file for one model
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ModelOverride {
+    /// Provider serving the model, for a model not listed by any provider; routes the bare
+    /// model name there
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub provider: Option<String>,
+    #[serde(flatten)]
+    pub capabilities: ModelCapabilities,
+}
+
+/// Models file
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ModelsConfig {
+    /// Keyed by `provider/model`, or by the bare model name to apply on every provider
+    #[serde(default)]
+    pub models: HashMap<String, ModelOverride>,
+}
+
+/// Load the models file, returning an empty config if it does not exist
+pub async fn load_config<P: AsRef<Path>>(path: P) -> Result<ModelsConfig> {
+    let path = path.as_ref();
+    match fs::read_to_string(path).await {
+        Ok(content) => serde_json::from_str(&content)
+            .map_err(|e| anyhow!("Failed to parse models config '{}': {}", path.display(), e)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(ModelsConfig::default()),
+        Err(e) => Err(anyhow!("Failed to read models config '{}': {}", path.display(), e)),
+    }
+}
+
+/// Load the model overrides from the models file, or none if it cannot be read
+pub async fn load_overrides() -> HashMap<String, ModelOverride> {
+    let path = std::env::var("MODELS_CONFIG_PATH").unwrap_or_else(|_| "models.json".to_string());
+    match load_config(&path).await {
+        Ok(config) => {
+            if !config.models.is_empty() {
+                info!("Loaded capabilities of {} models from {}", config.models.len(), path);
+            }
+            config.models
+        }
+        Err(e) => {
+            warn!("{}", e);
+            HashMap::new()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_only_known_capabilities_are_checked() {
+        let text_only = ModelCapabilities {
+            vision: Some(false),
+            max_output_tokens: Some(4096),
+            ..Default::default()
+        };
+        let images = RequestFeatures { images: true, ..Default::default() };
+        assert_eq!(text_only.check("gpt-3.5-turbo", &images).unwrap_err(), "gpt-3.5-turbo does not accept images");
+        assert!(ModelCapabilities::default().check("gpt-3.5-turbo", &images).is_ok());
+
+        let long = RequestFeatures { tools: true, max_tokens: Some(8192), ..Default::default() };
+        assert_eq!(
+            text_only.check("gpt-3.5-turbo", &long).unwrap_err(),
+            "max_tokens 8192 exceeds the 4096 output tokens of gpt-3.5-turbo"
+        );
+
+        let overridden = text_only.merged(&ModelCapabilities { vision: Some(true), ..Default::default() });
+        assert!(overridden.check("gpt-3.5-turbo", &images).is_ok());
+        assert_eq!(overridden.max_output_tokens, Some(4096));
+    }
+
+    #[test]
+    fn test_models_file_format() {
+        let config: ModelsConfig = serde_json::from_value(serde_json::json!({
+            "models": {
+                "ollama/llava": {"vision": true},
+                "my-finetune": {
+                    "provider": "vllm",
+                    "context_window": 32768,
+                    "pricing": {"input_per_million": 0.5, "output_per_million": 1.5}
+                }
+            }
+        }))
+        .unwrap();
+
+        assert_eq!(config.models["ollama/llava"].capabilities.vision, Some(true));
+        let finetune = &config.models["my-finetune"];
+        assert_eq!(finetune.provider.as_deref(), Some("vllm"));
+        assert_eq!(finetune.capabilities.context_window, Some(32768));
+        assert_eq!(finetune.capabilities.pricing.unwrap().output_per_million, 1.5);
+    }
+}
@@ -0,0 +1,597 @@
+//! Decoders for streamed provider responses
+//!
+//! Providers stream either Server-Sent Events (OpenAI, Anthropic, Gemini, OpenRouter),
+//! newline-delimited JSON (Ollama) or the AWS event stream binary framing (Bedrock). Network
+//! chunks split all of them at arbitrary byte offsets, in the middle of a line, a multibyte
+//! UTF-8 character or a frame, so the decoders buffer raw bytes and only decode complete lines
+//! and frames.
+//!
+//! SSE parsing follows the WHATWG event stream format: `event:` and `id:` fields, `data:`
+//! spread over several lines, comments, and any of CRLF, LF or CR as line terminator.
+//! See https://html.spec.whatwg.org/multipage/server-sent-events.html#event-stream-interpretation
+
+use anyhow::{anyhow, Result};
+use async_stream::stream;
+use futures::{Stream, StreamExt};
+use std::collections::HashMap;
+
+/// Length of an event stream prelude: total length, headers length and the prelude's CRC32
+const EVENT_STREAM_PRELUDE_LEN: usize = 12;
+/// Length of the CRC32 that ends every event stream message
+const EVENT_STREAM_CRC_LEN: usize = 4;
+/// Largest event stream message accepted, to catch a corrupt length before buffering it
+const MAX_EVENT_STREAM_MESSAGE: usize = 16 * 1024 * 1024;
+
+/// A dispatched Server-Sent Event
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SseEvent {
+    /// Value of the `event:` field, if the event had one
+    pub event: Option<String>,
+    /// The `data:` lines joined with `\n`
+    pub data: String,
+    /// Last event id seen on the stream
+    pub id: Option<String>,
+}
+
+/// Splits a byte stream into lines terminated by CRLF, LF or CR
+#[derive(Debug, Default)]
+struct LineBuffer {
+    buffer: Vec<u8>,
+    /// The previous chunk ended in CR, so a leading LF belongs to that terminator
+    pending_cr: bool,
+}
+
+impl LineBuffer {
+    fn push(&mut self, mut chunk: &[u8]) -> Vec<String> {
+        // An empty chunk leaves a pending CR pending
+        if self.pending_cr && !chunk.is_empty() {
+            self.pending_cr = false;
+            if let Some(rest) = chunk.strip_prefix(b"\n") {
+                chunk = rest;
+            }
+        }
+
+        let mut lines = Vec::new();
+        let mut bytes = chunk.iter().copied().peekable();
+        while let Some(byte) = bytes.next() {
+            match byte {
+                b'\n' => lines.push(self.take_line()),
+                b'\r' => {
+                    lines.push(self.take_line());
+                    match bytes.peek() {
+                        Some(b'\n') => {
+                            bytes.next();
+                        }
+                        Some(_) => {}
+                        None => self.pending_cr = true,
+                    }
+                }
+                _ => self.buffer.push(byte),
+            }
+        }
+        lines
+    }
+
+    /// The unterminated last line, at the end of the stream
+    fn finish(&mut self) -> Option<String> {
+        (!self.buffer.is_empty()).then(|| self.take_line())
+    }
+
+    fn take_line(&mut self) -> String {
+        // Terminators are ASCII, so a complete line never splits a UTF-8 sequence
+        let line = String::from_utf8_lossy(&self.buffer).into_owned();
+        self.buffer.clear();
+        line
+    }
+}
+
+/// Incremental Server-Sent Events decoder
+#[derive(Debug, Default)]
+pub struct SseDecoder {
+    lines: LineBuffer,
+    started: bool,
+    event: Option<String>,
+    data: Option<String>,
+    id: Option<String>,
+}
+
+impl SseDecoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Feed the next chunk of the stream, returning the events it completes
+    pub fn push(&mut self, chunk: &[u8]) -> Vec<SseEvent> {
+        self.lines
+            .push(chunk)
+            .into_iter()
+            .filter_map(|line| self.process_line(&line))
+            .collect()
+    }
+
+    /// Signal the end of the stream
+    ///
+    /// An event still missing its terminating blank line is dispatched rather than dropped,
+    /// since some servers close the connection right after the last `data:` line.
+    pub fn finish(&mut self) -> Option<SseEvent> {
+        let last = self.lines.finish().and_then(|line| self.process_line(&line));
+        last.or_else(|| self.dispatch())
+    }
+
+    fn process_line(&mut self, line: &str) -> Option<SseEvent> {
+        // A byte order mark may precede the first line
+        let line = if self.started {
+            line
+        } else {
+            self.started = true;
+            line.strip_prefix('\u{feff}').unwrap_or(line)
+        };
+
+        if line.is_empty() {
+            return self.dispatch();
+        }
+        if line.starts_with(':') {
+            return None;
+        }
+
+        let (field, value) = match line.split_once(':') {
+            Some((field, value)) => (field, value.strip_prefix(' ').unwrap_or(value)),
+            None => (line, ""),
+        };
+        match field {
+            "event" => self.event = Some(value.to_string()),
+            "data" => {
+                let data = self.data.get_or_insert_with(String::new);
+                data.push_str(value);
+                data.push('\n');
+            }
+            "id" if !value.contains('\0') => self.id = Some(value.to_string()),
+            _ => {}
+        }
+        None
+    }
+
+    fn dispatch(&mut self) -> Option<SseEvent> {
+        let event = self.event.take();
+        let mut data = self.data.take()?;
+        data.pop();
+        Some(SseEvent {
+            event,
+            data,
+            id: self.id.clone(),
+        })
+    }
+}
+
+/// Incremental newline-delimited JSON decoder, yielding each non-empty line
+#[derive(Debug, Default)]
+pub struct NdjsonDecoder {
+    lines: LineBuffer,
+}
+
+impl NdjsonDecoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Feed the next chunk of the stream, returning the lines it completes
+    pub fn push(&mut self, chunk: &[u8]) -> Vec<String> {
+        self.lines
+            .push(chunk)
+            .into_iter()
+            .filter(|line| !line.trim().is_empty())
+            .collect()
+    }
+
+    /// Signal the end of the stream, returning a last line without a trailing newline
+    pub fn finish(&mut self) -> Option<String> {
+        self.lines.finish().filter(|line| !line.trim().is_empty())
+    }
+}
+
+/// A message of the AWS event stream encoding (`application/vnd.amazon.eventstream`)
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct EventStreamMessage {
+    /// String headers, e.g. `:event-type`; headers of other types are skipped
+    pub headers: HashMap<String, String>,
+    pub payload: Vec<u8>,
+}
+
+impl EventStreamMessage {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.get(name).map(String::as_str)
+    }
+}
+
+/// Incremental decoder for the AWS event stream binary framing
+///
+/// Each message is a prelude (total length, headers length, CRC32 of both; big-endian), the
+/// headers, the payload and a CRC32 of everything before it. A message that fails its checks
+/// is an error, since the stream cannot be resynchronized after it.
+#[derive(Debug, Default)]
+pub struct EventStreamDecoder {
+    buffer: Vec<u8>,
+}
+
+impl EventStreamDecoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Feed the next chunk of the stream, returning the messages it completes
+    pub fn push(&mut self, chunk: &[u8]) -> Result<Vec<EventStreamMessage>> {
+        self.buffer.extend_from_slice(chunk);
+
+        let mut messages = Vec::new();
+        while self.buffer.len() >= EVENT_STREAM_PRELUDE_LEN {
+            let total_len = read_u32(&self.buffer[0..4]) as usize;
+            if !(EVENT_STREAM_PRELUDE_LEN + EVENT_STREAM_CRC_LEN..=MAX_EVENT_STREAM_MESSAGE).contains(&total_len) {
+                return Err(anyhow!("Invalid event stream message length {}", total_len));
+            }
+            if crc32fast::hash(&self.buffer[0..8]) != read_u32(&self.buffer[8..12]) {
+                return Err(anyhow!("Event stream prelude checksum mismatch"));
+            }
+            if self.buffer.len() < total_len {
+                break;
+            }
+
+            let message: Vec<u8> = self.buffer.drain(..total_len).collect();
+            messages.push(Self::decode(&message)?);
+        }
+        Ok(messages)
+    }
+
+    /// Signal the end of the stream; a partial message left over is an error
+    pub fn finish(&self) -> Result<()> {
+        if self.buffer.is_empty() {
+            Ok(())
+        } else {
+            Err(anyhow!("Event stream ended in the middle of a message"))
+        }
+    }
+
+    /// Decode one complete message, whose prelude has been checked
+    fn decode(message: &[u8]) -> Result<EventStreamMessage> {
+        let crc_start = message.len() - EVENT_STREAM_CRC_LEN;
+        if crc32fast::hash(&message[..crc_start]) != read_u32(&message[crc_start..]) {
+            return Err(anyhow!("Event stream message checksum mismatch"));
+        }
+
+        let headers_end = EVENT_STREAM_PRELUDE_LEN + read_u32(&message[4..8]) as usize;
+        if headers_end > crc_start {
+            return Err(anyhow!("Event stream headers overrun the message"));
+        }
+
+        let mut headers = HashMap::new();
+        let mut bytes = &message[EVENT_STREAM_PRELUDE_LEN..headers_end];
+        while !bytes.is_empty() {
+            let name_len = bytes[0] as usize;
+            let name = take(&mut bytes, 1 + name_len)?[1..].to_vec();
+            let value_type = take(&mut bytes, 1)?[0];
+            // Type 7 is a string; the others are skipped by their size
+            let value_len = match value_type {
+                0 | 1 => 0,
+                2 => 1,
+                3 => 2,
+                4 => 4,
+                5 | 8 => 8,
+                9 => 16,
+                6 | 7 => match bytes.get(..2) {
+                    Some(len) => 2 + read_u16(len) as usize,
+                    None => return Err(anyhow!("Event stream header is truncated")),
+                },
+                _ => return Err(anyhow!("Unknown event stream header type {}", value_type)),
+            };
+            let value = take(&mut bytes, value_len)?;
+            if value_type == 7 {
+                headers.insert(
+                    String::from_utf8_lossy(&name).into_owned(),
+                    String::from_utf8_lossy(&value[2..]).into_owned(),
+                );
+            }
+        }
+
+        Ok(EventStreamMessage {
+            headers,
+            payload: message[headers_end..crc_start].to_vec(),
+        })
+    }
+}
+
+fn read_u32(bytes: &[u8]) -> u32 {
+    u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+}
+
+fn read_u16(bytes: &[u8]) -> u16 {
+    u16::from_be_bytes([bytes[0], bytes[1]])
+}
+
+/// Split the first `len` bytes off `bytes`
+fn take<'a>(bytes: &mut &'a [u8], len: usize) -> Result<&'a [u8]> {
+    if bytes.len() < len {
+        return Err(anyhow!("Event stream header is truncated"));
+    }
+    let (head, rest) = bytes.split_at(len);
+    *bytes = rest;
+    Ok(head)
+}
+
+/// Decode a response body stream into Server-Sent Events
+///
+/// A transport error is yielded once and ends the stream.
+pub fn sse_events<S, B, E>(bytes: S) -> impl Stream<Item = Result<SseEvent>> + Send + Unpin
+where
+    S: Stream<Item = std::result::Result<B, E>> + Send + 'static,
+    B: AsRef<[u8]> + Send,
+    E: std::fmt::Display + Send,
+{
+    Box::pin(stream! {
+        let mut decoder = SseDecoder::new();
+        let mut bytes = Box::pin(bytes);
+        while let Some(chunk) = bytes.next().await {
+            match chunk {
+                Ok(chunk) => {
+                    for event in decoder.push(chunk.as_ref()) {
+                        yield Ok(event);
+                    }
+                }
+                Err(e) => {
+                    yield Err(anyhow!("Stream error: {}", e));
+                    return;
+                }
+            }
+        }
+        if let Some(event) = decoder.finish() {
+            yield Ok(event);
+        }
+    })
+}
+
+/// Decode a response body stream into newline-delimited JSON lines
+///
+/// A transport error is yielded once and ends the stream.
+pub fn ndjson_lines<S, B, E>(bytes: S) -> impl Stream<Item = Result<String>> + Send + Unpin
+where
+    S: Stream<Item = std::result::Result<B, E>> + Send + 'static,
+    B: AsRef<[u8]> + Send,
+    E: std::fmt::Display + Send,
+{
+    Box::pin(stream! {
+        let mut decoder = NdjsonDecoder::new();
+        let mut bytes = Box::pin(bytes);
+        while let Some(chunk) = bytes.next().await {
+            match chunk {
+                Ok(chunk) => {
+                    for line in decoder.push(chunk.as_ref()) {
+                        yield Ok(line);
+                    }
+                }
+                Err(e) => {
+                    yield Err(anyhow!("Stream error: {}", e));
+                    return;
+                }
+            }
+        }
+        if let Some(line) = decoder.finish() {
+            yield Ok(line);
+        }
+    })
+}
+
+/// Decode a response body stream into AWS event stream messages
+///
+/// A transport or framing error is yielded once and ends the stream.
+pub fn event_stream_messages<S, B, E>(bytes: S) -> impl Stream<Item = Result<EventStreamMessage>> + Send + Unpin
+where
+    S: Stream<Item = std::result::Result<B, E>> + Send + 'static,
+    B: AsRef<[u8]> + Send,
+    E: std::fmt::Display + Send,
+{
+    Box::pin(stream! {
+        let mut decoder = EventStreamDecoder::new();
+        let mut bytes = Box::pin(bytes);
+        while let Some(chunk) = bytes.next().await {
+            let messages = match chunk {
+                Ok(chunk) => decoder.push(chunk.as_ref()),
+                Err(e) => Err(anyhow!("Stream error: {}", e)),
+            };
+            match messages {
+                Ok(messages) => {
+                    for message in messages {
+                        yield Ok(message);
+                    }
+                }
+                Err(e) => {
+                    yield Err(e);
+                    return;
+                }
+            }
+        }
+        if let Err(e) = decoder.finish() {
+            yield Err(e);
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use quickcheck::{quickcheck, TestResult};
+
+    fn decode_sse(chunks: &[&[u8]]) -> Vec<SseEvent> {
+        let mut decoder = SseDecoder::new();
+        let mut events: Vec<SseEvent> = chunks.iter().flat_map(|chunk| decoder.push(chunk)).collect();
+        events.extend(decoder.finish());
+        events
+    }
+
+    fn decode_ndjson(chunks: &[&[u8]]) -> Vec<String> {
+        let mut decoder = NdjsonDecoder::new();
+        let mut lines: Vec<String> = chunks.iter().flat_map(|chunk| decoder.push(chunk)).collect();
+        lines.extend(decoder.finish());
+        lines
+    }
+
+    /// Cut `bytes` at the given offsets (taken modulo its length)
+    fn split_at_offsets<'a>(bytes: &'a [u8], offsets: &[usize]) -> Vec<&'a [u8]> {
+        let mut cuts: Vec<usize> = offsets.iter().map(|o| o % (bytes.len() + 1)).collect();
+        cuts.push(0);
+        cuts.push(bytes.len());
+        cuts.sort_unstable();
+        cuts.windows(2).map(|w| &bytes[w[0]..w[1]]).collect()
+    }
+
+    fn single_line(s: &str) -> String {
+        s.replace(['\r', '\n'], "")
+    }
+
+    #[test]
+    fn test_sse_fields() {
+        let stream = "\u{feff}: keep-alive\r\n\
+                      event: message_start\r\n\
+                      id: 7\r\n\
+                      data: {\"a\":\r\n\
+                      data:1}\r\n\
+                      \r\n\
+                      data: [DONE]\r\r\
+                      event: ignored-without-data\n\n";
+
+        assert_eq!(
+            decode_sse(&[stream.as_bytes()]),
+            vec![
+                SseEvent {
+                    event: Some("message_start".to_string()),
+                    data: "{\"a\":\n1}".to_string(),
+                    id: Some("7".to_string()),
+                },
+                SseEvent {
+                    event: None,
+                    data: "[DONE]".to_string(),
+                    id: Some("7".to_string()),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_split_multibyte_character() {
+        let stream = "data: h\u{e9}llo \u{1f980}\n\n".as_bytes();
+        // Cut inside both the two-byte and the four-byte character
+        let events = decode_sse(&[&stream[..8], &stream[8..15], &stream[15..]]);
+        assert_eq!(events[0].data, "h\u{e9}llo \u{1f980}");
+    }
+
+    #[test]
+    fn test_crlf_split_by_an_empty_chunk() {
+        let events = decode_sse(&[b"data: a\r\ndata: b\r", b"", b"\ndata: c\r\n\r\n"]);
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].data, "a\nb\nc");
+    }
+
+    #[test]
+    fn test_unterminated_last_event_is_dispatched() {
+        let events = decode_sse(&[b"data: {\"done\":true}"]);
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].data, "{\"done\":true}");
+
+        assert_eq!(decode_ndjson(&[b"{\"a\":1}\n\n{\"b\":", b"2}"]), vec!["{\"a\":1}", "{\"b\":2}"]);
+    }
+
+    #[test]
+    fn prop_sse_chunk_splits_do_not_change_events() {
+        fn prop(events: Vec<(Option<String>, Vec<String>)>, offsets: Vec<usize>, crlf: bool) -> TestResult {
+            let terminator = if crlf { "\r\n" } else { "\n" };
+            let mut stream = String::new();
+            let mut expected = Vec::new();
+            for (event, data) in &events {
+                let event = event.as_deref().map(single_line);
+                if let Some(event) = &event {
+                    stream.push_str(&format!("event: {}{}", event, terminator));
+                }
+                let data: Vec<String> = data.iter().map(|line| single_line(line)).collect();
+                for line in &data {
+                    stream.push_str(&format!("data: {}{}", line, terminator));
+                }
+                stream.push_str(terminator);
+                if !data.is_empty() {
+                    expected.push(SseEvent { event, data: data.join("\n"), id: None });
+                }
+            }
+
+            let chunks = split_at_offsets(stream.as_bytes(), &offsets);
+            TestResult::from_bool(decode_sse(&chunks) == expected)
+        }
+        quickcheck(prop as fn(Vec<(Option<String>, Vec<String>)>, Vec<usize>, bool) -> TestResult);
+    }
+
+    #[test]
+    fn prop_ndjson_chunk_splits_do_not_change_lines() {
+        fn prop(lines: Vec<String>, offsets: Vec<usize>) -> TestResult {
+            let lines: Vec<String> = lines
+                .iter()
+                .map(|line| single_line(line))
+                .filter(|line| !line.trim().is_empty())
+                .collect();
+            let stream: String = lines.iter().map(|line| format!("{}\n", line)).collect();
+
+            let chunks = split_at_offsets(stream.as_bytes(), &offsets);
+            TestResult::from_bool(decode_ndjson(&chunks) == lines)
+        }
+        quickcheck(prop as fn(Vec<String>, Vec<usize>) -> TestResult);
+    }
+
+    /// A `contentBlockDelta` message as Bedrock sends it, with its checksums
+    const EVENT_STREAM_FIXTURE: &str = "0000009f00000057c37babff0b3a6576656e742d74797065070011636f6e74656e74426c6f\
+        636b44656c74610d3a636f6e74656e742d747970650700106170706c69636174696f6e2f6a736f6e0d3a6d657373616765\
+        2d747970650700056576656e747b22636f6e74656e74426c6f636b496e646578223a302c2264656c7461223a7b227465\
+        7874223a224869227d2c2270223a2261626364227df2ace5ff";
+
+    fn unhex(hex: &str) -> Vec<u8> {
+        (0..hex.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_event_stream_fixture_at_every_split() {
+        let bytes = unhex(EVENT_STREAM_FIXTURE);
+        for cut in 0..=bytes.len() {
+            let mut decoder = EventStreamDecoder::new();
+            let mut messages = decoder.push(&bytes[..cut]).unwrap();
+            messages.extend(decoder.push(&bytes[cut..]).unwrap());
+            decoder.finish().unwrap();
+
+            assert_eq!(messages.len(), 1);
+            assert_eq!(messages[0].header(":event-type"), Some("contentBlockDelta"));
+            assert_eq!(messages[0].header(":message-type"), Some("event"));
+            assert_eq!(
+                messages[0].payload,
+                br#"{"contentBlockIndex":0,"delta":{"text":"Hi"},"p":"abcd"}"#.to_vec()
+            );
+        }
+    }
+
+    #[test]
+    fn test_event_stream_checksum_mismatch_is_an_error() {
+        let mut bytes = unhex(EVENT_STREAM_FIXTURE);
+        let last_payload_byte = bytes.len() - 5;
+        bytes[last_payload_byte] ^= 1;
+        assert!(EventStreamDecoder::new().push(&bytes).is_err());
+
+        let mut decoder = EventStreamDecoder::new();
+        decoder.push(&unhex(EVENT_STREAM_FIXTURE)[..20]).unwrap();
+        assert!(decoder.finish().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_transport_error_ends_stream() {
+        let chunks: Vec<std::result::Result<&'static [u8], &'static str>> =
+            vec![Ok(b"data: one\n\n"), Err("connection reset"), Ok(b"data: two\n\n")];
+        let events: Vec<Result<SseEvent>> = sse_events(futures::stream::iter(chunks)).collect().await;
+
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0].as_ref().unwrap().data, "one");
+        assert!(events[1].as_ref().unwrap_err().to_string().contains("connection reset"));
+    }
+}
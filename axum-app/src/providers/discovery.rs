@@ -0,0 +1,373 @@
+//! Live model discovery
+//!
+//! Each provider lists the models it serves through [`AIProvider::discover_models`]. The
+//! [`ModelCatalog`] asks every registered provider at startup and again once its TTL has
+//! passed, and serves the cached list in between. Only one refresh runs at a time; requests
+//! that find the cache expired while one runs wait for its result. A provider that cannot
+//! be reached keeps the models it listed last, or falls back to its built-in list.
+//!
+//! The catalog also holds the models file overrides (see [`super::capabilities`]), which are
+//! applied on top of what the providers report and can add models no provider lists.
+//!
+//! [`AIProvider::discover_models`]: super::AIProvider::discover_models
+
+use futures::future::join_all;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::RwLock;
+use std::time::{Duration, Instant};
+use tokio::sync::Mutex;
+use tracing::warn;
+
+use super::capabilities::{self, ModelCapabilities, ModelOverride};
+use super::ProviderRegistry;
+
+/// Default time before the catalog is refreshed
+const DEFAULT_TTL: Duration = Duration::from_secs(3600);
+
+/// A model as a provider lists it
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct ModelInfo {
+    /// Model id the provider knows it by
+    pub id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub display_name: Option<String>,
+    #[serde(flatten)]
+    pub capabilities: ModelCapabilities,
+}
+
+impl ModelInfo {
+    pub fn new(id: impl Into<String>) -> Self {
+        Self { id: id.into(), ..Default::default() }
+    }
+}
+
+/// A model in the catalog
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct CatalogModel {
+    /// Name to request the model by, `provider/model`
+    pub id: String,
+    pub provider: String,
+    /// Model id the provider knows it by
+    pub model: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub display_name: Option<String>,
+    #[serde(flatten)]
+    pub capabilities: ModelCapabilities,
+}
+
+impl CatalogModel {
+    fn new(provider: &str, info: ModelInfo) -> Self {
+        Self {
+            id: format!("{}/{}", provider, info.id),
+            provider: provider.to_string(),
+            model: info.id,
+            display_name: info.display_name,
+            capabilities: info.capabilities,
+        }
+    }
+}
+
+struct CachedModels {
+    fetched_at: Instant,
+    models: Vec<CatalogModel>,
+}
+
+/// Cache of the models every registered provider serves, with their capabilities
+pub struct ModelCatalog {
+    ttl: Duration,
+    overrides: HashMap<String, ModelOverride>,
+    cache: RwLock<Option<CachedModels>>,
+    /// Held while the providers are asked for their models
+    refreshing: Mutex<()>,
+}
+
+impl std::fmt::Debug for ModelCatalog {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ModelCatalog")
+            .field("ttl", &self.ttl)
+            .field("overrides", &self.overrides.len())
+            .finish()
+    }
+}
+
+impl Default for ModelCatalog {
+    fn default() -> Self {
+        Self::new(DEFAULT_TTL)
+    }
+}
+
+impl ModelCatalog {
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            ttl,
+            overrides: HashMap::new(),
+            cache: RwLock::new(None),
+            refreshing: Mutex::new(()),
+        }
+    }
+
+    /// Capabilities set for models, keyed by `provider/model` or by the bare model name
+    pub fn with_overrides(mut self, overrides: HashMap<String, ModelOverride>) -> Self {
+        self.overrides = overrides;
+        self
+    }
+
+    /// Catalog whose TTL is read from `MODEL_CACHE_TTL_SECS` (default one hour; 0 disables
+    /// caching), with the overrides of the models file
+    pub async fn from_env() -> Self {
+        let ttl = std::env::var("MODEL_CACHE_TTL_SECS")
+            .ok()
+            .and_then(|v| v.parse::<u64>().ok())
+            .map(Duration::from_secs)
+            .unwrap_or(DEFAULT_TTL);
+        Self::new(ttl).with_overrides(capabilities::load_overrides().await)
+    }
+
+    pub fn ttl(&self) -> Duration {
+        self.ttl
+    }
+
+    /// The cached models, refreshed first if the cache is empty or older than the TTL
+    pub async fn models(&self, registry: &ProviderRegistry) -> Vec<CatalogModel> {
+        if let Some(models) = self.fresh_models() {
+            return models;
+        }
+        let asked = Instant::now();
+        let _refreshing = self.refreshing.lock().await;
+        // A refresh that finished while this one waited answers it too
+        match self.models_fetched_since(asked) {
+            Some(models) => models,
+            None => self.fetch(registry).await,
+        }
+    }
+
+    fn fresh_models(&self) -> Option<Vec<CatalogModel>> {
+        let cache = self.cache.read().unwrap();
+        cache
+            .as_ref()
+            .filter(|cached| cached.fetched_at.elapsed() < self.ttl)
+            .map(|cached| cached.models.clone())
+    }
+
+    fn models_fetched_since(&self, since: Instant) -> Option<Vec<CatalogModel>> {
+        let cache = self.cache.read().unwrap();
+        cache
+            .as_ref()
+            .filter(|cached| cached.fetched_at >= since)
+            .map(|cached| cached.models.clone())
+    }
+
+    fn cached_models(&self) -> Vec<CatalogModel> {
+        let cache = self.cache.read().unwrap();
+        cache.as_ref().map(|cached| cached.models.clone()).unwrap_or_default()
+    }
+
+    /// Ask every provider for its models and replace the cache
+    pub async fn refresh(&self, registry: &ProviderRegistry) -> Vec<CatalogModel> {
+        let _refreshing = self.refreshing.lock().await;
+        self.fetch(registry).await
+    }
+
+    /// [`Self::refresh`], for a caller holding the refresh lock
+    async fn fetch(&self, registry: &ProviderRegistry) -> Vec<CatalogModel> {
+        let names = registry.names();
+        let discovered = join_all(names.iter().map(|name| async move {
+            match registry.get(name) {
+                Some(provider) => provider.discover_models().await,
+                None => Ok(Vec::new()),
+            }
+        }))
+        .await;
+
+        let previous = self.cached_models();
+        let mut models = Vec::new();
+        for (name, result) in names.iter().zip(discovered) {
+            match result {
+                Ok(listed) => models.extend(listed.into_iter().map(|info| CatalogModel::new(name, info))),
+                Err(e) => {
+                    warn!("Failed to list the models of {}: {}", name, e);
+                    let known: Vec<CatalogModel> =
+                        previous.iter().filter(|model| &model.provider == name).cloned().collect();
+                    if known.is_empty() {
+                        let built_in = registry.get(name).map(|p| p.get_available_models()).unwrap_or_default();
+                        models.extend(built_in.into_iter().map(|id| CatalogModel::new(name, ModelInfo::new(id))));
+                    } else {
+                        models.extend(known);
+                    }
+                }
+            }
+        }
+
+        // Models the file adds to a configured provider
+        for (key, entry) in &self.overrides {
+            if let Some((provider, model)) = self.override_target(key, entry) {
+                if registry.get(&provider).is_some()
+                    && !models.iter().any(|listed| listed.provider == provider && listed.model == model)
+                {
+                    models.push(CatalogModel::new(&provider, ModelInfo::new(model)));
+                }
+            }
+        }
+
+        for model in &mut models {
+            model.capabilities = self.apply_overrides(&model.provider, &model.model, model.capabilities.clone());
+        }
+
+        *self.cache.write().unwrap() = Some(CachedModels {
+            fetched_at: Instant::now(),
+            models: models.clone(),
+        });
+        models
+    }
+
+    /// Provider and model of an override that names its provider
+    fn override_target(&self, key: &str, entry: &ModelOverride) -> Option<(String, String)> {
+        match &entry.provider {
+            Some(provider) => Some((provider.clone(), key.to_string())),
+            None => key.split_once('/').map(|(provider, model)| (provider.to_string(), model.to_string())),
+        }
+    }
+
+    /// `capabilities` with the overrides for the bare model name and then for
+    /// `provider/model` applied
+    fn apply_overrides(&self, provider: &str, model: &str, capabilities: ModelCapabilities) -> ModelCapabilities {
+        let bare = self.overrides.get(model);
+        let qualified = self.overrides.get(&format!("{}/{}", provider, model));
+        [bare, qualified]
+            .into_iter()
+            .flatten()
+            .fold(capabilities, |capabilities, entry| capabilities.merged(&entry.capabilities))
+    }
+
+    /// What `model` is known to support when served by `provider`
+    pub fn capabilities(&self, provider: &str, model: &str) -> ModelCapabilities {
+        let listed = self
+            .cached_models()
+            .into_iter()
+            .find(|listed| listed.provider == provider && listed.model == model);
+        match listed {
+            Some(listed) => listed.capabilities,
+            None => self.apply_overrides(provider, model, ModelCapabilities::default()),
+        }
+    }
+
+    /// Whether `provider` is known to serve `model`
+    pub fn lists(&self, provider: &str, model: &str) -> bool {
+        let cache = self.cache.read().unwrap();
+        cache
+            .as_ref()
+            .is_some_and(|cached| cached.models.iter().any(|listed| listed.provider == provider && listed.model == model))
+    }
+
+    /// The provider serving a bare model name: the one the models file names, otherwise the
+    /// first provider that lists the model
+    pub fn provider_of(&self, model: &str) -> Option<String> {
+        if let Some(provider) = self.overrides.get(model).and_then(|entry| entry.provider.clone()) {
+            return Some(provider);
+        }
+        self.cached_models()
+            .into_iter()
+            .find(|listed| listed.model == model)
+            .map(|listed| listed.provider)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::providers::testing::StaticProvider;
+    use crate::chat::ChatMessage;
+    use crate::providers::{AIProvider, ChunkStream, GenerationOptions, ToolChoice, ToolSpec};
+    use anyhow::{anyhow, Result};
+    use async_trait::async_trait;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    /// Lists one model the first time it is asked, and fails after that
+    #[derive(Default)]
+    struct FlakyProvider {
+        calls: AtomicUsize,
+    }
+
+    #[async_trait]
+    impl AIProvider for FlakyProvider {
+        async fn chat_completion(
+            &self,
+            _messages: Vec<ChatMessage>,
+            _model: Option<String>,
+            _options: GenerationOptions,
+            _tools: Option<Vec<ToolSpec>>,
+            _tool_choice: Option<ToolChoice>,
+        ) -> Result<ChatMessage> {
+            Err(anyhow!("not used"))
+        }
+
+        async fn chat_completion_stream(
+            &self,
+            _messages: Vec<ChatMessage>,
+            _model: Option<String>,
+            _options: GenerationOptions,
+            _tools: Option<Vec<ToolSpec>>,
+            _tool_choice: Option<ToolChoice>,
+        ) -> Result<ChunkStream> {
+            Err(anyhow!("not used"))
+        }
+
+        fn get_available_models(&self) -> Vec<&'static str> {
+            vec!["built-in"]
+        }
+
+        async fn discover_models(&self) -> Result<Vec<ModelInfo>> {
+            // Let concurrent refreshes overlap
+            tokio::task::yield_now().await;
+            match self.calls.fetch_add(1, Ordering::SeqCst) {
+                0 => Ok(vec![ModelInfo {
+                    capabilities: ModelCapabilities { context_window: Some(8192), ..Default::default() },
+                    ..ModelInfo::new("live")
+                }]),
+                _ => Err(anyhow!("connection refused")),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_catalog_caches_and_keeps_models_on_failure() {
+        let flaky = Arc::new(FlakyProvider::default());
+        let mut registry = ProviderRegistry::new();
+        registry.register("flaky", flaky.clone());
+        registry.register("static", Arc::new(StaticProvider("static")));
+
+        let catalog = ModelCatalog::new(Duration::from_secs(60));
+        let models = catalog.models(&registry).await;
+        assert_eq!(models.len(), 1);
+        assert_eq!(models[0].id, "flaky/live");
+        assert_eq!(models[0].capabilities.context_window, Some(8192));
+
+        // Served from the cache until the TTL passes
+        assert_eq!(catalog.models(&registry).await, models);
+        assert_eq!(flaky.calls.load(Ordering::SeqCst), 1);
+
+        // A failed refresh keeps what the provider listed before
+        assert_eq!(catalog.refresh(&registry).await, models);
+
+        // Concurrent requests on an expired cache share one refresh
+        let flaky = Arc::new(FlakyProvider::default());
+        let mut registry = ProviderRegistry::new();
+        registry.register("flaky", flaky.clone());
+        let catalog = ModelCatalog::new(Duration::from_secs(60));
+        let (first, second) = tokio::join!(catalog.models(&registry), catalog.models(&registry));
+        assert_eq!(first, second);
+        assert_eq!(first[0].id, "flaky/live");
+        assert_eq!(flaky.calls.load(Ordering::SeqCst), 1);
+
+        // Without anything listed before, the built-in list is used
+        let catalog = ModelCatalog::new(Duration::ZERO);
+        let models = catalog.models(&registry).await;
+        assert_eq!(models[0].id, "flaky/built-in");
+        assert_eq!(
+            serde_json::to_value(&models[0]).unwrap(),
+            serde_json::json!({"id": "flaky/built-in", "provider": "flaky", "model": "built-in"})
+        );
+    }
+}
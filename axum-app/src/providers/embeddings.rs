@@ -0,0 +1,25 @@
+//! Text embeddings
+//!
+//! Providers with an embeddings API implement [`EmbeddingProvider`] and hand it out through
+//! [`super::AIProvider::as_embedder`]. Each provider embeds with its own default model when
+//! the request names none.
+
+use anyhow::Result;
+use async_trait::async_trait;
+
+/// One vector per input text, in input order
+#[derive(Debug, Clone, PartialEq)]
+pub struct Embeddings {
+    /// Model that produced the vectors
+    pub model: String,
+    pub vectors: Vec<Vec<f32>>,
+    /// Input tokens, for providers that report them
+    pub prompt_tokens: Option<u32>,
+}
+
+/// A provider that can embed text
+#[async_trait]
+pub trait EmbeddingProvider: Send + Sync {
+    /// Embed each of `texts` with `model`, or the provider's default embedding model
+    async fn embed(&self, texts: Vec<String>, model: Option<String>) -> Result<Embeddings>;
+}
@@ -0,0 +1,204 @@
+//! Named OpenAI-compatible endpoints
+//!
+//! Endpoints are read from a JSON file (`ENDPOINTS_CONFIG_PATH`, default `endpoints.json`) and
+//! registered as providers, addressed as `endpoint_name/model`, e.g. `groq/llama-3.1-70b-versatile`.
+//! The `OPENAI_API_KEY` environment configuration is registered as the `openai` endpoint unless
+//! the file defines one. Azure OpenAI configured in the environment is the `azure` endpoint, and
+//! the `openai` one as well when there is no OpenAI key. The same file defines model aliases,
+//! tried in order with failover.
+
+use anyhow::{anyhow, Result};
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::Arc;
+use tokio::fs;
+use tracing::{info, warn};
+
+use super::{AzureDeployments, OpenAIService};
+
+/// Name of the endpoint used for models without an endpoint prefix
+pub const DEFAULT_ENDPOINT: &str = "openai";
+
+/// Name of the Azure OpenAI endpoint configured in the environment
+pub const AZURE_ENDPOINT: &str = "azure";
+
+/// Endpoints keyed by name
+pub type OpenAIEndpoints = HashMap<String, Arc<OpenAIService>>;
+
+/// Model aliases and the models they stand for, in failover order
+pub type ModelAliases = HashMap<String, Vec<String>>;
+
+/// Endpoint configuration file
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct EndpointsConfig {
+    /// Endpoints keyed by the name used as model prefix
+    #[serde(default)]
+    pub endpoints: HashMap<String, EndpointConfig>,
+    /// Model aliases, e.g. `"smart": ["anthropic/claude-sonnet-4-5", "openai/gpt-4o"]`
+    #[serde(default)]
+    pub aliases: ModelAliases,
+}
+
+/// A single OpenAI-compatible endpoint
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EndpointConfig {
+    /// Base URL including the version segment, e.g. `https://api.groq.com/openai/v1`
+    pub base_url: String,
+    /// API key; `${VAR}` reads it from the environment. Omit for servers without auth
+    #[serde(default)]
+    pub api_key: Option<String>,
+    /// Extra headers sent with every request; values support `${VAR}`
+    #[serde(default)]
+    pub headers: HashMap<String, String>,
+    /// Models served by this endpoint
+    #[serde(default)]
+    pub models: Vec<String>,
+    /// Model used when a request names the endpoint without a model
+    #[serde(default)]
+    pub default_model: Option<String>,
+    /// Makes this an Azure OpenAI resource, with `base_url` its endpoint
+    #[serde(default)]
+    pub azure: Option<AzureDeployments>,
+}
+
+impl EndpointConfig {
+    /// Build the service for this endpoint
+    pub fn to_service(&self) -> Result<OpenAIService> {
+        let api_key = self
+            .api_key
+            .as_deref()
+            .map(expand_env)
+            .transpose()?
+            .unwrap_or_default();
+
+        let mut headers = HeaderMap::new();
+        for (name, value) in &self.headers {
+            let name = HeaderName::from_bytes(name.as_bytes())
+                .map_err(|e| anyhow!("Invalid header name '{}': {}", name, e))?;
+            let value = HeaderValue::from_str(&expand_env(value)?)
+                .map_err(|e| anyhow!("Invalid value for header '{}': {}", name, e))?;
+            headers.insert(name, value);
+        }
+
+        let default_model = self.default_model.clone().or_else(|| self.models.first().cloned());
+
+        let service = OpenAIService::new(api_key, default_model)
+            .with_base_url(self.base_url.clone())
+            .with_headers(headers)
+            .with_models(self.models.clone());
+        Ok(match &self.azure {
+            Some(azure) => service.with_azure(azure.clone()),
+            None => service,
+        })
+    }
+}
+
+/// Replace a `${VAR}` value with the environment variable it names
+fn expand_env(value: &str) -> Result<String> {
+    match value.strip_prefix("${").and_then(|v| v.strip_suffix('}')) {
+        Some(var) => std::env::var(var)
+            .map_err(|_| anyhow!("{} environment variable not set", var)),
+        None => Ok(value.to_string()),
+    }
+}
+
+/// Load the endpoints file, returning an empty config if it does not exist
+pub async fn load_config<P: AsRef<Path>>(path: P) -> Result<EndpointsConfig> {
+    let path = path.as_ref();
+    match fs::read_to_string(path).await {
+        Ok(content) => serde_json::from_str(&content)
+            .map_err(|e| anyhow!("Failed to parse endpoints config '{}': {}", path.display(), e)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(EndpointsConfig::default()),
+        Err(e) => Err(anyhow!("Failed to read endpoints config '{}': {}", path.display(), e)),
+    }
+}
+
+/// Build the endpoint map from the environment and the endpoints file, with the file's aliases
+///
+/// Endpoints that fail to build (e.g. a missing key variable) are skipped with a warning so
+/// one bad entry does not take down the others.
+pub async fn load_endpoints() -> (OpenAIEndpoints, ModelAliases) {
+    let mut endpoints = OpenAIEndpoints::new();
+
+    if let Ok(service) = OpenAIService::from_env() {
+        endpoints.insert(DEFAULT_ENDPOINT.to_string(), Arc::new(service));
+    }
+    if let Ok(service) = OpenAIService::azure_from_env() {
+        let service = Arc::new(service);
+        endpoints
+            .entry(DEFAULT_ENDPOINT.to_string())
+            .or_insert_with(|| service.clone());
+        endpoints.insert(AZURE_ENDPOINT.to_string(), service);
+    }
+
+    let path = std::env::var("ENDPOINTS_CONFIG_PATH").unwrap_or_else(|_| "endpoints.json".to_string());
+    let config = match load_config(&path).await {
+        Ok(config) => config,
+        Err(e) => {
+            warn!("{}", e);
+            return (endpoints, ModelAliases::new());
+        }
+    };
+
+    for (name, endpoint) in &config.endpoints {
+        match endpoint.to_service() {
+            Ok(service) => {
+                endpoints.insert(name.clone(), Arc::new(service));
+            }
+            Err(e) => warn!("Skipping endpoint '{}': {}", name, e),
+        }
+    }
+
+    if !config.endpoints.is_empty() {
+        info!("Loaded {} OpenAI-compatible endpoints from {}", config.endpoints.len(), path);
+    }
+    (endpoints, config.aliases)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_missing_key_variable_is_an_error() {
+        let endpoint = EndpointConfig {
+            base_url: "https://api.together.xyz/v1".to_string(),
+            api_key: Some("${ENDPOINTS_TEST_UNSET_KEY}".to_string()),
+            headers: HashMap::new(),
+            models: vec![],
+            default_model: None,
+            azure: None,
+        };
+        assert!(endpoint.to_service().is_err());
+    }
+
+    #[test]
+    fn test_azure_endpoint_serves_its_deployments() {
+        let config: EndpointsConfig = serde_json::from_value(serde_json::json!({
+            "endpoints": {
+                "azure": {
+                    "base_url": "https://my-resource.openai.azure.com",
+                    "api_key": "key",
+                    "azure": {"deployments": {"gpt-4o": "prod-gpt4o", "gpt-4o-mini": "mini"}}
+                }
+            }
+        }))
+        .unwrap();
+
+        let endpoint = &config.endpoints["azure"];
+        let azure = endpoint.azure.as_ref().unwrap();
+        assert_eq!(azure.api_version, crate::providers::openai::DEFAULT_AZURE_API_VERSION);
+        assert_eq!(azure.deployment("gpt-4o"), "prod-gpt4o");
+        assert_eq!(azure.deployment("o3-mini"), "o3-mini");
+        assert_eq!(endpoint.to_service().unwrap().models(), ["gpt-4o", "gpt-4o-mini"]);
+    }
+
+    #[tokio::test]
+    async fn test_missing_config_file_is_empty() {
+        let config = load_config("does-not-exist-endpoints.json").await.unwrap();
+        assert!(config.endpoints.is_empty());
+        assert!(config.aliases.is_empty());
+    }
+}
@@ -0,0 +1,94 @@
+//! Errors reported by provider APIs
+//!
+//! Providers return these wrapped in `anyhow::Error`; callers that care about the upstream
+//! status (e.g. to pass a 429 through) downcast with [`ProviderError::from_error`], and
+//! callers that report blocked content with [`ContentFilterError::from_error`].
+
+use reqwest::{Response, StatusCode};
+
+/// A provider API answered with a non-success status
+#[derive(Debug, Clone)]
+pub struct ProviderError {
+    /// Display name of the provider, e.g. `OpenAI`
+    pub provider: &'static str,
+    pub status: StatusCode,
+    /// Response body, usually the provider's JSON error object
+    pub message: String,
+}
+
+impl ProviderError {
+    /// Build the error from a failed response, consuming its body
+    pub async fn from_response(provider: &'static str, response: Response) -> Self {
+        let status = response.status();
+        let message = response.text().await.unwrap_or_default();
+        Self { provider, status, message }
+    }
+
+    /// The provider error behind an `anyhow::Error`, if that is what it is
+    pub fn from_error(error: &anyhow::Error) -> Option<&Self> {
+        error.downcast_ref::<Self>()
+    }
+}
+
+impl std::fmt::Display for ProviderError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} API error: {} - {}", self.provider, self.status, self.message)
+    }
+}
+
+impl std::error::Error for ProviderError {}
+
+/// A provider's content filter blocked the prompt or the answer
+#[derive(Debug, Clone, PartialEq)]
+pub struct ContentFilterError {
+    /// Display name of the provider, e.g. `Gemini`
+    pub provider: &'static str,
+    /// Why it was blocked, e.g. `SAFETY` or `PROHIBITED_CONTENT`
+    pub reason: String,
+    /// Whether the prompt was blocked rather than the answer
+    pub prompt: bool,
+    /// Harm categories that caused the block, if the provider names them
+    pub categories: Vec<String>,
+}
+
+impl ContentFilterError {
+    /// The content filter error behind an `anyhow::Error`, if that is what it is
+    pub fn from_error(error: &anyhow::Error) -> Option<&Self> {
+        error.downcast_ref::<Self>()
+    }
+}
+
+impl std::fmt::Display for ContentFilterError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let what = if self.prompt { "prompt" } else { "response" };
+        write!(f, "{} blocked the {}: {}", self.provider, what, self.reason)?;
+        if !self.categories.is_empty() {
+            write!(f, " ({})", self.categories.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ContentFilterError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_provider_error_survives_anyhow() {
+        let error: anyhow::Error = ProviderError {
+            provider: "OpenAI",
+            status: StatusCode::TOO_MANY_REQUESTS,
+            message: r#"{"error":{"message":"Rate limit reached"}}"#.to_string(),
+        }
+        .into();
+
+        assert!(error.to_string().starts_with("OpenAI API error: 429 Too Many Requests - "));
+        let provider_error = ProviderError::from_error(&error).unwrap();
+        assert_eq!(provider_error.status, StatusCode::TOO_MANY_REQUESTS);
+
+        let context = error.context("while streaming");
+        assert!(ProviderError::from_error(&context).is_some());
+    }
+}
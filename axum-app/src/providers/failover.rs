@@ -0,0 +1,122 @@
+//! Failover along a chain of providers
+//!
+//! A model alias resolves to several providers (see [`ProviderRegistry::resolve_chain`]).
+//! Requests go to the first; when it is unavailable, rate limited or erroring, the next one
+//! is tried. Errors that would fail the same way anywhere, such as a rejected request, end
+//! the chain straight away.
+//!
+//! [`ProviderRegistry::resolve_chain`]: super::ProviderRegistry::resolve_chain
+
+use std::future::Future;
+
+use anyhow::Result;
+use tracing::warn;
+
+use super::retry::is_retryable;
+use super::{ProviderError, ResolvedProvider};
+use crate::chat::ChatMessage;
+
+/// Whether `error` means the provider could not answer, so the next provider should be tried
+pub fn should_fail_over(error: &anyhow::Error) -> bool {
+    match ProviderError::from_error(error) {
+        // Bad credentials or an unknown model are specific to this provider
+        Some(e) => is_retryable(e.status) || matches!(e.status.as_u16(), 401 | 403 | 404),
+        // Connection failures and timeouts
+        None => error.downcast_ref::<reqwest::Error>().is_some(),
+    }
+}
+
+/// Call each provider in `chain` in turn until one answers
+///
+/// Returns the provider that answered, or the last one tried along with its error.
+pub async fn run<'a, T, F, Fut>(
+    chain: &'a [ResolvedProvider],
+    mut call: F,
+) -> (&'a ResolvedProvider, Result<T>)
+where
+    F: FnMut(&'a ResolvedProvider) -> Fut,
+    Fut: Future<Output = Result<T>>,
+{
+    assert!(!chain.is_empty(), "failover chain must not be empty");
+
+    let mut providers = chain.iter().peekable();
+    while let Some(resolved) = providers.next() {
+        let result = call(resolved).await;
+        match &result {
+            Err(e) if providers.peek().is_some() && should_fail_over(e) => {
+                warn!("{} ({}) failed, trying the next provider: {}", resolved.name, resolved.model, e);
+            }
+            _ => return (resolved, result),
+        }
+    }
+    unreachable!("the last provider always returns")
+}
+
+/// Record the provider that answered on the response, keeping the model it reported
+pub fn record_provider(message: &mut ChatMessage, resolved: &ResolvedProvider) {
+    let metadata = message.metadata.get_or_insert_with(Default::default);
+    metadata.insert("provider".to_string(), serde_json::Value::String(resolved.name.clone()));
+    metadata
+        .entry("model".to_string())
+        .or_insert_with(|| serde_json::Value::String(resolved.model.clone()));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::providers::testing::StaticProvider;
+    use reqwest::StatusCode;
+    use std::sync::Arc;
+
+    fn chain(names: &[&'static str]) -> Vec<ResolvedProvider> {
+        names
+            .iter()
+            .map(|name| ResolvedProvider {
+                name: name.to_string(),
+                provider: Arc::new(StaticProvider(name)),
+                model: format!("{}-model", name),
+            })
+            .collect()
+    }
+
+    fn provider_error(status: StatusCode) -> anyhow::Error {
+        ProviderError { provider: "Test", status, message: String::new() }.into()
+    }
+
+    #[test]
+    fn test_failover_errors() {
+        assert!(should_fail_over(&provider_error(StatusCode::TOO_MANY_REQUESTS)));
+        assert!(should_fail_over(&provider_error(StatusCode::SERVICE_UNAVAILABLE)));
+        assert!(should_fail_over(&provider_error(StatusCode::UNAUTHORIZED)));
+        assert!(!should_fail_over(&provider_error(StatusCode::BAD_REQUEST)));
+        assert!(!should_fail_over(&anyhow::anyhow!("No valid messages to process")));
+    }
+
+    #[tokio::test]
+    async fn test_falls_through_to_the_next_provider() {
+        let chain = chain(&["anthropic", "openai", "openrouter"]);
+        let (resolved, result) = run(&chain, |resolved| async move {
+            match resolved.name.as_str() {
+                "anthropic" => Err(provider_error(StatusCode::from_u16(529).unwrap())),
+                _ => Ok(resolved.model.clone()),
+            }
+        })
+        .await;
+
+        assert_eq!(resolved.name, "openai");
+        assert_eq!(result.unwrap(), "openai-model");
+    }
+
+    #[tokio::test]
+    async fn test_stops_at_errors_that_would_repeat() {
+        let chain = chain(&["anthropic", "openai"]);
+        let (resolved, result) = run(&chain, |_| async { Err::<(), _>(provider_error(StatusCode::BAD_REQUEST)) }).await;
+        assert_eq!(resolved.name, "anthropic");
+        assert!(result.is_err());
+
+        // The last provider's error is returned when every provider fails
+        let (resolved, result) = run(&chain, |_| async { Err::<(), _>(provider_error(StatusCode::BAD_GATEWAY)) }).await;
+        assert_eq!(resolved.name, "openai");
+        assert!(result.is_err());
+    }
+}
@@ -1,23 +1,56 @@
 use anyhow::Result;
 use async_stream::stream;
-use futures::{stream::BoxStream, Stream, StreamExt};
-use reqwest::Client;
+use futures::StreamExt;
+use reqwest::{Client, RequestBuilder};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::time::Duration;
 
-use crate::chat::{ChatMessage, ChatRole, UIMessageChunk};
+use crate::chat::{ChatMessage, ChatRole, UIMessageChunk, Usage};
 use async_trait::async_trait;
-use super::AIProvider;
+use super::attachments;
+use super::decoder;
+use super::google_auth::{ServiceAccountKey, ServiceAccountTokenSource};
+use super::reasoning::{Reasoning, ReasoningStream};
+use super::tools::{TOOL_CALLS_KEY, TOOL_NAME_KEY};
+use super::{
+    AIProvider, ChunkStream, ContentFilterError, EmbeddingProvider, Embeddings, GenerationOptions, ModelCapabilities,
+    ModelInfo, ResponseFormat, RetryPolicy, ToolCall, ToolChoice, ToolSpec,
+};
+
+/// Embedding model used when a request names none
+const DEFAULT_EMBEDDING_MODEL: &str = "text-embedding-004";
+
+/// Vertex AI region used when none is configured
+const DEFAULT_VERTEX_LOCATION: &str = "us-central1";
+
+/// Finish reasons of an answer stopped by the content filter
+const BLOCKED_FINISH_REASONS: [&str; 5] = ["SAFETY", "BLOCKLIST", "PROHIBITED_CONTENT", "SPII", "IMAGE_SAFETY"];
 
 /// Google Gemini API Service
-/// Provides integration with Google's Gemini AI models
+/// Provides integration with Google's Gemini AI models, through the Gemini API (AI Studio)
+/// or Vertex AI
 #[derive(Debug)]
 pub struct GeminiService {
     client: Client,
+    /// Client for streams, which can outlive the request timeout of `client`
+    stream_client: Client,
     api_key: String,
     base_url: String,
     default_model: String,
+    /// Thought summaries and thinking budget of thinking models; `None` keeps the model defaults
+    thinking_config: Option<GeminiThinkingConfig>,
+    /// Set in Vertex AI mode, which authenticates with OAuth tokens instead of the API key
+    vertex: Option<VertexAI>,
+    retry: RetryPolicy,
+}
+
+/// The Google Cloud project and region Vertex AI requests go to, and their credentials
+#[derive(Debug)]
+struct VertexAI {
+    project: String,
+    location: String,
+    auth: ServiceAccountTokenSource,
 }
 
 impl GeminiService {
@@ -28,19 +61,99 @@ impl GeminiService {
                 .timeout(Duration::from_secs(60))
                 .build()
                 .unwrap(),
+            stream_client: super::stream_client_builder().build().unwrap(),
             api_key,
             base_url: "https://generativelanguage.googleapis.com".to_string(),
             default_model: model.unwrap_or_else(|| "gemini-1.5-flash".to_string()),
+            thinking_config: None,
+            vertex: None,
+            retry: RetryPolicy::from_env(),
         }
     }
 
+    /// Send requests to Vertex AI in `project` and `location`, authenticated by `auth`
+    pub fn with_vertex(mut self, project: String, location: String, auth: ServiceAccountTokenSource) -> Self {
+        // The global location has no regional host
+        self.base_url = match location.as_str() {
+            "global" => "https://aiplatform.googleapis.com".to_string(),
+            location => format!("https://{}-aiplatform.googleapis.com", location),
+        };
+        self.vertex = Some(VertexAI { project, location, auth });
+        self
+    }
+
     /// Create Gemini service from environment variables
     pub fn from_env() -> Result<Self> {
         let api_key = std::env::var("GOOGLE_AI_API_KEY")
             .map_err(|_| anyhow::anyhow!("GOOGLE_AI_API_KEY environment variable not set"))?;
 
         let model = std::env::var("GEMINI_MODEL").ok();
-        Ok(Self::new(api_key, model))
+        Ok(Self::new(api_key, model).with_thinking_from_env())
+    }
+
+    /// Configure Vertex AI from the service account key in `GOOGLE_APPLICATION_CREDENTIALS`
+    ///
+    /// The project comes from `VERTEX_PROJECT`, defaulting to the key's project, and the region
+    /// from `VERTEX_LOCATION`. `VERTEX_TOKEN_URI` overrides the key's token endpoint.
+    pub fn vertex_from_env() -> Result<Self> {
+        let path = std::env::var("GOOGLE_APPLICATION_CREDENTIALS")
+            .map_err(|_| anyhow::anyhow!("GOOGLE_APPLICATION_CREDENTIALS environment variable not set"))?;
+        let mut auth = ServiceAccountTokenSource::new(ServiceAccountKey::from_file(path)?)?;
+        if let Ok(token_uri) = std::env::var("VERTEX_TOKEN_URI") {
+            auth = auth.with_token_uri(token_uri);
+        }
+
+        let project = std::env::var("VERTEX_PROJECT")
+            .ok()
+            .or_else(|| auth.project_id().map(str::to_string))
+            .ok_or_else(|| anyhow::anyhow!("VERTEX_PROJECT environment variable not set"))?;
+        let location = std::env::var("VERTEX_LOCATION").unwrap_or_else(|_| DEFAULT_VERTEX_LOCATION.to_string());
+        let model = std::env::var("GEMINI_MODEL").ok();
+
+        Ok(Self::new(String::new(), model)
+            .with_vertex(project, location, auth)
+            .with_thinking_from_env())
+    }
+
+    /// Apply `GEMINI_INCLUDE_THOUGHTS` and `GEMINI_THINKING_BUDGET`
+    fn with_thinking_from_env(mut self) -> Self {
+        // Only thinking models accept a thinking config, so it is opt-in
+        let include_thoughts = std::env::var("GEMINI_INCLUDE_THOUGHTS")
+            .map(|v| v == "true" || v == "1")
+            .unwrap_or(false);
+        let thinking_budget = std::env::var("GEMINI_THINKING_BUDGET")
+            .ok()
+            .and_then(|v| v.parse::<i32>().ok());
+        if include_thoughts || thinking_budget.is_some() {
+            self.thinking_config = Some(GeminiThinkingConfig {
+                include_thoughts: include_thoughts.then_some(true),
+                thinking_budget,
+            });
+        }
+        self
+    }
+
+    /// URL of a model method, e.g. `generateContent`
+    fn url(&self, model: &str, method: &str) -> String {
+        match &self.vertex {
+            Some(vertex) => format!(
+                "{}/v1/projects/{}/locations/{}/publishers/google/models/{}:{}",
+                self.base_url,
+                vertex.project,
+                vertex.location,
+                model.strip_prefix("models/").unwrap_or(model),
+                method
+            ),
+            None => format!("{}/v1beta/models/{}:{}", self.base_url, model, method),
+        }
+    }
+
+    /// Add the API key, or in Vertex AI mode a current access token
+    async fn authorize(&self, request: RequestBuilder) -> Result<RequestBuilder> {
+        match &self.vertex {
+            Some(vertex) => Ok(request.bearer_auth(vertex.auth.token().await?)),
+            None => Ok(request.query(&[("key", &self.api_key)])),
+        }
     }
 
     /// Get available Gemini models
@@ -56,50 +169,214 @@ impl GeminiService {
 
     /// Convert chat messages to Gemini format
     fn convert_to_gemini_messages(messages: &[ChatMessage]) -> Vec<GeminiContent> {
-        messages
-            .iter()
-            .filter_map(|msg| {
-                match msg.role {
-                    ChatRole::User => Some(GeminiContent {
+        let mut contents: Vec<GeminiContent> = Vec::new();
+
+        for msg in messages {
+            match msg.role {
+                ChatRole::User => {
+                    // Images and documents go first, as Gemini recommends for media prompts
+                    let mut parts: Vec<GeminiPart> = attachments::images(msg)
+                        .chain(attachments::documents(msg))
+                        .filter_map(attachments::inline_data)
+                        .map(|inline| GeminiPart {
+                            inline_data: Some(GeminiInlineData {
+                                mime_type: inline.media_type,
+                                data: inline.data,
+                            }),
+                            ..Default::default()
+                        })
+                        .collect();
+                    if !msg.content.is_empty() || parts.is_empty() {
+                        parts.push(GeminiPart::text(msg.content.clone()));
+                    }
+                    contents.push(GeminiContent {
                         role: "user".to_string(),
-                        parts: vec![GeminiPart {
-                            text: msg.content.clone(),
-                        }],
-                    }),
-                    ChatRole::Assistant => Some(GeminiContent {
+                        parts,
+                    });
+                }
+                ChatRole::Assistant => {
+                    let mut parts = Vec::new();
+                    if !msg.content.is_empty() {
+                        parts.push(GeminiPart::text(msg.content.clone()));
+                    }
+                    for call in ToolCall::from_metadata(msg.metadata.as_ref()).unwrap_or_default() {
+                        parts.push(GeminiPart {
+                            function_call: Some(GeminiFunctionCall {
+                                name: call.name,
+                                args: call.arguments,
+                            }),
+                            ..Default::default()
+                        });
+                    }
+                    contents.push(GeminiContent {
                         role: "model".to_string(),
-                        parts: vec![GeminiPart {
-                            text: msg.content.clone(),
-                        }],
-                    }),
-                    ChatRole::System => {
-                        // Gemini doesn't have system messages, so we prepend to the first user message
-                        None
+                        parts,
+                    });
+                }
+                ChatRole::Tool => {
+                    let name = msg
+                        .metadata
+                        .as_ref()
+                        .and_then(|m| m.get(TOOL_NAME_KEY))
+                        .and_then(|v| v.as_str())
+                        .unwrap_or_default()
+                        .to_string();
+                    let part = GeminiPart {
+                        function_response: Some(GeminiFunctionResponse {
+                            name,
+                            response: Self::function_response_body(&msg.content),
+                        }),
+                        ..Default::default()
+                    };
+
+                    // Responses to parallel function calls must share a single turn
+                    match contents.last_mut() {
+                        Some(last) if last.role == "user"
+                            && last.parts.iter().all(|p| p.function_response.is_some()) =>
+                        {
+                            last.parts.push(part);
+                        }
+                        _ => contents.push(GeminiContent {
+                            role: "user".to_string(),
+                            parts: vec![part],
+                        }),
                     }
                 }
-            })
-            .collect()
+                ChatRole::System => {
+                    // Sent as the system instruction
+                }
+            }
         }
 
-    /// Extract system message and prepend to first user message
-    fn extract_system_message(messages: &[ChatMessage]) -> (Option<String>, Vec<ChatMessage>) {
-        let system_msg = messages
-            .iter()
-            .find(|msg| matches!(msg.role, ChatRole::System))
-            .map(|msg| msg.content.clone());
+        contents
+    }
 
-        let mut filtered_messages: Vec<ChatMessage> = messages
+    /// Gemini expects function responses as JSON objects
+    fn function_response_body(content: &str) -> serde_json::Value {
+        match serde_json::from_str::<serde_json::Value>(content) {
+            Ok(value @ serde_json::Value::Object(_)) => value,
+            Ok(value) => serde_json::json!({ "result": value }),
+            Err(_) => serde_json::json!({ "result": content }),
+        }
+    }
+
+    /// Convert tool specs to Gemini function declarations
+    fn convert_tools(tools: Option<Vec<ToolSpec>>) -> Option<Vec<GeminiTool>> {
+        tools.filter(|t| !t.is_empty()).map(|tools| {
+            vec![GeminiTool {
+                function_declarations: tools
+                    .into_iter()
+                    .map(|tool| GeminiFunctionDeclaration {
+                        name: tool.name,
+                        description: tool.description,
+                        parameters: Self::sanitize_schema(tool.parameters),
+                    })
+                    .collect(),
+            }]
+        })
+    }
+
+    /// Convert tool choice to Gemini's function calling config
+    fn convert_tool_choice(tool_choice: Option<ToolChoice>) -> Option<serde_json::Value> {
+        let config = match tool_choice? {
+            ToolChoice::Auto => serde_json::json!({ "mode": "AUTO" }),
+            ToolChoice::None => serde_json::json!({ "mode": "NONE" }),
+            ToolChoice::Required => serde_json::json!({ "mode": "ANY" }),
+            ToolChoice::Function { name } => serde_json::json!({
+                "mode": "ANY",
+                "allowedFunctionNames": [name]
+            }),
+        };
+        Some(serde_json::json!({ "functionCallingConfig": config }))
+    }
+
+    /// Remove JSON Schema keywords that Gemini's OpenAPI schema subset rejects
+    fn sanitize_schema(schema: serde_json::Value) -> serde_json::Value {
+        match schema {
+            serde_json::Value::Object(map) => serde_json::Value::Object(
+                map.into_iter()
+                    .filter(|(key, _)| key != "$schema" && key != "additionalProperties")
+                    .map(|(key, value)| (key, Self::sanitize_schema(value)))
+                    .collect(),
+            ),
+            serde_json::Value::Array(items) => {
+                serde_json::Value::Array(items.into_iter().map(Self::sanitize_schema).collect())
+            }
+            other => other,
+        }
+    }
+
+    /// Convert a function call part into a tool call
+    ///
+    /// Gemini does not report call ids; the full random range keeps the ids of parallel calls
+    /// in one turn apart, so that their results can be matched to them.
+    fn tool_call_from_part(part: &GeminiPart) -> Option<ToolCall> {
+        part.function_call.as_ref().map(|call| ToolCall {
+            id: format!("gemini_call_{}", fastrand::u64(..)),
+            name: call.name.clone(),
+            arguments: call.args.clone(),
+        })
+    }
+
+    /// Split the system messages off as a system instruction, one part per message
+    fn extract_system_instruction(messages: &[ChatMessage]) -> (Option<GeminiParts>, Vec<ChatMessage>) {
+        let (system, filtered_messages): (Vec<ChatMessage>, Vec<ChatMessage>) = messages
             .iter()
-            .filter(|msg| !matches!(msg.role, ChatRole::System))
             .cloned()
+            .partition(|msg| matches!(msg.role, ChatRole::System));
+
+        let parts: Vec<GeminiPart> = system
+            .into_iter()
+            .filter(|msg| !msg.content.is_empty())
+            .map(|msg| GeminiPart::text(msg.content))
             .collect();
+        let instruction = (!parts.is_empty()).then_some(GeminiParts { parts });
 
-        // If we have a system message and user messages, prepend it
-        if let (Some(sys_msg), Some(first_user)) = (system_msg.as_ref(), filtered_messages.first_mut()) {
-            first_user.content = format!("{}\n\n{}", sys_msg, first_user.content);
+        (instruction, filtered_messages)
+    }
+
+    fn build_request(
+        &self,
+        contents: Vec<GeminiContent>,
+        system_instruction: Option<GeminiParts>,
+        options: GenerationOptions,
+        tools: Option<Vec<ToolSpec>>,
+        tool_choice: Option<ToolChoice>,
+    ) -> GeminiRequest {
+        GeminiRequest {
+            contents,
+            system_instruction,
+            safety_settings: Some(Self::safety_settings(&options)),
+            generation_config: Some(self.generation_config(options)),
+            tools: Self::convert_tools(tools),
+            tool_config: Self::convert_tool_choice(tool_choice),
         }
+    }
 
-        (system_msg, filtered_messages)
+    /// The content filter error for a blocked prompt or answer, if either was blocked
+    fn content_filter(
+        feedback: Option<&GeminiPromptFeedback>,
+        candidate: Option<&GeminiCandidate>,
+    ) -> Option<ContentFilterError> {
+        if let Some(feedback) = feedback {
+            if let Some(reason) = &feedback.block_reason {
+                return Some(ContentFilterError {
+                    provider: "Gemini",
+                    reason: reason.clone(),
+                    prompt: true,
+                    categories: GeminiSafetyRating::blocked_categories(feedback.safety_ratings.as_deref()),
+                });
+            }
+        }
+
+        let candidate = candidate?;
+        let reason = candidate.finish_reason.as_deref()?;
+        BLOCKED_FINISH_REASONS.contains(&reason).then(|| ContentFilterError {
+            provider: "Gemini",
+            reason: reason.to_string(),
+            prompt: false,
+            categories: GeminiSafetyRating::blocked_categories(candidate.safety_ratings.as_deref()),
+        })
     }
 
     /// Generate chat completion (non-streaming)
@@ -107,64 +384,95 @@ impl GeminiService {
         &self,
         messages: Vec<ChatMessage>,
         model: Option<String>,
-        temperature: Option<f32>,
-        max_tokens: Option<u32>,
+        options: GenerationOptions,
+        tools: Option<Vec<ToolSpec>>,
+        tool_choice: Option<ToolChoice>,
     ) -> Result<ChatMessage> {
         let model = model.unwrap_or_else(|| self.default_model.clone());
-        let (system_msg, filtered_messages) = Self::extract_system_message(&messages);
+        let (system_instruction, filtered_messages) = Self::extract_system_instruction(&messages);
 
         if filtered_messages.is_empty() {
             return Err(anyhow::anyhow!("No valid messages to process"));
         }
 
+        let filtered_messages = attachments::inline_remote_attachments(filtered_messages, attachments::is_media).await?;
         let gemini_contents = Self::convert_to_gemini_messages(&filtered_messages);
 
-        let request = GeminiRequest {
-            contents: gemini_contents,
-            generation_config: Some(GeminiGenerationConfig {
-                temperature,
-                max_output_tokens: max_tokens,
-                top_p: None,
-                top_k: None,
-                candidate_count: Some(1),
-                stop_sequences: None,
-            }),
-            safety_settings: Some(Self::default_safety_settings()),
-        };
+        let request = self.build_request(gemini_contents, system_instruction, options, tools, tool_choice);
 
-        let url = format!(
-            "{}/v1beta/models/{}:generateContent?key={}",
-            self.base_url, model, self.api_key
-        );
+        let url = self.url(&model, "generateContent");
+        let builder = self.authorize(self.client.post(&url).json(&request)).await?;
+        let response = self.retry.send("Gemini", builder).await?;
 
-        let response = self
-            .client
-            .post(&url)
-            .json(&request)
-            .send()
-            .await?;
+        let gemini_response: GeminiResponse = response.json().await?;
 
-        if !response.status().is_success() {
-            let error_text = response.text().await?;
-            return Err(anyhow::anyhow!("Gemini API error: {}", error_text));
+        let first = gemini_response.candidates.as_ref().and_then(|candidates| candidates.first());
+        if let Some(blocked) = Self::content_filter(gemini_response.prompt_feedback.as_ref(), first) {
+            return Err(blocked.into());
         }
 
-        let gemini_response: GeminiResponse = response.json().await?;
-
-        if let Some(candidates) = gemini_response.candidates {
+        if let Some(candidates) = &gemini_response.candidates {
             if let Some(candidate) = candidates.first() {
                 if let Some(content) = &candidate.content {
-                    if let Some(part) = content.parts.first() {
+                    let text: String = content
+                        .parts
+                        .iter()
+                        .filter(|part| !part.is_thought())
+                        .filter_map(|part| part.text.as_deref())
+                        .collect();
+                    let reasoning: String = content
+                        .parts
+                        .iter()
+                        .filter(|part| part.is_thought())
+                        .filter_map(|part| part.text.as_deref())
+                        .collect();
+                    let tool_calls: Vec<ToolCall> = content
+                        .parts
+                        .iter()
+                        .filter_map(Self::tool_call_from_part)
+                        .collect();
+
+                    if !text.is_empty() || !tool_calls.is_empty() {
+                        let mut metadata = HashMap::from([
+                            ("model".to_string(), serde_json::Value::String(model)),
+                            ("provider".to_string(), serde_json::Value::String("gemini".to_string())),
+                        ]);
+                        if let Some(usage) = &gemini_response.usage_metadata {
+                            metadata.insert("usage".to_string(), serde_json::to_value(usage.to_usage()).unwrap_or(serde_json::Value::Null));
+                        }
+                        if !tool_calls.is_empty() {
+                            metadata.insert(
+                                TOOL_CALLS_KEY.to_string(),
+                                serde_json::to_value(&tool_calls).unwrap_or(serde_json::Value::Null),
+                            );
+                        }
+                        if !reasoning.is_empty() {
+                            Reasoning::insert_into(&[Reasoning::new(reasoning)], &mut metadata);
+                        }
+                        // Further answers, when more than one candidate was asked for
+                        let others: Vec<String> = candidates[1..]
+                            .iter()
+                            .filter_map(|candidate| candidate.content.as_ref())
+                            .map(|content| {
+                                content
+                                    .parts
+                                    .iter()
+                                    .filter(|part| !part.is_thought())
+                                    .filter_map(|part| part.text.as_deref())
+                                    .collect()
+                            })
+                            .collect();
+                        if !others.is_empty() {
+                            metadata.insert("candidates".to_string(), serde_json::json!(others));
+                        }
+
                         return Ok(ChatMessage {
                             id: format!("gemini_{}", fastrand::u64(1000..9999)),
                             role: ChatRole::Assistant,
-                            content: part.text.clone(),
+                            content: text,
                             created_at: Some(chrono::Utc::now()),
                             attachments: None,
-                            metadata: Some(HashMap::from([
-                                ("model".to_string(), serde_json::Value::String(model)),
-                                ("provider".to_string(), serde_json::Value::String("gemini".to_string())),
-                            ])),
+                            metadata: Some(metadata),
                         });
                     }
                 }
@@ -179,125 +487,200 @@ impl GeminiService {
         &self,
         messages: Vec<ChatMessage>,
         model: Option<String>,
-        temperature: Option<f32>,
-        max_tokens: Option<u32>,
-    ) -> BoxStream<'static, Result<UIMessageChunk, anyhow::Error>> {
+        options: GenerationOptions,
+        tools: Option<Vec<ToolSpec>>,
+        tool_choice: Option<ToolChoice>,
+    ) -> Result<ChunkStream> {
         let model = model.unwrap_or_else(|| self.default_model.clone());
-        let (system_msg, filtered_messages) = Self::extract_system_message(&messages);
+        let (system_instruction, filtered_messages) = Self::extract_system_instruction(&messages);
 
         if filtered_messages.is_empty() {
-            return Box::pin(stream! {
-                yield Err(anyhow::anyhow!("No valid messages to process"));
-            });
+            return Err(anyhow::anyhow!("No valid messages to process"));
         }
 
+        let filtered_messages = attachments::inline_remote_attachments(filtered_messages, attachments::is_media).await?;
         let gemini_contents = Self::convert_to_gemini_messages(&filtered_messages);
-        let api_key = self.api_key.clone();
-        let base_url = self.base_url.clone();
 
-        Box::pin(stream! {
-            // Send text-start event
-            yield Ok(UIMessageChunk::TextStart);
+        let request = self.build_request(gemini_contents, system_instruction, options, tools, tool_choice);
 
-            let request = GeminiRequest {
-                contents: gemini_contents,
-                generation_config: Some(GeminiGenerationConfig {
-                    temperature,
-                    max_output_tokens: max_tokens,
-                    top_p: None,
-                    top_k: None,
-                    candidate_count: Some(1),
-                    stop_sequences: None,
-                }),
-                safety_settings: Some(Self::default_safety_settings()),
-            };
+        let url = format!("{}?alt=sse", self.url(&model, "streamGenerateContent"));
 
-            let url = format!(
-                "{}/v1beta/models/{}:streamGenerateContent?key={}",
-                base_url, model, api_key
-            );
+        let builder = self.authorize(self.stream_client.post(&url).json(&request)).await?;
+        let response = self.retry.send("Gemini", builder).await?;
 
-            let client = Client::new();
+        Ok(Box::pin(stream! {
+            // Send text-start event
+            yield Ok(UIMessageChunk::TextStart);
 
-            match client.post(&url).json(&request).send().await {
-                Ok(response) => {
-                    if !response.status().is_success() {
-                        let error_text = response.text().await.unwrap_or_default();
-                        yield Ok(UIMessageChunk::Error {
-                            error: format!("Gemini API error: {}", error_text),
-                        });
+            let mut events = decoder::sse_events(response.bytes_stream());
+            let mut reasoning = ReasoningStream::new();
+            let mut usage = None;
+
+            while let Some(event) = events.next().await {
+                let event = match event {
+                    Ok(event) => event,
+                    Err(e) => {
+                        yield Err(e);
                         return;
                     }
+                };
 
-                    let mut stream = response.bytes_stream();
-                    let mut buffer = String::new();
-
-                    while let Some(chunk_result) = stream.next().await {
-                        match chunk_result {
-                            Ok(chunk) => {
-                                if let Ok(text) = String::from_utf8(chunk.to_vec()) {
-                                    buffer.push_str(&text);
-
-                                    // Process complete JSON objects
-                                    while let Some(newline_pos) = buffer.find('\n') {
-                                        let line = buffer[..newline_pos].to_string();
-                                        buffer = buffer[newline_pos + 1..].to_string();
-
-                                        if line.trim().starts_with("data: ") {
-                                            let json_str = &line[6..];
-                                            if let Ok(gemini_chunk) = serde_json::from_str::<GeminiStreamResponse>(json_str) {
-                                                let has_candidates = gemini_chunk.candidates.is_some();
-                                                if let Some(ref candidates) = gemini_chunk.candidates {
-                                                    if let Some(candidate) = candidates.first() {
-                                                        if let Some(content) = &candidate.content {
-                                                            if let Some(part) = content.parts.first() {
-                                                                yield Ok(UIMessageChunk::TextDelta {
-                                                                    textDelta: part.text.clone(),
-                                                                });
-                                                            }
-                                                        }
-                                                    }
-                                                }
-
-                                                // Check if this is a completion chunk
-                                                if !has_candidates && gemini_chunk.prompt_feedback.is_none() {
-                                                    yield Ok(UIMessageChunk::TextFinish);
-
-                                                    // Send finish event
-                                                    let usage = crate::chat::Usage {
-                                                        prompt_tokens: 0,
-                                                        completion_tokens: 0,
-                                                        total_tokens: 0,
-                                                    };
-                                                    yield Ok(UIMessageChunk::Finish {
-                                                        reasoning: None,
-                                                        sources: None,
-                                                        usage: Some(usage),
-                                                        logprobs: None,
-                                                    });
-                                                    return;
-                                                }
-                                            }
-                                        }
+                // Each event carries a complete GenerateContentResponse
+                if let Ok(gemini_chunk) = serde_json::from_str::<GeminiStreamResponse>(&event.data) {
+                    // Every chunk reports the usage so far; the last one has the totals
+                    if let Some(usage_metadata) = &gemini_chunk.usage_metadata {
+                        usage = Some(usage_metadata.to_usage());
+                    }
+                    // Only the first candidate is streamed
+                    let candidate = gemini_chunk
+                        .candidates
+                        .as_ref()
+                        .and_then(|candidates| candidates.iter().find(|c| c.index.unwrap_or(0) == 0));
+                    if let Some(blocked) = Self::content_filter(gemini_chunk.prompt_feedback.as_ref(), candidate) {
+                        yield Err(blocked.into());
+                        return;
+                    }
+                    if let Some(candidate) = candidate {
+                        if let Some(content) = &candidate.content {
+                            for part in &content.parts {
+                                // Thought summaries come before the answer
+                                if part.is_thought() {
+                                    for chunk in reasoning.delta(part.text.as_deref().unwrap_or_default()) {
+                                        yield Ok(chunk);
                                     }
+                                    continue;
+                                }
+                                if let Some(chunk) = reasoning.end(None) {
+                                    yield Ok(chunk);
+                                }
+
+                                if let Some(text) = &part.text {
+                                    yield Ok(UIMessageChunk::TextDelta {
+                                        textDelta: text.clone(),
+                                    });
+                                }
+                                // Function calls always arrive whole, never fragmented
+                                if let Some(call) = Self::tool_call_from_part(part) {
+                                    yield Ok(call.into_chunk());
                                 }
-                            }
-                            Err(e) => {
-                                yield Ok(UIMessageChunk::Error {
-                                    error: format!("Stream error: {}", e),
-                                });
-                                break;
                             }
                         }
                     }
                 }
-                Err(e) => {
-                    yield Ok(UIMessageChunk::Error {
-                        error: format!("Request error: {}", e),
-                    });
+            }
+
+            // Gemini has no end-of-stream marker; the response is complete once the body ends
+            if let Some(chunk) = reasoning.end(None) {
+                yield Ok(chunk);
+            }
+            yield Ok(UIMessageChunk::TextFinish);
+
+            // Send finish event
+            yield Ok(UIMessageChunk::Finish {
+                reasoning: reasoning.text(),
+                sources: None,
+                usage,
+                logprobs: None,
+            });
+        }))
+    }
+
+    /// Generation config for `options`; a JSON response format becomes a JSON MIME type,
+    /// with the schema if one is given
+    fn generation_config(&self, options: GenerationOptions) -> GeminiGenerationConfig {
+        let (response_mime_type, response_schema) = match &options.response_format {
+            Some(ResponseFormat::JsonObject) => (Some("application/json".to_string()), None),
+            Some(ResponseFormat::JsonSchema { json_schema }) => (
+                Some("application/json".to_string()),
+                Some(Self::convert_schema(&json_schema.schema)),
+            ),
+            Some(ResponseFormat::Text) | None => (None, None),
+        };
+
+        GeminiGenerationConfig {
+            temperature: options.temperature,
+            max_output_tokens: options.max_tokens,
+            top_p: options.top_p,
+            top_k: options.top_k.map(|top_k| top_k as i32),
+            candidate_count: Some(options.candidate_count.unwrap_or(1) as i32),
+            stop_sequences: options.stop_sequences(),
+            seed: options.seed,
+            presence_penalty: options.presence_penalty,
+            frequency_penalty: options.frequency_penalty,
+            response_mime_type,
+            response_schema,
+            thinking_config: self.thinking_config.clone(),
+        }
+    }
+
+    /// Convert a JSON Schema to the OpenAPI subset Gemini accepts
+    ///
+    /// Types are upper-cased, a `["T", "null"]` type becomes a nullable `T`, and keywords
+    /// Gemini rejects, such as `additionalProperties` and `$schema`, are dropped.
+    fn convert_schema(schema: &serde_json::Value) -> serde_json::Value {
+        let object = match schema.as_object() {
+            Some(object) => object,
+            None => return schema.clone(),
+        };
+
+        let mut converted = serde_json::Map::new();
+        for (key, value) in object {
+            match key.as_str() {
+                "type" => match value {
+                    serde_json::Value::String(type_) => {
+                        converted.insert(key.clone(), serde_json::json!(type_.to_uppercase()));
+                    }
+                    serde_json::Value::Array(types) => {
+                        if let Some(type_) = types.iter().filter_map(|t| t.as_str()).find(|t| *t != "null") {
+                            converted.insert(key.clone(), serde_json::json!(type_.to_uppercase()));
+                        }
+                        if types.iter().any(|t| t == "null") {
+                            converted.insert("nullable".to_string(), serde_json::json!(true));
+                        }
+                    }
+                    _ => {}
+                },
+                "properties" => {
+                    let properties: serde_json::Map<String, serde_json::Value> = value
+                        .as_object()
+                        .into_iter()
+                        .flatten()
+                        .map(|(name, property)| (name.clone(), Self::convert_schema(property)))
+                        .collect();
+                    converted.insert(key.clone(), serde_json::Value::Object(properties));
+                }
+                "items" => {
+                    converted.insert(key.clone(), Self::convert_schema(value));
+                }
+                "anyOf" => {
+                    let variants: Vec<serde_json::Value> =
+                        value.as_array().into_iter().flatten().map(Self::convert_schema).collect();
+                    converted.insert(key.clone(), serde_json::Value::Array(variants));
                 }
+                "description" | "enum" | "format" | "nullable" | "required" | "minItems" | "maxItems"
+                | "minimum" | "maximum" | "propertyOrdering" => {
+                    converted.insert(key.clone(), value.clone());
+                }
+                _ => {}
             }
-        })
+        }
+        serde_json::Value::Object(converted)
+    }
+
+    /// The default safety settings, with the thresholds `options` sets for a category replaced
+    fn safety_settings(options: &GenerationOptions) -> Vec<GeminiSafetySetting> {
+        let mut settings = Self::default_safety_settings();
+        for setting in options.safety_settings.iter().flatten() {
+            let threshold = setting.threshold.clone();
+            match settings.iter_mut().find(|default| default.category == setting.category) {
+                Some(default) => default.threshold = threshold,
+                None => settings.push(GeminiSafetySetting {
+                    category: setting.category.clone(),
+                    threshold,
+                }),
+            }
+        }
+        settings
     }
 
     /// Default safety settings for Gemini
@@ -328,9 +711,28 @@ impl GeminiService {
 struct GeminiRequest {
     contents: Vec<GeminiContent>,
     #[serde(skip_serializing_if = "Option::is_none")]
+    system_instruction: Option<GeminiParts>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     generation_config: Option<GeminiGenerationConfig>,
     #[serde(skip_serializing_if = "Option::is_none")]
     safety_settings: Option<Vec<GeminiSafetySetting>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tools: Option<Vec<GeminiTool>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tool_config: Option<serde_json::Value>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct GeminiTool {
+    function_declarations: Vec<GeminiFunctionDeclaration>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct GeminiFunctionDeclaration {
+    name: String,
+    description: String,
+    parameters: serde_json::Value,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -339,12 +741,63 @@ struct GeminiContent {
     parts: Vec<GeminiPart>,
 }
 
+/// Content without a role, for the system instruction and for embeddings
 #[derive(Debug, Clone, Serialize, Deserialize)]
+struct GeminiParts {
+    parts: Vec<GeminiPart>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
 struct GeminiPart {
-    text: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    text: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    function_call: Option<GeminiFunctionCall>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    function_response: Option<GeminiFunctionResponse>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    inline_data: Option<GeminiInlineData>,
+    /// Marks the text as a thought summary rather than part of the answer
+    #[serde(skip_serializing_if = "Option::is_none")]
+    thought: Option<bool>,
+}
+
+impl GeminiPart {
+    fn text(text: String) -> Self {
+        Self {
+            text: Some(text),
+            ..Default::default()
+        }
+    }
+
+    fn is_thought(&self) -> bool {
+        self.thought.unwrap_or(false)
+    }
 }
 
+/// Base64-encoded media sent inline with a prompt
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct GeminiInlineData {
+    mime_type: String,
+    data: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct GeminiFunctionCall {
+    name: String,
+    #[serde(default)]
+    args: serde_json::Value,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct GeminiFunctionResponse {
+    name: String,
+    response: serde_json::Value,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 struct GeminiGenerationConfig {
     #[serde(skip_serializing_if = "Option::is_none")]
     temperature: Option<f32>,
@@ -358,6 +811,30 @@ struct GeminiGenerationConfig {
     candidate_count: Option<i32>,
     #[serde(skip_serializing_if = "Option::is_none")]
     stop_sequences: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    seed: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    presence_penalty: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    frequency_penalty: Option<f32>,
+    /// `application/json` constrains the answer to JSON
+    #[serde(skip_serializing_if = "Option::is_none")]
+    response_mime_type: Option<String>,
+    /// Schema the JSON answer must match
+    #[serde(skip_serializing_if = "Option::is_none")]
+    response_schema: Option<serde_json::Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    thinking_config: Option<GeminiThinkingConfig>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct GeminiThinkingConfig {
+    /// Return thought summaries as parts marked `thought`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    include_thoughts: Option<bool>,
+    /// Thinking tokens allowed; `0` turns thinking off and `-1` lets the model decide
+    #[serde(skip_serializing_if = "Option::is_none")]
+    thinking_budget: Option<i32>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -366,19 +843,148 @@ struct GeminiSafetySetting {
     threshold: String,
 }
 
+/// Request of `embedContent`, and of each text in `batchEmbedContents`
+#[derive(Debug, Clone, Serialize)]
+struct GeminiEmbedRequest {
+    /// `models/{model}`
+    model: String,
+    content: GeminiParts,
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct GeminiBatchEmbedRequest {
+    requests: Vec<GeminiEmbedRequest>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct GeminiEmbedResponse {
+    embedding: GeminiEmbedding,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct GeminiBatchEmbedResponse {
+    embeddings: Vec<GeminiEmbedding>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct GeminiEmbedding {
+    values: Vec<f32>,
+}
+
+/// Vertex AI `predict` request for text embedding models
+#[derive(Debug, Clone, Serialize)]
+struct VertexEmbedRequest {
+    instances: Vec<VertexEmbedInstance>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct VertexEmbedInstance {
+    content: String,
+}
+
 #[derive(Debug, Clone, Deserialize)]
+struct VertexEmbedResponse {
+    predictions: Vec<VertexEmbedPrediction>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct VertexEmbedPrediction {
+    embeddings: VertexEmbedding,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct VertexEmbedding {
+    values: Vec<f32>,
+    statistics: Option<VertexEmbeddingStatistics>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct VertexEmbeddingStatistics {
+    /// Sent as a JSON number that may have a fractional part
+    token_count: f64,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct GeminiModelList {
+    #[serde(default)]
+    models: Vec<GeminiModel>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct GeminiModel {
+    /// `models/` followed by the model id
+    name: String,
+    display_name: Option<String>,
+    input_token_limit: Option<u32>,
+    output_token_limit: Option<u32>,
+    #[serde(default)]
+    supported_generation_methods: Vec<String>,
+    /// Whether the model thinks before answering
+    thinking: Option<bool>,
+}
+
+impl GeminiModelList {
+    /// The models that support `generateContent`, leaving out embedding and other models
+    fn chat_models(self) -> Vec<ModelInfo> {
+        self.models
+            .into_iter()
+            .filter(|model| model.supported_generation_methods.iter().any(|m| m == "generateContent"))
+            .map(|model| ModelInfo {
+                id: model.name.strip_prefix("models/").unwrap_or(&model.name).to_string(),
+                display_name: model.display_name,
+                capabilities: ModelCapabilities {
+                    context_window: model.input_token_limit,
+                    max_output_tokens: model.output_token_limit,
+                    reasoning: model.thinking,
+                    ..Default::default()
+                },
+            })
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
 struct GeminiResponse {
     candidates: Option<Vec<GeminiCandidate>>,
     prompt_feedback: Option<GeminiPromptFeedback>,
+    #[serde(rename = "usageMetadata")]
+    usage_metadata: Option<GeminiUsageMetadata>,
 }
 
 #[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
 struct GeminiStreamResponse {
     candidates: Option<Vec<GeminiCandidate>>,
     prompt_feedback: Option<GeminiPromptFeedback>,
+    #[serde(rename = "usageMetadata")]
+    usage_metadata: Option<GeminiUsageMetadata>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct GeminiUsageMetadata {
+    #[serde(default)]
+    prompt_token_count: u32,
+    #[serde(default)]
+    candidates_token_count: u32,
+    /// Tokens spent thinking, billed as output by thinking models
+    #[serde(default)]
+    thoughts_token_count: u32,
+}
+
+impl GeminiUsageMetadata {
+    fn to_usage(&self) -> Usage {
+        Usage::new(
+            self.prompt_token_count,
+            self.candidates_token_count + self.thoughts_token_count,
+        )
+    }
 }
 
 #[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
 struct GeminiCandidate {
     content: Option<GeminiContent>,
     finish_reason: Option<String>,
@@ -387,6 +993,7 @@ struct GeminiCandidate {
 }
 
 #[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
 struct GeminiPromptFeedback {
     block_reason: Option<String>,
     safety_ratings: Option<Vec<GeminiSafetyRating>>,
@@ -399,36 +1006,143 @@ struct GeminiSafetyRating {
     blocked: Option<bool>,
 }
 
+impl GeminiSafetyRating {
+    /// Categories that were blocked, or else rated at least medium probability
+    fn blocked_categories(ratings: Option<&[GeminiSafetyRating]>) -> Vec<String> {
+        let ratings = ratings.unwrap_or_default();
+        let blocked: Vec<String> = ratings
+            .iter()
+            .filter(|rating| rating.blocked == Some(true))
+            .map(|rating| rating.category.clone())
+            .collect();
+        if !blocked.is_empty() {
+            return blocked;
+        }
+        ratings
+            .iter()
+            .filter(|rating| rating.probability == "MEDIUM" || rating.probability == "HIGH")
+            .map(|rating| rating.category.clone())
+            .collect()
+    }
+}
+
 #[async_trait]
 impl AIProvider for GeminiService {
     async fn chat_completion(
         &self,
         messages: Vec<ChatMessage>,
         model: Option<String>,
-        temperature: Option<f32>,
-        max_tokens: Option<u32>,
+        options: GenerationOptions,
+        tools: Option<Vec<ToolSpec>>,
+        tool_choice: Option<ToolChoice>,
     ) -> Result<ChatMessage> {
-        self.chat_completion(messages, model, temperature, max_tokens).await
+        self.chat_completion(messages, model, options, tools, tool_choice).await
     }
 
     async fn chat_completion_stream(
         &self,
         messages: Vec<ChatMessage>,
         model: Option<String>,
-        temperature: Option<f32>,
-        max_tokens: Option<u32>,
-    ) -> BoxStream<'static, Result<UIMessageChunk, anyhow::Error>> {
-        self.chat_completion_stream(messages, model, temperature, max_tokens).await
+        options: GenerationOptions,
+        tools: Option<Vec<ToolSpec>>,
+        tool_choice: Option<ToolChoice>,
+    ) -> Result<ChunkStream> {
+        self.chat_completion_stream(messages, model, options, tools, tool_choice).await
     }
 
     fn get_available_models(&self) -> Vec<&'static str> {
         Self::get_model_options()
     }
+
+    async fn discover_models(&self) -> Result<Vec<ModelInfo>> {
+        // Vertex AI lists publisher models through another API, so its models are the built-in ones
+        if self.vertex.is_some() {
+            return Ok(self.get_available_models().into_iter().map(ModelInfo::new).collect());
+        }
+
+        let url = format!("{}/v1beta/models?pageSize=1000", self.base_url);
+        let response = self.retry.send("Gemini", self.authorize(self.client.get(&url)).await?).await?;
+        let list: GeminiModelList = response.json().await?;
+        Ok(list.chat_models())
+    }
+
+    fn unsupported_options(&self, _model: &str, options: &GenerationOptions) -> Vec<&'static str> {
+        options.set_among(&["user", "logit_bias"])
+    }
+
+    fn as_embedder(&self) -> Option<&dyn EmbeddingProvider> {
+        Some(self)
+    }
+}
+
+impl GeminiService {
+    fn embed_requests(model: &str, texts: Vec<String>) -> Vec<GeminiEmbedRequest> {
+        texts
+            .into_iter()
+            .map(|text| GeminiEmbedRequest {
+                model: format!("models/{}", model),
+                content: GeminiParts { parts: vec![GeminiPart::text(text)] },
+            })
+            .collect()
+    }
+}
+
+#[async_trait]
+impl EmbeddingProvider for GeminiService {
+    /// A single text goes to `embedContent`, several to `batchEmbedContents`
+    async fn embed(&self, texts: Vec<String>, model: Option<String>) -> Result<Embeddings> {
+        let model = model.unwrap_or_else(|| DEFAULT_EMBEDDING_MODEL.to_string());
+        let model = model.strip_prefix("models/").unwrap_or(&model).to_string();
+
+        // Vertex AI embeds through its prediction API instead
+        if self.vertex.is_some() {
+            let request = VertexEmbedRequest {
+                instances: texts.into_iter().map(|content| VertexEmbedInstance { content }).collect(),
+            };
+            let builder = self.authorize(self.client.post(self.url(&model, "predict")).json(&request)).await?;
+            let response: VertexEmbedResponse = self.retry.send("Gemini", builder).await?.json().await?;
+            let prompt_tokens = response
+                .predictions
+                .iter()
+                .filter_map(|prediction| prediction.embeddings.statistics.as_ref())
+                .map(|statistics| statistics.token_count as u32)
+                .sum();
+            return Ok(Embeddings {
+                model,
+                vectors: response.predictions.into_iter().map(|p| p.embeddings.values).collect(),
+                prompt_tokens: Some(prompt_tokens),
+            });
+        }
+
+        let mut requests = Self::embed_requests(&model, texts);
+        let vectors = if requests.len() == 1 {
+            let request = requests.remove(0);
+            let builder = self.authorize(self.client.post(self.url(&model, "embedContent")).json(&request)).await?;
+            let response = self.retry.send("Gemini", builder).await?;
+            let response: GeminiEmbedResponse = response.json().await?;
+            vec![response.embedding.values]
+        } else {
+            let request = GeminiBatchEmbedRequest { requests };
+            let builder = self
+                .authorize(self.client.post(self.url(&model, "batchEmbedContents")).json(&request))
+                .await?;
+            let response = self.retry.send("Gemini", builder).await?;
+            let response: GeminiBatchEmbedResponse = response.json().await?;
+            response.embeddings.into_iter().map(|embedding| embedding.values).collect()
+        };
+
+        Ok(Embeddings {
+            model,
+            vectors,
+            prompt_tokens: None,
+        })
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::providers::testing::{message, serve, user};
 
     #[test]
     fn test_model_options() {
@@ -438,25 +1152,65 @@ mod tests {
         assert!(models.contains(&"gemini-1.5-pro"));
     }
 
+    #[test]
+    fn test_batch_embed_request_names_the_model_per_text() {
+        let request = GeminiBatchEmbedRequest {
+            requests: GeminiService::embed_requests("text-embedding-004", vec!["a".to_string(), "b".to_string()]),
+        };
+
+        assert_eq!(
+            serde_json::to_value(&request).unwrap(),
+            serde_json::json!({
+                "requests": [
+                    {"model": "models/text-embedding-004", "content": {"parts": [{"text": "a"}]}},
+                    {"model": "models/text-embedding-004", "content": {"parts": [{"text": "b"}]}}
+                ]
+            })
+        );
+    }
+
+    #[test]
+    fn test_discovered_models_can_generate_content() {
+        let list: GeminiModelList = serde_json::from_value(serde_json::json!({
+            "models": [
+                {
+                    "name": "models/gemini-2.5-flash",
+                    "displayName": "Gemini 2.5 Flash",
+                    "inputTokenLimit": 1048576,
+                    "outputTokenLimit": 65536,
+                    "supportedGenerationMethods": ["generateContent", "countTokens"],
+                    "thinking": true
+                },
+                {
+                    "name": "models/text-embedding-004",
+                    "displayName": "Text Embedding 004",
+                    "inputTokenLimit": 2048,
+                    "supportedGenerationMethods": ["embedContent"]
+                }
+            ]
+        }))
+        .unwrap();
+
+        assert_eq!(
+            list.chat_models(),
+            vec![ModelInfo {
+                id: "gemini-2.5-flash".to_string(),
+                display_name: Some("Gemini 2.5 Flash".to_string()),
+                capabilities: ModelCapabilities {
+                    context_window: Some(1048576),
+                    max_output_tokens: Some(65536),
+                    reasoning: Some(true),
+                    ..Default::default()
+                },
+            }]
+        );
+    }
+
     #[test]
     fn test_convert_messages() {
         let messages = vec![
-            ChatMessage {
-                id: "1".to_string(),
-                role: ChatRole::User,
-                content: "Hello".to_string(),
-                created_at: None,
-                attachments: None,
-                metadata: None,
-            },
-            ChatMessage {
-                id: "2".to_string(),
-                role: ChatRole::Assistant,
-                content: "Hi there!".to_string(),
-                created_at: None,
-                attachments: None,
-                metadata: None,
-            },
+            user("Hello"),
+            message(ChatRole::Assistant, "Hi there!"),
         ];
 
         let gemini_messages = GeminiService::convert_to_gemini_messages(&messages);
@@ -467,29 +1221,340 @@ mod tests {
 
     #[test]
     fn test_extract_system_message() {
+        let messages = vec![
+            message(ChatRole::System, "You are a helpful assistant."),
+            user("Hello"),
+        ];
+
+        let (instruction, filtered) = GeminiService::extract_system_instruction(&messages);
+        assert_eq!(filtered.len(), 1);
+        assert_eq!(filtered[0].content, "Hello");
+
+        let service = GeminiService::new("key".to_string(), None);
+        let contents = GeminiService::convert_to_gemini_messages(&filtered);
+        let request = service.build_request(contents, instruction, GenerationOptions::default(), None, None);
+        let body = serde_json::to_value(&request).unwrap();
+        assert_eq!(
+            body["system_instruction"],
+            serde_json::json!({"parts": [{"text": "You are a helpful assistant."}]})
+        );
+        assert_eq!(body["contents"][0]["parts"], serde_json::json!([{"text": "Hello"}]));
+    }
+
+    #[test]
+    fn test_safety_settings_override_defaults() {
+        let options: GenerationOptions = serde_json::from_value(serde_json::json!({
+            "candidate_count": 2,
+            "safety_settings": [
+                {"category": "HARM_CATEGORY_DANGEROUS_CONTENT", "threshold": "BLOCK_ONLY_HIGH"},
+                {"category": "HARM_CATEGORY_CIVIC_INTEGRITY", "threshold": "BLOCK_NONE"}
+            ]
+        }))
+        .unwrap();
+
+        let settings = GeminiService::safety_settings(&options);
+        let threshold = |category: &str| {
+            settings
+                .iter()
+                .find(|setting| setting.category == category)
+                .map(|setting| setting.threshold.as_str())
+        };
+        assert_eq!(threshold("HARM_CATEGORY_HARASSMENT"), Some("BLOCK_MEDIUM_AND_ABOVE"));
+        assert_eq!(threshold("HARM_CATEGORY_DANGEROUS_CONTENT"), Some("BLOCK_ONLY_HIGH"));
+        assert_eq!(threshold("HARM_CATEGORY_CIVIC_INTEGRITY"), Some("BLOCK_NONE"));
+        assert_eq!(settings.len(), 5);
+
+        let service = GeminiService::new("key".to_string(), None);
+        assert!(service.unsupported_options("", &options).is_empty());
+        assert_eq!(serde_json::to_value(service.generation_config(options)).unwrap()["candidate_count"], 2);
+    }
+
+    #[test]
+    fn test_blocked_prompt_and_answer_are_content_filter_errors() {
+        let response: GeminiResponse = serde_json::from_str(
+            r#"{"promptFeedback":{"blockReason":"SAFETY","safetyRatings":[
+                {"category":"HARM_CATEGORY_HARASSMENT","probability":"NEGLIGIBLE"},
+                {"category":"HARM_CATEGORY_DANGEROUS_CONTENT","probability":"HIGH","blocked":true}
+            ]},"usageMetadata":{"promptTokenCount":9,"totalTokenCount":9}}"#,
+        )
+        .unwrap();
+        let blocked = GeminiService::content_filter(response.prompt_feedback.as_ref(), None).unwrap();
+        assert!(blocked.prompt);
+        assert_eq!(blocked.categories, vec!["HARM_CATEGORY_DANGEROUS_CONTENT"]);
+        assert_eq!(
+            blocked.to_string(),
+            "Gemini blocked the prompt: SAFETY (HARM_CATEGORY_DANGEROUS_CONTENT)"
+        );
+
+        let chunk: GeminiStreamResponse = serde_json::from_str(
+            r#"{"candidates":[{"finishReason":"SAFETY","index":0,"safetyRatings":[
+                {"category":"HARM_CATEGORY_HATE_SPEECH","probability":"MEDIUM"}
+            ]}]}"#,
+        )
+        .unwrap();
+        let candidate = chunk.candidates.as_ref().and_then(|c| c.first());
+        let blocked = GeminiService::content_filter(chunk.prompt_feedback.as_ref(), candidate).unwrap();
+        assert!(!blocked.prompt);
+        assert_eq!(blocked.categories, vec!["HARM_CATEGORY_HATE_SPEECH"]);
+
+        let error: anyhow::Error = blocked.into();
+        assert!(ContentFilterError::from_error(&error).is_some());
+
+        let finished: GeminiStreamResponse = serde_json::from_str(
+            r#"{"candidates":[{"content":{"parts":[{"text":"Hi"}],"role":"model"},"finishReason":"STOP"}]}"#,
+        )
+        .unwrap();
+        assert!(GeminiService::content_filter(None, finished.candidates.as_ref().and_then(|c| c.first())).is_none());
+    }
+
+    #[test]
+    fn test_function_call_round_trip() {
         let messages = vec![
             ChatMessage {
-                id: "1".to_string(),
-                role: ChatRole::System,
-                content: "You are a helpful assistant.".to_string(),
-                created_at: None,
-                attachments: None,
-                metadata: None,
+                metadata: Some(HashMap::from([(
+                    TOOL_CALLS_KEY.to_string(),
+                    serde_json::json!([{"id": "call_1", "name": "calculator", "arguments": {"expression": "2 + 2"}}]),
+                )])),
+                ..message(ChatRole::Assistant, "")
             },
             ChatMessage {
-                id: "2".to_string(),
-                role: ChatRole::User,
-                content: "Hello".to_string(),
-                created_at: None,
-                attachments: None,
-                metadata: None,
+                metadata: Some(HashMap::from([(TOOL_NAME_KEY.to_string(), serde_json::json!("calculator"))])),
+                ..message(ChatRole::Tool, "4")
             },
         ];
 
-        let (system_msg, filtered) = GeminiService::extract_system_message(&messages);
-        assert!(system_msg.is_some());
-        assert_eq!(filtered.len(), 1);
-        assert!(filtered[0].content.contains("You are a helpful assistant."));
-        assert!(filtered[0].content.contains("Hello"));
+        let contents = serde_json::to_value(GeminiService::convert_to_gemini_messages(&messages)).unwrap();
+        assert_eq!(contents[0]["role"], "model");
+        assert_eq!(contents[0]["parts"][0]["functionCall"]["name"], "calculator");
+        assert_eq!(contents[1]["parts"][0]["functionResponse"]["name"], "calculator");
+        assert_eq!(contents[1]["parts"][0]["functionResponse"]["response"]["result"], 4);
+    }
+
+    #[test]
+    fn test_parse_function_call_part() {
+        let response: GeminiResponse = serde_json::from_value(serde_json::json!({
+            "candidates": [{
+                "content": {
+                    "role": "model",
+                    "parts": [{"functionCall": {"name": "web_search", "args": {"query": "rust"}}}]
+                }
+            }]
+        }))
+        .unwrap();
+
+        let part = &response.candidates.unwrap()[0].content.clone().unwrap().parts[0];
+        let call = GeminiService::tool_call_from_part(part).unwrap();
+        assert_eq!(call.name, "web_search");
+        assert_eq!(call.arguments, serde_json::json!({"query": "rust"}));
+    }
+
+    #[test]
+    fn test_function_declarations_drop_unsupported_schema_keys() {
+        let tools = GeminiService::convert_tools(Some(vec![ToolSpec {
+            name: "calculator".to_string(),
+            description: "Math".to_string(),
+            parameters: serde_json::json!({
+                "$schema": "http://json-schema.org/draft-07/schema#",
+                "type": "object",
+                "additionalProperties": false,
+                "properties": {"expression": {"type": "string"}}
+            }),
+        }]))
+        .unwrap();
+
+        let tools = serde_json::to_value(tools).unwrap();
+        let parameters = &tools[0]["functionDeclarations"][0]["parameters"];
+        assert!(parameters.get("$schema").is_none());
+        assert!(parameters.get("additionalProperties").is_none());
+        assert_eq!(parameters["properties"]["expression"]["type"], "string");
+    }
+
+    #[test]
+    fn test_parse_usage_metadata() {
+        let chunk: GeminiStreamResponse = serde_json::from_str(
+            r#"{"candidates":[{"content":{"parts":[{"text":"Hi"}],"role":"model"},"finishReason":"STOP"}],"usageMetadata":{"promptTokenCount":8,"candidatesTokenCount":2,"thoughtsTokenCount":5,"totalTokenCount":15}}"#,
+        )
+        .unwrap();
+        let usage = chunk.usage_metadata.unwrap().to_usage();
+        assert_eq!(usage.prompt_tokens, 8);
+        assert_eq!(usage.completion_tokens, 7);
+        assert_eq!(usage.total_tokens, 15);
+
+        // Early chunks may omit candidate counts
+        let chunk: GeminiStreamResponse =
+            serde_json::from_str(r#"{"usageMetadata":{"promptTokenCount":8,"totalTokenCount":8}}"#).unwrap();
+        assert_eq!(chunk.usage_metadata.unwrap().to_usage().total_tokens, 8);
+    }
+
+    #[test]
+    fn test_thought_parts_are_not_answer_text() {
+        let chunk: GeminiStreamResponse = serde_json::from_str(
+            r#"{"candidates":[{"content":{"parts":[{"text":"**Adding numbers**","thought":true},{"text":"4"}],"role":"model"}}]}"#,
+        )
+        .unwrap();
+        let parts = &chunk.candidates.unwrap()[0].content.clone().unwrap().parts;
+        assert!(parts[0].is_thought());
+        assert!(!parts[1].is_thought());
+
+        let config = GeminiGenerationConfig {
+            thinking_config: Some(GeminiThinkingConfig { include_thoughts: Some(true), thinking_budget: None }),
+            ..Default::default()
+        };
+        assert_eq!(
+            serde_json::to_value(config).unwrap(),
+            serde_json::json!({"thinking_config": {"include_thoughts": true}})
+        );
+    }
+
+    #[test]
+    fn test_generation_options_map_to_config() {
+        let service = GeminiService::new("key".to_string(), None);
+        let options = GenerationOptions {
+            top_k: Some(40),
+            stop: Some(vec!["END".to_string()]),
+            seed: Some(7),
+            logit_bias: Some(HashMap::from([("50256".to_string(), -100)])),
+            response_format: Some(ResponseFormat::JsonObject),
+            ..GenerationOptions::sampling(None, Some(256))
+        };
+        assert_eq!(service.unsupported_options("", &options), vec!["logit_bias"]);
+
+        let config = serde_json::to_value(service.generation_config(options)).unwrap();
+        assert_eq!(
+            config,
+            serde_json::json!({
+                "max_output_tokens": 256,
+                "top_k": 40,
+                "candidate_count": 1,
+                "stop_sequences": ["END"],
+                "seed": 7,
+                "response_mime_type": "application/json"
+            })
+        );
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_json_schema_becomes_response_schema() {
+        let service = GeminiService::new("key".to_string(), None);
+        let options: GenerationOptions = serde_json::from_value(serde_json::json!({
+            "response_format": {
+                "type": "json_schema",
+                "schema": {
+                    "$schema": "https://json-schema.org/draft/2020-12/schema",
+                    "type": "object",
+                    "properties": {
+                        "answer": {"type": "integer", "description": "The sum"},
+                        "steps": {"type": "array", "items": {"type": ["string", "null"]}}
+                    },
+                    "required": ["answer"],
+                    "additionalProperties": false
+                }
+            }
+        }))
+        .unwrap();
+
+        let config = serde_json::to_value(service.generation_config(options)).unwrap();
+        assert_eq!(config["response_mime_type"], "application/json");
+        assert_eq!(
+            config["response_schema"],
+            serde_json::json!({
+                "type": "OBJECT",
+                "properties": {
+                    "answer": {"type": "INTEGER", "description": "The sum"},
+                    "steps": {"type": "ARRAY", "items": {"type": "STRING", "nullable": true}}
+                },
+                "required": ["answer"]
+            })
+        );
+    }
+
+    #[test]
+    fn test_image_attachments_become_inline_data() {
+        let messages = vec![ChatMessage {
+            attachments: Some(vec![
+                crate::chat::Attachment {
+                    attachment_type: "file".to_string(),
+                    url: "data:image/png;base64,iVBORw0KGgo=".to_string(),
+                    media_type: Some("image/png".to_string()),
+                    filename: Some("screenshot.png".to_string()),
+                },
+            ]),
+            ..user("What is in this picture?")
+        }];
+
+        let json = serde_json::to_value(GeminiService::convert_to_gemini_messages(&messages)).unwrap();
+        let parts = &json[0]["parts"];
+        assert_eq!(parts[0]["inlineData"]["mimeType"], "image/png");
+        assert_eq!(parts[0]["inlineData"]["data"], "iVBORw0KGgo=");
+        assert_eq!(parts[1]["text"], "What is in this picture?");
+    }
+
+    #[test]
+    fn test_pdf_attachments_become_inline_data() {
+        let messages = vec![ChatMessage {
+            attachments: Some(vec![crate::chat::Attachment {
+                attachment_type: "file".to_string(),
+                url: "data:application/pdf;base64,JVBERi0xLjQ=".to_string(),
+                media_type: None,
+                filename: Some("spec.pdf".to_string()),
+            }]),
+            ..user("Summarize this spec")
+        }];
+
+        let json = serde_json::to_value(GeminiService::convert_to_gemini_messages(&messages)).unwrap();
+        assert_eq!(json[0]["parts"][0]["inlineData"]["mimeType"], "application/pdf");
+        assert_eq!(json[0]["parts"][1]["text"], "Summarize this spec");
+    }
+
+    #[tokio::test]
+    async fn test_vertex_requests_use_cached_access_tokens() {
+        let tokens = std::sync::Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let issued = tokens.clone();
+        let token = move || {
+            issued.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            std::future::ready(axum::Json(serde_json::json!({"access_token": "ya29.test", "expires_in": 3600})))
+        };
+        let vertex = |uri: axum::http::Uri, headers: axum::http::HeaderMap| async move {
+            assert_eq!(headers["authorization"], "Bearer ya29.test");
+            assert!(uri.query().is_none());
+            let prefix = "/v1/projects/test-project/locations/europe-west4/publishers/google/models/";
+            match uri.path().strip_prefix(prefix).unwrap() {
+                "gemini-1.5-pro:generateContent" => axum::Json(serde_json::json!({
+                    "candidates": [{"content": {"role": "model", "parts": [{"text": "Hi"}]}, "finishReason": "STOP"}]
+                })),
+                "text-embedding-004:predict" => axum::Json(serde_json::json!({
+                    "predictions": [{"embeddings": {"values": [0.5, 0.25], "statistics": {"token_count": 3.0}}}]
+                })),
+                path => panic!("unexpected path {}", path),
+            }
+        };
+        let app = axum::Router::new()
+            .route("/token", axum::routing::post(token))
+            .fallback(vertex);
+        let url = serve(app).await;
+
+        let key = crate::providers::testing::service_account_key(Some(format!("{}/token", url)));
+        let auth = ServiceAccountTokenSource::new(key).unwrap();
+        let mut service = GeminiService::new(String::new(), Some("models/gemini-1.5-pro".to_string())).with_vertex(
+            "test-project".to_string(),
+            "europe-west4".to_string(),
+            auth,
+        );
+        assert_eq!(service.base_url, "https://europe-west4-aiplatform.googleapis.com");
+        service.base_url = url;
+
+        let message = user("Hello");
+        for _ in 0..2 {
+            let reply = service
+                .chat_completion(vec![message.clone()], None, GenerationOptions::default(), None, None)
+                .await
+                .unwrap();
+            assert_eq!(reply.content, "Hi");
+        }
+        let embeddings = service.embed(vec!["Hello".to_string()], None).await.unwrap();
+        assert_eq!(embeddings.vectors, vec![vec![0.5, 0.25]]);
+        assert_eq!(embeddings.prompt_tokens, Some(3));
+
+        assert_eq!(tokens.load(std::sync::atomic::Ordering::SeqCst), 1);
+    }
+}
@@ -1,46 +1,145 @@
 //! AI Provider Services Module
 //!
-//! This module contains implementations for various AI providers including OpenAI, Anthropic, Google Gemini, and OpenRouter.
+//! This module contains implementations for various AI providers including OpenAI, Anthropic, Google Gemini, OpenRouter, Ollama, and AWS Bedrock.
 //! Each provider implements a common interface for chat completion and streaming.
 
+use std::time::Duration;
+
 use async_trait::async_trait;
 use anyhow::Result;
 use futures::stream::BoxStream;
+use reqwest::ClientBuilder;
 use crate::chat::{ChatMessage, UIMessageChunk};
 
 pub mod openai;
 pub mod anthropic;
 pub mod gemini;
 pub mod openrouter;
+pub mod ollama;
+pub mod bedrock;
+pub mod attachments;
+pub mod capabilities;
+pub mod decoder;
+pub mod discovery;
+pub mod embeddings;
+pub mod endpoints;
+pub mod error;
+pub mod failover;
+pub mod google_auth;
+pub mod options;
+pub mod reasoning;
+pub mod registry;
+pub mod retry;
+pub mod sigv4;
+pub mod structured;
+#[cfg(test)]
+pub(crate) mod testing;
+pub mod tools;
 
 // Re-export the main service structs
-pub use openai::OpenAIService;
+pub use openai::{AzureDeployments, OpenAIService};
 pub use anthropic::AnthropicService;
 pub use gemini::GeminiService;
-pub use openrouter::OpenRouterService;
+pub use openrouter::{OpenRouterProviderPreferences, OpenRouterService};
+pub use ollama::OllamaService;
+pub use bedrock::BedrockService;
+pub use reasoning::Reasoning;
+pub use capabilities::{ModelCapabilities, RequestFeatures};
+pub use discovery::{ModelCatalog, ModelInfo};
+pub use embeddings::{EmbeddingProvider, Embeddings};
+pub use registry::{ProviderRegistry, ResolvedProvider};
+pub use error::{ContentFilterError, ProviderError};
+pub use options::{GenerationOptions, ResponseFormat, SafetySetting};
+pub use retry::RetryPolicy;
+pub use tools::{ToolCall, ToolChoice, ToolSpec};
+
+/// Stream of chunks from a provider
+///
+/// A failure after the stream has started is yielded as a single `Err` item, after which the
+/// stream ends. That includes a response body that ends before the provider's end-of-response
+/// marker, so a cut-off stream never ends in [`UIMessageChunk::Finish`]. Providers never yield [`UIMessageChunk::Error`]; turning errors into client
+/// events is up to the caller.
+pub type ChunkStream = BoxStream<'static, Result<UIMessageChunk>>;
+
+/// Time allowed to connect to a provider before a streaming request fails
+const STREAM_CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Longest silence tolerated between reads of a streaming response
+const STREAM_READ_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// Builder for the client a service streams with
+///
+/// A stream lasts as long as the model keeps generating, so unlike the request clients this one
+/// has no total timeout, only limits on connecting and on silence between reads. Services build
+/// it once and keep it, so that streams share its connection pool.
+pub(crate) fn stream_client_builder() -> ClientBuilder {
+    ClientBuilder::new()
+        .connect_timeout(STREAM_CONNECT_TIMEOUT)
+        .read_timeout(STREAM_READ_TIMEOUT)
+}
 
 /// Common trait for AI providers
 #[async_trait]
-pub trait AIProvider {
+pub trait AIProvider: Send + Sync {
     /// Non-streaming chat completion
     async fn chat_completion(
         &self,
         messages: Vec<ChatMessage>,
         model: Option<String>,
-        temperature: Option<f32>,
-        max_tokens: Option<u32>,
+        options: GenerationOptions,
+        tools: Option<Vec<ToolSpec>>,
+        tool_choice: Option<ToolChoice>,
     ) -> Result<ChatMessage>;
 
     /// Streaming chat completion
+    ///
+    /// Failures before the first chunk (invalid input, connection errors, non-success status)
+    /// are returned as `Err`, with a [`ProviderError`] for API errors.
     async fn chat_completion_stream(
         &self,
         messages: Vec<ChatMessage>,
         model: Option<String>,
-        temperature: Option<f32>,
-        max_tokens: Option<u32>,
-    ) -> BoxStream<'static, Result<UIMessageChunk, anyhow::Error>>;
+        options: GenerationOptions,
+        tools: Option<Vec<ToolSpec>>,
+        tool_choice: Option<ToolChoice>,
+    ) -> Result<ChunkStream>;
 
     /// Get available models for this provider
     fn get_available_models(&self) -> Vec<&'static str>;
+
+    /// List the models the provider currently serves, by asking its API
+    ///
+    /// Defaults to [`Self::get_available_models`] for providers without a model listing.
+    async fn discover_models(&self) -> Result<Vec<ModelInfo>> {
+        Ok(self.get_available_models().into_iter().map(ModelInfo::new).collect())
+    }
+
+    /// The options set in `options` that this provider ignores for `model`
+    fn unsupported_options(&self, _model: &str, _options: &GenerationOptions) -> Vec<&'static str> {
+        Vec::new()
+    }
+
+    /// Whether this provider honours `tool_choice`; callers warn about one it ignores
+    fn supports_tool_choice(&self, _tool_choice: &ToolChoice) -> bool {
+        true
+    }
+
+    /// This provider's embeddings API
+    ///
+    /// Returns `None` for providers that cannot embed text.
+    fn as_embedder(&self) -> Option<&dyn EmbeddingProvider> {
+        None
+    }
+
+    /// A copy of this provider that applies OpenRouter-style routing preferences
+    ///
+    /// Returns `None` for providers without provider routing.
+    fn with_provider_routing(
+        &self,
+        _preferences: Option<OpenRouterProviderPreferences>,
+        _transforms: Option<Vec<String>>,
+    ) -> Option<std::sync::Arc<dyn AIProvider>> {
+        None
+    }
 }
 
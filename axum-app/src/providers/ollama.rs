@@ -0,0 +1,709 @@
+use anyhow::{anyhow, Result};
+use async_stream::stream;
+use async_trait::async_trait;
+use futures::StreamExt;
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::time::Duration;
+
+use crate::chat::{ChatMessage, ChatRole, UIMessageChunk, Usage};
+use super::attachments;
+use super::decoder;
+use super::tools::{TOOL_CALLS_KEY, TOOL_NAME_KEY};
+use super::{
+    AIProvider, ChunkStream, EmbeddingProvider, Embeddings, GenerationOptions, ModelInfo, ProviderError,
+    ResponseFormat, RetryPolicy, ToolCall, ToolChoice, ToolSpec,
+};
+
+/// Model prefix that routes a request to Ollama, e.g. `ollama/llama3.2`
+pub const OLLAMA_MODEL_PREFIX: &str = "ollama/";
+
+/// Embedding model used when a request names none
+const DEFAULT_EMBEDDING_MODEL: &str = "nomic-embed-text";
+
+/// Ollama Service
+/// Runs models locally through Ollama's native chat API
+#[derive(Debug, Clone)]
+pub struct OllamaService {
+    client: Client,
+    /// Client for streams, which can outlive the request timeout of `client`
+    stream_client: Client,
+    base_url: String,
+    default_model: String,
+    retry: RetryPolicy,
+}
+
+impl OllamaService {
+    /// Create a new Ollama service talking to the server at `base_url`
+    pub fn new(base_url: String, default_model: Option<String>) -> Self {
+        Self {
+            // Local models can take a long time to load and generate, so allow generous timeouts
+            client: Client::builder()
+                .timeout(Duration::from_secs(300))
+                .build()
+                .expect("Failed to create HTTP client"),
+            stream_client: super::stream_client_builder()
+                .build()
+                .expect("Failed to create HTTP client"),
+            base_url: base_url.trim_end_matches('/').to_string(),
+            default_model: default_model.unwrap_or_else(|| "llama3.2".to_string()),
+            retry: RetryPolicy::from_env(),
+        }
+    }
+
+    /// Create Ollama service from environment variables
+    ///
+    /// No API key is needed; `OLLAMA_BASE_URL` defaults to the local Ollama server.
+    pub fn from_env() -> Result<Self> {
+        let base_url = std::env::var("OLLAMA_BASE_URL")
+            .unwrap_or_else(|_| "http://localhost:11434".to_string());
+        let model = std::env::var("OLLAMA_MODEL").ok();
+
+        Ok(Self::new(base_url, model))
+    }
+
+    /// Get commonly used Ollama models
+    pub fn get_model_options() -> Vec<&'static str> {
+        vec![
+            "llama3.2",
+            "llama3.1",
+            "qwen2.5",
+            "mistral",
+            "gemma2",
+            "llava",
+        ]
+    }
+
+    /// List the models installed on the Ollama server
+    pub async fn list_models(&self) -> Result<Vec<String>> {
+        let response = self
+            .retry
+            .send("Ollama", self.client.get(format!("{}/api/tags", self.base_url)))
+            .await?;
+
+        let tags: OllamaTagsResponse = response.json().await?;
+        Ok(tags.models.into_iter().map(|m| m.name).collect())
+    }
+
+    /// Model name without the `ollama/` routing prefix
+    fn resolve_model(&self, model: Option<String>) -> String {
+        let model = model.unwrap_or_else(|| self.default_model.clone());
+        model
+            .strip_prefix(OLLAMA_MODEL_PREFIX)
+            .map(|m| m.to_string())
+            .unwrap_or(model)
+    }
+
+    /// Convert chat messages to Ollama format
+    fn convert_to_ollama_messages(messages: &[ChatMessage]) -> Vec<OllamaMessage> {
+        messages
+            .iter()
+            .map(|msg| {
+                let role = match msg.role {
+                    ChatRole::User => "user",
+                    ChatRole::Assistant => "assistant",
+                    ChatRole::System => "system",
+                    ChatRole::Tool => "tool",
+                };
+
+                let images: Vec<String> = attachments::images(msg)
+                    .filter_map(attachments::inline_data)
+                    .map(|inline| inline.data)
+                    .collect();
+
+                let tool_calls = ToolCall::from_metadata(msg.metadata.as_ref()).map(|calls| {
+                    calls
+                        .into_iter()
+                        .map(|call| OllamaToolCall {
+                            function: OllamaFunctionCall {
+                                name: call.name,
+                                arguments: call.arguments,
+                            },
+                        })
+                        .collect()
+                });
+
+                OllamaMessage {
+                    role: role.to_string(),
+                    // Ollama has no document input; PDF text is already inlined
+                    content: msg.content.clone(),
+                    images: if images.is_empty() { None } else { Some(images) },
+                    tool_calls,
+                    tool_name: msg
+                        .metadata
+                        .as_ref()
+                        .and_then(|m| m.get(TOOL_NAME_KEY))
+                        .and_then(|v| v.as_str())
+                        .map(|name| name.to_string()),
+                }
+            })
+            .collect()
+    }
+
+    /// Convert tool specs to Ollama format
+    fn convert_tools(tools: Option<Vec<ToolSpec>>) -> Option<Vec<OllamaTool>> {
+        tools.filter(|t| !t.is_empty()).map(|tools| {
+            tools
+                .into_iter()
+                .map(|tool| OllamaTool {
+                    type_: "function".to_string(),
+                    function: OllamaFunction {
+                        name: tool.name,
+                        description: tool.description,
+                        parameters: tool.parameters,
+                    },
+                })
+                .collect()
+        })
+    }
+
+    /// Ollama does not report tool call ids, so generate one per call
+    fn tool_call_from_ollama(call: OllamaToolCall) -> ToolCall {
+        ToolCall {
+            id: format!("ollama_call_{}", fastrand::u64(..)),
+            name: call.function.name,
+            arguments: call.function.arguments,
+        }
+    }
+
+    /// The tools to send for `tool_choice`
+    ///
+    /// Ollama cannot forbid tool use, so the tools are left out instead.
+    fn allowed_tools(tools: Option<Vec<ToolSpec>>, tool_choice: Option<ToolChoice>) -> Option<Vec<ToolSpec>> {
+        match tool_choice {
+            Some(ToolChoice::None) => None,
+            _ => tools,
+        }
+    }
+
+    fn usage(response: &OllamaChatResponse) -> Usage {
+        Usage::new(
+            response.prompt_eval_count.unwrap_or(0),
+            response.eval_count.unwrap_or(0),
+        )
+    }
+
+    fn build_request(
+        model: String,
+        messages: Vec<OllamaMessage>,
+        options: &GenerationOptions,
+        tools: Option<Vec<ToolSpec>>,
+        stream: bool,
+    ) -> OllamaChatRequest {
+        // `format` takes "json" or a JSON Schema
+        let format = match &options.response_format {
+            Some(ResponseFormat::JsonObject) => Some(serde_json::Value::String("json".to_string())),
+            Some(ResponseFormat::JsonSchema { json_schema }) => Some(json_schema.schema.clone()),
+            Some(ResponseFormat::Text) | None => None,
+        };
+
+        OllamaChatRequest {
+            model,
+            messages,
+            stream,
+            tools: Self::convert_tools(tools),
+            format,
+            options: Some(OllamaOptions {
+                temperature: options.temperature,
+                num_predict: options.max_tokens,
+                top_p: options.top_p,
+                top_k: options.top_k,
+                stop: options.stop_sequences(),
+                seed: options.seed,
+                frequency_penalty: options.frequency_penalty,
+                presence_penalty: options.presence_penalty,
+            }),
+        }
+    }
+
+    /// Generate chat completion (non-streaming)
+    pub async fn chat_completion(
+        &self,
+        messages: Vec<ChatMessage>,
+        model: Option<String>,
+        options: GenerationOptions,
+        tools: Option<Vec<ToolSpec>>,
+    ) -> Result<ChatMessage> {
+        let model = self.resolve_model(model);
+        let messages = attachments::inline_remote_attachments(messages, attachments::is_image).await?;
+        let messages = attachments::inline_document_text(messages).await?;
+        let request = Self::build_request(
+            model.clone(),
+            Self::convert_to_ollama_messages(&messages),
+            &options,
+            tools,
+            false,
+        );
+
+        let response = self
+            .retry
+            .send("Ollama", self.client.post(format!("{}/api/chat", self.base_url)).json(&request))
+            .await?;
+
+        let ollama_response: OllamaChatResponse = response.json().await?;
+        let usage = Self::usage(&ollama_response);
+
+        let mut metadata = HashMap::from([
+            ("model".to_string(), serde_json::Value::String(model)),
+            ("provider".to_string(), serde_json::Value::String("ollama".to_string())),
+            ("usage".to_string(), serde_json::to_value(&usage).unwrap_or(serde_json::Value::Null)),
+        ]);
+        if let Some(done_reason) = &ollama_response.done_reason {
+            metadata.insert("finish_reason".to_string(), serde_json::Value::String(done_reason.clone()));
+        }
+
+        let message = ollama_response
+            .message
+            .ok_or_else(|| anyhow!("No message in Ollama response"))?;
+
+        let tool_calls: Vec<ToolCall> = message
+            .tool_calls
+            .into_iter()
+            .flatten()
+            .map(Self::tool_call_from_ollama)
+            .collect();
+        if !tool_calls.is_empty() {
+            metadata.insert(
+                TOOL_CALLS_KEY.to_string(),
+                serde_json::to_value(&tool_calls).unwrap_or(serde_json::Value::Null),
+            );
+        }
+
+        Ok(ChatMessage {
+            id: format!("ollama_{}", fastrand::u64(1000..9999)),
+            role: ChatRole::Assistant,
+            content: message.content,
+            created_at: Some(chrono::Utc::now()),
+            attachments: None,
+            metadata: Some(metadata),
+        })
+    }
+
+    /// Generate chat completion (streaming)
+    ///
+    /// Ollama streams newline-delimited JSON objects; the last one has `done: true` and
+    /// carries the token counts.
+    pub async fn chat_completion_stream(
+        &self,
+        messages: Vec<ChatMessage>,
+        model: Option<String>,
+        options: GenerationOptions,
+        tools: Option<Vec<ToolSpec>>,
+    ) -> Result<ChunkStream> {
+        let model = self.resolve_model(model);
+        let messages = attachments::inline_remote_attachments(messages, attachments::is_image).await?;
+        let messages = attachments::inline_document_text(messages).await?;
+        let request = Self::build_request(
+            model,
+            Self::convert_to_ollama_messages(&messages),
+            &options,
+            tools,
+            true,
+        );
+
+        let response = self
+            .retry
+            .send("Ollama", self.stream_client.post(format!("{}/api/chat", self.base_url)).json(&request))
+            .await
+            .map_err(|e| match ProviderError::from_error(&e) {
+                Some(_) => e,
+                None => e.context("Failed to connect to Ollama"),
+            })?;
+
+        Ok(Box::pin(stream! {
+            yield Ok(UIMessageChunk::TextStart);
+
+            let mut lines = decoder::ndjson_lines(response.bytes_stream());
+
+            while let Some(line) = lines.next().await {
+                let line = match line {
+                    Ok(line) => line,
+                    Err(e) => {
+                        yield Err(e);
+                        return;
+                    }
+                };
+
+                let parsed: OllamaChatResponse = match serde_json::from_str(&line) {
+                    Ok(parsed) => parsed,
+                    Err(e) => {
+                        yield Err(anyhow!("Failed to parse Ollama chunk: {}", e));
+                        return;
+                    }
+                };
+
+                if let Some(error) = parsed.error.clone() {
+                    yield Err(anyhow!("Ollama error: {}", error));
+                    return;
+                }
+
+                if let Some(message) = parsed.message.clone() {
+                    if !message.content.is_empty() {
+                        yield Ok(UIMessageChunk::TextDelta {
+                            textDelta: message.content,
+                        });
+                    }
+                    // Ollama sends each tool call whole rather than in fragments
+                    for call in message.tool_calls.into_iter().flatten() {
+                        yield Ok(Self::tool_call_from_ollama(call).into_chunk());
+                    }
+                }
+
+                if parsed.done {
+                    yield Ok(UIMessageChunk::TextFinish);
+                    yield Ok(UIMessageChunk::Finish {
+                        reasoning: None,
+                        sources: None,
+                        usage: Some(Self::usage(&parsed)),
+                        logprobs: None,
+                    });
+                    return;
+                }
+            }
+
+            yield Err(anyhow!("Ollama stream ended before done"));
+        }))
+    }
+}
+
+#[async_trait]
+impl AIProvider for OllamaService {
+    async fn chat_completion(
+        &self,
+        messages: Vec<ChatMessage>,
+        model: Option<String>,
+        options: GenerationOptions,
+        tools: Option<Vec<ToolSpec>>,
+        tool_choice: Option<ToolChoice>,
+    ) -> Result<ChatMessage> {
+        self.chat_completion(messages, model, options, Self::allowed_tools(tools, tool_choice)).await
+    }
+
+    async fn chat_completion_stream(
+        &self,
+        messages: Vec<ChatMessage>,
+        model: Option<String>,
+        options: GenerationOptions,
+        tools: Option<Vec<ToolSpec>>,
+        tool_choice: Option<ToolChoice>,
+    ) -> Result<ChunkStream> {
+        self.chat_completion_stream(messages, model, options, Self::allowed_tools(tools, tool_choice)).await
+    }
+
+    fn get_available_models(&self) -> Vec<&'static str> {
+        Self::get_model_options()
+    }
+
+    async fn discover_models(&self) -> Result<Vec<ModelInfo>> {
+        Ok(self.list_models().await?.into_iter().map(ModelInfo::new).collect())
+    }
+
+    fn unsupported_options(&self, _model: &str, options: &GenerationOptions) -> Vec<&'static str> {
+        options.set_among(&["user", "logit_bias", "candidate_count", "safety_settings"])
+    }
+
+    /// Ollama has no tool_choice: the model always decides, unless it is given no tools
+    fn supports_tool_choice(&self, tool_choice: &ToolChoice) -> bool {
+        matches!(tool_choice, ToolChoice::Auto | ToolChoice::None)
+    }
+
+    fn as_embedder(&self) -> Option<&dyn EmbeddingProvider> {
+        Some(self)
+    }
+}
+
+#[async_trait]
+impl EmbeddingProvider for OllamaService {
+    async fn embed(&self, texts: Vec<String>, model: Option<String>) -> Result<Embeddings> {
+        let model = model
+            .as_deref()
+            .map(|model| model.strip_prefix(OLLAMA_MODEL_PREFIX).unwrap_or(model))
+            .unwrap_or(DEFAULT_EMBEDDING_MODEL)
+            .to_string();
+        let request = OllamaEmbedRequest { model, input: texts };
+
+        let response = self
+            .retry
+            .send("Ollama", self.client.post(format!("{}/api/embed", self.base_url)).json(&request))
+            .await?;
+        let response: OllamaEmbedResponse = response.json().await?;
+
+        Ok(Embeddings {
+            model: response.model,
+            vectors: response.embeddings,
+            prompt_tokens: response.prompt_eval_count,
+        })
+    }
+}
+
+// Ollama API Types
+
+#[derive(Debug, Serialize)]
+struct OllamaChatRequest {
+    model: String,
+    messages: Vec<OllamaMessage>,
+    stream: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tools: Option<Vec<OllamaTool>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    format: Option<serde_json::Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    options: Option<OllamaOptions>,
+}
+
+#[derive(Debug, Serialize)]
+struct OllamaOptions {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    temperature: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    num_predict: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    top_p: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    top_k: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    stop: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    seed: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    frequency_penalty: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    presence_penalty: Option<f32>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct OllamaMessage {
+    role: String,
+    #[serde(default)]
+    content: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    images: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tool_calls: Option<Vec<OllamaToolCall>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tool_name: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct OllamaTool {
+    #[serde(rename = "type")]
+    type_: String,
+    function: OllamaFunction,
+}
+
+#[derive(Debug, Serialize)]
+struct OllamaFunction {
+    name: String,
+    description: String,
+    parameters: serde_json::Value,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct OllamaToolCall {
+    function: OllamaFunctionCall,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct OllamaFunctionCall {
+    name: String,
+    #[serde(default)]
+    arguments: serde_json::Value,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct OllamaChatResponse {
+    message: Option<OllamaMessage>,
+    #[serde(default)]
+    done: bool,
+    done_reason: Option<String>,
+    prompt_eval_count: Option<u32>,
+    eval_count: Option<u32>,
+    error: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct OllamaEmbedRequest {
+    model: String,
+    input: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct OllamaEmbedResponse {
+    model: String,
+    embeddings: Vec<Vec<f32>>,
+    prompt_eval_count: Option<u32>,
+}
+
+#[derive(Debug, Deserialize)]
+struct OllamaTagsResponse {
+    #[serde(default)]
+    models: Vec<OllamaModelTag>,
+}
+
+#[derive(Debug, Deserialize)]
+struct OllamaModelTag {
+    name: String,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::providers::testing::{message, serve, user};
+
+    #[test]
+    fn test_model_prefix_is_stripped() {
+        let service = OllamaService::new("http://localhost:11434/".to_string(), None);
+        assert_eq!(service.resolve_model(Some("ollama/qwen2.5:7b".to_string())), "qwen2.5:7b");
+        assert_eq!(service.resolve_model(Some("mistral".to_string())), "mistral");
+        assert_eq!(service.resolve_model(None), "llama3.2");
+        assert_eq!(service.base_url, "http://localhost:11434");
+    }
+
+    #[tokio::test]
+    async fn test_embed_strips_model_prefix() {
+        let app = axum::Router::new().route(
+            "/api/embed",
+            axum::routing::post(|axum::Json(body): axum::Json<serde_json::Value>| async move {
+                assert_eq!(body["model"], "mxbai-embed-large");
+                assert_eq!(body["input"], serde_json::json!(["hello"]));
+                axum::Json(serde_json::json!({
+                    "model": "mxbai-embed-large",
+                    "embeddings": [[0.1, 0.2, 0.3]],
+                    "prompt_eval_count": 1
+                }))
+            }),
+        );
+        let url = serve(app).await;
+
+        let service = OllamaService::new(url, None);
+        let embeddings = service
+            .embed(vec!["hello".to_string()], Some("ollama/mxbai-embed-large".to_string()))
+            .await
+            .unwrap();
+
+        assert_eq!(embeddings.vectors, vec![vec![0.1, 0.2, 0.3]]);
+        assert_eq!(embeddings.prompt_tokens, Some(1));
+    }
+
+    #[test]
+    fn test_convert_messages() {
+        let messages = vec![
+            ChatMessage {
+                attachments: Some(vec![crate::chat::Attachment {
+                    attachment_type: "file".to_string(),
+                    url: "data:image/png;base64,iVBORw0KGgo=".to_string(),
+                    media_type: None,
+                    filename: None,
+                }]),
+                ..user("What is in this picture?")
+            },
+            ChatMessage {
+                metadata: Some(HashMap::from([(
+                    TOOL_CALLS_KEY.to_string(),
+                    serde_json::json!([{"id": "c1", "name": "calculator", "arguments": {"expression": "2 + 2"}}]),
+                )])),
+                ..message(ChatRole::Assistant, "")
+            },
+            ChatMessage {
+                metadata: Some(HashMap::from([(
+                    TOOL_NAME_KEY.to_string(),
+                    serde_json::json!("calculator"),
+                )])),
+                ..message(ChatRole::Tool, "4")
+            },
+        ];
+
+        let json = serde_json::to_value(OllamaService::convert_to_ollama_messages(&messages)).unwrap();
+        assert_eq!(json[0]["images"][0], "iVBORw0KGgo=");
+        assert_eq!(json[1]["tool_calls"][0]["function"]["arguments"]["expression"], "2 + 2");
+        assert_eq!(json[2]["role"], "tool");
+        assert_eq!(json[2]["tool_name"], "calculator");
+    }
+
+    #[test]
+    fn test_generation_options_map_to_ollama_options() {
+        let options = GenerationOptions {
+            top_k: Some(40),
+            stop: Some(vec!["END".to_string()]),
+            seed: Some(7),
+            user: Some("user-1".to_string()),
+            response_format: Some(ResponseFormat::JsonObject),
+            ..GenerationOptions::sampling(Some(0.2), Some(64))
+        };
+        let request = OllamaService::build_request("llama3.2".to_string(), Vec::new(), &options, None, false);
+
+        let json = serde_json::to_value(&request).unwrap();
+        assert_eq!(json["format"], "json");
+        assert_eq!(json["options"]["num_predict"], 64);
+        assert_eq!(json["options"]["top_k"], 40);
+        assert_eq!(json["options"]["stop"][0], "END");
+        assert_eq!(json["options"]["seed"], 7);
+        assert!(json["options"].get("top_p").is_none());
+
+        let service = OllamaService::new("http://localhost:11434".to_string(), None);
+        assert_eq!(service.unsupported_options("", &options), vec!["user"]);
+    }
+
+    #[test]
+    fn test_parse_final_stream_chunk() {
+        let chunk: OllamaChatResponse = serde_json::from_str(
+            r#"{"model":"llama3.2","created_at":"2024-01-01T00:00:00Z","message":{"role":"assistant","content":""},"done":true,"done_reason":"stop","prompt_eval_count":26,"eval_count":12}"#,
+        )
+        .unwrap();
+
+        assert!(chunk.done);
+        let usage = OllamaService::usage(&chunk);
+        assert_eq!(usage.prompt_tokens, 26);
+        assert_eq!(usage.total_tokens, 38);
+    }
+
+    #[tokio::test]
+    async fn test_stream_cut_off_before_done_is_an_error() {
+        let body = concat!(
+            r#"{"model":"llama3.2","message":{"role":"assistant","content":"The answer"},"done":false}"#,
+            "\n",
+            r#"{"model":"llama3.2","message":{"role":"assistant","content":" is"},"done":false}"#,
+            "\n",
+        );
+        let app = axum::Router::new().route("/api/chat", axum::routing::post(move || async move { body }));
+        let url = serve(app).await;
+
+        let service = OllamaService::new(url, None);
+        let results: Vec<Result<UIMessageChunk>> = service
+            .chat_completion_stream(vec![user("2 + 2?")], None, GenerationOptions::default(), None)
+            .await
+            .unwrap()
+            .collect()
+            .await;
+
+        assert!(results.iter().all(|chunk| !matches!(chunk, Ok(UIMessageChunk::Finish { .. }))));
+        let error = results.last().unwrap().as_ref().unwrap_err();
+        assert_eq!(error.to_string(), "Ollama stream ended before done");
+    }
+
+    #[test]
+    fn test_tool_choice_none_leaves_tools_out_and_forcing_is_unsupported() {
+        let tools = Some(vec![ToolSpec {
+            name: "calculator".to_string(),
+            description: "Evaluate an expression".to_string(),
+            parameters: serde_json::json!({"type": "object"}),
+        }]);
+        assert!(OllamaService::allowed_tools(tools.clone(), Some(ToolChoice::None)).is_none());
+        assert_eq!(OllamaService::allowed_tools(tools.clone(), Some(ToolChoice::Auto)).unwrap().len(), 1);
+
+        let service = OllamaService::new("http://localhost:11434".to_string(), None);
+        assert!(service.supports_tool_choice(&ToolChoice::None));
+        assert!(!service.supports_tool_choice(&ToolChoice::Required));
+        assert!(!service.supports_tool_choice(&ToolChoice::Function { name: "calculator".to_string() }));
+    }
+
+    #[test]
+    fn test_parse_tags() {
+        let tags: OllamaTagsResponse = serde_json::from_str(
+            r#"{"models":[{"name":"llama3.2:latest","model":"llama3.2:latest","size":2019393189}]}"#,
+        )
+        .unwrap();
+        assert_eq!(tags.models[0].name, "llama3.2:latest");
+    }
+}
@@ -1,21 +1,67 @@
 use anyhow::{anyhow, Result};
 use async_stream::stream;
 use async_trait::async_trait;
-use futures::{stream::BoxStream, StreamExt};
-use reqwest::Client;
+use futures::StreamExt;
+use reqwest::header::HeaderMap;
+use reqwest::{Client, RequestBuilder};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::time::Duration;
 
-use crate::chat::{ChatMessage, ChatRole, UIMessageChunk};
-use super::AIProvider;
+use crate::chat::{ChatMessage, ChatRole, UIMessageChunk, Usage};
+use super::attachments;
+use super::decoder;
+use super::reasoning::{Reasoning, ReasoningStream};
+use super::tools::{ToolCallAccumulator, TOOL_CALLS_KEY, TOOL_CALL_ID_KEY};
+use super::{
+    AIProvider, ChunkStream, EmbeddingProvider, Embeddings, GenerationOptions, ModelCapabilities, ModelInfo,
+    ResponseFormat, RetryPolicy, ToolCall, ToolChoice, ToolSpec,
+};
+
+/// Embedding model used when a request names none
+const DEFAULT_EMBEDDING_MODEL: &str = "text-embedding-3-small";
+
+/// Azure OpenAI API version used when none is configured
+pub const DEFAULT_AZURE_API_VERSION: &str = "2024-10-21";
+
+/// Azure OpenAI addressing, where requests go to a deployment instead of naming a model
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AzureDeployments {
+    /// Value of the `api-version` query parameter
+    #[serde(default = "default_azure_api_version")]
+    pub api_version: String,
+    /// Deployment name per model; a model without one is used as the deployment name
+    #[serde(default)]
+    pub deployments: HashMap<String, String>,
+}
+
+fn default_azure_api_version() -> String {
+    DEFAULT_AZURE_API_VERSION.to_string()
+}
+
+impl AzureDeployments {
+    pub fn new(api_version: String, deployments: HashMap<String, String>) -> Self {
+        Self { api_version, deployments }
+    }
+
+    /// The deployment serving `model`
+    pub fn deployment<'a>(&'a self, model: &'a str) -> &'a str {
+        self.deployments.get(model).map(String::as_str).unwrap_or(model)
+    }
+}
 
 #[derive(Debug, Clone)]
 pub struct OpenAIService {
     client: Client,
+    /// Client for streams, which can outlive the request timeout of `client`
+    stream_client: Client,
     api_key: String,
     base_url: String,
     default_model: String,
+    headers: HeaderMap,
+    models: Vec<String>,
+    azure: Option<AzureDeployments>,
+    retry: RetryPolicy,
 }
 
 impl OpenAIService {
@@ -32,9 +78,104 @@ impl OpenAIService {
 
         Self {
             client,
+            stream_client: super::stream_client_builder()
+                .build()
+                .expect("Failed to create HTTP client"),
             api_key,
             base_url,
             default_model,
+            headers: HeaderMap::new(),
+            models: Vec::new(),
+            azure: None,
+            retry: RetryPolicy::from_env(),
+        }
+    }
+
+    /// Talk to Azure OpenAI: `base_url` is the resource endpoint, e.g.
+    /// `https://my-resource.openai.azure.com`, and models are sent to their deployments
+    ///
+    /// Unless models were configured, the mapped models are the ones served.
+    pub fn with_azure(mut self, azure: AzureDeployments) -> Self {
+        if self.models.is_empty() {
+            self.models = azure.deployments.keys().cloned().collect();
+            self.models.sort();
+        }
+        self.azure = Some(azure);
+        self
+    }
+
+    /// Point the service at another OpenAI-compatible API (vLLM, LM Studio, Groq, ...)
+    pub fn with_base_url(mut self, base_url: String) -> Self {
+        self.base_url = base_url.trim_end_matches('/').to_string();
+        self
+    }
+
+    /// Extra headers sent with every request
+    pub fn with_headers(mut self, headers: HeaderMap) -> Self {
+        self.headers = headers;
+        self
+    }
+
+    /// Models served by this endpoint
+    pub fn with_models(mut self, models: Vec<String>) -> Self {
+        self.models = models;
+        self
+    }
+
+    /// Models served by this endpoint, as configured
+    pub fn models(&self) -> &[String] {
+        &self.models
+    }
+
+    /// Name used in errors
+    fn name(&self) -> &'static str {
+        if self.azure.is_some() {
+            "Azure OpenAI"
+        } else {
+            "OpenAI"
+        }
+    }
+
+    /// URL of `path` for `model`; on Azure that is under the model's deployment
+    fn url(&self, path: &str, model: &str) -> String {
+        match &self.azure {
+            Some(azure) => format!(
+                "{}/openai/deployments/{}{}?api-version={}",
+                self.base_url,
+                azure.deployment(model),
+                path,
+                azure.api_version
+            ),
+            None => format!("{}{}", self.base_url, path),
+        }
+    }
+
+    /// POST to `path` for `model` with authentication and the configured extra headers
+    fn post(&self, path: &str, model: &str) -> RequestBuilder {
+        self.post_with(&self.client, path, model)
+    }
+
+    /// [`Self::post`] through `client`
+    fn post_with(&self, client: &Client, path: &str, model: &str) -> RequestBuilder {
+        self.authorize(client.post(self.url(path, model)))
+            .header("Content-Type", "application/json")
+    }
+
+    /// GET `path` with authentication and the configured extra headers
+    fn get(&self, path: &str) -> RequestBuilder {
+        self.authorize(self.client.get(format!("{}{}", self.base_url, path)))
+    }
+
+    fn authorize(&self, request: RequestBuilder) -> RequestBuilder {
+        let request = request.headers(self.headers.clone());
+
+        // Local servers such as vLLM or LM Studio usually run without a key
+        if self.api_key.is_empty() {
+            request
+        } else if self.azure.is_some() {
+            request.header("api-key", self.api_key.as_str())
+        } else {
+            request.header("Authorization", format!("Bearer {}", self.api_key))
         }
     }
 
@@ -47,26 +188,177 @@ impl OpenAIService {
         Ok(Self::new(api_key, default_model))
     }
 
+    /// Configure Azure OpenAI from `AZURE_OPENAI_ENDPOINT` and `AZURE_OPENAI_API_KEY`
+    ///
+    /// `AZURE_OPENAI_DEPLOYMENTS` maps models to deployments as `gpt-4o=prod-gpt4o,...`, and
+    /// `AZURE_OPENAI_API_VERSION` overrides the API version.
+    pub fn azure_from_env() -> Result<Self> {
+        let endpoint = std::env::var("AZURE_OPENAI_ENDPOINT")
+            .map_err(|_| anyhow!("AZURE_OPENAI_ENDPOINT environment variable not set"))?;
+        let api_key = std::env::var("AZURE_OPENAI_API_KEY")
+            .map_err(|_| anyhow!("AZURE_OPENAI_API_KEY environment variable not set"))?;
+        let api_version = std::env::var("AZURE_OPENAI_API_VERSION").unwrap_or_else(|_| default_azure_api_version());
+        let deployments = std::env::var("AZURE_OPENAI_DEPLOYMENTS")
+            .map(|value| Self::parse_deployments(&value))
+            .unwrap_or_default();
+
+        // Without a configured default, the first mapped model
+        let default_model = std::env::var("AZURE_OPENAI_DEFAULT_MODEL")
+            .ok()
+            .or_else(|| deployments.first().map(|(model, _)| model.clone()));
+
+        Ok(Self::new(api_key, default_model)
+            .with_base_url(endpoint)
+            .with_azure(AzureDeployments::new(api_version, deployments.into_iter().collect())))
+    }
+
+    /// Parse `model=deployment` pairs separated by commas; a bare name maps to itself
+    fn parse_deployments(value: &str) -> Vec<(String, String)> {
+        value
+            .split(',')
+            .map(str::trim)
+            .filter(|pair| !pair.is_empty())
+            .map(|pair| match pair.split_once('=') {
+                Some((model, deployment)) => (model.trim().to_string(), deployment.trim().to_string()),
+                None => (pair.to_string(), pair.to_string()),
+            })
+            .collect()
+    }
+
     /// Convert our ChatMessage to OpenAI format
     fn convert_to_openai_message(message: ChatMessage) -> OpenAIChatMessage {
         let role = match message.role {
             ChatRole::User => "user",
             ChatRole::Assistant => "assistant",
             ChatRole::System => "system",
+            ChatRole::Tool => "tool",
+        };
+
+        let tool_calls = ToolCall::from_metadata(message.metadata.as_ref()).map(|calls| {
+            calls
+                .iter()
+                .map(|call| OpenAIToolCall {
+                    id: call.id.clone(),
+                    type_: "function".to_string(),
+                    function: OpenAIFunctionCall {
+                        name: call.name.clone(),
+                        arguments: call.arguments_string(),
+                    },
+                })
+                .collect::<Vec<_>>()
+        });
+
+        let tool_call_id = message
+            .metadata
+            .as_ref()
+            .and_then(|m| m.get(TOOL_CALL_ID_KEY))
+            .and_then(|v| v.as_str())
+            .map(|id| id.to_string());
+
+        // PDFs are not accepted on the chat completions path; their text is already inlined
+        let text = message.content.clone();
+
+        // Images are sent as content parts alongside the text; OpenAI accepts both
+        // data: URLs and remote URLs
+        let image_parts: Vec<OpenAIContentPart> = attachments::images(&message)
+            .map(|image| OpenAIContentPart::ImageUrl {
+                image_url: OpenAIImageUrl { url: image.url.clone() },
+            })
+            .collect();
+
+        // Assistant messages that only carry tool calls are sent with null content
+        let content = if !image_parts.is_empty() {
+            let mut parts = Vec::new();
+            if !text.is_empty() {
+                parts.push(OpenAIContentPart::Text { text });
+            }
+            parts.extend(image_parts);
+            Some(OpenAIContent::Parts(parts))
+        } else if tool_calls.is_some() && text.is_empty() {
+            None
+        } else {
+            Some(OpenAIContent::Text(text))
         };
 
         OpenAIChatMessage {
             role: role.to_string(),
-            content: message.content,
+            content,
+            tool_calls,
+            tool_call_id,
         }
     }
 
+    /// Convert tool specs to OpenAI format
+    fn convert_tools(tools: Option<Vec<ToolSpec>>) -> Option<Vec<OpenAITool>> {
+        tools.filter(|t| !t.is_empty()).map(|tools| {
+            tools
+                .into_iter()
+                .map(|tool| OpenAITool {
+                    type_: "function".to_string(),
+                    function: OpenAIFunction {
+                        name: tool.name,
+                        description: tool.description,
+                        parameters: tool.parameters,
+                    },
+                })
+                .collect()
+        })
+    }
+
+    /// Convert tool choice to OpenAI format
+    fn convert_tool_choice(tool_choice: Option<ToolChoice>) -> Option<serde_json::Value> {
+        tool_choice.map(|choice| match choice {
+            ToolChoice::Auto => serde_json::json!("auto"),
+            ToolChoice::None => serde_json::json!("none"),
+            ToolChoice::Required => serde_json::json!("required"),
+            ToolChoice::Function { name } => serde_json::json!({
+                "type": "function",
+                "function": { "name": name }
+            }),
+        })
+    }
+
     /// Convert OpenAI response to our format
     fn convert_from_openai_response(response: &OpenAIChatResponse) -> ChatMessage {
         let choice = response.choices.first();
         let content = choice
-            .map(|c| c.message.content.clone())
-            .unwrap_or_else(|| "No response generated".to_string());
+            .and_then(|c| c.message.content.clone())
+            .unwrap_or_default();
+
+        let mut metadata = HashMap::from([
+            ("model".to_string(), serde_json::Value::String(response.model.clone())),
+            ("finish_reason".to_string(), serde_json::Value::String(
+                choice.and_then(|c| c.finish_reason.clone()).unwrap_or("unknown".to_string())
+            )),
+        ]);
+        // Some OpenAI-compatible servers leave usage out
+        if let Some(usage) = &response.usage {
+            metadata.insert("usage".to_string(), serde_json::to_value(usage).unwrap_or(serde_json::Value::Null));
+        }
+
+        let tool_calls: Vec<ToolCall> = choice
+            .and_then(|c| c.message.tool_calls.as_ref())
+            .map(|calls| {
+                calls
+                    .iter()
+                    .map(|call| ToolCall::from_raw_arguments(
+                        call.id.clone(),
+                        call.function.name.clone(),
+                        &call.function.arguments,
+                    ))
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        if !tool_calls.is_empty() {
+            metadata.insert(
+                TOOL_CALLS_KEY.to_string(),
+                serde_json::to_value(&tool_calls).unwrap_or(serde_json::Value::Null),
+            );
+        }
+        if let Some(reasoning) = choice.and_then(|c| c.message.reasoning_text()) {
+            Reasoning::insert_into(&[Reasoning::new(reasoning)], &mut metadata);
+        }
 
         ChatMessage {
             id: response.id.clone(),
@@ -74,13 +366,35 @@ impl OpenAIService {
             content,
             created_at: Some(chrono::Utc::now()),
             attachments: None,
-            metadata: Some(HashMap::from([
-                ("model".to_string(), serde_json::Value::String(response.model.clone())),
-                ("usage".to_string(), serde_json::to_value(&response.usage).unwrap_or(serde_json::Value::Null)),
-                ("finish_reason".to_string(), serde_json::Value::String(
-                    choice.and_then(|c| c.finish_reason.clone()).unwrap_or("unknown".to_string())
-                )),
-            ])),
+            metadata: Some(metadata),
+        }
+    }
+
+    fn build_request(
+        model: String,
+        messages: Vec<OpenAIChatMessage>,
+        options: GenerationOptions,
+        tools: Option<Vec<ToolSpec>>,
+        tool_choice: Option<ToolChoice>,
+        stream: bool,
+    ) -> OpenAIChatRequest {
+        OpenAIChatRequest {
+            model,
+            messages,
+            temperature: options.temperature,
+            max_tokens: options.max_tokens,
+            top_p: options.top_p,
+            frequency_penalty: options.frequency_penalty,
+            presence_penalty: options.presence_penalty,
+            stop: options.stop_sequences(),
+            seed: options.seed,
+            user: options.user,
+            logit_bias: options.logit_bias.filter(|bias| !bias.is_empty()),
+            response_format: options.response_format,
+            stream,
+            stream_options: stream.then_some(OpenAIStreamOptions { include_usage: true }),
+            tools: Self::convert_tools(tools),
+            tool_choice: Self::convert_tool_choice(tool_choice),
         }
     }
 
@@ -89,35 +403,20 @@ impl OpenAIService {
         &self,
         messages: Vec<ChatMessage>,
         model: Option<String>,
-        temperature: Option<f32>,
-        max_tokens: Option<u32>,
+        options: GenerationOptions,
+        tools: Option<Vec<ToolSpec>>,
+        tool_choice: Option<ToolChoice>,
     ) -> Result<ChatMessage> {
+        let messages = attachments::inline_document_text(messages).await?;
         let openai_messages: Vec<OpenAIChatMessage> =
             messages.into_iter().map(Self::convert_to_openai_message).collect();
 
         let model_name = model.unwrap_or_else(|| self.default_model.clone());
 
-        let request = OpenAIChatRequest {
-            model: model_name,
-            messages: openai_messages,
-            temperature: temperature.unwrap_or(0.7),
-            max_tokens: max_tokens.unwrap_or(1000),
-            stream: false,
-        };
+        let builder = self.post("/chat/completions", &model_name);
+        let request = Self::build_request(model_name, openai_messages, options, tools, tool_choice, false);
 
-        let response = self.client
-            .post(format!("{}/chat/completions", self.base_url))
-            .header("Authorization", format!("Bearer {}", self.api_key))
-            .header("Content-Type", "application/json")
-            .json(&request)
-            .send()
-            .await?;
-
-        if !response.status().is_success() {
-            let status = response.status();
-            let error_text = response.text().await.unwrap_or_default();
-            return Err(anyhow!("OpenAI API error: {} - {}", status, error_text));
-        }
+        let response = self.retry.send(self.name(), builder.json(&request)).await?;
 
         let openai_response: OpenAIChatResponse = response.json().await?;
         Ok(Self::convert_from_openai_response(&openai_response))
@@ -128,96 +427,108 @@ impl OpenAIService {
         &self,
         messages: Vec<ChatMessage>,
         model: Option<String>,
-        temperature: Option<f32>,
-        max_tokens: Option<u32>,
-    ) -> Result<BoxStream<'static, Result<UIMessageChunk, anyhow::Error>>> {
+        options: GenerationOptions,
+        tools: Option<Vec<ToolSpec>>,
+        tool_choice: Option<ToolChoice>,
+    ) -> Result<ChunkStream> {
+        let messages = attachments::inline_document_text(messages).await?;
         let openai_messages: Vec<OpenAIChatMessage> =
             messages.into_iter().map(Self::convert_to_openai_message).collect();
 
         let model_name = model.unwrap_or_else(|| self.default_model.clone());
 
-        let request = OpenAIChatRequest {
-            model: model_name,
-            messages: openai_messages,
-            temperature: temperature.unwrap_or(0.7),
-            max_tokens: max_tokens.unwrap_or(1000),
-            stream: true,
-        };
+        let builder = self.post_with(&self.stream_client, "/chat/completions", &model_name);
+        let request = Self::build_request(model_name, openai_messages, options, tools, tool_choice, true);
 
-        let response = self.client
-            .post(format!("{}/chat/completions", self.base_url))
-            .header("Authorization", format!("Bearer {}", self.api_key))
-            .header("Content-Type", "application/json")
-            .json(&request)
-            .send()
-            .await?;
-
-        if !response.status().is_success() {
-            let status = response.status();
-            let error_text = response.text().await.unwrap_or_default();
-            return Err(anyhow!("OpenAI API error: {} - {}", status, error_text));
-        }
+        let name = self.name();
+        let response = self.retry.send(name, builder.json(&request)).await?;
 
         let stream = Box::pin(stream! {
-            let mut bytes_stream = response.bytes_stream();
-
-            while let Some(chunk_result) = bytes_stream.next().await {
-                match chunk_result {
-                    Ok(chunk) => {
-                        let chunk_str = String::from_utf8_lossy(&chunk);
-
-                        // Process SSE data
-                        for line in chunk_str.lines() {
-                            let line = line.trim();
-                            if line.starts_with("data: ") {
-                                let data = &line[6..];
-
-                                if data == "[DONE]" {
-                                    // Send finish event
-                                    yield Ok(UIMessageChunk::Finish {
-                                        reasoning: None,
-                                        sources: None,
-                                        usage: None,
-                                        logprobs: None,
-                                    });
-                                    break;
+            let mut events = decoder::sse_events(response.bytes_stream());
+            let mut tool_calls = ToolCallAccumulator::new();
+            let mut reasoning = ReasoningStream::new();
+            let mut usage = None;
+            let mut done = false;
+
+            while let Some(event) = events.next().await {
+                let event = match event {
+                    Ok(event) => event,
+                    Err(e) => {
+                        yield Err(e);
+                        return;
+                    }
+                };
+
+                if event.data == "[DONE]" {
+                    done = true;
+                    break;
+                }
+
+                // Parse JSON chunk
+                if let Ok(parsed) = serde_json::from_str::<serde_json::Value>(&event.data) {
+                    // Usage arrives in a final chunk without choices, after the finish reason
+                    if let Some(chunk_usage) = parsed.get("usage").and_then(|u| serde_json::from_value::<Usage>(u.clone()).ok()) {
+                        usage = Some(chunk_usage);
+                    }
+
+                    if let Some(choice) = parsed.get("choices").and_then(|c| c.as_array()).and_then(|c| c.first()) {
+                        if let Some(delta) = choice.get("delta") {
+                            // DeepSeek and vLLM send `reasoning_content`, OpenRouter and Groq `reasoning`
+                            let reasoning_delta = ["reasoning_content", "reasoning"]
+                                .iter()
+                                .find_map(|key| delta.get(*key).and_then(|r| r.as_str()));
+                            if let Some(text) = reasoning_delta {
+                                for chunk in reasoning.delta(text) {
+                                    yield Ok(chunk);
                                 }
+                            }
 
-                                // Parse JSON chunk
-                                if let Ok(parsed) = serde_json::from_str::<serde_json::Value>(data) {
-                                    if let Some(choices) = parsed.get("choices").and_then(|c| c.as_array()) {
-                                        if let Some(choice) = choices.first() {
-                                            if let Some(delta) = choice.get("delta") {
-                                                if let Some(content) = delta.get("content").and_then(|c| c.as_str()) {
-                                                    yield Ok(UIMessageChunk::TextDelta {
-                                                        textDelta: content.to_string(),
-                                                    });
-                                                }
-                                            }
-
-                                            // Check for finish reason
-                                            if let Some(finish_reason) = choice.get("finish_reason").and_then(|f| f.as_str()) {
-                                                if finish_reason != "null" {
-                                                    yield Ok(UIMessageChunk::Finish {
-                                                        reasoning: None,
-                                                        sources: None,
-                                                        usage: None,
-                                                        logprobs: None,
-                                                    });
-                                                }
-                                            }
-                                        }
-                                    }
+                            if let Some(content) = delta.get("content").and_then(|c| c.as_str()).filter(|c| !c.is_empty()) {
+                                if let Some(chunk) = reasoning.end(None) {
+                                    yield Ok(chunk);
                                 }
+                                yield Ok(UIMessageChunk::TextDelta {
+                                    textDelta: content.to_string(),
+                                });
+                            }
+
+                            // Tool calls arrive as fragments keyed by index
+                            if let Some(deltas) = delta.get("tool_calls").and_then(|t| t.as_array()) {
+                                tool_calls.push_openai_delta(deltas);
+                            }
+                        }
+
+                        // Check for finish reason
+                        if choice.get("finish_reason").and_then(|f| f.as_str()).is_some() {
+                            if let Some(chunk) = reasoning.end(None) {
+                                yield Ok(chunk);
+                            }
+                            for call in tool_calls.finish() {
+                                yield Ok(call.into_chunk());
                             }
                         }
-                    }
-                    Err(e) => {
-                        yield Err(anyhow!("Stream error: {}", e));
-                        break;
                     }
                 }
             }
+
+            if !done {
+                yield Err(anyhow!("{} stream ended before [DONE]", name));
+                return;
+            }
+
+            // Flush any reasoning and tool calls and send finish event
+            if let Some(chunk) = reasoning.end(None) {
+                yield Ok(chunk);
+            }
+            for call in tool_calls.finish() {
+                yield Ok(call.into_chunk());
+            }
+            yield Ok(UIMessageChunk::Finish {
+                reasoning: reasoning.text(),
+                sources: None,
+                usage,
+                logprobs: None,
+            });
         });
 
         Ok(stream)
@@ -235,19 +546,149 @@ impl OpenAIService {
     }
 }
 
+/// Parts of model ids that `/models` lists but that cannot chat
+const NON_CHAT_MODELS: [&str; 9] = [
+    "embedding",
+    "whisper",
+    "tts",
+    "transcribe",
+    "dall-e",
+    "gpt-image",
+    "moderation",
+    "davinci",
+    "babbage",
+];
+
 #[derive(Debug, Serialize)]
 struct OpenAIChatRequest {
     model: String,
     messages: Vec<OpenAIChatMessage>,
-    temperature: f32,
-    max_tokens: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    temperature: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    max_tokens: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    top_p: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    frequency_penalty: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    presence_penalty: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    stop: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    seed: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    user: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    logit_bias: Option<HashMap<String, i32>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    response_format: Option<ResponseFormat>,
     stream: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    stream_options: Option<OpenAIStreamOptions>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tools: Option<Vec<OpenAITool>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tool_choice: Option<serde_json::Value>,
+}
+
+#[derive(Debug, Serialize)]
+struct OpenAIStreamOptions {
+    include_usage: bool,
 }
 
 #[derive(Debug, Serialize)]
 struct OpenAIChatMessage {
     role: String,
-    content: String,
+    content: Option<OpenAIContent>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tool_calls: Option<Vec<OpenAIToolCall>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tool_call_id: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(untagged)]
+enum OpenAIContent {
+    Text(String),
+    Parts(Vec<OpenAIContentPart>),
+}
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum OpenAIContentPart {
+    Text { text: String },
+    ImageUrl { image_url: OpenAIImageUrl },
+}
+
+#[derive(Debug, Serialize)]
+struct OpenAIImageUrl {
+    url: String,
+}
+
+#[derive(Debug, Serialize)]
+struct OpenAITool {
+    #[serde(rename = "type")]
+    type_: String,
+    function: OpenAIFunction,
+}
+
+#[derive(Debug, Serialize)]
+struct OpenAIFunction {
+    name: String,
+    description: String,
+    parameters: serde_json::Value,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct OpenAIToolCall {
+    id: String,
+    #[serde(rename = "type")]
+    type_: String,
+    function: OpenAIFunctionCall,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct OpenAIFunctionCall {
+    name: String,
+    arguments: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct OpenAIModelList {
+    data: Vec<OpenAIModel>,
+}
+
+#[derive(Debug, Deserialize)]
+struct OpenAIModel {
+    id: String,
+    /// Reported by some compatible servers (Groq, vLLM), not by OpenAI
+    #[serde(default, alias = "max_model_len")]
+    context_window: Option<u32>,
+}
+
+#[derive(Debug, Serialize)]
+struct OpenAIEmbeddingRequest {
+    model: String,
+    input: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct OpenAIEmbeddingResponse {
+    model: String,
+    data: Vec<OpenAIEmbedding>,
+    usage: Option<OpenAIEmbeddingUsage>,
+}
+
+#[derive(Debug, Deserialize)]
+struct OpenAIEmbedding {
+    index: usize,
+    embedding: Vec<f32>,
+}
+
+#[derive(Debug, Deserialize)]
+struct OpenAIEmbeddingUsage {
+    prompt_tokens: u32,
 }
 
 #[derive(Debug, Deserialize)]
@@ -257,7 +698,7 @@ struct OpenAIChatResponse {
     created: u64,
     model: String,
     choices: Vec<OpenAIChatChoice>,
-    usage: OpenAIUsage,
+    usage: Option<Usage>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -270,14 +711,21 @@ struct OpenAIChatChoice {
 #[derive(Debug, Deserialize)]
 struct OpenAIMessage {
     role: String,
-    content: String,
+    content: Option<String>,
+    tool_calls: Option<Vec<OpenAIToolCall>>,
+    /// Reasoning of DeepSeek, vLLM and other reasoning models
+    reasoning_content: Option<String>,
+    /// Reasoning as OpenRouter and Groq name it
+    reasoning: Option<String>,
 }
 
-#[derive(Debug, Deserialize, Serialize)]
-struct OpenAIUsage {
-    prompt_tokens: u32,
-    completion_tokens: u32,
-    total_tokens: u32,
+impl OpenAIMessage {
+    fn reasoning_text(&self) -> Option<String> {
+        self.reasoning_content
+            .clone()
+            .or_else(|| self.reasoning.clone())
+            .filter(|text| !text.is_empty())
+    }
 }
 
 #[async_trait]
@@ -286,30 +734,85 @@ impl AIProvider for OpenAIService {
         &self,
         messages: Vec<ChatMessage>,
         model: Option<String>,
-        temperature: Option<f32>,
-        max_tokens: Option<u32>,
+        options: GenerationOptions,
+        tools: Option<Vec<ToolSpec>>,
+        tool_choice: Option<ToolChoice>,
     ) -> Result<ChatMessage> {
-        Self::chat_completion(self, messages, model, temperature, max_tokens).await
+        Self::chat_completion(self, messages, model, options, tools, tool_choice).await
     }
 
     async fn chat_completion_stream(
         &self,
         messages: Vec<ChatMessage>,
         model: Option<String>,
-        temperature: Option<f32>,
-        max_tokens: Option<u32>,
-    ) -> BoxStream<'static, Result<UIMessageChunk, anyhow::Error>> {
-        Self::chat_completion_stream(self, messages, model, temperature, max_tokens).await.unwrap()
+        options: GenerationOptions,
+        tools: Option<Vec<ToolSpec>>,
+        tool_choice: Option<ToolChoice>,
+    ) -> Result<ChunkStream> {
+        Self::chat_completion_stream(self, messages, model, options, tools, tool_choice).await
     }
 
     fn get_available_models(&self) -> Vec<&'static str> {
         Self::get_model_options()
     }
+
+    async fn discover_models(&self) -> Result<Vec<ModelInfo>> {
+        // Azure lists the models it offers, not the deployed ones
+        if self.azure.is_some() {
+            return Ok(self.models.iter().map(ModelInfo::new).collect());
+        }
+
+        let response = self.retry.send(self.name(), self.get("/models")).await?;
+        let list: OpenAIModelList = response.json().await?;
+
+        Ok(list
+            .data
+            .into_iter()
+            .filter(|model| !NON_CHAT_MODELS.iter().any(|kind| model.id.contains(kind)))
+            .map(|model| ModelInfo {
+                capabilities: ModelCapabilities {
+                    context_window: model.context_window,
+                    ..Default::default()
+                },
+                ..ModelInfo::new(model.id)
+            })
+            .collect())
+    }
+
+    fn unsupported_options(&self, _model: &str, options: &GenerationOptions) -> Vec<&'static str> {
+        options.set_among(&["top_k", "candidate_count", "safety_settings"])
+    }
+
+    fn as_embedder(&self) -> Option<&dyn EmbeddingProvider> {
+        Some(self)
+    }
+}
+
+#[async_trait]
+impl EmbeddingProvider for OpenAIService {
+    async fn embed(&self, texts: Vec<String>, model: Option<String>) -> Result<Embeddings> {
+        let request = OpenAIEmbeddingRequest {
+            model: model.unwrap_or_else(|| DEFAULT_EMBEDDING_MODEL.to_string()),
+            input: texts,
+        };
+        let builder = self.post("/embeddings", &request.model);
+        let response = self.retry.send(self.name(), builder.json(&request)).await?;
+        let mut response: OpenAIEmbeddingResponse = response.json().await?;
+
+        // The API does not promise to keep the input order
+        response.data.sort_by_key(|embedding| embedding.index);
+        Ok(Embeddings {
+            model: response.model,
+            vectors: response.data.into_iter().map(|embedding| embedding.embedding).collect(),
+            prompt_tokens: response.usage.map(|usage| usage.prompt_tokens),
+        })
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::providers::testing::{message, serve, user};
 
     #[tokio::test]
     #[ignore] // Requires OPENAI_API_KEY environment variable
@@ -319,4 +822,359 @@ mod tests {
         let service = OpenAIService::from_env();
         assert!(service.is_ok());
     }
-}
\ No newline at end of file
+
+    #[tokio::test]
+    async fn test_discovered_models_skip_non_chat_models() {
+        let app = axum::Router::new().route(
+            "/v1/models",
+            axum::routing::get(|headers: axum::http::HeaderMap| async move {
+                assert_eq!(headers["authorization"], "Bearer key");
+                axum::Json(serde_json::json!({
+                    "object": "list",
+                    "data": [
+                        {"id": "gpt-4o", "object": "model", "owned_by": "openai"},
+                        {"id": "text-embedding-3-small", "object": "model", "owned_by": "openai"},
+                        {"id": "llama-3.1-8b-instant", "object": "model", "context_window": 131072}
+                    ]
+                }))
+            }),
+        );
+        let url = format!("{}/v1", serve(app).await);
+
+        let service = OpenAIService::new("key".to_string(), None).with_base_url(url);
+        let models = service.discover_models().await.unwrap();
+
+        assert_eq!(
+            models,
+            vec![
+                ModelInfo::new("gpt-4o"),
+                ModelInfo {
+                    capabilities: ModelCapabilities { context_window: Some(131072), ..Default::default() },
+                    ..ModelInfo::new("llama-3.1-8b-instant")
+                },
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_embeddings_keep_input_order() {
+        let app = axum::Router::new().route(
+            "/v1/embeddings",
+            axum::routing::post(|axum::Json(body): axum::Json<serde_json::Value>| async move {
+                assert_eq!(body["model"], "text-embedding-3-small");
+                assert_eq!(body["input"], serde_json::json!(["first", "second"]));
+                axum::Json(serde_json::json!({
+                    "object": "list",
+                    "data": [
+                        {"object": "embedding", "index": 1, "embedding": [0.5, 0.25]},
+                        {"object": "embedding", "index": 0, "embedding": [1.0, 0.0]}
+                    ],
+                    "model": "text-embedding-3-small",
+                    "usage": {"prompt_tokens": 4, "total_tokens": 4}
+                }))
+            }),
+        );
+        let url = format!("{}/v1", serve(app).await);
+
+        let service = OpenAIService::new("key".to_string(), None).with_base_url(url);
+        let embeddings = service
+            .embed(vec!["first".to_string(), "second".to_string()], None)
+            .await
+            .unwrap();
+
+        assert_eq!(embeddings.vectors, vec![vec![1.0, 0.0], vec![0.5, 0.25]]);
+        assert_eq!(embeddings.prompt_tokens, Some(4));
+    }
+
+    /// A mock Azure OpenAI resource with the deployment `prod-gpt4o`, answering "Hi" as a
+    /// completion and as a stream
+    async fn azure_mock() -> String {
+        async fn complete(
+            axum::extract::Path(deployment): axum::extract::Path<String>,
+            axum::extract::Query(query): axum::extract::Query<HashMap<String, String>>,
+            headers: axum::http::HeaderMap,
+            axum::Json(body): axum::Json<serde_json::Value>,
+        ) -> axum::response::Response {
+            use axum::response::IntoResponse;
+
+            assert_eq!(deployment, "prod-gpt4o");
+            assert_eq!(query["api-version"], DEFAULT_AZURE_API_VERSION);
+            assert_eq!(headers["api-key"], "azure-key");
+            assert!(headers.get("authorization").is_none());
+
+            if body["stream"] == true {
+                // Azure opens with a choice-less chunk of prompt filter results
+                concat!(
+                    "data: {\"choices\":[],\"prompt_filter_results\":[{\"prompt_index\":0}]}\n\n",
+                    "data: {\"choices\":[{\"index\":0,\"delta\":{\"content\":\"Hi\"}}]}\n\n",
+                    "data: {\"choices\":[{\"index\":0,\"delta\":{},\"finish_reason\":\"stop\"}]}\n\n",
+                    "data: {\"choices\":[],\"usage\":{\"prompt_tokens\":3,\"completion_tokens\":1,\"total_tokens\":4}}\n\n",
+                    "data: [DONE]\n\n",
+                )
+                .into_response()
+            } else {
+                axum::Json(serde_json::json!({
+                    "id": "chatcmpl-azure",
+                    "object": "chat.completion",
+                    "created": 0,
+                    "model": "gpt-4o-2024-08-06",
+                    "choices": [{"index": 0, "message": {"role": "assistant", "content": "Hi"}, "finish_reason": "stop"}],
+                    "usage": {"prompt_tokens": 3, "completion_tokens": 1, "total_tokens": 4}
+                }))
+                .into_response()
+            }
+        }
+
+        let app = axum::Router::new().route(
+            "/openai/deployments/{deployment}/chat/completions",
+            axum::routing::post(complete),
+        );
+        serve(app).await
+    }
+
+    fn azure_service(url: String) -> OpenAIService {
+        let deployments = HashMap::from([("gpt-4o".to_string(), "prod-gpt4o".to_string())]);
+        OpenAIService::new("azure-key".to_string(), None)
+            .with_base_url(url)
+            .with_azure(AzureDeployments::new(DEFAULT_AZURE_API_VERSION.to_string(), deployments))
+    }
+
+    fn hello() -> Vec<ChatMessage> {
+        vec![user("Hello")]
+    }
+
+    #[tokio::test]
+    async fn test_azure_requests_go_to_the_deployment() {
+        let service = azure_service(azure_mock().await);
+        assert_eq!(service.models(), ["gpt-4o"]);
+
+        let message = service
+            .chat_completion(hello(), Some("gpt-4o".to_string()), GenerationOptions::default(), None, None)
+            .await
+            .unwrap();
+        assert_eq!(message.content, "Hi");
+        assert_eq!(Usage::from_metadata(message.metadata.as_ref()).unwrap().total_tokens, 4);
+
+        let chunks: Vec<serde_json::Value> = service
+            .chat_completion_stream(hello(), Some("gpt-4o".to_string()), GenerationOptions::default(), None, None)
+            .await
+            .unwrap()
+            .map(|chunk| serde_json::to_value(chunk.unwrap()).unwrap())
+            .collect()
+            .await;
+        let types: Vec<&str> = chunks.iter().map(|c| c["type"].as_str().unwrap()).collect();
+        assert_eq!(types, vec!["text-delta", "finish"]);
+        assert_eq!(chunks[1]["usage"]["total_tokens"], 4);
+    }
+
+    #[test]
+    fn test_parse_azure_deployments() {
+        assert_eq!(
+            OpenAIService::parse_deployments("gpt-4o = prod-gpt4o, gpt-4o-mini,"),
+            vec![
+                ("gpt-4o".to_string(), "prod-gpt4o".to_string()),
+                ("gpt-4o-mini".to_string(), "gpt-4o-mini".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_tool_calls_from_response() {
+        let response: OpenAIChatResponse = serde_json::from_value(serde_json::json!({
+            "id": "chatcmpl-1",
+            "object": "chat.completion",
+            "created": 0,
+            "model": "gpt-4o",
+            "choices": [{
+                "index": 0,
+                "message": {
+                    "role": "assistant",
+                    "content": null,
+                    "tool_calls": [{
+                        "id": "call_abc",
+                        "type": "function",
+                        "function": {"name": "calculator", "arguments": "{\"expression\":\"2 + 2\"}"}
+                    }]
+                },
+                "finish_reason": "tool_calls"
+            }],
+            "usage": {"prompt_tokens": 10, "completion_tokens": 5, "total_tokens": 15}
+        }))
+        .unwrap();
+
+        let message = OpenAIService::convert_from_openai_response(&response);
+        let calls = ToolCall::from_metadata(message.metadata.as_ref()).unwrap();
+        assert_eq!(message.content, "");
+        assert_eq!(Usage::from_metadata(message.metadata.as_ref()).unwrap().total_tokens, 15);
+        assert_eq!(calls.len(), 1);
+        assert_eq!(calls[0].id, "call_abc");
+        assert_eq!(calls[0].arguments, serde_json::json!({"expression": "2 + 2"}));
+    }
+
+    #[test]
+    fn test_reasoning_content_is_kept_in_metadata() {
+        let response: OpenAIChatResponse = serde_json::from_value(serde_json::json!({
+            "id": "chatcmpl-2",
+            "object": "chat.completion",
+            "created": 0,
+            "model": "deepseek-reasoner",
+            "choices": [{
+                "index": 0,
+                "message": {"role": "assistant", "content": "4", "reasoning_content": "2 + 2 is 4."},
+                "finish_reason": "stop"
+            }]
+        }))
+        .unwrap();
+
+        let message = OpenAIService::convert_from_openai_response(&response);
+        let reasoning = Reasoning::from_metadata(message.metadata.as_ref()).unwrap();
+        assert_eq!(reasoning, vec![Reasoning::new("2 + 2 is 4.".to_string())]);
+    }
+
+    #[tokio::test]
+    async fn test_stream_reasoning_precedes_text() {
+        let body = concat!(
+            "data: {\"choices\":[{\"index\":0,\"delta\":{\"role\":\"assistant\",\"content\":\"\",\"reasoning_content\":\"Two plus\"}}]}\n\n",
+            "data: {\"choices\":[{\"index\":0,\"delta\":{\"content\":null,\"reasoning_content\":\" two.\"}}]}\n\n",
+            "data: {\"choices\":[{\"index\":0,\"delta\":{\"content\":\"4\",\"reasoning_content\":null}}]}\n\n",
+            "data: {\"choices\":[{\"index\":0,\"delta\":{},\"finish_reason\":\"stop\"}]}\n\n",
+            "data: [DONE]\n\n",
+        );
+        let app = axum::Router::new().route("/chat/completions", axum::routing::post(move || async move { body }));
+        let url = serve(app).await;
+
+        let service = OpenAIService::new(String::new(), Some("deepseek-reasoner".to_string())).with_base_url(url);
+        let messages = vec![user("2 + 2?")];
+        let chunks: Vec<serde_json::Value> = service
+            .chat_completion_stream(messages, None, GenerationOptions::default(), None, None)
+            .await
+            .unwrap()
+            .map(|chunk| serde_json::to_value(chunk.unwrap()).unwrap())
+            .collect()
+            .await;
+
+        let types: Vec<&str> = chunks.iter().map(|c| c["type"].as_str().unwrap()).collect();
+        assert_eq!(
+            types,
+            vec!["reasoning-start", "reasoning-delta", "reasoning-delta", "reasoning-end", "text-delta", "finish"]
+        );
+        assert_eq!(chunks[5]["reasoning"], "Two plus two.");
+    }
+
+    #[tokio::test]
+    async fn test_stream_cut_off_before_done_is_an_error() {
+        let body = "data: {\"choices\":[{\"index\":0,\"delta\":{\"content\":\"The answer is\"}}]}\n\n";
+        let app = axum::Router::new().route("/chat/completions", axum::routing::post(move || async move { body }));
+        let url = serve(app).await;
+
+        let service = OpenAIService::new(String::new(), None).with_base_url(url);
+        let results: Vec<Result<UIMessageChunk>> = service
+            .chat_completion_stream(hello(), None, GenerationOptions::default(), None, None)
+            .await
+            .unwrap()
+            .collect()
+            .await;
+
+        assert!(matches!(results[0], Ok(UIMessageChunk::TextDelta { .. })));
+        let error = results.last().unwrap().as_ref().unwrap_err();
+        assert_eq!(error.to_string(), "OpenAI stream ended before [DONE]");
+    }
+
+    #[test]
+    fn test_generation_options_are_sent() {
+        let options = GenerationOptions {
+            top_p: Some(0.9),
+            top_k: Some(40),
+            stop: Some(vec!["END".to_string()]),
+            seed: Some(7),
+            user: Some("user-1".to_string()),
+            logit_bias: Some(HashMap::from([("50256".to_string(), -100)])),
+            response_format: Some(ResponseFormat::JsonObject),
+            ..GenerationOptions::sampling(Some(0.2), None)
+        };
+        let service = OpenAIService::new(String::new(), None);
+        assert_eq!(service.unsupported_options("", &options), vec!["top_k"]);
+
+        let request = OpenAIService::build_request("gpt-4o".to_string(), Vec::new(), options, None, None, false);
+        let json = serde_json::to_value(&request).unwrap();
+        assert_eq!(json["temperature"].as_f64().unwrap() as f32, 0.2);
+        assert!(json.get("max_tokens").is_none());
+        assert_eq!(json["top_p"].as_f64().unwrap() as f32, 0.9);
+        assert_eq!(json["stop"][0], "END");
+        assert_eq!(json["seed"], 7);
+        assert_eq!(json["user"], "user-1");
+        assert_eq!(json["logit_bias"]["50256"], -100);
+        assert_eq!(json["response_format"]["type"], "json_object");
+        assert!(json.get("frequency_penalty").is_none());
+        assert!(json.get("stream_options").is_none());
+    }
+
+    #[test]
+    fn test_unset_options_are_left_to_the_api() {
+        // Reasoning models reject temperature and max_tokens, and a default cap would truncate
+        let request = OpenAIService::build_request(
+            "o3-mini".to_string(),
+            Vec::new(),
+            GenerationOptions::default(),
+            None,
+            None,
+            false,
+        );
+        let json = serde_json::to_value(&request).unwrap();
+        assert!(json.get("temperature").is_none());
+        assert!(json.get("max_tokens").is_none());
+    }
+
+    #[test]
+    fn test_convert_tool_messages() {
+        let assistant = ChatMessage {
+            metadata: Some(HashMap::from([(
+                TOOL_CALLS_KEY.to_string(),
+                serde_json::json!([{"id": "call_abc", "name": "calculator", "arguments": {"expression": "2 + 2"}}]),
+            )])),
+            ..message(ChatRole::Assistant, "")
+        };
+        let tool = ChatMessage {
+            metadata: Some(HashMap::from([(
+                TOOL_CALL_ID_KEY.to_string(),
+                serde_json::json!("call_abc"),
+            )])),
+            ..message(ChatRole::Tool, "{\"result\":4}")
+        };
+
+        let assistant = serde_json::to_value(OpenAIService::convert_to_openai_message(assistant)).unwrap();
+        assert_eq!(assistant["content"], serde_json::Value::Null);
+        assert_eq!(assistant["tool_calls"][0]["function"]["arguments"], "{\"expression\":\"2 + 2\"}");
+
+        let tool = serde_json::to_value(OpenAIService::convert_to_openai_message(tool)).unwrap();
+        assert_eq!(tool["role"], "tool");
+        assert_eq!(tool["tool_call_id"], "call_abc");
+    }
+
+    #[test]
+    fn test_image_attachments_become_content_parts() {
+        let message = ChatMessage {
+            attachments: Some(vec![
+                crate::chat::Attachment {
+                    attachment_type: "file".to_string(),
+                    url: "data:image/png;base64,iVBORw0KGgo=".to_string(),
+                    media_type: Some("image/png".to_string()),
+                    filename: Some("screenshot.png".to_string()),
+                },
+                crate::chat::Attachment {
+                    attachment_type: "file".to_string(),
+                    url: "https://example.com/cat.jpg".to_string(),
+                    media_type: None,
+                    filename: None,
+                },
+            ]),
+            ..user("What is in this picture?")
+        };
+
+        let json = serde_json::to_value(OpenAIService::convert_to_openai_message(message)).unwrap();
+        assert_eq!(json["content"][0]["type"], "text");
+        assert_eq!(json["content"][1]["type"], "image_url");
+        assert_eq!(json["content"][1]["image_url"]["url"], "data:image/png;base64,iVBORw0KGgo=");
+        assert_eq!(json["content"][2]["image_url"]["url"], "https://example.com/cat.jpg");
+    }
+}
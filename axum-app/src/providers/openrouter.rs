@@ -1,61 +1,120 @@
 use anyhow::{anyhow, Result};
 use serde::{Deserialize, Serialize};
 use reqwest::Client;
-use futures::stream::BoxStream;
 use futures::StreamExt;
 use async_trait::async_trait;
 
-use crate::chat::{ChatMessage, ChatRole, UIMessageChunk};
-use crate::providers::AIProvider;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use crate::chat::{ChatMessage, ChatRole, UIMessageChunk, Usage};
+use crate::providers::attachments;
+use crate::providers::capabilities::Pricing;
+use crate::providers::decoder;
+use crate::providers::reasoning::{Reasoning, ReasoningStream};
+use crate::providers::tools::{ToolCallAccumulator, TOOL_CALLS_KEY, TOOL_CALL_ID_KEY};
+use crate::providers::{
+    AIProvider, ChunkStream, GenerationOptions, ModelCapabilities, ModelInfo, ResponseFormat, RetryPolicy, ToolCall, ToolChoice,
+    ToolSpec,
+};
+
+/// OpenRouter provider routing preferences
+/// See https://openrouter.ai/docs/features/provider-routing
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct OpenRouterProviderPreferences {
+    /// Providers to try, in order (e.g. ["Anthropic", "Together"])
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub order: Option<Vec<String>>,
+    /// Whether to fall back to other providers when the preferred ones fail
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub allow_fallbacks: Option<bool>,
+    /// "allow" or "deny" providers that may store or train on prompts
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub data_collection: Option<String>,
+}
 
 /// OpenRouter service for AI model access
 #[derive(Debug, Clone)]
 pub struct OpenRouterService {
     client: Client,
+    /// Client for streams, with connect and read timeouts instead of a total one
+    stream_client: Client,
     api_key: String,
     base_url: String,
+    provider_preferences: Option<OpenRouterProviderPreferences>,
+    transforms: Option<Vec<String>>,
+    retry: RetryPolicy,
 }
 
 impl OpenRouterService {
     pub fn new(api_key: String) -> Self {
         Self {
             client: Client::new(),
+            stream_client: super::stream_client_builder()
+                .build()
+                .expect("Failed to create HTTP client"),
             api_key,
             base_url: "https://openrouter.ai/api/v1".to_string(),
+            provider_preferences: None,
+            transforms: None,
+            retry: RetryPolicy::from_env(),
         }
     }
 
+    /// Set the provider routing preferences and transforms sent with every request
+    pub fn with_routing(
+        mut self,
+        provider_preferences: Option<OpenRouterProviderPreferences>,
+        transforms: Option<Vec<String>>,
+    ) -> Self {
+        self.provider_preferences = provider_preferences;
+        self.transforms = transforms;
+        self
+    }
+
     pub fn from_env() -> Result<Self> {
         let api_key = std::env::var("OPENROUTER_API_KEY")
             .map_err(|_| anyhow!("OPENROUTER_API_KEY environment variable not set"))?;
         Ok(Self::new(api_key))
     }
 
-    async fn make_request(&self, messages: Vec<OpenRouterMessage>, model: Option<String>,
-                         temperature: Option<f32>, max_tokens: Option<u32>,
-                         stream: bool) -> Result<OpenRouterResponse> {
+    /// Send a chat request, returning the response once its status is known to be successful
+    async fn send_request(&self, messages: Vec<OpenRouterMessage>, model: Option<String>,
+                          options: GenerationOptions,
+                          tools: Option<Vec<ToolSpec>>, tool_choice: Option<ToolChoice>,
+                          stream: bool) -> Result<reqwest::Response> {
         let request = OpenRouterRequest {
             model: model.unwrap_or_else(|| "openai/gpt-3.5-turbo".to_string()),
             messages,
-            temperature,
-            max_tokens,
             stream,
-            ..Default::default()
+            // Streams only report usage when asked to
+            usage: stream.then_some(OpenRouterUsageOptions { include: true }),
+            tools: Self::convert_tools(tools),
+            tool_choice: Self::convert_tool_choice(tool_choice),
+            provider: self.provider_preferences.clone(),
+            transforms: self.transforms.clone(),
+            ..OpenRouterRequest::default().with_options(options)
         };
 
-        let response = self.client
+        let client = if stream { &self.stream_client } else { &self.client };
+        let builder = client
             .post(&format!("{}/chat/completions", self.base_url))
             .header("Authorization", format!("Bearer {}", self.api_key))
             .header("HTTP-Referer", "https://github.com/iroh-chatbot")
             .header("X-Title", "Iroh Chatbot")
-            .json(&request)
-            .send()
-            .await?;
+            .json(&request);
 
-        if !response.status().is_success() {
-            let error_text = response.text().await?;
-            return Err(anyhow!("OpenRouter API error: {}", error_text));
-        }
+        let response = self.retry.send("OpenRouter", builder).await?;
+
+        Ok(response)
+    }
+
+    async fn make_request(&self, messages: Vec<OpenRouterMessage>, model: Option<String>,
+                         options: GenerationOptions,
+                         tools: Option<Vec<ToolSpec>>, tool_choice: Option<ToolChoice>) -> Result<OpenRouterResponse> {
+        let response = self
+            .send_request(messages, model, options, tools, tool_choice, false)
+            .await?;
 
         let response_text = response.text().await?;
         let openrouter_response: OpenRouterResponse = serde_json::from_str(&response_text)
@@ -67,30 +126,140 @@ impl OpenRouterService {
     fn convert_to_openrouter_messages(messages: &[ChatMessage]) -> Vec<OpenRouterMessage> {
         messages
             .iter()
-            .map(|msg| OpenRouterMessage {
-                role: match msg.role {
-                    ChatRole::User => "user".to_string(),
-                    ChatRole::Assistant => "assistant".to_string(),
-                    ChatRole::System => "system".to_string(),
-                },
-                content: msg.content.clone(),
+            .map(|msg| {
+                let tool_calls = ToolCall::from_metadata(msg.metadata.as_ref()).map(|calls| {
+                    calls
+                        .iter()
+                        .map(|call| OpenRouterToolCall {
+                            id: call.id.clone(),
+                            type_: "function".to_string(),
+                            function: OpenRouterFunctionCall {
+                                name: call.name.clone(),
+                                arguments: call.arguments_string(),
+                            },
+                        })
+                        .collect::<Vec<_>>()
+                });
+
+                OpenRouterMessage {
+                    role: match msg.role {
+                        ChatRole::User => "user".to_string(),
+                        ChatRole::Assistant => "assistant".to_string(),
+                        ChatRole::System => "system".to_string(),
+                        ChatRole::Tool => "tool".to_string(),
+                    },
+                    content: Self::convert_content(msg, tool_calls.is_some()),
+                    tool_calls,
+                    tool_call_id: msg
+                        .metadata
+                        .as_ref()
+                        .and_then(|m| m.get(TOOL_CALL_ID_KEY))
+                        .and_then(|v| v.as_str())
+                        .map(|id| id.to_string()),
+                }
             })
             .collect()
     }
 
-    fn convert_from_openrouter_message(openrouter_msg: &OpenRouterChoice) -> ChatMessage {
+    /// Message content as plain text, or as text and image parts when images are attached
+    ///
+    /// PDF attachments have already been replaced by their text, since OpenRouter has no
+    /// document input that works across models.
+    fn convert_content(msg: &ChatMessage, has_tool_calls: bool) -> Option<OpenRouterContent> {
+        let text = msg.content.clone();
+        let image_parts: Vec<OpenRouterContentPart> = attachments::images(msg)
+            .map(|image| OpenRouterContentPart::ImageUrl {
+                image_url: OpenRouterImageUrl { url: image.url.clone() },
+            })
+            .collect();
+
+        if !image_parts.is_empty() {
+            let mut parts = Vec::new();
+            if !text.is_empty() {
+                parts.push(OpenRouterContentPart::Text { text });
+            }
+            parts.extend(image_parts);
+            Some(OpenRouterContent::Parts(parts))
+        } else if has_tool_calls && text.is_empty() {
+            None
+        } else {
+            Some(OpenRouterContent::Text(text))
+        }
+    }
+
+    fn convert_tools(tools: Option<Vec<ToolSpec>>) -> Option<Vec<OpenRouterTool>> {
+        tools.filter(|t| !t.is_empty()).map(|tools| {
+            tools
+                .into_iter()
+                .map(|tool| OpenRouterTool {
+                    type_: "function".to_string(),
+                    function: OpenRouterFunction {
+                        name: tool.name,
+                        description: tool.description,
+                        parameters: tool.parameters,
+                    },
+                })
+                .collect()
+        })
+    }
+
+    fn convert_tool_choice(tool_choice: Option<ToolChoice>) -> Option<serde_json::Value> {
+        tool_choice.map(|choice| match choice {
+            ToolChoice::Auto => serde_json::json!("auto"),
+            ToolChoice::None => serde_json::json!("none"),
+            ToolChoice::Required => serde_json::json!("required"),
+            ToolChoice::Function { name } => serde_json::json!({
+                "type": "function",
+                "function": { "name": name }
+            }),
+        })
+    }
+
+    fn convert_from_openrouter_message(response: &OpenRouterResponse, openrouter_msg: &OpenRouterChoice) -> ChatMessage {
+        let mut metadata = HashMap::from([
+            ("model".to_string(), serde_json::Value::String(response.model.clone())),
+            ("provider".to_string(), serde_json::Value::String("openrouter".to_string())),
+        ]);
+        if let Some(usage) = &response.usage {
+            metadata.insert("usage".to_string(), serde_json::to_value(usage).unwrap_or(serde_json::Value::Null));
+        }
+        if let Some(finish_reason) = &openrouter_msg.finish_reason {
+            metadata.insert("finish_reason".to_string(), serde_json::Value::String(finish_reason.clone()));
+        }
+
+        let tool_calls: Vec<ToolCall> = openrouter_msg
+            .message
+            .tool_calls
+            .iter()
+            .flatten()
+            .map(|call| ToolCall::from_raw_arguments(
+                call.id.clone(),
+                call.function.name.clone(),
+                &call.function.arguments,
+            ))
+            .collect();
+        if !tool_calls.is_empty() {
+            metadata.insert(
+                TOOL_CALLS_KEY.to_string(),
+                serde_json::to_value(&tool_calls).unwrap_or(serde_json::Value::Null),
+            );
+        }
+        if let Some(reasoning) = openrouter_msg.message.reasoning.clone().filter(|r| !r.is_empty()) {
+            Reasoning::insert_into(&[Reasoning::new(reasoning)], &mut metadata);
+        }
+
         ChatMessage {
-            id: openrouter_msg.message.id.clone(),
+            id: response.id.clone(),
             role: match openrouter_msg.message.role.as_str() {
                 "user" => ChatRole::User,
                 "assistant" => ChatRole::Assistant,
                 "system" => ChatRole::System,
                 _ => ChatRole::Assistant,
             },
-            content: openrouter_msg.message.content.clone(),
+            content: openrouter_msg.message.content.clone().unwrap_or_default(),
             created_at: Some(chrono::Utc::now()),
             attachments: None,
-            metadata: None,
+            metadata: Some(metadata),
         }
     }
 
@@ -128,113 +297,149 @@ impl OpenRouterService {
 #[async_trait::async_trait]
 impl AIProvider for OpenRouterService {
     async fn chat_completion(&self, messages: Vec<ChatMessage>, model: Option<String>,
-                            temperature: Option<f32>, max_tokens: Option<u32>) -> Result<ChatMessage> {
+                            options: GenerationOptions,
+                            tools: Option<Vec<ToolSpec>>, tool_choice: Option<ToolChoice>) -> Result<ChatMessage> {
+        let messages = attachments::inline_document_text(messages).await?;
         let openrouter_messages = Self::convert_to_openrouter_messages(&messages);
-        let response = self.make_request(openrouter_messages, model, temperature, max_tokens, false).await?;
+        let response = self.make_request(openrouter_messages, model, options, tools, tool_choice).await?;
 
         if let Some(choice) = response.choices.first() {
-            Ok(Self::convert_from_openrouter_message(choice))
+            Ok(Self::convert_from_openrouter_message(&response, choice))
         } else {
             Err(anyhow!("No response choices returned from OpenRouter"))
         }
     }
 
     async fn chat_completion_stream(&self, messages: Vec<ChatMessage>, model: Option<String>,
-                                  temperature: Option<f32>, max_tokens: Option<u32>) -> BoxStream<'static, Result<UIMessageChunk, anyhow::Error>> {
+                                  options: GenerationOptions,
+                                  tools: Option<Vec<ToolSpec>>, tool_choice: Option<ToolChoice>) -> Result<ChunkStream> {
+        let messages = attachments::inline_document_text(messages).await?;
         let openrouter_messages = Self::convert_to_openrouter_messages(&messages);
-        let api_key = self.api_key.clone();
-        let base_url = self.base_url.clone();
-        let selected_model = model.unwrap_or_else(|| "openai/gpt-3.5-turbo".to_string());
+        let response = self
+            .send_request(openrouter_messages, model, options, tools, tool_choice, true)
+            .await?;
 
         let stream = async_stream::stream! {
-            let request = OpenRouterRequest {
-                model: selected_model,
-                messages: openrouter_messages,
-                temperature,
-                max_tokens,
-                stream: true,
-                ..Default::default()
-            };
-
-            let client = Client::new();
-            match client
-                .post(&format!("{}/chat/completions", base_url))
-                .header("Authorization", format!("Bearer {}", api_key))
-                .header("HTTP-Referer", "https://github.com/iroh-chatbot")
-                .header("X-Title", "Iroh Chatbot")
-                .json(&request)
-                .send()
-                .await {
-                Ok(response) => {
-                    if !response.status().is_success() {
-                        let error_text = response.text().await.unwrap_or_default();
-                        yield Err(anyhow!("OpenRouter API error: {}", error_text));
+            let mut events = decoder::sse_events(response.bytes_stream());
+            let mut tool_calls = ToolCallAccumulator::new();
+            let mut reasoning = ReasoningStream::new();
+            let mut usage = None;
+            let mut done = false;
+
+            while let Some(event) = events.next().await {
+                let event = match event {
+                    Ok(event) => event,
+                    Err(e) => {
+                        yield Err(e);
                         return;
                     }
+                };
+
+                if event.data.trim() == "[DONE]" {
+                    done = true;
+                    break;
+                }
+
+                // Skip malformed JSON but continue processing
+                let chunk = match serde_json::from_str::<OpenRouterStreamChunk>(&event.data) {
+                    Ok(chunk) => chunk,
+                    Err(_) => continue,
+                };
+                // Usage arrives in a final chunk without choices
+                if chunk.usage.is_some() {
+                    usage = chunk.usage;
+                }
+                if let Some(choice) = chunk.choices.first() {
+                    if let Some(text) = &choice.delta.reasoning {
+                        for chunk in reasoning.delta(text) {
+                            yield Ok(chunk);
+                        }
+                    }
 
-                    let mut byte_stream = response.bytes_stream();
-                    let mut buffer = String::new();
-
-                    while let Some(chunk_result) = byte_stream.next().await {
-                        match chunk_result {
-                            Ok(chunk) => {
-                                if let Ok(chunk_str) = std::str::from_utf8(&chunk) {
-                                    buffer.push_str(chunk_str);
-
-                                    // Process complete lines
-                                    while let Some(newline_pos) = buffer.find('\n') {
-                                        let line = buffer[..newline_pos].to_string();
-                                        buffer = buffer[newline_pos + 1..].to_string();
-
-                                        if line.trim().is_empty() || !line.starts_with("data: ") {
-                                            continue;
-                                        }
-
-                                        let data = &line[6..]; // Remove "data: " prefix
-
-                                        if data.trim() == "[DONE]" {
-                                            return;
-                                        }
-
-                                        match serde_json::from_str::<OpenRouterStreamChunk>(data) {
-                                            Ok(chunk) => {
-                                                if let Some(content) = chunk.choices
-                                                    .first()
-                                                    .and_then(|choice| choice.delta.content.as_ref()) {
-
-                                                    yield Ok(UIMessageChunk::TextDelta {
-                                                        textDelta: content.clone(),
-                                                    });
-                                                }
-                                            }
-                                            Err(e) => {
-                                                // Skip malformed JSON but continue processing
-                                                continue;
-                                            }
-                                        }
-                                    }
-                                }
-                            }
-                            Err(e) => {
-                                yield Err(anyhow!("Stream error: {}", e));
-                                return;
-                            }
+                    if let Some(content) = choice.delta.content.as_ref().filter(|c| !c.is_empty()) {
+                        if let Some(chunk) = reasoning.end(None) {
+                            yield Ok(chunk);
+                        }
+                        yield Ok(UIMessageChunk::TextDelta {
+                            textDelta: content.clone(),
+                        });
+                    }
+
+                    // Tool calls arrive as fragments keyed by index
+                    if let Some(deltas) = &choice.delta.tool_calls {
+                        tool_calls.push_openai_delta(deltas);
+                    }
+
+                    if choice.finish_reason.is_some() {
+                        if let Some(chunk) = reasoning.end(None) {
+                            yield Ok(chunk);
+                        }
+                        for call in tool_calls.finish() {
+                            yield Ok(call.into_chunk());
                         }
                     }
                 }
-                Err(e) => {
-                    yield Err(anyhow!("Failed to start stream: {}", e));
-                    return;
-                }
             }
+
+            if !done {
+                yield Err(anyhow!("OpenRouter stream ended before [DONE]"));
+                return;
+            }
+
+            if let Some(chunk) = reasoning.end(None) {
+                yield Ok(chunk);
+            }
+            for call in tool_calls.finish() {
+                yield Ok(call.into_chunk());
+            }
+            yield Ok(UIMessageChunk::Finish {
+                reasoning: reasoning.text(),
+                sources: None,
+                usage,
+                logprobs: None,
+            });
         };
 
-        Box::pin(stream)
+        Ok(Box::pin(stream))
     }
 
     fn get_available_models(&self) -> Vec<&'static str> {
         Self::get_model_options()
     }
+
+    async fn discover_models(&self) -> Result<Vec<ModelInfo>> {
+        let builder = self
+            .client
+            .get(format!("{}/models", self.base_url))
+            .header("Authorization", format!("Bearer {}", self.api_key));
+        let response = self.retry.send("OpenRouter", builder).await?;
+        let list: OpenRouterModelList = response.json().await?;
+
+        Ok(list
+            .data
+            .into_iter()
+            .map(|model| {
+                let capabilities = model.capabilities();
+                ModelInfo {
+                    id: model.id,
+                    display_name: model.name,
+                    capabilities,
+                }
+            })
+            .collect())
+    }
+
+    fn unsupported_options(&self, _model: &str, options: &GenerationOptions) -> Vec<&'static str> {
+        options.set_among(&["candidate_count", "safety_settings"])
+    }
+
+    fn with_provider_routing(
+        &self,
+        preferences: Option<OpenRouterProviderPreferences>,
+        transforms: Option<Vec<String>>,
+    ) -> Option<Arc<dyn AIProvider>> {
+        Some(Arc::new(self.clone().with_routing(preferences, transforms)))
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -245,6 +450,8 @@ struct OpenRouterRequest {
     max_tokens: Option<u32>,
     stream: bool,
     #[serde(skip_serializing_if = "Option::is_none")]
+    usage: Option<OpenRouterUsageOptions>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     top_p: Option<f32>,
     #[serde(skip_serializing_if = "Option::is_none")]
     frequency_penalty: Option<f32>,
@@ -252,6 +459,24 @@ struct OpenRouterRequest {
     presence_penalty: Option<f32>,
     #[serde(skip_serializing_if = "Option::is_none")]
     stop: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    top_k: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    seed: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    user: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    logit_bias: Option<HashMap<String, i32>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    response_format: Option<ResponseFormat>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tools: Option<Vec<OpenRouterTool>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tool_choice: Option<serde_json::Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    provider: Option<OpenRouterProviderPreferences>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    transforms: Option<Vec<String>>,
 }
 
 impl Default for OpenRouterRequest {
@@ -262,18 +487,171 @@ impl Default for OpenRouterRequest {
             temperature: None,
             max_tokens: None,
             stream: false,
+            usage: None,
             top_p: None,
             frequency_penalty: None,
             presence_penalty: None,
             stop: None,
+            top_k: None,
+            seed: None,
+            user: None,
+            logit_bias: None,
+            response_format: None,
+            tools: None,
+            tool_choice: None,
+            provider: None,
+            transforms: None,
         }
     }
 }
 
+impl OpenRouterRequest {
+    /// Set the sampling options; OpenRouter forwards each one to providers that support it
+    fn with_options(mut self, options: GenerationOptions) -> Self {
+        self.temperature = options.temperature;
+        self.max_tokens = options.max_tokens;
+        self.top_p = options.top_p;
+        self.top_k = options.top_k;
+        self.frequency_penalty = options.frequency_penalty;
+        self.presence_penalty = options.presence_penalty;
+        self.stop = options.stop_sequences();
+        self.seed = options.seed;
+        self.user = options.user;
+        self.logit_bias = options.logit_bias.filter(|bias| !bias.is_empty());
+        self.response_format = options.response_format;
+        self
+    }
+}
+
+/// See https://openrouter.ai/docs/use-cases/usage-accounting
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct OpenRouterUsageOptions {
+    include: bool,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 struct OpenRouterMessage {
     role: String,
-    content: String,
+    content: Option<OpenRouterContent>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tool_calls: Option<Vec<OpenRouterToolCall>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tool_call_id: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+enum OpenRouterContent {
+    Text(String),
+    Parts(Vec<OpenRouterContentPart>),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum OpenRouterContentPart {
+    Text { text: String },
+    ImageUrl { image_url: OpenRouterImageUrl },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct OpenRouterImageUrl {
+    url: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct OpenRouterTool {
+    #[serde(rename = "type")]
+    type_: String,
+    function: OpenRouterFunction,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct OpenRouterFunction {
+    name: String,
+    description: String,
+    parameters: serde_json::Value,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct OpenRouterToolCall {
+    id: String,
+    #[serde(rename = "type")]
+    type_: String,
+    function: OpenRouterFunctionCall,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct OpenRouterFunctionCall {
+    name: String,
+    arguments: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct OpenRouterModelList {
+    data: Vec<OpenRouterModel>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct OpenRouterModel {
+    id: String,
+    name: Option<String>,
+    context_length: Option<u32>,
+    top_provider: Option<OpenRouterTopProvider>,
+    architecture: Option<OpenRouterArchitecture>,
+    /// Request parameters the model accepts, e.g. `tools` or `response_format`
+    supported_parameters: Option<Vec<String>>,
+    pricing: Option<OpenRouterPricing>,
+}
+
+/// Limits of the provider OpenRouter routes a model to first
+#[derive(Debug, Clone, Deserialize)]
+struct OpenRouterTopProvider {
+    max_completion_tokens: Option<u32>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct OpenRouterArchitecture {
+    #[serde(default)]
+    input_modalities: Vec<String>,
+}
+
+/// Prices in USD per token, as decimal strings
+#[derive(Debug, Clone, Deserialize)]
+struct OpenRouterPricing {
+    prompt: String,
+    completion: String,
+}
+
+impl OpenRouterModel {
+    fn capabilities(&self) -> ModelCapabilities {
+        let supports = |parameter: &str| {
+            self.supported_parameters
+                .as_ref()
+                .map(|parameters| parameters.iter().any(|p| p == parameter))
+        };
+        let pricing = self.pricing.as_ref().and_then(|pricing| {
+            match (pricing.prompt.parse::<f64>(), pricing.completion.parse::<f64>()) {
+                (Ok(input), Ok(output)) => Some(Pricing {
+                    input_per_million: input * 1_000_000.0,
+                    output_per_million: output * 1_000_000.0,
+                }),
+                _ => None,
+            }
+        });
+
+        ModelCapabilities {
+            context_window: self.context_length,
+            max_output_tokens: self.top_provider.as_ref().and_then(|provider| provider.max_completion_tokens),
+            vision: self
+                .architecture
+                .as_ref()
+                .map(|architecture| architecture.input_modalities.iter().any(|m| m == "image")),
+            tools: supports("tools"),
+            reasoning: supports("reasoning"),
+            json_mode: supports("response_format"),
+            pricing,
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -283,7 +661,7 @@ struct OpenRouterResponse {
     created: u64,
     model: String,
     choices: Vec<OpenRouterChoice>,
-    usage: Option<OpenRouterUsage>,
+    usage: Option<Usage>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -296,15 +674,10 @@ struct OpenRouterChoice {
 #[derive(Debug, Clone, Serialize, Deserialize)]
 struct OpenRouterResponseMessage {
     role: String,
-    content: String,
-    id: String,
-}
-
-#[derive(Debug, Clone, Serialize, Deserialize)]
-struct OpenRouterUsage {
-    prompt_tokens: u32,
-    completion_tokens: u32,
-    total_tokens: u32,
+    content: Option<String>,
+    tool_calls: Option<Vec<OpenRouterToolCall>>,
+    #[serde(default)]
+    reasoning: Option<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -314,6 +687,7 @@ struct OpenRouterStreamChunk {
     created: u64,
     model: String,
     choices: Vec<OpenRouterStreamChoice>,
+    usage: Option<Usage>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -327,4 +701,145 @@ struct OpenRouterStreamChoice {
 struct OpenRouterDelta {
     role: Option<String>,
     content: Option<String>,
-}
\ No newline at end of file
+    tool_calls: Option<Vec<serde_json::Value>>,
+    #[serde(default)]
+    reasoning: Option<String>,
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::providers::testing::{message, serve, user};
+
+    #[test]
+    fn test_request_includes_tools_and_routing() {
+        let request = OpenRouterRequest {
+            messages: OpenRouterService::convert_to_openrouter_messages(&[user("What is 2 + 2?")]),
+            tools: OpenRouterService::convert_tools(Some(vec![ToolSpec {
+                name: "calculator".to_string(),
+                description: "Evaluate an expression".to_string(),
+                parameters: serde_json::json!({"type": "object", "properties": {}}),
+            }])),
+            tool_choice: OpenRouterService::convert_tool_choice(Some(ToolChoice::Required)),
+            provider: Some(OpenRouterProviderPreferences {
+                order: Some(vec!["Anthropic".to_string(), "Together".to_string()]),
+                allow_fallbacks: Some(false),
+                data_collection: Some("deny".to_string()),
+            }),
+            transforms: Some(vec!["middle-out".to_string()]),
+            ..OpenRouterRequest::default().with_options(GenerationOptions {
+                top_k: Some(40),
+                seed: Some(7),
+                ..GenerationOptions::sampling(Some(0.2), None)
+            })
+        };
+
+        let json = serde_json::to_value(&request).unwrap();
+        assert_eq!(json["top_k"], 40);
+        assert_eq!(json["seed"], 7);
+        assert!(json.get("logit_bias").is_none());
+        assert_eq!(json["tools"][0]["function"]["name"], "calculator");
+        assert_eq!(json["tool_choice"], "required");
+        assert_eq!(json["provider"]["order"][0], "Anthropic");
+        assert_eq!(json["provider"]["allow_fallbacks"], false);
+        assert_eq!(json["provider"]["data_collection"], "deny");
+        assert_eq!(json["transforms"][0], "middle-out");
+    }
+
+    #[test]
+    fn test_parse_tool_calls_from_response() {
+        let response: OpenRouterResponse = serde_json::from_value(serde_json::json!({
+            "id": "gen-1",
+            "object": "chat.completion",
+            "created": 0,
+            "model": "anthropic/claude-3.5-sonnet",
+            "choices": [{
+                "index": 0,
+                "message": {
+                    "role": "assistant",
+                    "content": null,
+                    "tool_calls": [{
+                        "id": "call_1",
+                        "type": "function",
+                        "function": {"name": "calculator", "arguments": "{\"expression\":\"2 + 2\"}"}
+                    }]
+                },
+                "finish_reason": "tool_calls"
+            }]
+        }))
+        .unwrap();
+
+        let message = OpenRouterService::convert_from_openrouter_message(&response, &response.choices[0]);
+        let calls = ToolCall::from_metadata(message.metadata.as_ref()).unwrap();
+        assert_eq!(message.id, "gen-1");
+        assert_eq!(calls[0].name, "calculator");
+        assert_eq!(calls[0].arguments, serde_json::json!({"expression": "2 + 2"}));
+    }
+
+    #[test]
+    fn test_parse_usage_stream_chunk() {
+        let chunk: OpenRouterStreamChunk = serde_json::from_str(
+            r#"{"id":"gen-1","object":"chat.completion.chunk","created":0,"model":"openai/gpt-4o","choices":[],"usage":{"prompt_tokens":12,"completion_tokens":30,"total_tokens":42,"cost":0.0003}}"#,
+        )
+        .unwrap();
+        assert!(chunk.choices.is_empty());
+        assert_eq!(chunk.usage.unwrap().total_tokens, 42);
+    }
+
+    #[test]
+    fn test_parse_reasoning_stream_chunk() {
+        let chunk: OpenRouterStreamChunk = serde_json::from_str(
+            r#"{"id":"gen-2","object":"chat.completion.chunk","created":0,"model":"deepseek/deepseek-r1","choices":[{"index":0,"delta":{"role":"assistant","content":"","reasoning":"First, add"},"finish_reason":null}]}"#,
+        )
+        .unwrap();
+        assert_eq!(chunk.choices[0].delta.reasoning.as_deref(), Some("First, add"));
+    }
+
+    #[tokio::test]
+    async fn test_stream_cut_off_before_done_is_an_error() {
+        let body = concat!(
+            "data: {\"id\":\"gen-1\",\"object\":\"chat.completion.chunk\",\"created\":0,\"model\":\"openai/gpt-4o\",",
+            "\"choices\":[{\"index\":0,\"delta\":{\"content\":\"The answer is\"}}]}\n\n",
+        );
+        let app = axum::Router::new().route("/chat/completions", axum::routing::post(move || async move { body }));
+
+        let mut service = OpenRouterService::new("key".to_string());
+        service.base_url = serve(app).await;
+        let results: Vec<Result<UIMessageChunk>> = service
+            .chat_completion_stream(vec![user("2 + 2?")], None, GenerationOptions::default(), None, None)
+            .await
+            .unwrap()
+            .collect()
+            .await;
+
+        assert!(matches!(results[0], Ok(UIMessageChunk::TextDelta { .. })));
+        let error = results.last().unwrap().as_ref().unwrap_err();
+        assert_eq!(error.to_string(), "OpenRouter stream ended before [DONE]");
+    }
+
+    #[test]
+    fn test_convert_tool_messages() {
+        let mut assistant_meta = HashMap::new();
+        assistant_meta.insert(
+            TOOL_CALLS_KEY.to_string(),
+            serde_json::json!([{"id": "call_1", "name": "calculator", "arguments": {"expression": "1"}}]),
+        );
+        let mut tool_meta = HashMap::new();
+        tool_meta.insert(TOOL_CALL_ID_KEY.to_string(), serde_json::json!("call_1"));
+
+        let messages = OpenRouterService::convert_to_openrouter_messages(&[
+            ChatMessage {
+                metadata: Some(assistant_meta),
+                ..message(ChatRole::Assistant, "")
+            },
+            ChatMessage {
+                metadata: Some(tool_meta),
+                ..message(ChatRole::Tool, "1")
+            },
+        ]);
+
+        assert!(messages[0].content.is_none());
+        assert_eq!(messages[0].tool_calls.as_ref().unwrap()[0].function.arguments, "{\"expression\":\"1\"}");
+        assert_eq!(messages[1].role, "tool");
+        assert_eq!(messages[1].tool_call_id.as_deref(), Some("call_1"));
+    }
+}
@@ -0,0 +1,226 @@
+//! Provider-agnostic generation options
+//!
+//! [`GenerationOptions`] carries the sampling parameters of a request. Each provider maps the
+//! options it supports to its native request and reports the rest through
+//! [`super::AIProvider::unsupported_options`], so callers can warn that they were ignored.
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+
+/// Metadata key holding warnings about the request, such as ignored options
+pub const WARNINGS_KEY: &str = "warnings";
+
+/// Sampling and output options for a chat completion
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct GenerationOptions {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub temperature: Option<f32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_tokens: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub top_p: Option<f32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub top_k: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub frequency_penalty: Option<f32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub presence_penalty: Option<f32>,
+    /// Sequences that end generation
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stop: Option<Vec<String>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub seed: Option<i64>,
+    /// End-user identifier, for the provider's abuse monitoring
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub user: Option<String>,
+    /// Token id to bias (-100 to 100)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub logit_bias: Option<HashMap<String, i32>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub response_format: Option<ResponseFormat>,
+    /// Answers to generate; only the first is the response
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub candidate_count: Option<u32>,
+    /// Block thresholds per harm category, replacing the provider's defaults for those categories
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub safety_settings: Option<Vec<SafetySetting>>,
+}
+
+/// Block threshold for one harm category, in Gemini's terms
+///
+/// E.g. `{"category": "HARM_CATEGORY_DANGEROUS_CONTENT", "threshold": "BLOCK_ONLY_HIGH"}`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SafetySetting {
+    pub category: String,
+    pub threshold: String,
+}
+
+impl GenerationOptions {
+    /// Options with only a temperature and a token limit
+    pub fn sampling(temperature: Option<f32>, max_tokens: Option<u32>) -> Self {
+        Self {
+            temperature,
+            max_tokens,
+            ..Default::default()
+        }
+    }
+
+    /// The options among `names` that are set
+    pub fn set_among(&self, names: &[&'static str]) -> Vec<&'static str> {
+        names.iter().copied().filter(|name| self.is_set(name)).collect()
+    }
+
+    fn is_set(&self, name: &str) -> bool {
+        match name {
+            "temperature" => self.temperature.is_some(),
+            "max_tokens" => self.max_tokens.is_some(),
+            "top_p" => self.top_p.is_some(),
+            "top_k" => self.top_k.is_some(),
+            "frequency_penalty" => self.frequency_penalty.is_some(),
+            "presence_penalty" => self.presence_penalty.is_some(),
+            "stop" => self.stop.as_ref().is_some_and(|stop| !stop.is_empty()),
+            "seed" => self.seed.is_some(),
+            "user" => self.user.is_some(),
+            "logit_bias" => self.logit_bias.as_ref().is_some_and(|bias| !bias.is_empty()),
+            "response_format" => self.response_format.is_some(),
+            "candidate_count" => self.candidate_count.is_some_and(|count| count > 1),
+            "safety_settings" => self.safety_settings.as_ref().is_some_and(|settings| !settings.is_empty()),
+            _ => false,
+        }
+    }
+
+    /// Stop sequences, if any are set
+    pub fn stop_sequences(&self) -> Option<Vec<String>> {
+        self.stop.clone().filter(|stop| !stop.is_empty())
+    }
+
+    /// The schema the answer must match, if one is requested
+    pub fn json_schema(&self) -> Option<&JsonSchemaFormat> {
+        match &self.response_format {
+            Some(ResponseFormat::JsonSchema { json_schema }) => Some(json_schema),
+            _ => None,
+        }
+    }
+}
+
+/// Format of the model's answer, in the OpenAI wire format
+///
+/// A schema may also be given directly, as `{"type": "json_schema", "schema": {...}}`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", try_from = "WireResponseFormat")]
+pub enum ResponseFormat {
+    Text,
+    /// Any valid JSON object
+    JsonObject,
+    /// JSON matching a schema
+    JsonSchema { json_schema: JsonSchemaFormat },
+}
+
+/// A named JSON Schema for [`ResponseFormat::JsonSchema`]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct JsonSchemaFormat {
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    #[serde(default)]
+    pub schema: Value,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub strict: Option<bool>,
+}
+
+/// Name given to a schema that arrives without one
+const DEFAULT_SCHEMA_NAME: &str = "response";
+
+/// Either shape of a response format as it arrives in a request
+#[derive(Deserialize)]
+struct WireResponseFormat {
+    #[serde(rename = "type")]
+    type_: String,
+    #[serde(default)]
+    json_schema: Option<JsonSchemaFormat>,
+    #[serde(default)]
+    schema: Option<Value>,
+    #[serde(default)]
+    name: Option<String>,
+}
+
+impl TryFrom<WireResponseFormat> for ResponseFormat {
+    type Error = String;
+
+    fn try_from(wire: WireResponseFormat) -> Result<Self, Self::Error> {
+        match wire.type_.as_str() {
+            "text" => Ok(Self::Text),
+            "json_object" => Ok(Self::JsonObject),
+            "json_schema" => {
+                let json_schema = match (wire.json_schema, wire.schema) {
+                    (Some(json_schema), _) => json_schema,
+                    (None, Some(schema)) => JsonSchemaFormat {
+                        name: wire.name.unwrap_or_else(|| DEFAULT_SCHEMA_NAME.to_string()),
+                        description: None,
+                        schema,
+                        strict: None,
+                    },
+                    (None, None) => return Err("a json_schema response format needs a schema".to_string()),
+                };
+                Ok(Self::JsonSchema { json_schema })
+            }
+            other => Err(format!("unknown response format type: {}", other)),
+        }
+    }
+}
+
+/// Warnings for the options `provider` ignores
+pub fn unsupported_warnings(provider: &str, unsupported: &[&str]) -> Vec<String> {
+    unsupported
+        .iter()
+        .map(|option| format!("{} is not supported by {} and was ignored", option, provider))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_set_options() {
+        let options = GenerationOptions {
+            top_k: Some(40),
+            stop: Some(vec![]),
+            seed: Some(7),
+            ..GenerationOptions::sampling(Some(0.2), None)
+        };
+        assert_eq!(
+            options.set_among(&["temperature", "max_tokens", "top_k", "stop", "seed"]),
+            vec!["temperature", "top_k", "seed"]
+        );
+        assert!(options.stop_sequences().is_none());
+        assert_eq!(
+            unsupported_warnings("anthropic", &["seed"]),
+            vec!["seed is not supported by anthropic and was ignored"]
+        );
+    }
+
+    #[test]
+    fn test_response_format_wire_format() {
+        let format: ResponseFormat = serde_json::from_value(serde_json::json!({
+            "type": "json_schema",
+            "json_schema": {"name": "answer", "schema": {"type": "object"}, "strict": true}
+        }))
+        .unwrap();
+        assert!(matches!(&format, ResponseFormat::JsonSchema { json_schema } if json_schema.name == "answer"));
+
+        let short: ResponseFormat = serde_json::from_value(serde_json::json!({
+            "type": "json_schema",
+            "schema": {"type": "object"}
+        }))
+        .unwrap();
+        assert_eq!(
+            serde_json::to_value(&short).unwrap(),
+            serde_json::json!({"type": "json_schema", "json_schema": {"name": "response", "schema": {"type": "object"}}})
+        );
+        assert!(serde_json::from_value::<ResponseFormat>(serde_json::json!({"type": "json_schema"})).is_err());
+
+        assert_eq!(serde_json::to_value(ResponseFormat::Text).unwrap(), serde_json::json!({"type": "text"}));
+    }
+}
@@ -0,0 +1,155 @@
+//! Reasoning ("thinking") output of models that think before answering
+//!
+//! Streams carry reasoning as `reasoning-start`, `reasoning-delta` and `reasoning-end` chunks
+//! ahead of the answer, and the full text again on the `finish` chunk. Non-streaming
+//! responses carry it in the message metadata under [`REASONING_KEY`].
+//!
+//! Anthropic signs its thinking blocks and requires them back unchanged on the assistant
+//! message that precedes a tool result, so each part keeps its signature, or the opaque data
+//! of a redacted block. Reasoning from other providers is never sent back.
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+
+use crate::chat::UIMessageChunk;
+
+/// Metadata key holding the reasoning parts of an assistant message
+pub const REASONING_KEY: &str = "reasoning";
+
+/// One block of reasoning
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Reasoning {
+    #[serde(default)]
+    pub text: String,
+    /// Provider signature that verifies the text when it is sent back
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signature: Option<String>,
+    /// Encrypted reasoning the provider did not show
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub redacted: Option<String>,
+}
+
+impl Reasoning {
+    pub fn new(text: String) -> Self {
+        Self { text, ..Default::default() }
+    }
+
+    /// Read the reasoning stored in a message's metadata, if any
+    pub fn from_metadata(metadata: Option<&HashMap<String, Value>>) -> Option<Vec<Reasoning>> {
+        metadata
+            .and_then(|m| m.get(REASONING_KEY))
+            .and_then(|v| serde_json::from_value::<Vec<Reasoning>>(v.clone()).ok())
+            .filter(|parts| !parts.is_empty())
+    }
+
+    /// Store `parts` in `metadata`, unless there are none
+    pub fn insert_into(parts: &[Reasoning], metadata: &mut HashMap<String, Value>) {
+        if !parts.is_empty() {
+            metadata.insert(
+                REASONING_KEY.to_string(),
+                serde_json::to_value(parts).unwrap_or(Value::Null),
+            );
+        }
+    }
+}
+
+/// Turns streamed reasoning text into reasoning chunks, opening and closing the part as needed
+#[derive(Debug, Default)]
+pub struct ReasoningStream {
+    open: bool,
+    text: String,
+}
+
+impl ReasoningStream {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Chunk starting a reasoning part, unless one is open
+    pub fn start(&mut self) -> Option<UIMessageChunk> {
+        if self.open {
+            return None;
+        }
+        self.open = true;
+        Some(UIMessageChunk::ReasoningStart)
+    }
+
+    /// Chunks for a piece of reasoning text, starting a reasoning part if none is open
+    pub fn delta(&mut self, delta: &str) -> Vec<UIMessageChunk> {
+        if delta.is_empty() {
+            return Vec::new();
+        }
+        let mut chunks: Vec<UIMessageChunk> = self.start().into_iter().collect();
+        self.text.push_str(delta);
+        chunks.push(UIMessageChunk::ReasoningDelta {
+            reasoningDelta: delta.to_string(),
+        });
+        chunks
+    }
+
+    /// Chunk closing the open reasoning part, if any
+    pub fn end(&mut self, signature: Option<String>) -> Option<UIMessageChunk> {
+        if !self.open {
+            return None;
+        }
+        self.open = false;
+        Some(UIMessageChunk::ReasoningEnd {
+            signature,
+            redacted: None,
+        })
+    }
+
+    /// Chunks for a redacted reasoning block, which has no text
+    pub fn redacted(&mut self, data: String) -> Vec<UIMessageChunk> {
+        let mut chunks: Vec<UIMessageChunk> = self.end(None).into_iter().collect();
+        chunks.push(UIMessageChunk::ReasoningStart);
+        chunks.push(UIMessageChunk::ReasoningEnd {
+            signature: None,
+            redacted: Some(data),
+        });
+        chunks
+    }
+
+    /// All reasoning text seen so far, for the finish chunk
+    pub fn text(&self) -> Option<String> {
+        (!self.text.is_empty()).then(|| self.text.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reasoning_part_opens_once_and_closes() {
+        let mut reasoning = ReasoningStream::new();
+        assert!(reasoning.end(None).is_none());
+        assert!(reasoning.delta("").is_empty());
+
+        let first = reasoning.delta("Let me ");
+        assert!(matches!(first[..], [UIMessageChunk::ReasoningStart, UIMessageChunk::ReasoningDelta { .. }]));
+        assert_eq!(reasoning.delta("think.").len(), 1);
+        assert!(matches!(
+            reasoning.end(Some("sig".to_string())),
+            Some(UIMessageChunk::ReasoningEnd { signature: Some(_), .. })
+        ));
+        assert!(reasoning.end(None).is_none());
+        assert_eq!(reasoning.text().as_deref(), Some("Let me think."));
+    }
+
+    #[test]
+    fn test_reasoning_metadata_round_trip() {
+        let parts = vec![
+            Reasoning { text: "Thinking".to_string(), signature: Some("sig".to_string()), redacted: None },
+            Reasoning { redacted: Some("opaque".to_string()), ..Default::default() },
+        ];
+        let mut metadata = HashMap::new();
+        Reasoning::insert_into(&parts, &mut metadata);
+        assert_eq!(Reasoning::from_metadata(Some(&metadata)), Some(parts));
+
+        let mut metadata = HashMap::new();
+        Reasoning::insert_into(&[], &mut metadata);
+        assert!(metadata.is_empty());
+    }
+}
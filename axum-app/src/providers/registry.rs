@@ -0,0 +1,455 @@
+//! Registry of the configured AI providers
+//!
+//! Providers are registered once under a name and looked up by name (agents) or by model
+//! (chat endpoints). Model routing, in order of precedence:
+//!
+//! 1. `provider_name/model` selects that provider, e.g. `groq/llama-3.1-70b-versatile`; a
+//!    built-in provider that is not configured resolves to nothing
+//! 2. model-name prefixes, e.g. `claude` routes to `anthropic`
+//! 3. exact model names listed by a provider, e.g. an endpoint's `models`
+//! 4. model names the model catalog knows a provider for, from the providers' model listings
+//!    or the models file
+//! 5. the default provider
+//!
+//! A model alias stands for several models, each routed as above, that are tried in order
+//! (see [`super::failover`]).
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use super::capabilities::RequestFeatures;
+use super::endpoints::{self, ModelAliases, DEFAULT_ENDPOINT};
+use super::{AIProvider, AnthropicService, BedrockService, GeminiService, ModelCatalog, OllamaService, OpenRouterService};
+
+/// Names the built-in providers register under
+const BUILT_IN_PROVIDERS: [&str; 7] =
+    [DEFAULT_ENDPOINT, "gemini", "vertex", "anthropic", "openrouter", "ollama", "bedrock"];
+
+/// A provider resolved for a model, with the model name to send to it
+#[derive(Clone)]
+pub struct ResolvedProvider {
+    pub name: String,
+    pub provider: Arc<dyn AIProvider>,
+    pub model: String,
+}
+
+/// Named AI providers and the rules that route models to them
+#[derive(Clone, Default)]
+pub struct ProviderRegistry {
+    providers: HashMap<String, Arc<dyn AIProvider>>,
+    prefixes: Vec<(String, String)>,
+    models: HashMap<String, String>,
+    aliases: ModelAliases,
+    default_provider: Option<String>,
+    catalog: Arc<ModelCatalog>,
+}
+
+impl std::fmt::Debug for ProviderRegistry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut names: Vec<&String> = self.providers.keys().collect();
+        names.sort();
+        f.debug_struct("ProviderRegistry")
+            .field("providers", &names)
+            .field("aliases", &self.aliases)
+            .field("default_provider", &self.default_provider)
+            .field("catalog", &self.catalog)
+            .finish()
+    }
+}
+
+impl ProviderRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Register every provider configured in the environment and the endpoints file
+    pub async fn from_env() -> Self {
+        let mut registry = Self::new();
+        registry.catalog = Arc::new(ModelCatalog::from_env().await);
+
+        // OpenAI and the named OpenAI-compatible endpoints, in name order so that a model
+        // listed by several endpoints always goes to the same one
+        let (endpoints, aliases) = endpoints::load_endpoints().await;
+        let mut names: Vec<&String> = endpoints.keys().collect();
+        names.sort();
+        for name in names {
+            let service = endpoints[name].clone();
+            for model in service.models() {
+                registry.route_model(model, name);
+            }
+            registry.register(name, service);
+        }
+        for (alias, models) in aliases {
+            registry.add_alias(&alias, models);
+        }
+
+        if let Ok(service) = GeminiService::from_env() {
+            registry.register("gemini", Arc::new(service));
+        }
+        // Vertex AI also serves Gemini models when there is no Gemini API key
+        if let Ok(service) = GeminiService::vertex_from_env() {
+            let service: Arc<dyn AIProvider> = Arc::new(service);
+            if !registry.providers.contains_key("gemini") {
+                registry.register("gemini", service.clone());
+            }
+            registry.register("vertex", service);
+        }
+        if let Ok(service) = AnthropicService::from_env() {
+            registry.register("anthropic", Arc::new(service));
+        }
+        if let Ok(service) = OpenRouterService::from_env() {
+            registry.register("openrouter", Arc::new(service));
+        }
+        if let Ok(service) = OllamaService::from_env() {
+            registry.register("ollama", Arc::new(service));
+        }
+        if let Ok(service) = BedrockService::from_env() {
+            registry.register("bedrock", Arc::new(service));
+        }
+
+        registry.add_default_routes();
+        registry
+    }
+
+    /// Model-name routes for the built-in providers
+    ///
+    /// These apply even when the provider is not configured, so e.g. a `claude` model
+    /// without an Anthropic key resolves to nothing instead of being sent to OpenAI.
+    fn add_default_routes(&mut self) {
+        self.route_prefix("gemini", "gemini");
+        self.route_prefix("models/gemini", "gemini");
+        // Gemini's embedding models do not start with `gemini`
+        self.route_prefix("text-embedding-004", "gemini");
+        self.route_prefix("embedding-001", "gemini");
+        self.route_prefix("claude", "anthropic");
+        // Bedrock model ids start with the vendor, inference profiles with the geography
+        for prefix in ["anthropic.", "amazon.", "meta.", "mistral.", "us.", "eu.", "apac."] {
+            self.route_prefix(prefix, "bedrock");
+        }
+        self.set_default(DEFAULT_ENDPOINT);
+    }
+
+    /// Register a provider under `name`, replacing any provider of the same name
+    pub fn register(&mut self, name: &str, provider: Arc<dyn AIProvider>) {
+        self.providers.insert(name.to_string(), provider);
+    }
+
+    /// Route models starting with `prefix` to the provider `name`
+    pub fn route_prefix(&mut self, prefix: &str, name: &str) {
+        self.prefixes.push((prefix.to_string(), name.to_string()));
+    }
+
+    /// Route the exact model name `model` to the provider `name`; the first route wins
+    pub fn route_model(&mut self, model: &str, name: &str) {
+        self.models
+            .entry(model.to_string())
+            .or_insert_with(|| name.to_string());
+    }
+
+    /// Make `alias` stand for `models`, tried in order
+    pub fn add_alias(&mut self, alias: &str, models: Vec<String>) {
+        self.aliases.insert(alias.to_string(), models);
+    }
+
+    /// Whether `model` is an alias for several models
+    pub fn is_alias(&self, model: &str) -> bool {
+        self.aliases.contains_key(model)
+    }
+
+    /// Provider used for models no other rule matches
+    pub fn set_default(&mut self, name: &str) {
+        self.default_provider = Some(name.to_string());
+    }
+
+    /// Look up a provider by name
+    pub fn get(&self, name: &str) -> Option<Arc<dyn AIProvider>> {
+        self.providers.get(name).cloned()
+    }
+
+    /// Whether `name` is a registered provider or a built-in one, configured or not
+    pub fn knows(&self, name: &str) -> bool {
+        self.providers.contains_key(name) || BUILT_IN_PROVIDERS.contains(&name)
+    }
+
+    /// Models and capabilities of the registered providers
+    pub fn catalog(&self) -> &ModelCatalog {
+        &self.catalog
+    }
+
+    /// Names of the registered providers, sorted
+    pub fn names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.providers.keys().cloned().collect();
+        names.sort();
+        names
+    }
+
+    /// The default provider and its name, if configured
+    pub fn default_provider(&self) -> Option<(&str, Arc<dyn AIProvider>)> {
+        let name = self.default_provider.as_deref()?;
+        self.get(name).map(|provider| (name, provider))
+    }
+
+    /// Find the provider for `model`
+    ///
+    /// Returns `None` when the model routes to a provider that is not configured.
+    pub fn resolve(&self, model: &str) -> Option<ResolvedProvider> {
+        let (name, model) = self.route(model)?;
+        let provider = self.get(&name)?;
+        Some(ResolvedProvider { name, provider, model })
+    }
+
+    /// The providers to try for `model`, in order
+    ///
+    /// This is every configured entry of an alias, or the single provider `model` resolves to.
+    /// Alias entries whose provider is not configured are skipped.
+    pub fn resolve_chain(&self, model: &str) -> Vec<ResolvedProvider> {
+        match self.aliases.get(model) {
+            Some(models) => models.iter().filter_map(|model| self.resolve(model)).collect(),
+            None => self.resolve(model).into_iter().collect(),
+        }
+    }
+
+    /// The entries of `chain` whose models support `features`
+    ///
+    /// Fails with what the models lack when none of them does.
+    pub fn supporting(
+        &self,
+        chain: Vec<ResolvedProvider>,
+        features: &RequestFeatures,
+    ) -> Result<Vec<ResolvedProvider>, String> {
+        let mut reasons = Vec::new();
+        let supported: Vec<ResolvedProvider> = chain
+            .into_iter()
+            .filter(|resolved| {
+                let capabilities = self.catalog.capabilities(&resolved.name, &resolved.model);
+                match capabilities.check(&resolved.model, features) {
+                    Ok(()) => true,
+                    Err(reason) => {
+                        reasons.push(reason);
+                        false
+                    }
+                }
+            })
+            .collect();
+
+        if supported.is_empty() && !reasons.is_empty() {
+            Err(reasons.join("; "))
+        } else {
+            Ok(supported)
+        }
+    }
+
+    /// Name of the provider `model` routes to, and the model name to send to it
+    ///
+    /// Exact model routes come first, then a `provider/` prefix, then model-name prefixes.
+    /// Many model ids start with a vendor, e.g. OpenRouter's `anthropic/claude-3.5-sonnet`,
+    /// so a provider prefix whose remainder that provider doesn't list yields to a provider
+    /// that lists the whole id.
+    fn route(&self, model: &str) -> Option<(String, String)> {
+        if let Some(name) = self.models.get(model) {
+            return Some((name.clone(), model.to_string()));
+        }
+
+        if let Some((name, rest)) = model.split_once('/') {
+            let configured = self.providers.contains_key(name);
+            if configured && self.serves(name, rest) {
+                return Some((name.to_string(), rest.to_string()));
+            }
+            if let Some(other) = self.listed_by(model) {
+                return Some((other, model.to_string()));
+            }
+            if configured {
+                return Some((name.to_string(), rest.to_string()));
+            }
+            // An unconfigured built-in provider is not a model-name prefix
+            if BUILT_IN_PROVIDERS.contains(&name) {
+                return None;
+            }
+        }
+
+        self.prefixes
+            .iter()
+            .find(|(prefix, _)| model.starts_with(prefix.as_str()))
+            .map(|(_, name)| name.clone())
+            .or_else(|| self.listed_by(model))
+            .or_else(|| self.default_provider.clone())
+            .map(|name| (name, model.to_string()))
+    }
+
+    /// Whether the provider `name` lists `model`, by discovery or among its built-in models
+    fn serves(&self, name: &str, model: &str) -> bool {
+        self.catalog.lists(name, model)
+            || self.get(name).is_some_and(|provider| provider.get_available_models().contains(&model))
+    }
+
+    /// The configured provider that lists `model`, if any
+    fn listed_by(&self, model: &str) -> Option<String> {
+        self.catalog
+            .provider_of(model)
+            .filter(|name| self.providers.contains_key(name))
+            .or_else(|| self.names().into_iter().find(|name| self.serves(name, model)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::providers::testing::StaticProvider;
+    use crate::providers::GenerationOptions;
+
+    fn registry_with(names: &[&'static str]) -> ProviderRegistry {
+        let mut registry = ProviderRegistry::new();
+        for name in names {
+            registry.register(name, Arc::new(StaticProvider(name)));
+        }
+        registry.add_default_routes();
+        registry
+    }
+
+    fn routed(registry: &ProviderRegistry, model: &str) -> Option<(String, String)> {
+        registry.resolve(model).map(|r| (r.name, r.model))
+    }
+
+    #[test]
+    fn test_provider_detection() {
+        let registry = registry_with(&["openai", "gemini", "anthropic", "ollama", "groq"]);
+        let to = |name: &str, model: &str| Some((name.to_string(), model.to_string()));
+
+        // OpenAI models and unknown models go to the default provider
+        assert_eq!(routed(&registry, "gpt-4"), to("openai", "gpt-4"));
+        assert_eq!(routed(&registry, "gpt-3.5-turbo"), to("openai", "gpt-3.5-turbo"));
+        assert_eq!(routed(&registry, "unknown-model"), to("openai", "unknown-model"));
+
+        // Model-name prefixes
+        assert_eq!(routed(&registry, "gemini-1.5-flash"), to("gemini", "gemini-1.5-flash"));
+        assert_eq!(routed(&registry, "gemini-pro"), to("gemini", "gemini-pro"));
+        assert_eq!(routed(&registry, "models/gemini-1.5-pro"), to("gemini", "models/gemini-1.5-pro"));
+        assert_eq!(routed(&registry, "claude-3-5-sonnet-20241022"), to("anthropic", "claude-3-5-sonnet-20241022"));
+        assert_eq!(routed(&registry, "claude-3-opus-20240229"), to("anthropic", "claude-3-opus-20240229"));
+
+        // Provider-name prefixes are stripped
+        assert_eq!(routed(&registry, "ollama/llama3.2"), to("ollama", "llama3.2"));
+        assert_eq!(routed(&registry, "ollama/gemma2:9b"), to("ollama", "gemma2:9b"));
+        assert_eq!(routed(&registry, "groq/llama-3.1-8b-instant"), to("groq", "llama-3.1-8b-instant"));
+    }
+
+    #[test]
+    fn test_unconfigured_provider_resolves_to_nothing() {
+        let registry = registry_with(&["openai"]);
+        assert!(registry.resolve("claude-3-haiku-20240307").is_none());
+        assert!(registry.resolve("gemini-pro").is_none());
+        assert!(registry.resolve("ollama/llama3.2").is_none());
+        assert!(registry_with(&[]).resolve("gpt-4o").is_none());
+    }
+
+    #[test]
+    fn test_exact_model_routes() {
+        let mut registry = registry_with(&["openai", "vllm", "zeta"]);
+        registry.route_model("meta-llama/Llama-3.1-8B-Instruct", "vllm");
+        registry.route_model("meta-llama/Llama-3.1-8B-Instruct", "zeta");
+
+        let resolved = registry.resolve("meta-llama/Llama-3.1-8B-Instruct").unwrap();
+        assert_eq!(resolved.name, "vllm");
+        assert_eq!(resolved.model, "meta-llama/Llama-3.1-8B-Instruct");
+
+        // Model names after a provider prefix may themselves contain slashes
+        let resolved = registry.resolve("zeta/meta-llama/Llama-3.1-8B-Instruct").unwrap();
+        assert_eq!(resolved.name, "zeta");
+        assert_eq!(resolved.model, "meta-llama/Llama-3.1-8B-Instruct");
+    }
+
+    #[test]
+    fn test_catalog_routes_and_checks_models() {
+        let mut registry = registry_with(&["openai", "ollama"]);
+        let overrides = serde_json::from_value(serde_json::json!({
+            "llava": {"provider": "ollama", "vision": true},
+            "gpt-3.5-turbo": {"vision": false},
+            "openai/gpt-4o": {"vision": true, "max_output_tokens": 16384}
+        }))
+        .unwrap();
+        registry.catalog = Arc::new(ModelCatalog::default().with_overrides(overrides));
+        registry.add_alias("seeing", vec!["gpt-3.5-turbo".to_string(), "gpt-4o".to_string()]);
+
+        // The models file routes a bare model name to its provider
+        assert_eq!(registry.resolve("llava").unwrap().name, "ollama");
+        assert_eq!(registry.resolve("mystery").unwrap().name, "openai");
+
+        let images = RequestFeatures { images: true, ..Default::default() };
+        let chain = registry.supporting(registry.resolve_chain("seeing"), &images).unwrap();
+        let models: Vec<&str> = chain.iter().map(|r| r.model.as_str()).collect();
+        assert_eq!(models, vec!["gpt-4o"]);
+
+        assert_eq!(
+            registry.supporting(registry.resolve_chain("gpt-3.5-turbo"), &images).err().as_deref(),
+            Some("gpt-3.5-turbo does not accept images")
+        );
+        let long = RequestFeatures { max_tokens: Some(32000), ..Default::default() };
+        assert!(registry.supporting(registry.resolve_chain("gpt-4o"), &long).is_err());
+        assert_eq!(registry.supporting(registry.resolve_chain("mystery"), &images).unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_vendor_prefix_yields_to_the_provider_listing_the_id() {
+        let mut registry = registry_with(&["openai", "anthropic", "openrouter", "vllm"]);
+        let overrides = serde_json::from_value(serde_json::json!({
+            "anthropic/claude-3.5-sonnet": {"provider": "openrouter"},
+            "claude-sonnet-4-5": {"provider": "anthropic"}
+        }))
+        .unwrap();
+        registry.catalog = Arc::new(ModelCatalog::default().with_overrides(overrides));
+        registry.catalog.refresh(&registry).await;
+        let to = |name: &str, model: &str| Some((name.to_string(), model.to_string()));
+
+        assert_eq!(routed(&registry, "anthropic/claude-3.5-sonnet"), to("openrouter", "anthropic/claude-3.5-sonnet"));
+        assert_eq!(routed(&registry, "anthropic/claude-sonnet-4-5"), to("anthropic", "claude-sonnet-4-5"));
+        // A model no provider lists still goes to the provider it names
+        assert_eq!(routed(&registry, "anthropic/claude-next"), to("anthropic", "claude-next"));
+
+        // Exact endpoint models win over the Bedrock prefixes
+        registry.route_model("meta.llama3-1-8b-instruct", "vllm");
+        assert_eq!(routed(&registry, "meta.llama3-1-8b-instruct"), to("vllm", "meta.llama3-1-8b-instruct"));
+        assert!(registry.resolve("meta.llama3-1-70b-instruct").is_none());
+    }
+
+    #[test]
+    fn test_alias_chain_skips_unconfigured_providers() {
+        let mut registry = registry_with(&["openai", "openrouter"]);
+        registry.add_alias(
+            "smart",
+            vec![
+                "anthropic/claude-sonnet-4-5".to_string(),
+                "claude-sonnet-4-5".to_string(),
+                "openai/gpt-4o".to_string(),
+                "openrouter/google/gemini-2.5-pro".to_string(),
+            ],
+        );
+
+        let chain: Vec<(String, String)> = registry
+            .resolve_chain("smart")
+            .into_iter()
+            .map(|r| (r.name, r.model))
+            .collect();
+        assert_eq!(
+            chain,
+            vec![
+                ("openai".to_string(), "gpt-4o".to_string()),
+                ("openrouter".to_string(), "google/gemini-2.5-pro".to_string()),
+            ]
+        );
+
+        assert_eq!(registry.resolve_chain("gpt-4o-mini").len(), 1);
+        assert!(registry.resolve_chain("claude-3-5-haiku-20241022").is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_resolved_provider_answers() {
+        let registry = registry_with(&["openai", "anthropic"]);
+        let resolved = registry.resolve("claude-3-5-haiku-20241022").unwrap();
+        let message = resolved
+            .provider
+            .chat_completion(vec![], Some(resolved.model), GenerationOptions::default(), None, None)
+            .await
+            .unwrap();
+        assert_eq!(message.content, "anthropic (claude-3-5-haiku-20241022)");
+    }
+}
@@ -0,0 +1,271 @@
+//! Retries for provider requests
+//!
+//! [`RetryPolicy::send`] wraps the request itself, up to the response status and headers.
+//! Providers build their streams only from the response it returns, so a stream is never
+//! retried once bytes have been forwarded to the client.
+
+use std::time::Duration;
+
+use anyhow::Result;
+use reqwest::header::{HeaderMap, RETRY_AFTER};
+use reqwest::{RequestBuilder, Response, StatusCode};
+
+use super::ProviderError;
+
+/// Anthropic's millisecond-precision variant of `Retry-After`
+const RETRY_AFTER_MS: &str = "retry-after-ms";
+
+/// How often and how long to retry transient provider failures
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    /// Attempts in total, including the first; `1` disables retries
+    pub max_attempts: u32,
+    /// Backoff before the first retry, doubled on each further retry
+    pub initial_backoff: Duration,
+    /// Longest wait between attempts; a provider asking for longer is not retried
+    pub max_backoff: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 3,
+            initial_backoff: Duration::from_millis(500),
+            max_backoff: Duration::from_secs(30),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// The default policy, adjusted by `PROVIDER_MAX_ATTEMPTS` and `PROVIDER_RETRY_BACKOFF_MS`
+    pub fn from_env() -> Self {
+        let mut policy = Self::default();
+        if let Some(attempts) = std::env::var("PROVIDER_MAX_ATTEMPTS").ok().and_then(|v| v.parse().ok()) {
+            policy.max_attempts = std::cmp::max(attempts, 1);
+        }
+        if let Some(ms) = std::env::var("PROVIDER_RETRY_BACKOFF_MS").ok().and_then(|v| v.parse().ok()) {
+            policy.initial_backoff = Duration::from_millis(ms);
+        }
+        policy
+    }
+
+    /// Send `request`, retrying transient failures, and return the successful response
+    ///
+    /// A final non-success status is returned as a [`ProviderError`] for `provider`.
+    pub async fn send(&self, provider: &'static str, request: RequestBuilder) -> Result<Response> {
+        let mut attempt = 1;
+        loop {
+            // Requests with streaming bodies cannot be cloned, and so cannot be retried
+            let this_try = match request.try_clone() {
+                Some(this_try) if attempt < self.max_attempts => this_try,
+                _ => return self.send_once(provider, request).await,
+            };
+
+            let delay = match this_try.send().await {
+                Ok(response) if response.status().is_success() => return Ok(response),
+                Ok(response) => {
+                    if !is_retryable(response.status()) {
+                        return Err(ProviderError::from_response(provider, response).await.into());
+                    }
+                    let delay = retry_after(response.headers()).unwrap_or_else(|| self.backoff(attempt));
+                    if delay > self.max_backoff {
+                        return Err(ProviderError::from_response(provider, response).await.into());
+                    }
+                    tracing::warn!(
+                        "{} returned {}, retrying in {:?} (attempt {}/{})",
+                        provider, response.status(), delay, attempt + 1, self.max_attempts
+                    );
+                    delay
+                }
+                Err(e) if e.is_connect() || e.is_timeout() => {
+                    let delay = self.backoff(attempt);
+                    tracing::warn!(
+                        "{} request failed: {}, retrying in {:?} (attempt {}/{})",
+                        provider, e, delay, attempt + 1, self.max_attempts
+                    );
+                    delay
+                }
+                Err(e) => return Err(e.into()),
+            };
+
+            tokio::time::sleep(delay).await;
+            attempt += 1;
+        }
+    }
+
+    async fn send_once(&self, provider: &'static str, request: RequestBuilder) -> Result<Response> {
+        let response = request.send().await?;
+        if !response.status().is_success() {
+            return Err(ProviderError::from_response(provider, response).await.into());
+        }
+        Ok(response)
+    }
+
+    /// Jittered exponential backoff before retry number `attempt`
+    fn backoff(&self, attempt: u32) -> Duration {
+        let exponential = self
+            .initial_backoff
+            .saturating_mul(2u32.saturating_pow(attempt - 1))
+            .min(self.max_backoff);
+        // Wait at least half the backoff so that retries still spread out under load
+        let half = exponential / 2;
+        half + half.mul_f64(fastrand::f64())
+    }
+}
+
+/// Rate limits, overload and server errors that are worth another attempt
+pub(crate) fn is_retryable(status: StatusCode) -> bool {
+    matches!(status.as_u16(), 408 | 409 | 429 | 500 | 502 | 503 | 504 | 529)
+}
+
+/// Delay the provider asked for, from `retry-after-ms` or `Retry-After`
+fn retry_after(headers: &HeaderMap) -> Option<Duration> {
+    if let Some(ms) = headers
+        .get(RETRY_AFTER_MS)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.trim().parse::<f64>().ok())
+    {
+        return delay_from_secs(ms / 1000.0);
+    }
+
+    let value = headers.get(RETRY_AFTER)?.to_str().ok()?.trim();
+    if let Ok(seconds) = value.parse::<f64>() {
+        return delay_from_secs(seconds);
+    }
+    // Otherwise an HTTP date
+    let date = chrono::DateTime::parse_from_rfc2822(value).ok()?;
+    let delay = date.with_timezone(&chrono::Utc) - chrono::Utc::now();
+    Some(delay.to_std().unwrap_or(Duration::ZERO))
+}
+
+/// A header delay in seconds; NaN is no hint, and delays too long to represent saturate so
+/// that they exceed any backoff cap instead of being retried
+fn delay_from_secs(seconds: f64) -> Option<Duration> {
+    if seconds.is_nan() {
+        return None;
+    }
+    Some(Duration::try_from_secs_f64(seconds.max(0.0)).unwrap_or(Duration::MAX))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::HeaderValue;
+    use axum::response::IntoResponse;
+    use axum::{routing::post, Router};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    fn fast_policy(max_attempts: u32) -> RetryPolicy {
+        RetryPolicy {
+            max_attempts,
+            initial_backoff: Duration::from_millis(1),
+            max_backoff: Duration::from_secs(1),
+        }
+    }
+
+    /// Serve `responses` in order, then 200, counting requests
+    async fn scripted(responses: Vec<(u16, Vec<(&'static str, &'static str)>)>) -> (String, Arc<AtomicUsize>) {
+        let count = Arc::new(AtomicUsize::new(0));
+        let counter = count.clone();
+        let app = Router::new().route(
+            "/",
+            post(move || {
+                let n = counter.fetch_add(1, Ordering::SeqCst);
+                let (status, headers) = responses.get(n).cloned().unwrap_or((200, vec![]));
+                async move {
+                    let mut response = format!("attempt {}", n + 1).into_response();
+                    *response.status_mut() = StatusCode::from_u16(status).unwrap();
+                    for (name, value) in headers {
+                        response.headers_mut().insert(name, HeaderValue::from_static(value));
+                    }
+                    response
+                }
+            }),
+        );
+        let url = format!("{}/", crate::providers::testing::serve(app).await);
+        (url, count)
+    }
+
+    #[test]
+    fn test_retry_after_headers() {
+        let mut headers = HeaderMap::new();
+        headers.insert(RETRY_AFTER, HeaderValue::from_static("2"));
+        assert_eq!(retry_after(&headers), Some(Duration::from_secs(2)));
+
+        // Anthropic's millisecond header takes precedence
+        headers.insert(RETRY_AFTER_MS, HeaderValue::from_static("150.5"));
+        assert_eq!(retry_after(&headers), Some(Duration::from_micros(150_500)));
+
+        let mut headers = HeaderMap::new();
+        headers.insert(RETRY_AFTER, HeaderValue::from_static("Wed, 21 Oct 2015 07:28:00 GMT"));
+        assert_eq!(retry_after(&headers), Some(Duration::ZERO));
+
+        assert_eq!(retry_after(&HeaderMap::new()), None);
+
+        // Values that don't fit a Duration must not panic
+        for (value, expected) in [
+            ("inf", Some(Duration::MAX)),
+            ("1e30", Some(Duration::MAX)),
+            ("-inf", Some(Duration::ZERO)),
+            ("NaN", None),
+        ] {
+            let mut headers = HeaderMap::new();
+            headers.insert(RETRY_AFTER, HeaderValue::from_static(value));
+            assert_eq!(retry_after(&headers), expected, "Retry-After: {}", value);
+            let mut headers = HeaderMap::new();
+            headers.insert(RETRY_AFTER_MS, HeaderValue::from_static(value));
+            assert_eq!(retry_after(&headers), expected, "retry-after-ms: {}", value);
+        }
+    }
+
+    #[test]
+    fn test_backoff_is_jittered_and_capped() {
+        let policy = RetryPolicy {
+            max_attempts: 10,
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_millis(1000),
+        };
+        for _ in 0..100 {
+            let first = policy.backoff(1);
+            assert!(first >= Duration::from_millis(50) && first <= Duration::from_millis(100));
+            let late = policy.backoff(8);
+            assert!(late >= Duration::from_millis(500) && late <= Duration::from_millis(1000));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_transient_errors_are_retried() {
+        let (url, count) = scripted(vec![
+            (429, vec![("retry-after-ms", "5")]),
+            (529, vec![]),
+        ])
+        .await;
+
+        let response = fast_policy(3).send("Test", reqwest::Client::new().post(&url)).await.unwrap();
+        assert_eq!(response.text().await.unwrap(), "attempt 3");
+        assert_eq!(count.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn test_gives_up_after_max_attempts() {
+        let (url, count) = scripted(vec![(503, vec![]), (503, vec![]), (503, vec![])]).await;
+
+        let error = fast_policy(2).send("Test", reqwest::Client::new().post(&url)).await.unwrap_err();
+        assert_eq!(ProviderError::from_error(&error).unwrap().status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(count.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn test_client_errors_and_long_waits_are_not_retried() {
+        let (url, count) = scripted(vec![(400, vec![])]).await;
+        let error = fast_policy(3).send("Test", reqwest::Client::new().post(&url)).await.unwrap_err();
+        assert_eq!(ProviderError::from_error(&error).unwrap().status, StatusCode::BAD_REQUEST);
+        assert_eq!(count.load(Ordering::SeqCst), 1);
+
+        let (url, count) = scripted(vec![(429, vec![("retry-after", "120")])]).await;
+        let error = fast_policy(3).send("Test", reqwest::Client::new().post(&url)).await.unwrap_err();
+        assert_eq!(ProviderError::from_error(&error).unwrap().status, StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(count.load(Ordering::SeqCst), 1);
+    }
+}
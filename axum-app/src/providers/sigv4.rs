@@ -0,0 +1,269 @@
+//! AWS Signature Version 4 request signing
+//!
+//! Requests to AWS APIs carry an `Authorization` header with an HMAC-SHA256 signature over a
+//! canonical form of the request, keyed by a key derived from the secret access key, the date,
+//! the region and the service. See
+//! https://docs.aws.amazon.com/IAM/latest/UserGuide/reference_sigv-create-signed-request.html
+
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Utc};
+use sha2::{Digest, Sha256};
+use url::Url;
+
+/// Block size of SHA-256, for HMAC
+const SHA256_BLOCK_SIZE: usize = 64;
+
+/// AWS access key credentials
+#[derive(Clone)]
+pub struct AwsCredentials {
+    pub access_key_id: String,
+    pub secret_access_key: String,
+    /// Set for temporary credentials, e.g. from an assumed role
+    pub session_token: Option<String>,
+}
+
+impl std::fmt::Debug for AwsCredentials {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("AwsCredentials")
+            .field("access_key_id", &self.access_key_id)
+            .field("secret_access_key", &"<redacted>")
+            .field("session_token", &self.session_token.as_ref().map(|_| "<redacted>"))
+            .finish()
+    }
+}
+
+impl AwsCredentials {
+    pub fn new(access_key_id: String, secret_access_key: String, session_token: Option<String>) -> Self {
+        Self {
+            access_key_id,
+            secret_access_key,
+            session_token,
+        }
+    }
+
+    /// Read `AWS_ACCESS_KEY_ID`, `AWS_SECRET_ACCESS_KEY` and the optional `AWS_SESSION_TOKEN`
+    pub fn from_env() -> Result<Self> {
+        let access_key_id = std::env::var("AWS_ACCESS_KEY_ID")
+            .map_err(|_| anyhow!("AWS_ACCESS_KEY_ID environment variable not set"))?;
+        let secret_access_key = std::env::var("AWS_SECRET_ACCESS_KEY")
+            .map_err(|_| anyhow!("AWS_SECRET_ACCESS_KEY environment variable not set"))?;
+        let session_token = std::env::var("AWS_SESSION_TOKEN").ok().filter(|token| !token.is_empty());
+        Ok(Self::new(access_key_id, secret_access_key, session_token))
+    }
+}
+
+/// Signs requests to one AWS service in one region
+#[derive(Debug, Clone)]
+pub struct SigV4Signer {
+    credentials: AwsCredentials,
+    region: String,
+    service: String,
+}
+
+impl SigV4Signer {
+    pub fn new(credentials: AwsCredentials, region: impl Into<String>, service: impl Into<String>) -> Self {
+        Self {
+            credentials,
+            region: region.into(),
+            service: service.into(),
+        }
+    }
+
+    /// Headers to add to a request so that it is signed at `time`
+    ///
+    /// `headers` are the request's own headers to sign, e.g. `content-type`; `host` and the
+    /// returned `x-amz-*` headers are always signed. The returned headers are `x-amz-date`,
+    /// `x-amz-security-token` for temporary credentials, and `authorization`.
+    pub fn sign(
+        &self,
+        method: &str,
+        url: &Url,
+        headers: &[(&str, &str)],
+        body: &[u8],
+        time: DateTime<Utc>,
+    ) -> Vec<(String, String)> {
+        let amz_date = time.format("%Y%m%dT%H%M%SZ").to_string();
+        let date = &amz_date[..8];
+
+        let mut added = vec![("x-amz-date".to_string(), amz_date.clone())];
+        if let Some(token) = &self.credentials.session_token {
+            added.push(("x-amz-security-token".to_string(), token.clone()));
+        }
+
+        let mut signed: Vec<(String, String)> = headers
+            .iter()
+            .map(|(name, value)| (name.to_lowercase(), value.trim().to_string()))
+            .chain(std::iter::once(("host".to_string(), host(url))))
+            .chain(added.iter().cloned())
+            .collect();
+        signed.sort();
+        let signed_headers: Vec<&str> = signed.iter().map(|(name, _)| name.as_str()).collect();
+        let signed_headers = signed_headers.join(";");
+
+        let canonical_request = [
+            method.to_string(),
+            canonical_uri(url),
+            canonical_query(url),
+            signed.iter().map(|(name, value)| format!("{}:{}\n", name, value)).collect(),
+            signed_headers.clone(),
+            hex(&Sha256::digest(body)),
+        ]
+        .join("\n");
+
+        let scope = format!("{}/{}/{}/aws4_request", date, self.region, self.service);
+        let string_to_sign = [
+            "AWS4-HMAC-SHA256",
+            &amz_date,
+            &scope,
+            &hex(&Sha256::digest(canonical_request.as_bytes())),
+        ]
+        .join("\n");
+
+        let key = [date, &self.region, &self.service, "aws4_request"]
+            .iter()
+            .fold(format!("AWS4{}", self.credentials.secret_access_key).into_bytes(), |key, part| {
+                hmac_sha256(&key, part.as_bytes())
+            });
+        let signature = hex(&hmac_sha256(&key, string_to_sign.as_bytes()));
+
+        added.push((
+            "authorization".to_string(),
+            format!(
+                "AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, Signature={}",
+                self.credentials.access_key_id, scope, signed_headers, signature
+            ),
+        ));
+        added
+    }
+}
+
+/// The `Host` header value for `url`, as the HTTP client sends it
+fn host(url: &Url) -> String {
+    let host = url.host_str().unwrap_or_default();
+    match url.port() {
+        Some(port) => format!("{}:{}", host, port),
+        None => host.to_string(),
+    }
+}
+
+/// The path, URI-encoded once more on top of the encoding it already has
+fn canonical_uri(url: &Url) -> String {
+    match url.path() {
+        "" => "/".to_string(),
+        path => uri_encode(path, false),
+    }
+}
+
+/// Query parameters, encoded and sorted by name and value
+fn canonical_query(url: &Url) -> String {
+    let mut pairs: Vec<(String, String)> = url
+        .query_pairs()
+        .map(|(name, value)| (uri_encode(&name, true), uri_encode(&value, true)))
+        .collect();
+    pairs.sort();
+    pairs
+        .iter()
+        .map(|(name, value)| format!("{}={}", name, value))
+        .collect::<Vec<_>>()
+        .join("&")
+}
+
+/// Percent-encode everything but unreserved characters, and `/` unless `encode_slash`
+pub fn uri_encode(value: &str, encode_slash: bool) -> String {
+    let mut encoded = String::with_capacity(value.len());
+    for byte in value.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => encoded.push(byte as char),
+            b'/' if !encode_slash => encoded.push('/'),
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    encoded
+}
+
+fn hmac_sha256(key: &[u8], message: &[u8]) -> Vec<u8> {
+    let mut block = [0u8; SHA256_BLOCK_SIZE];
+    if key.len() > SHA256_BLOCK_SIZE {
+        block[..32].copy_from_slice(&Sha256::digest(key));
+    } else {
+        block[..key.len()].copy_from_slice(key);
+    }
+
+    let inner_pad: Vec<u8> = block.iter().map(|byte| byte ^ 0x36).collect();
+    let outer_pad: Vec<u8> = block.iter().map(|byte| byte ^ 0x5c).collect();
+    let inner = Sha256::new().chain_update(&inner_pad).chain_update(message).finalize();
+    Sha256::new().chain_update(&outer_pad).chain_update(inner).finalize().to_vec()
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    /// Credentials of the AWS Signature Version 4 test suite
+    fn example_signer(service: &str, session_token: Option<&str>) -> SigV4Signer {
+        let credentials = AwsCredentials::new(
+            "AKIDEXAMPLE".to_string(),
+            "wJalrXUtnFEMI/K7MDENG+bPxRfiCYEXAMPLEKEY".to_string(),
+            session_token.map(str::to_string),
+        );
+        SigV4Signer::new(credentials, "us-east-1", service)
+    }
+
+    fn authorization(headers: &[(String, String)]) -> &str {
+        &headers.iter().find(|(name, _)| name == "authorization").unwrap().1
+    }
+
+    #[test]
+    fn test_signature_matches_aws_test_suite() {
+        let signer = example_signer("service", None);
+        let time = Utc.with_ymd_and_hms(2015, 8, 30, 12, 36, 0).unwrap();
+
+        // get-vanilla
+        let url = Url::parse("https://example.amazonaws.com/").unwrap();
+        let headers = signer.sign("GET", &url, &[], b"", time);
+        assert_eq!(headers[0], ("x-amz-date".to_string(), "20150830T123600Z".to_string()));
+        assert_eq!(
+            authorization(&headers),
+            "AWS4-HMAC-SHA256 Credential=AKIDEXAMPLE/20150830/us-east-1/service/aws4_request, \
+             SignedHeaders=host;x-amz-date, \
+             Signature=5fa00fa31553b73ebf1942676e86291e8372ff2a2260956d9b8aae1d763fbf31"
+        );
+
+        // get-vanilla-query-order-key-case
+        let url = Url::parse("https://example.amazonaws.com/?Param2=value2&Param1=value1").unwrap();
+        let headers = signer.sign("GET", &url, &[], b"", time);
+        assert!(authorization(&headers)
+            .ends_with("Signature=b97d918cfa904a5beff61c982a1b6f458b799221646efd99d3219ec94cdf2500"));
+    }
+
+    #[test]
+    fn test_bedrock_request_signature() {
+        let signer = example_signer("bedrock", Some("session-token"));
+        let time = Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap();
+        let url = Url::parse(
+            "https://bedrock-runtime.us-east-1.amazonaws.com/model/anthropic.claude-3-5-sonnet-20240620-v1%3A0/converse",
+        )
+        .unwrap();
+        let body = br#"{"messages":[{"role":"user","content":[{"text":"Hello"}]}]}"#;
+
+        let headers = signer.sign("POST", &url, &[("Content-Type", "application/json")], body, time);
+        assert_eq!(headers[1], ("x-amz-security-token".to_string(), "session-token".to_string()));
+        assert_eq!(
+            authorization(&headers),
+            "AWS4-HMAC-SHA256 Credential=AKIDEXAMPLE/20240101/us-east-1/bedrock/aws4_request, \
+             SignedHeaders=content-type;host;x-amz-date;x-amz-security-token, \
+             Signature=d012b3a75da9fed593e7d649159b1911f1f28234b2cb1a7aa8b237167cb7762c"
+        );
+    }
+
+    #[test]
+    fn test_uri_encode() {
+        assert_eq!(uri_encode("/model/a.b-v1%3A0/converse", false), "/model/a.b-v1%253A0/converse");
+        assert_eq!(uri_encode("arn:aws/x y", true), "arn%3Aaws%2Fx%20y");
+    }
+}
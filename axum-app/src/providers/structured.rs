@@ -0,0 +1,284 @@
+//! Validation of structured (JSON Schema) output
+//!
+//! Providers are asked for JSON matching a schema in their own way, but none of them
+//! guarantees it, so the answer is checked here. An answer that fails gets one repair
+//! attempt: the errors are sent back to the model, which is asked for corrected JSON.
+//!
+//! APIs without a JSON mode are asked for the answer as the input of a forced tool call,
+//! described by [`ResponseTool`].
+
+use anyhow::{anyhow, Result};
+use serde_json::Value;
+
+use crate::chat::{ChatMessage, ChatRole};
+use super::options::{GenerationOptions, JsonSchemaFormat, ResponseFormat};
+
+/// Errors reported for one answer, at most
+const MAX_ERRORS: usize = 10;
+
+/// Name of the response tool for a plain JSON object answer
+pub const JSON_OBJECT_TOOL: &str = "json_response";
+
+const RESPONSE_TOOL_DESCRIPTION: &str = "Give your final answer by calling this tool.";
+
+/// Property a schema that is not an object is wrapped in, since tool input must be an object
+const WRAPPED_ANSWER: &str = "value";
+
+/// Keywords holding the definitions that `#/...` references point into
+const DEFINITIONS: [&str; 2] = ["$defs", "definitions"];
+
+/// Tool whose input is the answer, for APIs that only produce schema-bound JSON as tool calls
+///
+/// A schema whose type is not `object` is wrapped in an object with a single `value`
+/// property, which is taken out of the answer again. Its definitions move to the root of the
+/// wrapper, so that references into them still resolve.
+#[derive(Debug, Clone)]
+pub struct ResponseTool {
+    pub name: String,
+    pub description: String,
+    pub input_schema: Value,
+    wrapped: bool,
+}
+
+impl ResponseTool {
+    /// The response tool for the answer format in `options`, if it asks for JSON
+    pub fn from_options(options: &GenerationOptions) -> Option<Self> {
+        match &options.response_format {
+            Some(ResponseFormat::JsonSchema { json_schema }) => {
+                let wrapped = json_schema.schema.get("type").and_then(Value::as_str) != Some("object");
+                let input_schema = if wrapped {
+                    let mut schema = json_schema.schema.clone();
+                    let mut wrapper = serde_json::json!({
+                        "type": "object",
+                        "required": [WRAPPED_ANSWER]
+                    });
+                    if let Some(fields) = schema.as_object_mut() {
+                        for keyword in DEFINITIONS {
+                            if let Some(definitions) = fields.remove(keyword) {
+                                wrapper[keyword] = definitions;
+                            }
+                        }
+                    }
+                    wrapper["properties"] = serde_json::json!({ WRAPPED_ANSWER: schema });
+                    wrapper
+                } else {
+                    json_schema.schema.clone()
+                };
+                Some(Self {
+                    name: json_schema.name.clone(),
+                    description: json_schema
+                        .description
+                        .clone()
+                        .unwrap_or_else(|| RESPONSE_TOOL_DESCRIPTION.to_string()),
+                    input_schema,
+                    wrapped,
+                })
+            }
+            Some(ResponseFormat::JsonObject) => Some(Self {
+                name: JSON_OBJECT_TOOL.to_string(),
+                description: RESPONSE_TOOL_DESCRIPTION.to_string(),
+                input_schema: serde_json::json!({ "type": "object" }),
+                wrapped: false,
+            }),
+            Some(ResponseFormat::Text) | None => None,
+        }
+    }
+
+    /// The answer text in the tool's `input`
+    pub fn answer(&self, input: &Value) -> String {
+        if self.wrapped {
+            input.get(WRAPPED_ANSWER).unwrap_or(&Value::Null).to_string()
+        } else {
+            input.to_string()
+        }
+    }
+
+    /// Turns the streamed input of this tool into answer text
+    pub fn answer_stream(&self) -> AnswerStream {
+        AnswerStream {
+            wrapped: self.wrapped,
+            input: String::new(),
+        }
+    }
+}
+
+/// The answer text of a streamed [`ResponseTool`] call
+///
+/// Input streams out as it arrives, unless the answer is wrapped: then it is held until the
+/// tool call is complete, to take the answer out of it.
+#[derive(Debug)]
+pub struct AnswerStream {
+    wrapped: bool,
+    input: String,
+}
+
+impl AnswerStream {
+    /// Text to send for the next piece of tool input
+    pub fn delta(&mut self, partial_json: String) -> Option<String> {
+        if !self.wrapped {
+            return Some(partial_json);
+        }
+        self.input.push_str(&partial_json);
+        None
+    }
+
+    /// Text to send once the tool input is complete
+    ///
+    /// Input that is not valid JSON is sent as it is, for validation to report.
+    pub fn finish(&mut self) -> Option<String> {
+        if !self.wrapped {
+            return None;
+        }
+        let input = std::mem::take(&mut self.input);
+        Some(match serde_json::from_str::<Value>(&input) {
+            Ok(Value::Object(mut fields)) => fields.remove(WRAPPED_ANSWER).unwrap_or(Value::Null).to_string(),
+            _ => input,
+        })
+    }
+}
+
+/// Check that `schema` is a valid JSON Schema
+pub fn check_schema(schema: &Value) -> Result<()> {
+    jsonschema::validator_for(schema)
+        .map(|_| ())
+        .map_err(|e| anyhow!("Invalid JSON schema: {}", e))
+}
+
+/// The JSON in `content` if it matches `format`, otherwise what is wrong with it
+///
+/// A Markdown code fence around the JSON is removed.
+pub fn check(format: &JsonSchemaFormat, content: &str) -> std::result::Result<String, Vec<String>> {
+    let json = strip_code_fence(content.trim());
+    let value: Value = serde_json::from_str(json).map_err(|e| vec![format!("not valid JSON: {}", e)])?;
+
+    let validator = jsonschema::validator_for(&format.schema).map_err(|e| vec![format!("invalid schema: {}", e)])?;
+    let errors: Vec<String> = validator
+        .iter_errors(&value)
+        .take(MAX_ERRORS)
+        .map(|error| {
+            let path = error.instance_path.to_string();
+            format!("{}: {}", if path.is_empty() { "/" } else { &path }, error)
+        })
+        .collect();
+
+    if errors.is_empty() {
+        Ok(json.to_string())
+    } else {
+        Err(errors)
+    }
+}
+
+/// User message asking the model to correct an answer that failed [`check`]
+pub fn repair_message(errors: &[String]) -> ChatMessage {
+    let errors: Vec<String> = errors.iter().map(|error| format!("- {}", error)).collect();
+    ChatMessage {
+        id: format!("repair_{}", fastrand::u64(1000..9999)),
+        role: ChatRole::User,
+        content: format!(
+            "Your answer does not match the required JSON schema:\n{}\nReply with only the corrected JSON.",
+            errors.join("\n")
+        ),
+        created_at: Some(chrono::Utc::now()),
+        attachments: None,
+        metadata: None,
+    }
+}
+
+fn strip_code_fence(content: &str) -> &str {
+    let inner = match content.strip_prefix("```").and_then(|rest| rest.strip_suffix("```")) {
+        Some(inner) => inner,
+        None => return content,
+    };
+    // Drop the language tag on the opening line
+    match inner.split_once('\n') {
+        Some((_, body)) => body.trim(),
+        None => inner.trim(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn format() -> JsonSchemaFormat {
+        JsonSchemaFormat {
+            name: "answer".to_string(),
+            description: None,
+            schema: serde_json::json!({
+                "type": "object",
+                "properties": {"answer": {"type": "integer"}},
+                "required": ["answer"]
+            }),
+            strict: None,
+        }
+    }
+
+    #[test]
+    fn test_matching_json_passes() {
+        assert_eq!(check(&format(), " {\"answer\": 4}\n").unwrap(), "{\"answer\": 4}");
+        assert_eq!(check(&format(), "```json\n{\"answer\": 4}\n```").unwrap(), "{\"answer\": 4}");
+    }
+
+    #[test]
+    fn test_mismatches_are_reported() {
+        let errors = check(&format(), "Sure! Here it is: {\"answer\": 4}").unwrap_err();
+        assert!(errors[0].starts_with("not valid JSON"));
+
+        let errors = check(&format(), "{\"answer\": \"four\"}").unwrap_err();
+        assert_eq!(errors, vec!["/answer: \"four\" is not of type \"integer\""]);
+        assert!(repair_message(&errors).content.contains("- /answer: "));
+
+        assert!(check_schema(&serde_json::json!({"type": "object"})).is_ok());
+        assert!(check_schema(&serde_json::json!({"type": "thing"})).is_err());
+    }
+
+    #[test]
+    fn test_non_object_schemas_are_wrapped() {
+        let options = |schema: Value| GenerationOptions {
+            response_format: Some(ResponseFormat::JsonSchema {
+                json_schema: JsonSchemaFormat { schema, ..format() },
+            }),
+            ..GenerationOptions::default()
+        };
+
+        let tool = ResponseTool::from_options(&options(format().schema)).unwrap();
+        assert_eq!(tool.input_schema, format().schema);
+        assert_eq!(tool.answer(&serde_json::json!({"answer": 4})), r#"{"answer":4}"#);
+        assert_eq!(tool.answer_stream().delta("{\"ans".to_string()).as_deref(), Some("{\"ans"));
+
+        let list = serde_json::json!({"type": "array", "items": {"type": "integer"}});
+        let tool = ResponseTool::from_options(&options(list.clone())).unwrap();
+        assert_eq!(tool.input_schema["type"], "object");
+        assert_eq!(tool.input_schema["properties"]["value"], list);
+        assert_eq!(tool.input_schema["required"], serde_json::json!(["value"]));
+        assert_eq!(tool.answer(&serde_json::json!({"value": [1, 2]})), "[1,2]");
+
+        let mut stream = tool.answer_stream();
+        assert_eq!(stream.delta("{\"value\": [1,".to_string()), None);
+        assert_eq!(stream.delta(" 2]}".to_string()), None);
+        assert_eq!(stream.finish().as_deref(), Some("[1,2]"));
+    }
+
+    #[test]
+    fn test_wrapped_schema_references_still_resolve() {
+        let items = serde_json::json!({
+            "type": "array",
+            "items": {"$ref": "#/$defs/Item"},
+            "$defs": {"Item": {"type": "object", "properties": {"name": {"type": "string"}}, "required": ["name"]}}
+        });
+        let options = GenerationOptions {
+            response_format: Some(ResponseFormat::JsonSchema {
+                json_schema: JsonSchemaFormat { schema: items, ..format() },
+            }),
+            ..GenerationOptions::default()
+        };
+
+        let tool = ResponseTool::from_options(&options).unwrap();
+        assert!(tool.input_schema["$defs"]["Item"].is_object());
+        assert!(tool.input_schema["properties"]["value"].get("$defs").is_none());
+
+        let validator = jsonschema::validator_for(&tool.input_schema).unwrap();
+        assert!(validator.is_valid(&serde_json::json!({"value": [{"name": "pen"}]})));
+        assert!(!validator.is_valid(&serde_json::json!({"value": [{"name": 4}]})));
+    }
+}
@@ -0,0 +1,142 @@
+//! Test helpers shared by the provider tests: a local mock server, message builders,
+//! providers with canned answers and credentials
+
+use anyhow::Result;
+use async_trait::async_trait;
+use futures::stream::{self, StreamExt};
+
+use crate::chat::{ChatMessage, ChatRole, UIMessageChunk};
+use crate::providers::google_auth::ServiceAccountKey;
+use crate::providers::{AIProvider, ChunkStream, GenerationOptions, ToolChoice, ToolSpec};
+
+/// An RSA key in PKCS#8 PEM, generated for these tests only; it signs nothing real
+pub const SERVICE_ACCOUNT_TEST_KEY: &str = include_str!("testdata/service_account_key.pem");
+
+/// A Google service account key with [`SERVICE_ACCOUNT_TEST_KEY`]
+pub fn service_account_key(token_uri: Option<String>) -> ServiceAccountKey {
+    ServiceAccountKey {
+        client_email: "chatbot@test-project.iam.gserviceaccount.com".to_string(),
+        private_key: SERVICE_ACCOUNT_TEST_KEY.to_string(),
+        private_key_id: Some("test-key-id".to_string()),
+        project_id: Some("test-project".to_string()),
+        token_uri,
+    }
+}
+
+/// Serve `app` on a free local port, returning its base URL, e.g. `http://127.0.0.1:4321`
+pub async fn serve(app: axum::Router) -> String {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+    url
+}
+
+/// A user message with `content` and nothing else
+pub fn user(content: &str) -> ChatMessage {
+    ChatMessage {
+        id: "1".to_string(),
+        role: ChatRole::User,
+        content: content.to_string(),
+        created_at: None,
+        attachments: None,
+        metadata: None,
+    }
+}
+
+/// A message of `role` with `content` and nothing else
+pub fn message(role: ChatRole, content: &str) -> ChatMessage {
+    ChatMessage { role, ..user(content) }
+}
+
+/// A provider that always answers with the same text
+///
+/// Honors only temperature and max_tokens
+pub struct StaticProvider(pub &'static str);
+
+#[async_trait]
+impl AIProvider for StaticProvider {
+    async fn chat_completion(
+        &self,
+        _messages: Vec<ChatMessage>,
+        model: Option<String>,
+        _options: GenerationOptions,
+        _tools: Option<Vec<ToolSpec>>,
+        _tool_choice: Option<ToolChoice>,
+    ) -> Result<ChatMessage> {
+        Ok(ChatMessage {
+            id: "static".to_string(),
+            role: ChatRole::Assistant,
+            content: format!("{} ({})", self.0, model.unwrap_or_default()),
+            created_at: None,
+            attachments: None,
+            metadata: None,
+        })
+    }
+
+    async fn chat_completion_stream(
+        &self,
+        _messages: Vec<ChatMessage>,
+        _model: Option<String>,
+        _options: GenerationOptions,
+        _tools: Option<Vec<ToolSpec>>,
+        _tool_choice: Option<ToolChoice>,
+    ) -> Result<ChunkStream> {
+        Ok(stream::iter(vec![Ok(UIMessageChunk::TextDelta {
+            textDelta: self.0.to_string(),
+        })])
+        .boxed())
+    }
+
+    fn get_available_models(&self) -> Vec<&'static str> {
+        vec![]
+    }
+
+    fn unsupported_options(&self, _model: &str, options: &GenerationOptions) -> Vec<&'static str> {
+        options.set_among(&["top_p", "top_k", "seed", "stop"])
+    }
+}
+
+/// Answers `{"answer": 4}` when asked for a JSON schema, but with a prose preamble
+/// until it is told what is wrong with that
+pub struct PreambleProvider;
+
+#[async_trait]
+impl AIProvider for PreambleProvider {
+    async fn chat_completion(
+        &self,
+        messages: Vec<ChatMessage>,
+        _model: Option<String>,
+        options: GenerationOptions,
+        _tools: Option<Vec<ToolSpec>>,
+        _tool_choice: Option<ToolChoice>,
+    ) -> Result<ChatMessage> {
+        if options.json_schema().is_none() {
+            return Err(anyhow::anyhow!("no JSON schema requested"));
+        }
+        let repairing = messages.last().is_some_and(|m| m.content.contains("JSON schema"));
+        let answer = r#"{"answer": 4}"#;
+        Ok(ChatMessage {
+            id: "preamble".to_string(),
+            role: ChatRole::Assistant,
+            content: if repairing { answer.to_string() } else { format!("Sure! Here it is: {}", answer) },
+            created_at: None,
+            attachments: None,
+            metadata: None,
+        })
+    }
+
+    async fn chat_completion_stream(
+        &self,
+        _messages: Vec<ChatMessage>,
+        _model: Option<String>,
+        _options: GenerationOptions,
+        _tools: Option<Vec<ToolSpec>>,
+        _tool_choice: Option<ToolChoice>,
+    ) -> Result<ChunkStream> {
+        Err(anyhow::anyhow!("structured answers are not streamed"))
+    }
+
+    fn get_available_models(&self) -> Vec<&'static str> {
+        vec![]
+    }
+}
@@ -0,0 +1,238 @@
+//! Provider-agnostic tool (function) calling types
+//!
+//! Tools are declared once as [`ToolSpec`] and translated by each provider into its native
+//! format. Tool calls requested by the model come back as [`ToolCall`] values, both on
+//! non-streaming responses (in the message metadata) and as `UIMessageChunk::ToolCall` chunks.
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::{BTreeMap, HashMap};
+
+use crate::chat::UIMessageChunk;
+
+/// Metadata key holding the tool calls requested by an assistant message
+pub const TOOL_CALLS_KEY: &str = "tool_calls";
+/// Metadata key holding the id of the tool call a tool message answers
+pub const TOOL_CALL_ID_KEY: &str = "tool_call_id";
+/// Metadata key holding the name of the tool that produced a tool message
+pub const TOOL_NAME_KEY: &str = "tool_name";
+
+/// A tool the model may call, with a JSON Schema describing its arguments
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ToolSpec {
+    pub name: String,
+    #[serde(default)]
+    pub description: String,
+    #[serde(default = "default_parameters")]
+    pub parameters: Value,
+}
+
+fn default_parameters() -> Value {
+    serde_json::json!({ "type": "object", "properties": {} })
+}
+
+/// How the model should choose among the offered tools
+///
+/// Deserializes from the OpenAI wire format: `"auto"`, `"none"`, `"required"` or
+/// `{"type": "function", "function": {"name": "..."}}`.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(try_from = "ToolChoiceRepr")]
+pub enum ToolChoice {
+    Auto,
+    None,
+    Required,
+    Function { name: String },
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ToolChoiceRepr {
+    Mode(String),
+    Function { function: ToolChoiceFunction },
+}
+
+#[derive(Deserialize)]
+struct ToolChoiceFunction {
+    name: String,
+}
+
+impl TryFrom<ToolChoiceRepr> for ToolChoice {
+    type Error = String;
+
+    fn try_from(repr: ToolChoiceRepr) -> Result<Self, Self::Error> {
+        match repr {
+            ToolChoiceRepr::Mode(mode) => match mode.as_str() {
+                "auto" => Ok(ToolChoice::Auto),
+                "none" => Ok(ToolChoice::None),
+                "required" | "any" => Ok(ToolChoice::Required),
+                _ => Err(format!("Unsupported tool_choice: {}", mode)),
+            },
+            ToolChoiceRepr::Function { function } => Ok(ToolChoice::Function { name: function.name }),
+        }
+    }
+}
+
+/// A tool call requested by the model
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ToolCall {
+    pub id: String,
+    pub name: String,
+    pub arguments: Value,
+}
+
+impl ToolCall {
+    /// Read the tool calls stored in a message's metadata, if any
+    pub fn from_metadata(metadata: Option<&HashMap<String, Value>>) -> Option<Vec<ToolCall>> {
+        metadata
+            .and_then(|m| m.get(TOOL_CALLS_KEY))
+            .and_then(|v| serde_json::from_value::<Vec<ToolCall>>(v.clone()).ok())
+            .filter(|calls| !calls.is_empty())
+    }
+
+    /// Build a tool call from raw JSON-encoded arguments
+    pub fn from_raw_arguments(id: String, name: String, raw_arguments: &str) -> Self {
+        Self {
+            id,
+            name,
+            arguments: parse_arguments(raw_arguments),
+        }
+    }
+
+    /// Arguments encoded as a JSON string, as OpenAI-style APIs expect them
+    pub fn arguments_string(&self) -> String {
+        match &self.arguments {
+            Value::String(raw) => raw.clone(),
+            other => other.to_string(),
+        }
+    }
+
+    /// Convert into an AI SDK stream chunk
+    pub fn into_chunk(self) -> UIMessageChunk {
+        UIMessageChunk::ToolCall {
+            toolCallId: self.id,
+            toolName: self.name,
+            args: self.arguments,
+        }
+    }
+}
+
+/// Parse JSON-encoded tool arguments, keeping the raw string if it is not valid JSON
+fn parse_arguments(raw: &str) -> Value {
+    if raw.trim().is_empty() {
+        return Value::Object(Default::default());
+    }
+    serde_json::from_str(raw).unwrap_or_else(|_| Value::String(raw.to_string()))
+}
+
+/// Reassembles tool calls streamed as fragments keyed by their index
+#[derive(Debug, Default)]
+pub struct ToolCallAccumulator {
+    calls: BTreeMap<u64, PartialToolCall>,
+}
+
+#[derive(Debug, Default)]
+struct PartialToolCall {
+    id: String,
+    name: String,
+    arguments: String,
+}
+
+impl ToolCallAccumulator {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a fragment for the tool call at `index`
+    pub fn push(&mut self, index: u64, id: Option<&str>, name: Option<&str>, arguments: Option<&str>) {
+        let call = self.calls.entry(index).or_default();
+        if let Some(id) = id.filter(|id| !id.is_empty()) {
+            call.id = id.to_string();
+        }
+        if let Some(name) = name {
+            call.name.push_str(name);
+        }
+        if let Some(arguments) = arguments {
+            call.arguments.push_str(arguments);
+        }
+    }
+
+    /// Add an OpenAI-style `delta.tool_calls` array
+    pub fn push_openai_delta(&mut self, tool_calls: &[Value]) {
+        for (position, call) in tool_calls.iter().enumerate() {
+            let index = call
+                .get("index")
+                .and_then(|i| i.as_u64())
+                .unwrap_or(position as u64);
+            let function = call.get("function");
+            self.push(
+                index,
+                call.get("id").and_then(|v| v.as_str()),
+                function.and_then(|f| f.get("name")).and_then(|v| v.as_str()),
+                function.and_then(|f| f.get("arguments")).and_then(|v| v.as_str()),
+            );
+        }
+    }
+
+    /// Take the completed tool calls, in index order
+    pub fn finish(&mut self) -> Vec<ToolCall> {
+        std::mem::take(&mut self.calls)
+            .into_iter()
+            .map(|(index, call)| {
+                let id = if call.id.is_empty() {
+                    format!("call_{}", index)
+                } else {
+                    call.id
+                };
+                ToolCall::from_raw_arguments(id, call.name, &call.arguments)
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_accumulator_reassembles_fragments() {
+        let mut acc = ToolCallAccumulator::new();
+        acc.push_openai_delta(&[serde_json::json!({
+            "index": 0, "id": "call_1", "type": "function",
+            "function": {"name": "calculator", "arguments": ""}
+        })]);
+        acc.push_openai_delta(&[serde_json::json!({"index": 0, "function": {"arguments": "{\"expres"}})]);
+        acc.push_openai_delta(&[serde_json::json!({"index": 0, "function": {"arguments": "sion\": \"2 + 2\"}"}})]);
+        acc.push_openai_delta(&[serde_json::json!({
+            "index": 1, "id": "call_2", "function": {"name": "web_search", "arguments": "{}"}
+        })]);
+
+        let calls = acc.finish();
+        assert!(acc.finish().is_empty());
+        assert_eq!(calls.len(), 2);
+        assert_eq!(calls[0].id, "call_1");
+        assert_eq!(calls[0].name, "calculator");
+        assert_eq!(calls[0].arguments, serde_json::json!({"expression": "2 + 2"}));
+        assert_eq!(calls[1].name, "web_search");
+    }
+
+    #[test]
+    fn test_tool_choice_deserialization() {
+        let auto: ToolChoice = serde_json::from_value(serde_json::json!("auto")).unwrap();
+        assert_eq!(auto, ToolChoice::Auto);
+
+        let named: ToolChoice = serde_json::from_value(serde_json::json!({
+            "type": "function", "function": {"name": "calculator"}
+        }))
+        .unwrap();
+        assert_eq!(named, ToolChoice::Function { name: "calculator".to_string() });
+
+        assert!(serde_json::from_value::<ToolChoice>(serde_json::json!("sometimes")).is_err());
+    }
+
+    #[test]
+    fn test_invalid_arguments_are_kept_raw() {
+        let call = ToolCall::from_raw_arguments("1".to_string(), "t".to_string(), "{not json");
+        assert_eq!(call.arguments, Value::String("{not json".to_string()));
+        assert_eq!(call.arguments_string(), "{not json");
+    }
+}